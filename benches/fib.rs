@@ -0,0 +1,33 @@
+//! Times a copy of `fib` from willcode.ws under each backend. Run with
+//! `cargo bench`, adding `--features jit` for the JIT.
+//!
+//! The tree-walker used to deep-clone a function's whole body on every call.
+//! Sharing bodies behind `Rc` took this bench, best of 5 runs on one machine,
+//! from about 285ms to about 100ms under the tree-walker. The VM never cloned
+//! bodies, so sharing them didn't change its time.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use willscript::{Backend, load, run};
+
+const RUNS: u32 = 5;
+
+fn main() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/fib.ws");
+    let backends = [("tree", Backend::Tree), ("vm", Backend::Vm)].into_iter();
+    #[cfg(feature = "jit")]
+    let backends = backends.chain([("jit", Backend::Jit)]);
+    for (label, backend) in backends {
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let program = load(&path).expect("benches/fib.ws should load");
+            let start = Instant::now();
+            run(program, backend).expect("benches/fib.ws should run");
+            best = best.min(start.elapsed());
+        }
+        println!("fib(25), {}: best of {} runs {:?}", label, RUNS, best);
+    }
+}
@@ -1,38 +1,779 @@
-use crate::lexer::Operator;
+use std::{cell::RefCell, cmp::Ordering, collections::HashSet, fmt, rc::Rc};
 
+use crate::lexer::{Operator, Span};
+
+/// The name of a function, struct, field, enum or variant. Names are
+/// interned, so the values that carry them at runtime share one copy
+/// instead of allocating their own.
+pub type Name = Rc<str>;
+
+thread_local! {
+    static NAMES: RefCell<HashSet<Name>> = RefCell::new(HashSet::new());
+}
+
+/// Gives back the one shared copy of `name`.
+pub fn intern(name: &str) -> Name {
+    NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        if let Some(x) = names.get(name) {
+            return x.clone();
+        }
+        let x: Name = Rc::from(name);
+        names.insert(x.clone());
+        x
+    })
+}
+
+/// The spans say where a runtime error in the expression gets reported: the
+/// name, operator, `[`, `.` or `{`.
 #[derive(Clone, Debug)]
 pub enum ExprAST {
-    Variable(String),
+    Variable(String, Span, Resolution),
     Val(Value),
-    BinOp(Operator, Box<ExprAST>, Box<ExprAST>),
-    Call(String, Vec<ExprAST>),
+    BinOp(Operator, Box<ExprAST>, Box<ExprAST>, Span),
+    /// A call by name, which may be a function value in a local variable, a
+    /// declared function, an enum variant or a builtin, in that order.
+    Call(String, Vec<Arg>, Span, Resolution),
+    /// A call of whatever the expression evaluates to, like `make_adder(1)(2)`.
+    CallExpr(Box<ExprAST>, Vec<Arg>, Span),
+    Closure(Rc<FunctionAST>),
+    List(Vec<ExprAST>),
+    /// `(a, b)`, or `(a,)` for a tuple of one.
+    Tuple(Vec<ExprAST>),
+    Map(Vec<(ExprAST, ExprAST)>, Span),
+    Index(Box<ExprAST>, Box<ExprAST>, Span),
+    StructInit(String, Vec<(String, ExprAST)>, Span),
+    Field(Box<ExprAST>, String, Span),
+    Match(Box<MatchBlock<ExprAST>>),
+    /// `if c { a } else { b }`, only evaluating the branch it picks.
+    If(Box<ExprAST>, Box<ExprAST>, Box<ExprAST>),
+    /// `x?`: the value inside an `Ok` or `Some`, or else the function returns
+    /// the `Err` or `None` right away.
+    Propagate(Box<ExprAST>, Span),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// Values compare by what they hold, with lists, maps and structs first
+/// checked for being the same one, so a list holding itself equals itself.
+#[derive(Clone, Debug)]
 pub enum Value {
     Str(String),
     Int(i32),
+    /// Lists have reference semantics: assigning a list to another variable
+    /// or passing it to a function shares the same backing storage, so
+    /// `xs[0] = 1` or `push(xs, 1)` is seen through every handle to it.
+    /// Evaluating a `[...]` literal always makes a fresh list.
+    List(Rc<RefCell<Vec<Value>>>),
+    /// Maps share storage the same way lists do.
+    Map(Rc<RefCell<OrderedMap>>),
+    /// So do struct instances.
+    Struct(Rc<RefCell<StructValue>>),
+    /// Enum values are immutable, so they can share without a RefCell.
+    Variant(Rc<VariantValue>),
+    /// Tuples are immutable too: `t[0]` reads an item, but nothing changes one.
+    Tuple(Rc<Vec<Value>>),
+    Function(Rc<FunctionValue>),
+    Error(Rc<ErrorValue>),
+}
+impl Value {
+    pub fn new_list(items: Vec<Value>) -> Self {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+    pub fn new_map(map: OrderedMap) -> Self {
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+    /// The value as the debugger shows it, with a string in quotes.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(x) => format!("{:?}", x),
+            x => x.to_string(),
+        }
+    }
+    /// A short form for stack traces, with long values cut off.
+    pub fn summary(&self) -> String {
+        const MAX_LEN: usize = 24;
+        let full = self.repr();
+        if full.chars().count() <= MAX_LEN {
+            return full;
+        }
+        let mut short: String = full.chars().take(MAX_LEN - 3).collect();
+        short.push_str("...");
+        short
+    }
+    // the order values of different kinds compare in
+    fn kind(&self) -> u8 {
+        match self {
+            Value::Str(_) => 0,
+            Value::Int(_) => 1,
+            Value::List(_) => 2,
+            Value::Map(_) => 3,
+            Value::Struct(_) => 4,
+            Value::Variant(_) => 5,
+            Value::Tuple(_) => 6,
+            Value::Function(_) => 7,
+            Value::Error(_) => 8,
+        }
+    }
+    // strings nested inside a list get quotes, so ["1"] and [1] print differently
+    fn fmt_nested(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Str(x) => write!(f, "{:?}", x),
+            x => x.fmt_seen(f, seen),
+        }
+    }
+    // `seen` holds the lists, maps and structs being printed, so one that
+    // holds itself shows up inside as [...], {...} or Name { ... }
+    fn fmt_seen(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        let ptr = match self {
+            Value::List(x) => Rc::as_ptr(x) as *const (),
+            Value::Map(x) => Rc::as_ptr(x) as *const (),
+            Value::Struct(x) => Rc::as_ptr(x) as *const (),
+            _ => std::ptr::null(),
+        };
+        if !ptr.is_null() && seen.contains(&ptr) {
+            return match self {
+                Value::List(_) => write!(f, "[...]"),
+                Value::Map(_) => write!(f, "{{...}}"),
+                Value::Struct(x) => write!(f, "{} {{ ... }}", x.borrow().name),
+                _ => unreachable!(),
+            };
+        }
+        seen.push(ptr);
+        let result = self.fmt_parts(f, seen);
+        seen.pop();
+        result
+    }
+    fn fmt_parts(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Str(x) => write!(f, "{}", x),
+            Value::Int(x) => write!(f, "{}", x),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f, seen)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.fmt_nested(f, seen)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f, seen)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Struct(instance) => {
+                let instance = instance.borrow();
+                write!(f, "{} {{", instance.name)?;
+                for (i, (field, value)) in instance.fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}: ", field)?;
+                    value.fmt_nested(f, seen)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Tuple(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f, seen)?;
+                }
+                if items.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::Error(error) => write!(f, "{}", error),
+            Value::Function(function) => match &**function {
+                FunctionValue::Named(name) => write!(f, "<fun {}>", name),
+                FunctionValue::Closure(_) => write!(f, "<closure>"),
+            },
+            Value::Variant(variant) => {
+                write!(f, "{}", variant.variant)?;
+                if !variant.payload.is_empty() {
+                    write!(f, "(")?;
+                    for (i, value) in variant.payload.iter().enumerate() {
+                        if i != 0 {
+                            write!(f, ", ")?;
+                        }
+                        value.fmt_nested(f, seen)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_seen(f, &mut Vec::new())
+    }
+}
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Value {}
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::List(a), Value::List(b)) if Rc::ptr_eq(a, b) => Ordering::Equal,
+            (Value::List(a), Value::List(b)) => a.borrow().cmp(&b.borrow()),
+            (Value::Map(a), Value::Map(b)) if Rc::ptr_eq(a, b) => Ordering::Equal,
+            (Value::Map(a), Value::Map(b)) => a.borrow().cmp(&b.borrow()),
+            (Value::Struct(a), Value::Struct(b)) if Rc::ptr_eq(a, b) => Ordering::Equal,
+            (Value::Struct(a), Value::Struct(b)) => a.borrow().cmp(&b.borrow()),
+            (Value::Variant(a), Value::Variant(b)) => a.cmp(b),
+            (Value::Tuple(a), Value::Tuple(b)) => a.cmp(b),
+            (Value::Function(a), Value::Function(b)) => a.cmp(b),
+            (Value::Error(a), Value::Error(b)) => a.cmp(b),
+            (a, b) => a.kind().cmp(&b.kind()),
+        }
+    }
+}
+/// A struct instance. Fields are kept in declaration order.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StructValue {
+    pub name: Name,
+    pub fields: Vec<(Name, Value)>,
+}
+impl StructValue {
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(f, _)| **f == *field)
+            .map(|(_, v)| v)
+    }
+    pub fn get_mut(&mut self, field: &str) -> Option<&mut Value> {
+        self.fields
+            .iter_mut()
+            .find(|(f, _)| **f == *field)
+            .map(|(_, v)| v)
+    }
+}
+
+/// Key/value storage for `Value::Map`. Iteration follows insertion order,
+/// and overwriting a key keeps its original position. Lookups are linear,
+/// which is fine for the config-sized maps scripts build.
+#[derive(Clone, Debug, Default)]
+pub struct OrderedMap {
+    entries: Vec<(Value, Value)>,
+}
+impl OrderedMap {
+    pub fn new() -> Self {
+        OrderedMap::default()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.get(key).is_some()
+    }
+    pub fn insert(&mut self, key: Value, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old)) => *old = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let pos = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(pos).1)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter()
+    }
+    fn sorted(&self) -> Vec<&(Value, Value)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+}
+// Maps holding the same pairs are equal whatever order they were inserted
+// in, so they compare by their entries sorted by key.
+impl PartialEq for OrderedMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for OrderedMap {}
+impl PartialOrd for OrderedMap {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for OrderedMap {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sorted().cmp(&other.sorted())
+    }
+}
+
+/// A value of one of an enum's variants, e.g. `Rect(2, 3)`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VariantValue {
+    pub enum_name: Name,
+    pub variant: Name,
+    pub payload: Vec<Value>,
+}
+
+/// What `catch e` binds: a runtime error or a thrown value. Scripts read
+/// `e.kind`, `e.message`, `e.line` and `e.col`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ErrorValue {
+    /// `TypeError`, `NameError`, `IndexError`, `KeyError`, `FieldError`,
+    /// `ArgumentError`, `ValueError`, `MatchError`, `DivisionByZero` and
+    /// `Overflow` come from the interpreter. `throw` of anything but an error
+    /// gives an `Error`, and `error(kind, message)` makes any other kind.
+    pub kind: String,
+    pub message: String,
+    pub span: Span,
+    /// The calls that were running when the error was thrown, innermost
+    /// last. Filled in as the error leaves its first function.
+    pub trace: Vec<Frame>,
+}
+impl ErrorValue {
+    /// An error from code that doesn't know where it is, like a builtin. The
+    /// interpreter places it with `at`.
+    pub fn new(kind: &str, message: String) -> Self {
+        ErrorValue {
+            kind: kind.to_owned(),
+            message,
+            span: Span::default(),
+            trace: Vec::new(),
+        }
+    }
+    pub fn at(mut self, span: Span) -> Rc<ErrorValue> {
+        self.span = span;
+        Rc::new(self)
+    }
+    /// The trace, innermost call first, one line per frame. A run of frames
+    /// repeating back to back, like a function recursing into itself or two
+    /// calling each other, is shown once with a count.
+    pub fn format_trace(&self) -> String {
+        // longest cycle of calls looked for, and how many times it has to
+        // come back before it's worth folding
+        const MAX_CYCLE: usize = 4;
+        const MIN_REPEATS: usize = 3;
+        let frames: Vec<&Frame> = self.trace.iter().rev().collect();
+        let same = |a: &[&Frame], b: &[&Frame]| {
+            a.iter()
+                .zip(b)
+                .all(|(x, y)| x.name == y.name && x.call_site == y.call_site)
+        };
+        let repeats_at = |i: usize, p: usize| {
+            let mut repeats = 1;
+            while i + (repeats + 1) * p <= frames.len()
+                && same(&frames[i..i + p], &frames[i + repeats * p..])
+            {
+                repeats += 1;
+            }
+            repeats
+        };
+        let mut lines = Vec::new();
+        let mut i = 0;
+        while i < frames.len() {
+            let cycle = (1..=MAX_CYCLE)
+                .map(|p| (p, repeats_at(i, p)))
+                .find(|&(_, repeats)| repeats >= MIN_REPEATS);
+            let Some((p, repeats)) = cycle else {
+                lines.push(format!("  {}", frames[i]));
+                i += 1;
+                continue;
+            };
+            for frame in &frames[i..i + p] {
+                lines.push(format!("  {}", frame));
+            }
+            if p == 1 {
+                lines.push(format!("  ... repeated {} more times", repeats - 1));
+            } else {
+                lines.push(format!(
+                    "  ... the {} calls above repeated {} more times",
+                    p,
+                    repeats - 1
+                ));
+            }
+            i += repeats * p;
+        }
+        lines.join("\n")
+    }
+}
+
+/// A call on the interpreter's stack: the function, where it was called
+/// from, and the values its parameters got.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Frame {
+    pub name: Name,
+    pub call_site: Span,
+    pub args: Vec<Value>,
+}
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "in {}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg.summary())?;
+        }
+        write!(f, ")")?;
+        // main is called by the interpreter, not from anywhere in the script
+        if self.call_site != Span::default() {
+            write!(f, " called at {}", self.call_site)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for ErrorValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.span, self.kind, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum FunctionValue {
+    /// A declared function, looked up by name when called.
+    Named(Name),
+    Closure(Closure),
+}
+// Named functions are equal when their names are, closures only to themselves.
+impl PartialEq for FunctionValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for FunctionValue {}
+impl PartialOrd for FunctionValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for FunctionValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (FunctionValue::Named(a), FunctionValue::Named(b)) => a.cmp(b),
+            (FunctionValue::Named(_), FunctionValue::Closure(_)) => Ordering::Less,
+            (FunctionValue::Closure(_), FunctionValue::Named(_)) => Ordering::Greater,
+            (FunctionValue::Closure(_), FunctionValue::Closure(_)) => {
+                (self as *const Self).cmp(&(other as *const Self))
+            }
+        }
+    }
+}
+
+/// An anonymous function together with the variables it captured. Capture is
+/// by reference: making the closure shares every variable in scope with it,
+/// so an assignment on either side is seen by the other, and a closure stored
+/// in a variable can call itself through that variable.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<FunctionAST>,
+    /// The locals of the function it was made in, by slot. The closure's own
+    /// slots start with those, so they're its frame to begin with.
+    pub captured: Vec<Local>,
+}
+
+/// A local variable in a frame. Making a closure turns the locals it captures
+/// into cells the two share.
+#[derive(Clone, Debug, Default)]
+pub enum Local {
+    #[default]
+    Unset,
+    Set(Value),
+    Shared(Rc<RefCell<Option<Value>>>),
+}
+impl Local {
+    pub fn get(&self) -> Option<Value> {
+        match self {
+            Local::Unset => None,
+            Local::Set(x) => Some(x.clone()),
+            Local::Shared(cell) => cell.borrow().clone(),
+        }
+    }
+    pub fn is_set(&self) -> bool {
+        match self {
+            Local::Unset => false,
+            Local::Set(_) => true,
+            Local::Shared(cell) => cell.borrow().is_some(),
+        }
+    }
+    /// Assigns the variable, which a closure sharing it sees too. Binding a
+    /// new variable in its place is plain assignment to the slot instead.
+    pub fn set(&mut self, value: Option<Value>) {
+        match self {
+            Local::Shared(cell) => *cell.borrow_mut() = value,
+            _ => *self = Local::from(value),
+        }
+    }
+    /// The variable for a closure to hold, shared from now on.
+    pub fn share(&mut self) -> Local {
+        if !matches!(self, Local::Shared(_)) {
+            *self = Local::Shared(Rc::new(RefCell::new(self.get())));
+        }
+        self.clone()
+    }
+}
+impl From<Option<Value>> for Local {
+    fn from(value: Option<Value>) -> Self {
+        match value {
+            Some(x) => Local::Set(x),
+            None => Local::Unset,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProgramAST {
+    pub functions: Vec<FunctionAST>,
+    pub structs: Vec<StructAST>,
+    pub enums: Vec<EnumAST>,
+    pub globals: Vec<GlobalAST>,
+    /// Emptied when the modules are linked into one program.
+    pub imports: Vec<ImportAST>,
+    /// The functions and constants marked `export`.
+    pub exports: Vec<String>,
+}
+impl ProgramAST {
+    /// A program with nothing but the prelude enums in it.
+    pub fn new() -> Self {
+        ProgramAST {
+            enums: EnumAST::prelude(),
+            ..ProgramAST::default()
+        }
+    }
+}
+
+/// A top-level `import "path.ws" as alias;`. The path is relative to the
+/// file the import is in.
+#[derive(Clone, Debug)]
+pub struct ImportAST {
+    pub path: String,
+    pub alias: String,
+    pub span: Span,
+}
+impl ImportAST {
+    pub fn new(path: String, alias: String, span: Span) -> Self {
+        ImportAST { path, alias, span }
+    }
+}
+
+/// A top-level `const NAME = expr;` or `global NAME = expr;`. Constants are
+/// initialized first, each after the constants its initializer names, then
+/// globals in source order. Both are visible in every function, unless a
+/// local of the same name shadows them.
+#[derive(Clone, Debug)]
+pub struct GlobalAST {
+    pub is_const: bool,
+    pub name: String,
+    pub init: ExprAST,
+    /// What match arms in the initializer bind, by slot. Filled in by the
+    /// resolver.
+    pub locals: Vec<String>,
+}
+impl GlobalAST {
+    pub fn new(is_const: bool, name: String, init: ExprAST) -> Self {
+        GlobalAST {
+            is_const,
+            name,
+            init,
+            locals: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StructAST {
+    pub name: Name,
+    pub fields: Vec<Name>,
+}
+impl StructAST {
+    pub fn new(name: Name, fields: Vec<Name>) -> Self {
+        StructAST { name, fields }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EnumAST {
+    pub name: Name,
+    pub variants: Vec<VariantAST>,
+}
+impl EnumAST {
+    pub fn new(name: Name, variants: Vec<VariantAST>) -> Self {
+        EnumAST { name, variants }
+    }
+    /// The enums every program has, for results of things that can fail:
+    /// `enum Result { Ok(value), Err(error) }` and
+    /// `enum Option { Some(value), None }`.
+    pub fn prelude() -> Vec<EnumAST> {
+        let variant = |name: &str, fields: &[&str]| {
+            let fields = fields.iter().map(|f| intern(f)).collect();
+            VariantAST::new(intern(name), fields)
+        };
+        vec![
+            EnumAST::new(
+                intern("Result"),
+                vec![variant("Ok", &["value"]), variant("Err", &["error"])],
+            ),
+            EnumAST::new(
+                intern("Option"),
+                vec![variant("Some", &["value"]), variant("None", &[])],
+            ),
+        ]
+    }
+}
+
+/// One variant of an enum. The payload names only document the positions,
+/// a variant is constructed and matched positionally: `Rect(2, 3)`.
+#[derive(Clone, Debug)]
+pub struct VariantAST {
+    pub name: Name,
+    pub fields: Vec<Name>,
+}
+impl VariantAST {
+    pub fn new(name: Name, fields: Vec<Name>) -> Self {
+        VariantAST { name, fields }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct FunctionAST {
     pub proto: PrototypeAST,
     pub body: Vec<Statement>,
+    /// Every name the function binds, by slot, filled in by the resolver. A
+    /// closure's start with the ones of the function it's in.
+    pub locals: Vec<String>,
+    /// The slot of each parameter, then of the rest parameter if there is one.
+    pub param_slots: Vec<usize>,
 }
 impl FunctionAST {
     pub fn new(proto: PrototypeAST, body: Vec<Statement>) -> Self {
-        FunctionAST { proto, body }
+        FunctionAST {
+            proto,
+            body,
+            locals: vec![],
+            param_slots: vec![],
+        }
     }
 }
 
+/// Where a name used in a function can be found, filled in by the resolver.
+/// The tree-walker's lookup order still holds: a bound local, then an
+/// initialized global, then a declared function, then anything else by name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Resolution {
+    /// The local slot, if the function binds the name anywhere.
+    pub slot: Option<usize>,
+    /// The index into ProgramAST::globals.
+    pub global: Option<usize>,
+    /// The index into ProgramAST::functions.
+    pub function: Option<usize>,
+}
+
 #[derive(Clone, Debug)]
 pub struct PrototypeAST {
-    pub name: String,
-    pub args: Vec<String>,
+    pub name: Name,
+    pub args: Vec<Param>,
+    /// `...name` as the last parameter collects any extra positional
+    /// arguments into a list.
+    pub rest: Option<String>,
 }
 impl PrototypeAST {
-    pub fn new(name: String, args: Vec<String>) -> Self {
-        PrototypeAST { name, args }
+    pub fn new(name: Name, args: Vec<Param>, rest: Option<String>) -> Self {
+        PrototypeAST { name, args, rest }
+    }
+    /// How many positional arguments a call can give, like `2` or `1 to 3`,
+    /// not counting any the rest parameter takes.
+    pub fn expected_args(&self) -> String {
+        let required = self.args.iter().filter(|a| a.default.is_none()).count();
+        if required == self.args.len() {
+            format!("{}", required)
+        } else {
+            format!("{} to {}", required, self.args.len())
+        }
+    }
+    /// Checks that a call with this many positional arguments and these named
+    /// ones gives every parameter without a default exactly one value.
+    pub fn check_args(&self, positional: usize, named: &[&str]) -> Result<(), String> {
+        if positional > self.args.len() && self.rest.is_none() {
+            return Err(format!(
+                "Function {} takes {} arguments, got {}.",
+                self.name,
+                self.expected_args(),
+                positional
+            ));
+        }
+        for name in named {
+            match self.args.iter().position(|a| a.name == *name) {
+                None => {
+                    return Err(format!(
+                        "Function {} has no parameter named {}.",
+                        self.name, name
+                    ));
+                }
+                Some(i) if i < positional => {
+                    return Err(format!(
+                        "Argument {} of function {} is given both by position and by name.",
+                        name, self.name
+                    ));
+                }
+                Some(_) => (),
+            }
+        }
+        for arg in self.args.iter().skip(positional) {
+            if arg.default.is_none() && !named.contains(&arg.name.as_str()) {
+                return Err(format!(
+                    "Function {} is missing argument {}.",
+                    self.name, arg.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A parameter, `x` or `x = default`. Defaults are evaluated at call time for
+/// each parameter the caller left out, and can use the parameters before them.
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<ExprAST>,
+}
+impl Param {
+    pub fn new(name: String, default: Option<ExprAST>) -> Self {
+        Param { name, default }
+    }
+}
+
+/// An argument at a call site, positional `x` or named `name: x`. Named
+/// arguments come after all positional ones.
+#[derive(Clone, Debug)]
+pub struct Arg {
+    pub name: Option<String>,
+    pub value: ExprAST,
+}
+impl Arg {
+    pub fn new(name: Option<String>, value: ExprAST) -> Self {
+        Arg { name, value }
     }
 }
 
@@ -43,20 +784,66 @@ pub enum Statement {
     While(WhileBlock),
     Call(ExprAST),
     Built(BuiltIn),
+    Match(MatchBlock<Vec<Statement>>),
+    Try(TryBlock),
+    /// `throw expr;`, the span being the `throw`.
+    Throw(ExprAST, Span),
+}
+impl Statement {
+    /// Where the statement starts, for the debugger's breakpoints and steps.
+    /// The `return 0` the interpreter adds to each function has no place.
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Assign(x) => x.span,
+            Statement::If(x) => x.span,
+            Statement::While(x) => x.span,
+            Statement::Call(ExprAST::Call(_, _, span, _) | ExprAST::CallExpr(_, _, span)) => *span,
+            Statement::Call(_) => Span::default(),
+            Statement::Built(BuiltIn::Print(_, span) | BuiltIn::Return(_, span)) => *span,
+            Statement::Built(
+                BuiltIn::Input(ExprAST::Variable(_, span, _))
+                | BuiltIn::Drop(ExprAST::Variable(_, span, _)),
+            ) => *span,
+            Statement::Built(_) => Span::default(),
+            Statement::Match(x) => x.span,
+            Statement::Try(x) => x.span,
+            Statement::Throw(_, span) => *span,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Assignment {
     pub is_declaration: bool,
+    /// False for `let` declarations, which the checker won't let be assigned
+    /// again. Plain assignments and `var` declarations are always mutable.
+    pub is_mutable: bool,
+    /// A variable, index or field. Declarations can also destructure, with a
+    /// tuple `(q, r)`, list `[a, b]` or struct `Point { x, y }` of names in
+    /// place of the variable; `_` skips a part.
     pub variable: ExprAST,
     pub right_hand: ExprAST,
+    /// The operator of a compound assignment like `x += 1`. The target is
+    /// evaluated once, then read, combined with the right hand and written.
+    pub compound: Option<Operator>,
+    /// Where the statement starts.
+    pub span: Span,
 }
 impl Assignment {
-    pub fn new(is_declaration: bool, variable: ExprAST, right_hand: ExprAST) -> Self {
+    pub fn new(
+        is_declaration: bool,
+        is_mutable: bool,
+        variable: ExprAST,
+        right_hand: ExprAST,
+        span: Span,
+    ) -> Self {
         Assignment {
             is_declaration,
+            is_mutable,
             variable,
             right_hand,
+            compound: None,
+            span,
         }
     }
 }
@@ -65,10 +852,24 @@ impl Assignment {
 pub struct IfBlock {
     pub conditional: ExprAST,
     pub body: Vec<Statement>,
+    /// Empty when there's no else.
+    pub else_body: Vec<Statement>,
+    /// The `if`.
+    pub span: Span,
 }
 impl IfBlock {
-    pub fn new(conditional: ExprAST, body: Vec<Statement>) -> Self {
-        IfBlock { conditional, body }
+    pub fn new(
+        conditional: ExprAST,
+        body: Vec<Statement>,
+        else_body: Vec<Statement>,
+        span: Span,
+    ) -> Self {
+        IfBlock {
+            conditional,
+            body,
+            else_body,
+            span,
+        }
     }
 }
 
@@ -76,17 +877,99 @@ impl IfBlock {
 pub struct WhileBlock {
     pub conditional: ExprAST,
     pub body: Vec<Statement>,
+    /// The `while`.
+    pub span: Span,
 }
 impl WhileBlock {
-    pub fn new(conditional: ExprAST, body: Vec<Statement>) -> Self {
-        WhileBlock { conditional, body }
+    pub fn new(conditional: ExprAST, body: Vec<Statement>, span: Span) -> Self {
+        WhileBlock {
+            conditional,
+            body,
+            span,
+        }
     }
 }
 
+/// `try { ... } catch e { ... } finally { ... }`, where either the catch or
+/// the finally may be left out. The catch gets any error thrown in the body,
+/// bound to its name for the catch block only. The finally runs however the
+/// rest ended, and a return or throw in it wins over the one it interrupted.
+#[derive(Clone, Debug)]
+pub struct TryBlock {
+    pub body: Vec<Statement>,
+    /// The name, its slot once resolved, and the block.
+    pub catch: Option<(String, usize, Vec<Statement>)>,
+    pub finally: Option<Vec<Statement>>,
+    /// The `try`.
+    pub span: Span,
+}
+impl TryBlock {
+    pub fn new(
+        body: Vec<Statement>,
+        catch: Option<(String, usize, Vec<Statement>)>,
+        finally: Option<Vec<Statement>>,
+        span: Span,
+    ) -> Self {
+        TryBlock {
+            body,
+            catch,
+            finally,
+            span,
+        }
+    }
+}
+
+/// `match` is both a statement, whose arms are blocks, and an expression,
+/// whose arms are expressions. `T` is the arm body.
+#[derive(Clone, Debug)]
+pub struct MatchBlock<T> {
+    pub scrutinee: ExprAST,
+    pub arms: Vec<MatchArm<T>>,
+    /// The `match`, where it's reported when no arm matches.
+    pub span: Span,
+}
+impl<T> MatchBlock<T> {
+    pub fn new(scrutinee: ExprAST, arms: Vec<MatchArm<T>>, span: Span) -> Self {
+        MatchBlock {
+            scrutinee,
+            arms,
+            span,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchArm<T> {
+    pub pattern: Pattern,
+    pub guard: Option<ExprAST>,
+    pub body: T,
+}
+impl<T> MatchArm<T> {
+    pub fn new(pattern: Pattern, guard: Option<ExprAST>, body: T) -> Self {
+        MatchArm {
+            pattern,
+            guard,
+            body,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Wildcard,
+    Literal(Value),
+    /// A bare name is a unit variant if one by that name exists, otherwise it
+    /// binds whatever it matched for the rest of the arm, in the slot the
+    /// resolver gives it.
+    Ident(String, Option<usize>),
+    Variant(String, Vec<Pattern>),
+}
+
 #[derive(Clone, Debug)]
 pub enum BuiltIn {
-    Print(ExprAST),
-    Return(ExprAST),
+    /// With the span of the keyword, as is `Return`.
+    Print(ExprAST, Span),
+    Return(ExprAST, Span),
     // these two are only with variables
     Input(ExprAST),
     Drop(ExprAST),
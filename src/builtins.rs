@@ -0,0 +1,272 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::ast::{ErrorValue, OrderedMap, Value};
+
+// Every name call_builtin knows, for the resolver.
+pub const BUILTINS: [&str; 15] = [
+    "len",
+    "push",
+    "pop",
+    "insert",
+    "remove",
+    "sort",
+    "reverse",
+    "keys",
+    "values",
+    "has",
+    "delete",
+    "unwrap",
+    "unwrap_or",
+    "is_ok",
+    "is_none",
+];
+
+// Builtin functions, called like any other function. A user function with the
+// same name shadows the builtin.
+pub fn call_builtin(name: &str, args: Vec<Value>) -> Result<Value, ErrorValue> {
+    let ans = match name {
+        "len" => {
+            check_arity(name, &args, 1)?;
+            match &args[0] {
+                Value::List(xs) => Value::Int(xs.borrow().len() as i32),
+                Value::Map(map) => Value::Int(map.borrow().len() as i32),
+                Value::Tuple(items) => Value::Int(items.len() as i32),
+                Value::Str(x) => Value::Int(x.chars().count() as i32),
+                x => {
+                    return Err(type_error(format!(
+                        "len needs a list, tuple, map or string, got {}",
+                        x
+                    )));
+                }
+            }
+        }
+        "push" => {
+            check_arity(name, &args, 2)?;
+            let mut args = args.into_iter();
+            let xs = expect_list(name, args.next().unwrap())?;
+            xs.borrow_mut().push(args.next().unwrap());
+            Value::Int(0)
+        }
+        "pop" => {
+            check_arity(name, &args, 1)?;
+            let xs = expect_list(name, args.into_iter().next().unwrap())?;
+            let popped = xs.borrow_mut().pop();
+            match popped {
+                Some(x) => x,
+                None => {
+                    return Err(ErrorValue::new(
+                        "IndexError",
+                        "Tried to pop from an empty list".to_owned(),
+                    ));
+                }
+            }
+        }
+        "insert" => {
+            check_arity(name, &args, 3)?;
+            let mut args = args.into_iter();
+            let xs = expect_list(name, args.next().unwrap())?;
+            let i = expect_int(name, args.next().unwrap())?;
+            let mut xs = xs.borrow_mut();
+            if i < 0 || i as usize > xs.len() {
+                return Err(ErrorValue::new(
+                    "IndexError",
+                    format!(
+                        "Insert index {} out of bounds for list of length {}",
+                        i,
+                        xs.len()
+                    ),
+                ));
+            }
+            xs.insert(i as usize, args.next().unwrap());
+            Value::Int(0)
+        }
+        "remove" => {
+            check_arity(name, &args, 2)?;
+            let mut args = args.into_iter();
+            let xs = expect_list(name, args.next().unwrap())?;
+            let i = expect_int(name, args.next().unwrap())?;
+            let mut xs = xs.borrow_mut();
+            if i < 0 || i as usize >= xs.len() {
+                return Err(ErrorValue::new(
+                    "IndexError",
+                    format!(
+                        "Remove index {} out of bounds for list of length {}",
+                        i,
+                        xs.len()
+                    ),
+                ));
+            }
+            xs.remove(i as usize)
+        }
+        "sort" => {
+            check_arity(name, &args, 1)?;
+            let xs = expect_list(name, args.into_iter().next().unwrap())?;
+            xs.borrow_mut().sort();
+            Value::Int(0)
+        }
+        "reverse" => {
+            check_arity(name, &args, 1)?;
+            let xs = expect_list(name, args.into_iter().next().unwrap())?;
+            xs.borrow_mut().reverse();
+            Value::Int(0)
+        }
+        "keys" => {
+            check_arity(name, &args, 1)?;
+            let map = expect_map(name, args.into_iter().next().unwrap())?;
+            let keys = map.borrow().iter().map(|(k, _)| k.clone()).collect();
+            Value::new_list(keys)
+        }
+        "values" => {
+            check_arity(name, &args, 1)?;
+            let map = expect_map(name, args.into_iter().next().unwrap())?;
+            let values = map.borrow().iter().map(|(_, v)| v.clone()).collect();
+            Value::new_list(values)
+        }
+        "has" => {
+            check_arity(name, &args, 2)?;
+            let mut args = args.into_iter();
+            let map = expect_map(name, args.next().unwrap())?;
+            let key = map_key(args.next().unwrap())?;
+            Value::Int(map.borrow().contains_key(&key) as i32)
+        }
+        "delete" => {
+            // returns whether the key was there to delete
+            check_arity(name, &args, 2)?;
+            let mut args = args.into_iter();
+            let map = expect_map(name, args.next().unwrap())?;
+            let key = map_key(args.next().unwrap())?;
+            Value::Int(map.borrow_mut().remove(&key).is_some() as i32)
+        }
+        "unwrap" => {
+            check_arity(name, &args, 1)?;
+            match (held_value(name, &args[0])?, &args[0]) {
+                (Some(x), _) => x.clone(),
+                // unwrapping an Err holding an error throws that error
+                (None, Value::Variant(v)) if let Some(Value::Error(error)) = v.payload.first() => {
+                    return Err((**error).clone());
+                }
+                (None, x) => {
+                    return Err(ErrorValue::new(
+                        "ValueError",
+                        format!("Called unwrap on {}", x),
+                    ));
+                }
+            }
+        }
+        "unwrap_or" => {
+            check_arity(name, &args, 2)?;
+            match held_value(name, &args[0])? {
+                Some(x) => x.clone(),
+                None => args[1].clone(),
+            }
+        }
+        "is_ok" => {
+            check_arity(name, &args, 1)?;
+            let Value::Variant(v) = &args[0] else {
+                return Err(type_error(format!("is_ok needs a Result, got {}", args[0])));
+            };
+            match &*v.variant {
+                "Ok" => Value::Int(1),
+                "Err" => Value::Int(0),
+                _ => return Err(type_error(format!("is_ok needs a Result, got {}", args[0]))),
+            }
+        }
+        "is_none" => {
+            check_arity(name, &args, 1)?;
+            let Value::Variant(v) = &args[0] else {
+                return Err(type_error(format!(
+                    "is_none needs an Option, got {}",
+                    args[0]
+                )));
+            };
+            match &*v.variant {
+                "None" => Value::Int(1),
+                "Some" => Value::Int(0),
+                _ => {
+                    return Err(type_error(format!(
+                        "is_none needs an Option, got {}",
+                        args[0]
+                    )));
+                }
+            }
+        }
+        _ => {
+            return Err(ErrorValue::new(
+                "NameError",
+                format!("Called unknown function {}", name),
+            ));
+        }
+    };
+    Ok(ans)
+}
+
+pub fn check_arity(name: &str, args: &[Value], expected: usize) -> Result<(), ErrorValue> {
+    if args.len() != expected {
+        return Err(ErrorValue::new(
+            "ArgumentError",
+            format!(
+                "Builtin {} takes {} arguments, got {}",
+                name,
+                expected,
+                args.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// The value in an Ok or Some, or None for an Err or None. Variant names are
+// unique across enums, so the name alone says it's a Result or Option.
+pub fn held_value<'a>(name: &str, value: &'a Value) -> Result<Option<&'a Value>, ErrorValue> {
+    match value {
+        Value::Variant(v) => match &*v.variant {
+            "Ok" | "Some" => Ok(v.payload.first()),
+            "Err" | "None" => Ok(None),
+            _ => Err(type_error(format!(
+                "{} needs a Result or Option, got {}",
+                name, value
+            ))),
+        },
+        _ => Err(type_error(format!(
+            "{} needs a Result or Option, got {}",
+            name, value
+        ))),
+    }
+}
+
+pub fn type_error(message: String) -> ErrorValue {
+    ErrorValue::new("TypeError", message)
+}
+
+fn expect_list(name: &str, value: Value) -> Result<Rc<RefCell<Vec<Value>>>, ErrorValue> {
+    let Value::List(xs) = value else {
+        return Err(type_error(format!("{} needs a list, got {}", name, value)));
+    };
+    Ok(xs)
+}
+
+fn expect_map(name: &str, value: Value) -> Result<Rc<RefCell<OrderedMap>>, ErrorValue> {
+    let Value::Map(map) = value else {
+        return Err(type_error(format!("{} needs a map, got {}", name, value)));
+    };
+    Ok(map)
+}
+
+// Only strings and ints can be map keys, since a list or map key could be
+// mutated after insertion.
+pub fn map_key(key: Value) -> Result<Value, ErrorValue> {
+    match key {
+        Value::Str(_) | Value::Int(_) => Ok(key),
+        x => Err(type_error(format!("{} can't be used as a map key", x))),
+    }
+}
+
+fn expect_int(name: &str, value: Value) -> Result<i32, ErrorValue> {
+    let Value::Int(x) = value else {
+        return Err(type_error(format!(
+            "{} needs an int index, got {}",
+            name, value
+        )));
+    };
+    Ok(x)
+}
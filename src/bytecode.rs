@@ -0,0 +1,203 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{FunctionAST, Name, PrototypeAST, Value},
+    lexer::{Operator, Span},
+};
+
+// One VM instruction. Operands index the tables of the function the
+// instruction is in, except jumps, which are positions in its code. Stack
+// effects are listed bottom to top.
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    Const(usize),
+    Pop,
+    // a local, falling back to the name when the local isn't bound
+    Load(usize),
+    // a global, falling back to the name when it isn't initialized yet
+    LoadGlobal(usize),
+    // a name that's no local or global: a function, a unit variant or a
+    // NameError
+    LoadName(usize),
+    // value -> , into an unbound local
+    Declare(usize),
+    // value -> , into the names of a destructuring target
+    Destructure(usize),
+    // value -> , `name op= value`, to the local if it's bound, or else the
+    // global
+    Assign {
+        slot: Option<usize>,
+        global: Option<usize>,
+        name: usize,
+        compound: Option<Operator>,
+    },
+    // value, container, index ->
+    SetIndex(Option<Operator>),
+    // value, instance -> , the field being a name
+    SetField(usize, Option<Operator>),
+    // value -> , binding a local whether it's bound or not
+    SetLocal(usize),
+    Unset(usize),
+    Input(usize),
+    Print,
+    // items -> list
+    List(usize),
+    Tuple(usize),
+    // key -> key, failing if it can't be a map key
+    MapKey,
+    // key, value, key, value... -> map
+    Map(usize),
+    // field values in source order -> instance
+    Struct(usize),
+    Closure(usize),
+    // container, index -> item
+    Index,
+    // instance -> field
+    Field(usize),
+    // lhs, rhs -> result
+    Binary(Operator),
+    // throws one of the errors the compiler already knew would happen
+    Fail(usize),
+    Jump(usize),
+    // condition -> , jumping when it's 0
+    JumpIfFalse(usize),
+    // positional args, named args -> result
+    Call(usize),
+    // callee, positional args, named args -> result
+    CallValue(usize),
+    // The prologue binding parameter `i` to what the caller gave, and jumping
+    // past the default's code. Without an argument it falls into the default.
+    BindParam(usize, usize),
+    BindRest,
+    // every parameter is bound, so the call goes on the stack trace
+    Enter,
+    // value -> , back to the caller
+    Return,
+    // result -> inner, jumping when it holds a value; otherwise the result
+    // stays for the code after, which returns it
+    Propagate(usize),
+    // value ->
+    Throw,
+    // until popped, a throw in this frame jumps to the target with the error
+    // on the stack
+    PushHandler(usize),
+    PopHandler,
+    // binds the pattern against the local `slot`, or jumps to `fail`
+    TestPattern {
+        pattern: usize,
+        slot: usize,
+        fail: usize,
+    },
+    // restores what a pattern's bindings hid
+    Unbind(usize),
+    // error -> , binding `slot` after saving it in `save`
+    Catch {
+        slot: usize,
+        save: usize,
+    },
+    Restore {
+        slot: usize,
+        save: usize,
+    },
+    // a MatchError for the value in the local
+    NoMatch(usize),
+}
+
+/// A compiled function, or a global's initializer.
+pub struct Function {
+    pub proto: PrototypeAST,
+    pub code: Vec<Op>,
+    // the span each instruction reports errors at
+    pub spans: Vec<Span>,
+    // Where the body starts, after the prologue that binds arguments and
+    // evaluates defaults. Calls that give every parameter start here.
+    pub body_start: usize,
+    // The names of the slots the resolver gave out. The compiler's hidden
+    // slots come after them, up to slot_count.
+    pub slot_names: Vec<String>,
+    pub slot_count: usize,
+    pub params: Vec<usize>,
+    pub rest: Option<usize>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub calls: Vec<CallSite>,
+    pub shapes: Vec<ArgShape>,
+    pub patterns: Vec<ArmPattern>,
+    pub targets: Vec<Target>,
+    pub structs: Vec<StructLiteral>,
+    pub closures: Vec<Rc<FunctionAST>>,
+    // (kind, message)
+    pub errors: Vec<(String, String)>,
+}
+
+/// The arguments on the stack at a call: positional ones, then named ones.
+pub struct ArgShape {
+    pub positional: usize,
+    pub named: Vec<String>,
+}
+impl ArgShape {
+    pub fn len(&self) -> usize {
+        self.positional + self.named.len()
+    }
+}
+
+/// A call by name, with everything the name could mean worked out ahead of
+/// time. At runtime the first one that holds a value is called, like the
+/// tree-walker's lookup order.
+pub struct CallSite {
+    pub name: String,
+    pub slot: Option<usize>,
+    pub global: Option<usize>,
+    pub function: Option<usize>,
+    pub args: ArgShape,
+}
+
+pub enum Pattern {
+    Wildcard,
+    Literal(Value),
+    // binds the next of the arm's slots
+    Bind,
+    // a bare variant name, which matches the variant whatever its payload
+    Unit(String),
+    Variant(String, Vec<Pattern>),
+}
+
+/// A match arm's pattern, with a hidden local to save what each of its
+/// bindings hides, in the order they bind.
+pub struct ArmPattern {
+    pub pattern: Pattern,
+    pub binds: Vec<(usize, usize)>,
+}
+
+pub enum Target {
+    Skip,
+    Name(usize, Span),
+    Tuple(Vec<Target>),
+    List(Vec<Target>),
+    Struct(String, Vec<(String, Target)>),
+}
+
+pub struct StructLiteral {
+    pub name: Name,
+    // in declaration order
+    pub fields: Vec<Name>,
+    // in the order the literal gives them
+    pub given: Vec<Name>,
+}
+
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub function_index: HashMap<Name, usize>,
+    // closure bodies by the address of their AST, which closure values share
+    pub closure_index: HashMap<*const FunctionAST, usize>,
+    pub globals: Vec<String>,
+    pub global_index: HashMap<String, usize>,
+    pub constants: HashSet<usize>,
+    // (global, initializer function), in the order they run
+    pub inits: Vec<(usize, usize)>,
+    // variant name -> (enum name, payload length)
+    pub variants: HashMap<Name, (Name, usize)>,
+}
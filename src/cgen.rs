@@ -0,0 +1,415 @@
+use crate::{
+    ast::{BuiltIn, ExprAST, FunctionAST, ProgramAST, Statement, Value},
+    checker::constant_order,
+    lexer::{Operator, Span},
+};
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+// Translates a resolved program to one standalone C file: the runtime, a C
+// function for each script function, and a main that initializes the globals
+// and calls the script's. The part of the language that translates is the one
+// with only ints and strings in it: functions, `if`, `while`, operators,
+// `print` and `input`. Anything else is reported, with where it is when the
+// AST knows. A runtime error ends the program with the interpreter's message,
+// without the stack trace.
+pub fn generate(program: &ProgramAST) -> Result<String, String> {
+    let mut out = String::from(RUNTIME);
+    out.push('\n');
+    for i in 0..program.globals.len() {
+        out += &format!("static ws_value {};\n", global_name(program, i));
+    }
+    for (i, func) in program.functions.iter().enumerate() {
+        out += &format!("static ws_value {};\n", signature(program, i, func)?);
+    }
+    for (i, func) in program.functions.iter().enumerate() {
+        let mut writer = FunctionWriter::new(program, &func.locals);
+        writer
+            .function(func)
+            .map_err(|e| format!("In function {}: {}", func.proto.name, e))?;
+        out += &format!("\nstatic ws_value {} {{\n", signature(program, i, func)?);
+        out += &writer.finish(&func.param_slots);
+        out += "}\n";
+    }
+    out += "\nint main(void) {\n";
+    let mut init_order: Vec<&str> = constant_order(program)?
+        .into_iter()
+        .map(|g| g.name.as_str())
+        .collect();
+    init_order.extend(
+        program
+            .globals
+            .iter()
+            .filter(|g| !g.is_const)
+            .map(|g| g.name.as_str()),
+    );
+    for name in init_order {
+        let index = program
+            .globals
+            .iter()
+            .position(|g| g.name == name)
+            .expect("Every global is in the order.");
+        let global = &program.globals[index];
+        let mut writer = FunctionWriter::new(program, &global.locals);
+        writer.indent = 2;
+        let value = writer
+            .expr(&global.init)
+            .map_err(|e| format!("In the initializer of {}: {}", global.name, e))?;
+        writer.line(format!("{} = {};", global_name(program, index), value));
+        out += "    {\n";
+        out += &writer.finish(&[]);
+        out += "    }\n";
+    }
+    let Some(main) = program
+        .functions
+        .iter()
+        .position(|f| &*f.proto.name == "main")
+    else {
+        return Err("There is no main function.".to_owned());
+    };
+    // main counts toward the recursion limit, as it does in the interpreter
+    out += "    ws_depth = 1;\n";
+    out += &format!("    {}();\n", function_name(program, main));
+    out += "    return 0;\n}\n";
+    Ok(out)
+}
+
+fn signature(program: &ProgramAST, index: usize, func: &FunctionAST) -> Result<String, String> {
+    let proto = &func.proto;
+    if proto.rest.is_some() || proto.args.iter().any(|p| p.default.is_some()) {
+        return Err(format!(
+            "In function {}: default and rest parameters can't be compiled to C.",
+            proto.name
+        ));
+    }
+    let params: Vec<String> = func
+        .param_slots
+        .iter()
+        .map(|&slot| format!("ws_value {}", local_name(&func.locals, slot)))
+        .collect();
+    let params = if params.is_empty() {
+        "void".to_owned()
+    } else {
+        params.join(", ")
+    };
+    Ok(format!("{}({})", function_name(program, index), params))
+}
+
+// C names start with what they are and their index, so the script's names
+// can't clash with each other or with C's. Module functions have a `.` in
+// them, which C names can't.
+fn c_name(kind: char, index: usize, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}{}_{}", kind, index, name)
+}
+
+fn function_name(program: &ProgramAST, index: usize) -> String {
+    c_name('f', index, &program.functions[index].proto.name)
+}
+
+fn global_name(program: &ProgramAST, index: usize) -> String {
+    c_name('g', index, &program.globals[index].name)
+}
+
+fn local_name(locals: &[String], slot: usize) -> String {
+    c_name('l', slot, &locals[slot])
+}
+
+// A C string literal with the same bytes as `s`.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out += "\\\"",
+            b'\\' => out += "\\\\",
+            b'\n' => out += "\\n",
+            b'\t' => out += "\\t",
+            b' '..=b'~' => out.push(byte as char),
+            _ => out += &format!("\\{:03o}", byte),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn at(span: &Span) -> String {
+    c_string(&span.to_string())
+}
+
+fn c_operator(op: &Operator) -> &'static str {
+    match op {
+        Operator::And => "WS_AND",
+        Operator::Or => "WS_OR",
+        Operator::Xor => "WS_XOR",
+        Operator::LEq => "WS_LEQ",
+        Operator::GEq => "WS_GEQ",
+        Operator::Eq => "WS_EQ",
+        Operator::Ls => "WS_LS",
+        Operator::Gr => "WS_GR",
+        Operator::BAnd => "WS_BAND",
+        Operator::BOr => "WS_BOR",
+        Operator::BXor => "WS_BXOR",
+        Operator::Add => "WS_ADD",
+        Operator::Sub => "WS_SUB",
+        Operator::Mult => "WS_MULT",
+        Operator::Div => "WS_DIV",
+        Operator::Mod => "WS_MOD",
+    }
+}
+
+fn unsupported<T>(what: &str, span: Option<&Span>) -> Result<T, String> {
+    match span {
+        Some(span) => Err(format!("{}: {} can't be compiled to C.", span, what)),
+        None => Err(format!("{} can't be compiled to C.", what)),
+    }
+}
+
+// Writes the body of one C function. Every value that's evaluated goes in a
+// temporary first, since C doesn't say what order it evaluates arguments in.
+struct FunctionWriter<'a> {
+    program: &'a ProgramAST,
+    locals: &'a [String],
+    code: String,
+    indent: usize,
+    temps: usize,
+}
+impl<'a> FunctionWriter<'a> {
+    fn new(program: &'a ProgramAST, locals: &'a [String]) -> Self {
+        FunctionWriter {
+            program,
+            locals,
+            code: String::new(),
+            indent: 1,
+            temps: 0,
+        }
+    }
+    // The locals that aren't parameters, all unset, then the code.
+    fn finish(self, params: &[usize]) -> String {
+        let mut out = String::new();
+        for slot in (0..self.locals.len()).filter(|s| !params.contains(s)) {
+            out += &"    ".repeat(self.indent);
+            out += &format!(
+                "ws_value {} = {{WS_UNSET, 0, NULL}};\n",
+                local_name(self.locals, slot)
+            );
+        }
+        out + &self.code
+    }
+    fn line(&mut self, line: String) {
+        self.code += &"    ".repeat(self.indent);
+        self.code += &line;
+        self.code.push('\n');
+    }
+    fn temp(&mut self, value: String) -> String {
+        let name = format!("t{}", self.temps);
+        self.temps += 1;
+        self.line(format!("ws_value {} = {};", name, value));
+        name
+    }
+    fn function(&mut self, func: &FunctionAST) -> Result<(), String> {
+        self.statements(&func.body)?;
+        self.line("return ws_int(0);".to_owned());
+        Ok(())
+    }
+    fn block(&mut self, body: &[Statement]) -> Result<(), String> {
+        self.indent += 1;
+        self.statements(body)?;
+        self.indent -= 1;
+        Ok(())
+    }
+    fn statements(&mut self, body: &[Statement]) -> Result<(), String> {
+        for statement in body {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Assign(x) => {
+                let ExprAST::Variable(name, span, resolution) = &x.variable else {
+                    return unsupported("Assigning to anything but a name", Some(&x.span));
+                };
+                let rhs = self.expr(&x.right_hand)?;
+                let local = resolution.slot.map(|s| local_name(self.locals, s));
+                if x.is_declaration {
+                    let local = local.expect("Declarations get a slot.");
+                    self.line(format!(
+                        "ws_declare(&{}, {}, {}, {});",
+                        local,
+                        rhs,
+                        at(span),
+                        c_string(name)
+                    ));
+                    return Ok(());
+                }
+                let global = resolution.global;
+                let is_const = global.is_some_and(|g| self.program.globals[g].is_const);
+                let op = x.compound.as_ref().map_or("WS_NONE", c_operator);
+                self.line(format!(
+                    "ws_assign({}, {}, {}, {}, {}, {}, {});",
+                    local.map_or("NULL".to_owned(), |l| format!("&{}", l)),
+                    global.map_or("NULL".to_owned(), |g| format!(
+                        "&{}",
+                        global_name(self.program, g)
+                    )),
+                    is_const as i32,
+                    op,
+                    rhs,
+                    at(span),
+                    c_string(name)
+                ));
+            }
+            Statement::If(x) => {
+                let condition = self.expr(&x.conditional)?;
+                self.line(format!("if (ws_truthy({})) {{", condition));
+                self.block(&x.body)?;
+                if !x.else_body.is_empty() {
+                    self.line("} else {".to_owned());
+                    self.block(&x.else_body)?;
+                }
+                self.line("}".to_owned());
+            }
+            Statement::While(x) => {
+                // the condition is evaluated again on every pass
+                self.line("for (;;) {".to_owned());
+                self.indent += 1;
+                let condition = self.expr(&x.conditional)?;
+                self.line(format!("if (!ws_truthy({})) break;", condition));
+                self.statements(&x.body)?;
+                self.indent -= 1;
+                self.line("}".to_owned());
+            }
+            Statement::Call(x) => {
+                let value = self.expr(x)?;
+                self.line(format!("(void){};", value));
+            }
+            Statement::Built(BuiltIn::Print(x, _)) => {
+                let value = self.expr(x)?;
+                self.line(format!("ws_print({});", value));
+            }
+            Statement::Built(BuiltIn::Return(x, _)) => {
+                let value = self.expr(x)?;
+                self.line(format!("return {};", value));
+            }
+            Statement::Built(BuiltIn::Input(x)) => {
+                let ExprAST::Variable(_, _, resolution) = x else {
+                    unreachable!();
+                };
+                let slot = resolution.slot.expect("Input binds a local.");
+                self.line(format!("ws_input(&{});", local_name(self.locals, slot)));
+            }
+            Statement::Built(BuiltIn::Drop(x)) => {
+                let ExprAST::Variable(_, _, resolution) = x else {
+                    unreachable!();
+                };
+                if let Some(slot) = resolution.slot {
+                    self.line(format!(
+                        "{}.kind = WS_UNSET;",
+                        local_name(self.locals, slot)
+                    ));
+                }
+            }
+            Statement::Match(x) => return unsupported("match", Some(&x.span)),
+            Statement::Try(_) => return unsupported("try", None),
+            Statement::Throw(_, span) => return unsupported("throw", Some(span)),
+        }
+        Ok(())
+    }
+    // Evaluates the expression, giving back a C expression for its value
+    // that has no side effects left.
+    fn expr(&mut self, expr: &ExprAST) -> Result<String, String> {
+        match expr {
+            ExprAST::Val(Value::Int(x)) => Ok(format!("ws_int({})", x)),
+            ExprAST::Val(Value::Str(x)) => Ok(format!("ws_str({})", c_string(x))),
+            ExprAST::Val(_) => unsupported("That value", None),
+            ExprAST::Variable(name, span, resolution) => {
+                if resolution.slot.is_none() && resolution.global.is_none() {
+                    let what = if resolution.function.is_some() {
+                        "A function used as a value"
+                    } else {
+                        "An enum variant"
+                    };
+                    return unsupported(what, Some(span));
+                }
+                let local = resolution.slot.map_or("NULL".to_owned(), |s| {
+                    format!("&{}", local_name(self.locals, s))
+                });
+                let global = resolution.global.map_or("NULL".to_owned(), |g| {
+                    format!("&{}", global_name(self.program, g))
+                });
+                Ok(self.temp(format!(
+                    "ws_load({}, {}, {}, {})",
+                    local,
+                    global,
+                    at(span),
+                    c_string(name)
+                )))
+            }
+            ExprAST::BinOp(op, lhs, rhs, span) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                Ok(self.temp(format!(
+                    "ws_binop({}, {}, {}, {})",
+                    c_operator(op),
+                    lhs,
+                    rhs,
+                    at(span)
+                )))
+            }
+            ExprAST::Call(name, args, span, resolution) => {
+                let Some(index) = resolution
+                    .function
+                    .filter(|_| resolution.slot.is_none() && resolution.global.is_none())
+                else {
+                    return unsupported(&format!("Calling {}", name), Some(span));
+                };
+                if args.iter().any(|a| a.name.is_some()) {
+                    return unsupported("A named argument", Some(span));
+                }
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.expr(&arg.value)?);
+                }
+                self.line(format!("ws_enter({}, {});", at(span), c_string(name)));
+                let result = self.temp(format!(
+                    "{}({})",
+                    function_name(self.program, index),
+                    values.join(", ")
+                ));
+                self.line("ws_leave();".to_owned());
+                Ok(result)
+            }
+            ExprAST::If(condition, then_expr, else_expr) => {
+                let condition = self.expr(condition)?;
+                let result = format!("t{}", self.temps);
+                self.temps += 1;
+                self.line(format!("ws_value {};", result));
+                self.line(format!("if (ws_truthy({})) {{", condition));
+                self.indent += 1;
+                let value = self.expr(then_expr)?;
+                self.line(format!("{} = {};", result, value));
+                self.indent -= 1;
+                self.line("} else {".to_owned());
+                self.indent += 1;
+                let value = self.expr(else_expr)?;
+                self.line(format!("{} = {};", result, value));
+                self.indent -= 1;
+                self.line("}".to_owned());
+                Ok(result)
+            }
+            ExprAST::CallExpr(_, _, span) => unsupported("Calling a function value", Some(span)),
+            ExprAST::Closure(_) => unsupported("A closure", None),
+            ExprAST::List(_) => unsupported("A list", None),
+            ExprAST::Tuple(_) => unsupported("A tuple", None),
+            ExprAST::Map(_, span) => unsupported("A map", Some(span)),
+            ExprAST::Index(_, _, span) => unsupported("Indexing", Some(span)),
+            ExprAST::StructInit(_, _, span) => unsupported("A struct", Some(span)),
+            ExprAST::Field(_, _, span) => unsupported("A field", Some(span)),
+            ExprAST::Match(x) => unsupported("match", Some(&x.span)),
+            ExprAST::Propagate(_, span) => unsupported("?", Some(span)),
+        }
+    }
+}
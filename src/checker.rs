@@ -0,0 +1,535 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{
+        Arg, Assignment, BuiltIn, EnumAST, ExprAST, GlobalAST, MatchBlock, Pattern, ProgramAST,
+        PrototypeAST, Statement,
+    },
+    lexer::Span,
+};
+
+// The locals declared so far in a function, and whether each is mutable.
+// Scoping is per function, like the interpreter's varmap.
+type Scope = HashMap<String, bool>;
+
+// Static checks run after parsing and before the program starts, for mistakes
+// we can catch without running anything.
+pub fn check_program(program: &ProgramAST) -> Result<(), String> {
+    let mut variants = HashMap::new();
+    for enum_ast in program.enums.iter() {
+        for variant in enum_ast.variants.iter() {
+            let entry = (enum_ast, variant.fields.len());
+            if let Some((other, _)) = variants.insert(&*variant.name, entry) {
+                return Err(format!(
+                    "Variant {} is declared in both enum {} and enum {}.",
+                    variant.name, other.name, enum_ast.name
+                ));
+            }
+        }
+    }
+    for func in program.functions.iter() {
+        if variants.contains_key(&*func.proto.name) {
+            return Err(format!(
+                "Function {} has the same name as an enum variant.",
+                func.proto.name
+            ));
+        }
+    }
+    for (i, global) in program.globals.iter().enumerate() {
+        if program.globals[..i].iter().any(|g| g.name == global.name) {
+            return Err(format!(
+                "{} is declared at the top level twice.",
+                global.name
+            ));
+        }
+    }
+    constant_order(program)?;
+    initialization_order(program)?;
+    let constants = program
+        .globals
+        .iter()
+        .filter(|g| g.is_const)
+        .map(|g| g.name.as_str())
+        .collect();
+    let functions = program
+        .functions
+        .iter()
+        .map(|f| (&*f.proto.name, &f.proto))
+        .collect();
+    let globals = program.globals.iter().map(|g| g.name.as_str()).collect();
+    let checker = Checker {
+        variants,
+        constants,
+        functions,
+        globals,
+    };
+    for global in program.globals.iter() {
+        checker
+            .check_expr(&global.init, &Scope::new())
+            .map_err(|e| format!("In the initializer of {}: {}", global.name, e))?;
+    }
+    for func in program.functions.iter() {
+        checker
+            .check_function(&func.proto, &func.body, Scope::new())
+            .map_err(|e| format!("In function {}: {}", func.proto.name, e))?;
+    }
+    Ok(())
+}
+
+// The order to initialize constants in, so every constant comes after the
+// ones its initializer names. Only direct references count: a function called
+// from an initializer that reads an uninitialized constant fails at runtime.
+pub fn constant_order(program: &ProgramAST) -> Result<Vec<&GlobalAST>, String> {
+    let constants: HashMap<&str, &GlobalAST> = program
+        .globals
+        .iter()
+        .filter(|g| g.is_const)
+        .map(|g| (g.name.as_str(), g))
+        .collect();
+    let mut order = Vec::with_capacity(constants.len());
+    let mut path = Vec::new();
+    for global in program.globals.iter().filter(|g| g.is_const) {
+        visit_constant(global, &constants, &mut path, &mut order)?;
+    }
+    Ok(order)
+}
+
+// Every constant is initialized before any global, then the globals go in the
+// order they're declared, so an initializer can't name a global that isn't set
+// yet. Like constant_order, only direct references count.
+fn initialization_order(program: &ProgramAST) -> Result<(), String> {
+    let globals: HashMap<&str, usize> = program
+        .globals
+        .iter()
+        .enumerate()
+        .filter(|(_, g)| !g.is_const)
+        .map(|(i, g)| (g.name.as_str(), i))
+        .collect();
+    for (i, global) in program.globals.iter().enumerate() {
+        let mut names = Vec::new();
+        collect_names(&global.init, &mut names);
+        for name in names {
+            match globals.get(name) {
+                Some(_) if global.is_const => {
+                    return Err(format!(
+                        "Constant {} can't use global {}, constants are initialized before any global.",
+                        global.name, name
+                    ));
+                }
+                Some(&j) if j >= i => {
+                    return Err(format!(
+                        "Global {} uses global {} before it's initialized, globals are initialized in the order they're declared.",
+                        global.name, name
+                    ));
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn visit_constant<'a>(
+    constant: &'a GlobalAST,
+    constants: &HashMap<&str, &'a GlobalAST>,
+    path: &mut Vec<&'a str>,
+    order: &mut Vec<&'a GlobalAST>,
+) -> Result<(), String> {
+    if order.iter().any(|c| c.name == constant.name) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|c| *c == constant.name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(&constant.name);
+        return Err(format!(
+            "Constant initializers form a cycle: {}.",
+            cycle.join(" -> ")
+        ));
+    }
+    path.push(&constant.name);
+    let mut names = Vec::new();
+    collect_names(&constant.init, &mut names);
+    for name in names {
+        if let Some(dependency) = constants.get(name) {
+            visit_constant(dependency, constants, path, order)?;
+        }
+    }
+    path.pop();
+    order.push(constant);
+    Ok(())
+}
+
+// Every variable or function name an expression uses when it's evaluated.
+// Closure bodies don't run until they're called, so they're skipped.
+fn collect_names<'a>(expr: &'a ExprAST, names: &mut Vec<&'a str>) {
+    match expr {
+        ExprAST::Variable(x, ..) => names.push(x),
+        ExprAST::Val(_) | ExprAST::Closure(_) => (),
+        ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => {
+            collect_names(lhs, names);
+            collect_names(rhs, names);
+        }
+        ExprAST::Call(name, args, ..) => {
+            names.push(name);
+            args.iter().for_each(|x| collect_names(&x.value, names));
+        }
+        ExprAST::CallExpr(callee, args, _) => {
+            collect_names(callee, names);
+            args.iter().for_each(|x| collect_names(&x.value, names));
+        }
+        ExprAST::List(items) | ExprAST::Tuple(items) => {
+            items.iter().for_each(|x| collect_names(x, names));
+        }
+        ExprAST::Map(entries, _) => {
+            for (key, value) in entries {
+                collect_names(key, names);
+                collect_names(value, names);
+            }
+        }
+        ExprAST::StructInit(_, fields, _) => {
+            fields.iter().for_each(|(_, x)| collect_names(x, names));
+        }
+        ExprAST::Field(instance, ..) | ExprAST::Propagate(instance, _) => {
+            collect_names(instance, names);
+        }
+        ExprAST::If(conditional, then_expr, else_expr) => {
+            collect_names(conditional, names);
+            collect_names(then_expr, names);
+            collect_names(else_expr, names);
+        }
+        ExprAST::Match(x) => {
+            collect_names(&x.scrutinee, names);
+            for arm in x.arms.iter() {
+                if let Some(guard) = &arm.guard {
+                    collect_names(guard, names);
+                }
+                collect_names(&arm.body, names);
+            }
+        }
+    }
+}
+
+fn restore(shadowed: Vec<(String, Option<bool>)>, scope: &mut Scope) {
+    for (name, mutable) in shadowed.into_iter().rev() {
+        match mutable {
+            Some(x) => scope.insert(name, x),
+            None => scope.remove(&name),
+        };
+    }
+}
+
+struct Checker<'a> {
+    // variant name -> (its enum, payload length)
+    variants: HashMap<&'a str, (&'a EnumAST, usize)>,
+    constants: HashSet<&'a str>,
+    functions: HashMap<&'a str, &'a PrototypeAST>,
+    globals: HashSet<&'a str>,
+}
+impl Checker<'_> {
+    // `scope` is what the function can see before its parameters, which is
+    // nothing for declared functions and everything captured for closures.
+    fn check_function(
+        &self,
+        proto: &PrototypeAST,
+        body: &[Statement],
+        mut scope: Scope,
+    ) -> Result<(), String> {
+        for arg in proto.args.iter() {
+            if let Some(default) = &arg.default {
+                self.check_expr(default, &scope)?;
+            }
+            scope.insert(arg.name.clone(), true);
+        }
+        if let Some(rest) = &proto.rest {
+            scope.insert(rest.clone(), true);
+        }
+        self.check_statements(body, &mut scope)
+    }
+    fn check_args(&self, args: &[Arg], scope: &Scope) -> Result<(), String> {
+        for arg in args {
+            self.check_expr(&arg.value, scope)?;
+        }
+        Ok(())
+    }
+    fn check_statements(&self, body: &[Statement], scope: &mut Scope) -> Result<(), String> {
+        for statement in body {
+            match statement {
+                Statement::Assign(x) => {
+                    self.check_expr(&x.variable, scope)?;
+                    self.check_expr(&x.right_hand, scope)?;
+                    self.check_assignment(x, scope)?;
+                }
+                Statement::If(x) => {
+                    self.check_expr(&x.conditional, scope)?;
+                    self.check_statements(&x.body, scope)?;
+                    self.check_statements(&x.else_body, scope)?;
+                }
+                Statement::While(x) => {
+                    self.check_expr(&x.conditional, scope)?;
+                    self.check_statements(&x.body, scope)?;
+                }
+                Statement::Call(x) => self.check_expr(x, scope)?,
+                Statement::Built(BuiltIn::Print(x, _) | BuiltIn::Return(x, _)) => {
+                    self.check_expr(x, scope)?;
+                }
+                Statement::Built(BuiltIn::Input(x)) => {
+                    if let ExprAST::Variable(name, span, _) = x {
+                        self.check_reassign(name, *span, scope)?;
+                    }
+                }
+                Statement::Built(BuiltIn::Drop(x)) => {
+                    if let ExprAST::Variable(name, ..) = x {
+                        scope.remove(name);
+                    }
+                }
+                Statement::Match(x) => {
+                    self.check_match(x, scope)?;
+                    for arm in x.arms.iter() {
+                        let shadowed = self.bind_pattern(&arm.pattern, scope);
+                        self.check_statements(&arm.body, scope)?;
+                        restore(shadowed, scope);
+                    }
+                }
+                Statement::Try(x) => {
+                    self.check_statements(&x.body, scope)?;
+                    if let Some((name, _, body)) = &x.catch {
+                        // the error is only bound inside the catch block
+                        let shadowed = scope.insert(name.clone(), true);
+                        self.check_statements(body, scope)?;
+                        match shadowed {
+                            Some(x) => scope.insert(name.clone(), x),
+                            None => scope.remove(name),
+                        };
+                    }
+                    if let Some(body) = &x.finally {
+                        self.check_statements(body, scope)?;
+                    }
+                }
+                Statement::Throw(x, _) => self.check_expr(x, scope)?,
+            }
+        }
+        Ok(())
+    }
+    fn check_assignment(&self, assignment: &Assignment, scope: &mut Scope) -> Result<(), String> {
+        if assignment.is_declaration {
+            declare(&assignment.variable, assignment.is_mutable, scope);
+            return Ok(());
+        }
+        let ExprAST::Variable(name, span, _) = &assignment.variable else {
+            // fields and indexes change the value, not the binding
+            return Ok(());
+        };
+        self.check_reassign(name, *span, scope)
+    }
+    fn check_reassign(&self, name: &str, span: Span, scope: &Scope) -> Result<(), String> {
+        match scope.get(name) {
+            Some(false) => Err(format!(
+                "{}: Can't assign to {}, it was declared with let.",
+                span, name
+            )),
+            None if self.constants.contains(name) => {
+                Err(format!("{}: Can't assign to constant {}.", span, name))
+            }
+            _ => Ok(()),
+        }
+    }
+    fn check_expr(&self, expr: &ExprAST, scope: &Scope) -> Result<(), String> {
+        match expr {
+            ExprAST::Variable(..) | ExprAST::Val(_) => (),
+            ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => {
+                self.check_expr(lhs, scope)?;
+                self.check_expr(rhs, scope)?;
+            }
+            ExprAST::Call(name, args, span, _) => {
+                self.check_args(args, scope)?;
+                // calls that can only mean a declared function get their
+                // arguments checked now, the rest are checked when they run
+                let shadowed = scope.contains_key(name) || self.globals.contains(name.as_str());
+                if let Some(proto) = self.functions.get(name.as_str())
+                    && !shadowed
+                {
+                    let positional = args.iter().filter(|a| a.name.is_none()).count();
+                    let named: Vec<&str> = args.iter().filter_map(|a| a.name.as_deref()).collect();
+                    proto
+                        .check_args(positional, &named)
+                        .map_err(|e| format!("{}: {}", span, e))?;
+                }
+            }
+            ExprAST::List(items) | ExprAST::Tuple(items) => {
+                for item in items {
+                    self.check_expr(item, scope)?;
+                }
+            }
+            ExprAST::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.check_expr(key, scope)?;
+                    self.check_expr(value, scope)?;
+                }
+            }
+            ExprAST::StructInit(_, fields, _) => {
+                for (_, value) in fields {
+                    self.check_expr(value, scope)?;
+                }
+            }
+            ExprAST::Field(instance, ..) | ExprAST::Propagate(instance, _) => {
+                self.check_expr(instance, scope)?;
+            }
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                self.check_expr(conditional, scope)?;
+                self.check_expr(then_expr, scope)?;
+                self.check_expr(else_expr, scope)?;
+            }
+            ExprAST::CallExpr(callee, args, _) => {
+                self.check_expr(callee, scope)?;
+                self.check_args(args, scope)?;
+            }
+            ExprAST::Closure(function) => {
+                // the closure starts with a copy of everything in scope, and
+                // captured lets stay immutable
+                self.check_function(&function.proto, &function.body, scope.clone())?;
+            }
+            ExprAST::Match(x) => {
+                self.check_match(x, scope)?;
+                for arm in x.arms.iter() {
+                    let mut scope = scope.clone();
+                    self.bind_pattern(&arm.pattern, &mut scope);
+                    self.check_expr(&arm.body, &scope)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    fn check_match<T>(&self, match_block: &MatchBlock<T>, scope: &Scope) -> Result<(), String> {
+        self.check_expr(&match_block.scrutinee, scope)?;
+        for arm in match_block.arms.iter() {
+            self.check_pattern(&arm.pattern)?;
+            if let Some(guard) = &arm.guard {
+                let mut scope = scope.clone();
+                self.bind_pattern(&arm.pattern, &mut scope);
+                self.check_expr(guard, &scope)?;
+            }
+        }
+        self.check_exhaustive(match_block)
+    }
+    // An arm's names are fresh, mutable bindings, like a catch's error. Gives
+    // back what they shadowed, for `restore` once the arm is checked.
+    fn bind_pattern(&self, pattern: &Pattern, scope: &mut Scope) -> Vec<(String, Option<bool>)> {
+        let mut shadowed = Vec::new();
+        let mut patterns = vec![pattern];
+        while let Some(pattern) = patterns.pop() {
+            match pattern {
+                Pattern::Ident(name, _) if !self.variants.contains_key(name.as_str()) => {
+                    shadowed.push((name.clone(), scope.insert(name.clone(), true)));
+                }
+                Pattern::Variant(_, subpatterns) => patterns.extend(subpatterns),
+                _ => (),
+            }
+        }
+        shadowed
+    }
+    fn check_pattern(&self, pattern: &Pattern) -> Result<(), String> {
+        let Pattern::Variant(name, subpatterns) = pattern else {
+            return Ok(());
+        };
+        let Some((_, arity)) = self.variants.get(name.as_str()) else {
+            return Err(format!("Unknown variant {} in pattern.", name));
+        };
+        if *arity != subpatterns.len() {
+            return Err(format!(
+                "Variant {} has {} values, but the pattern has {}.",
+                name,
+                arity,
+                subpatterns.len()
+            ));
+        }
+        for subpattern in subpatterns {
+            self.check_pattern(subpattern)?;
+        }
+        Ok(())
+    }
+    // A match over variants of a known enum has to cover every variant with an
+    // unguarded arm, or have a catch-all arm. Matches over plain values can't
+    // be checked, so they fail at runtime if nothing matches.
+    fn check_exhaustive<T>(&self, match_block: &MatchBlock<T>) -> Result<(), String> {
+        let mut matched_enum: Option<&EnumAST> = None;
+        let mut covered = Vec::new();
+        for arm in match_block.arms.iter() {
+            let (name, irrefutable) = match &arm.pattern {
+                Pattern::Wildcard => {
+                    if arm.guard.is_none() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Pattern::Literal(_) => continue,
+                Pattern::Ident(name, _) if !self.variants.contains_key(name.as_str()) => {
+                    if arm.guard.is_none() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Pattern::Ident(name, _) => (name, true),
+                Pattern::Variant(name, subpatterns) => {
+                    (name, subpatterns.iter().all(|p| self.is_irrefutable(p)))
+                }
+            };
+            let (enum_ast, _) = self.variants[name.as_str()];
+            match matched_enum {
+                Some(x) if x.name != enum_ast.name => {
+                    return Err(format!(
+                        "Match mixes variants of enum {} and enum {}.",
+                        x.name, enum_ast.name
+                    ));
+                }
+                _ => matched_enum = Some(enum_ast),
+            }
+            if irrefutable && arm.guard.is_none() {
+                covered.push(name.as_str());
+            }
+        }
+        let Some(enum_ast) = matched_enum else {
+            return Ok(());
+        };
+        let missing: Vec<&str> = enum_ast
+            .variants
+            .iter()
+            .map(|v| &*v.name)
+            .filter(|v| !covered.contains(v))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Non-exhaustive match over enum {}, missing: {}.",
+                enum_ast.name,
+                missing.join(", ")
+            ))
+        }
+    }
+    fn is_irrefutable(&self, pattern: &Pattern) -> bool {
+        match pattern {
+            Pattern::Wildcard => true,
+            Pattern::Ident(name, _) => !self.variants.contains_key(name.as_str()),
+            Pattern::Literal(_) | Pattern::Variant(_, _) => false,
+        }
+    }
+}
+
+// Adds the names a declaration binds, which a destructuring one has nested in
+// a tuple, list or struct, to the scope.
+fn declare(target: &ExprAST, is_mutable: bool, scope: &mut Scope) {
+    match target {
+        ExprAST::Variable(name, ..) => {
+            scope.insert(name.clone(), is_mutable);
+        }
+        ExprAST::Tuple(items) | ExprAST::List(items) => {
+            items.iter().for_each(|x| declare(x, is_mutable, scope));
+        }
+        ExprAST::StructInit(_, fields, _) => {
+            fields
+                .iter()
+                .for_each(|(_, x)| declare(x, is_mutable, scope));
+        }
+        _ => (),
+    }
+}
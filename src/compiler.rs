@@ -0,0 +1,665 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{
+        Arg, Assignment, BuiltIn, ExprAST, FunctionAST, MatchBlock, Name, Pattern, ProgramAST,
+        PrototypeAST, Statement, TryBlock, Value, intern,
+    },
+    bytecode::{
+        ArgShape, ArmPattern, CallSite, Function, Op, Pattern as CompiledPattern, Program,
+        StructLiteral, Target,
+    },
+    checker::constant_order,
+    lexer::Span,
+};
+
+// Compiles a checked program for the VM.
+pub fn compile(program: &ProgramAST) -> Program {
+    let mut compiler = ProgramCompiler {
+        function_index: HashMap::new(),
+        global_index: HashMap::new(),
+        structs: HashMap::new(),
+        variants: HashMap::new(),
+        functions: Vec::new(),
+        closure_index: HashMap::new(),
+    };
+    for (i, func) in program.functions.iter().enumerate() {
+        compiler.function_index.insert(func.proto.name.clone(), i);
+    }
+    for (i, global) in program.globals.iter().enumerate() {
+        compiler.global_index.insert(global.name.clone(), i);
+    }
+    for struct_ast in program.structs.iter() {
+        compiler
+            .structs
+            .insert(struct_ast.name.clone(), struct_ast.fields.clone());
+    }
+    for enum_ast in program.enums.iter() {
+        for variant in enum_ast.variants.iter() {
+            compiler.variants.insert(
+                variant.name.clone(),
+                (enum_ast.name.clone(), variant.fields.len()),
+            );
+        }
+    }
+    // declared functions take the first indexes, so calls can refer to ones
+    // that aren't compiled yet
+    compiler
+        .functions
+        .resize_with(program.functions.len(), || None);
+    for (i, func) in program.functions.iter().enumerate() {
+        let compiled =
+            FunctionCompiler::new(&mut compiler, &func.proto, &func.locals).finish_function(func);
+        compiler.functions[i] = Some(compiled);
+    }
+    let mut init_order: Vec<&str> = constant_order(program)
+        .expect("Constant cycles are caught by the checker.")
+        .into_iter()
+        .map(|g| g.name.as_str())
+        .collect();
+    init_order.extend(
+        program
+            .globals
+            .iter()
+            .filter(|g| !g.is_const)
+            .map(|g| g.name.as_str()),
+    );
+    let mut inits = Vec::with_capacity(init_order.len());
+    for name in init_order {
+        let global = compiler.global_index[name];
+        let proto = PrototypeAST::new(intern(name), vec![], None);
+        let global_ast = &program.globals[global];
+        let compiled = FunctionCompiler::new(&mut compiler, &proto, &global_ast.locals)
+            .finish_init(&global_ast.init);
+        compiler.functions.push(Some(compiled));
+        inits.push((global, compiler.functions.len() - 1));
+    }
+    Program {
+        functions: compiler
+            .functions
+            .into_iter()
+            .map(|f| f.expect("Every function is compiled."))
+            .collect(),
+        function_index: compiler.function_index,
+        closure_index: compiler.closure_index,
+        globals: program.globals.iter().map(|g| g.name.clone()).collect(),
+        global_index: compiler.global_index,
+        constants: program
+            .globals
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| g.is_const)
+            .map(|(i, _)| i)
+            .collect(),
+        inits,
+        variants: compiler.variants,
+    }
+}
+
+struct ProgramCompiler {
+    function_index: HashMap<Name, usize>,
+    global_index: HashMap<String, usize>,
+    // struct name -> fields in declaration order
+    structs: HashMap<Name, Vec<Name>>,
+    variants: HashMap<Name, (Name, usize)>,
+    functions: Vec<Option<Function>>,
+    closure_index: HashMap<*const FunctionAST, usize>,
+}
+
+// What a return has to undo on its way out, innermost last.
+#[derive(Clone)]
+enum Exit<'a> {
+    // a try whose handler is pushed, and its finally if it has one
+    Try(Option<&'a [Statement]>),
+    // bindings to restore with `op`, with a handler restoring them on a throw
+    // if there's a try in this function to catch it
+    Unbind { op: Op, handler: bool },
+}
+
+struct FunctionCompiler<'a, 'p> {
+    program: &'p mut ProgramCompiler,
+    function: Function,
+    exits: Vec<Exit<'a>>,
+}
+impl<'a, 'p> FunctionCompiler<'a, 'p> {
+    // `locals` are what the resolver gave slots, before any hidden ones.
+    fn new(program: &'p mut ProgramCompiler, proto: &PrototypeAST, locals: &[String]) -> Self {
+        let slot_names = locals.to_vec();
+        FunctionCompiler {
+            program,
+            function: Function {
+                proto: proto.clone(),
+                code: Vec::new(),
+                spans: Vec::new(),
+                body_start: 0,
+                slot_count: slot_names.len(),
+                slot_names,
+                params: Vec::new(),
+                rest: None,
+                constants: Vec::new(),
+                names: Vec::new(),
+                calls: Vec::new(),
+                shapes: Vec::new(),
+                patterns: Vec::new(),
+                targets: Vec::new(),
+                structs: Vec::new(),
+                closures: Vec::new(),
+                errors: Vec::new(),
+            },
+            exits: Vec::new(),
+        }
+    }
+    fn finish_function(mut self, func: &'a FunctionAST) -> Function {
+        let params = func.proto.args.len();
+        self.function.params = func.param_slots[..params].to_vec();
+        self.function.rest = func.param_slots.get(params).copied();
+        // the prologue, for calls that leave out an argument
+        for (i, param) in func.proto.args.iter().enumerate() {
+            let bind = self.emit(Op::BindParam(i, 0), Span::default());
+            if let Some(default) = &param.default {
+                self.compile_expr(default);
+                self.emit(Op::SetLocal(func.param_slots[i]), Span::default());
+            }
+            self.patch(bind);
+        }
+        if self.function.rest.is_some() {
+            self.emit(Op::BindRest, Span::default());
+        }
+        self.emit(Op::Enter, Span::default());
+        self.function.body_start = self.function.code.len();
+        self.compile_statements(&func.body);
+        // falling off the end returns 0
+        let zero = self.constant(Value::Int(0));
+        self.emit(Op::Const(zero), Span::default());
+        self.emit(Op::Return, Span::default());
+        self.function
+    }
+    fn finish_init(mut self, init: &'a ExprAST) -> Function {
+        self.compile_expr(init);
+        self.emit(Op::Return, Span::default());
+        self.function
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.function.code.push(op);
+        self.function.spans.push(span);
+        self.function.code.len() - 1
+    }
+    // Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.function.code.len();
+        match &mut self.function.code[at] {
+            Op::Jump(x)
+            | Op::JumpIfFalse(x)
+            | Op::PushHandler(x)
+            | Op::Propagate(x)
+            | Op::BindParam(_, x)
+            | Op::TestPattern { fail: x, .. } => *x = target,
+            op => unreachable!("{:?} doesn't jump", op),
+        }
+    }
+    fn constant(&mut self, value: Value) -> usize {
+        self.function.constants.push(value);
+        self.function.constants.len() - 1
+    }
+    fn name(&mut self, name: &str) -> usize {
+        match self.function.names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.function.names.push(name.to_owned());
+                self.function.names.len() - 1
+            }
+        }
+    }
+    fn hidden_slot(&mut self) -> usize {
+        self.function.slot_count += 1;
+        self.function.slot_count - 1
+    }
+    fn error(&mut self, kind: &str, message: String, span: Span) {
+        self.function.errors.push((kind.to_owned(), message));
+        let index = self.function.errors.len() - 1;
+        self.emit(Op::Fail(index), span);
+    }
+    fn try_active(&self) -> bool {
+        self.exits.iter().any(|e| matches!(e, Exit::Try(_)))
+    }
+
+    fn compile_statements(&mut self, body: &'a [Statement]) {
+        for statement in body {
+            self.compile_statement(statement);
+        }
+    }
+    fn compile_statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Assign(x) => self.compile_assignment(x),
+            Statement::If(x) => {
+                self.compile_expr(&x.conditional);
+                let to_else = self.emit(Op::JumpIfFalse(0), Span::default());
+                self.compile_statements(&x.body);
+                let to_end = self.emit(Op::Jump(0), Span::default());
+                self.patch(to_else);
+                self.compile_statements(&x.else_body);
+                self.patch(to_end);
+            }
+            Statement::While(x) => {
+                let top = self.function.code.len();
+                self.compile_expr(&x.conditional);
+                let to_end = self.emit(Op::JumpIfFalse(0), Span::default());
+                self.compile_statements(&x.body);
+                self.emit(Op::Jump(top), Span::default());
+                self.patch(to_end);
+            }
+            Statement::Call(x) => {
+                self.compile_expr(x);
+                self.emit(Op::Pop, Span::default());
+            }
+            Statement::Built(BuiltIn::Print(x, _)) => {
+                self.compile_expr(x);
+                self.emit(Op::Print, Span::default());
+            }
+            Statement::Built(BuiltIn::Return(x, _)) => {
+                self.compile_expr(x);
+                self.compile_return();
+            }
+            Statement::Built(BuiltIn::Input(x)) => {
+                let ExprAST::Variable(_, span, resolution) = x else {
+                    unreachable!();
+                };
+                let slot = resolution.slot.expect("Input binds a local.");
+                self.emit(Op::Input(slot), *span);
+            }
+            Statement::Built(BuiltIn::Drop(x)) => {
+                let ExprAST::Variable(_, span, resolution) = x else {
+                    unreachable!();
+                };
+                // dropping a name that's never a local does nothing
+                if let Some(slot) = resolution.slot {
+                    self.emit(Op::Unset(slot), *span);
+                }
+            }
+            Statement::Match(x) => {
+                self.compile_match(x, |compiler, body| compiler.compile_statements(body));
+            }
+            Statement::Try(x) => self.compile_try(x),
+            Statement::Throw(x, span) => {
+                self.compile_expr(x);
+                self.emit(Op::Throw, *span);
+            }
+        }
+    }
+    // The value to return is on the stack. Leaving undoes what's in the way,
+    // innermost first, running finally blocks as it passes their try.
+    fn compile_return(&mut self) {
+        for i in (0..self.exits.len()).rev() {
+            match self.exits[i].clone() {
+                Exit::Unbind { op, handler } => {
+                    if handler {
+                        self.emit(Op::PopHandler, Span::default());
+                    }
+                    self.emit(op, Span::default());
+                }
+                Exit::Try(finally) => {
+                    self.emit(Op::PopHandler, Span::default());
+                    if let Some(body) = finally {
+                        // a return in the finally only passes the tries around it
+                        let mut inner = self.exits.split_off(i);
+                        self.compile_statements(body);
+                        self.exits.append(&mut inner);
+                    }
+                }
+            }
+        }
+        self.emit(Op::Return, Span::default());
+    }
+    fn compile_assignment(&mut self, assignment: &'a Assignment) {
+        self.compile_expr(&assignment.right_hand);
+        let compound = assignment.compound;
+        match &assignment.variable {
+            ExprAST::Variable(_, span, resolution) if assignment.is_declaration => {
+                let slot = resolution.slot.expect("Declarations get a slot.");
+                self.emit(Op::Declare(slot), *span);
+            }
+            ExprAST::Variable(name, span, resolution) => {
+                let name = self.name(name);
+                self.emit(
+                    Op::Assign {
+                        slot: resolution.slot,
+                        global: resolution.global,
+                        name,
+                        compound,
+                    },
+                    *span,
+                );
+            }
+            target @ (ExprAST::Tuple(_) | ExprAST::List(_) | ExprAST::StructInit(..)) => {
+                let target = self.target(target);
+                self.function.targets.push(target);
+                let index = self.function.targets.len() - 1;
+                self.emit(Op::Destructure(index), assignment.span);
+            }
+            ExprAST::Index(container, index, span) => {
+                self.compile_expr(container);
+                self.compile_expr(index);
+                self.emit(Op::SetIndex(compound), *span);
+            }
+            ExprAST::Field(instance, field, span) => {
+                self.compile_expr(instance);
+                let field = self.name(field);
+                self.emit(Op::SetField(field, compound), *span);
+            }
+            _ => {
+                eprintln!("The parser messed up, and this exprast is wrong");
+                panic!();
+            }
+        }
+    }
+    fn target(&self, target: &ExprAST) -> Target {
+        match target {
+            ExprAST::Variable(name, ..) if name == "_" => Target::Skip,
+            ExprAST::Variable(_, span, resolution) => {
+                Target::Name(resolution.slot.expect("Declarations get a slot."), *span)
+            }
+            ExprAST::Tuple(items) => Target::Tuple(items.iter().map(|x| self.target(x)).collect()),
+            ExprAST::List(items) => Target::List(items.iter().map(|x| self.target(x)).collect()),
+            ExprAST::StructInit(name, fields, _) => Target::Struct(
+                name.clone(),
+                fields
+                    .iter()
+                    .map(|(field, x)| (field.clone(), self.target(x)))
+                    .collect(),
+            ),
+            _ => unreachable!("The parser only allows names in destructuring."),
+        }
+    }
+    fn compile_try(&mut self, try_block: &'a TryBlock) {
+        let finally = try_block.finally.as_deref();
+        let to_handler = self.emit(Op::PushHandler(0), Span::default());
+        self.exits.push(Exit::Try(finally));
+        self.compile_statements(&try_block.body);
+        self.exits.pop();
+        self.emit(Op::PopHandler, Span::default());
+        if let Some(body) = finally {
+            self.compile_statements(body);
+        }
+        let mut to_end = vec![self.emit(Op::Jump(0), Span::default())];
+        self.patch(to_handler);
+        // the error is on the stack from here
+        if let Some((_, slot, body)) = &try_block.catch {
+            let slot = *slot;
+            let save = self.hidden_slot();
+            self.emit(Op::Catch { slot, save }, Span::default());
+            // with a finally, a throw from the catch block still runs it
+            let to_finally = finally.map(|_| {
+                let at = self.emit(Op::PushHandler(0), Span::default());
+                self.exits.push(Exit::Try(finally));
+                at
+            });
+            self.compile_bound(Op::Restore { slot, save }, |compiler| {
+                compiler.compile_statements(body);
+            });
+            if let Some(to_finally) = to_finally {
+                self.exits.pop();
+                self.emit(Op::PopHandler, Span::default());
+                self.compile_statements(finally.unwrap());
+                to_end.push(self.emit(Op::Jump(0), Span::default()));
+                self.patch(to_finally);
+            } else {
+                to_end.push(self.emit(Op::Jump(0), Span::default()));
+            }
+        }
+        if let Some(body) = finally {
+            // the finally runs and the error carries on, unless the finally
+            // returned
+            self.compile_statements(body);
+            self.emit(Op::Throw, Span::default());
+        }
+        for at in to_end {
+            self.patch(at);
+        }
+    }
+    // Compiles code run with some names bound, which `unbind` restores after,
+    // also when it's cut short.
+    fn compile_bound(&mut self, unbind: Op, compile: impl FnOnce(&mut Self)) {
+        let handler = self.try_active();
+        let to_cleanup = handler.then(|| self.emit(Op::PushHandler(0), Span::default()));
+        self.exits.push(Exit::Unbind {
+            op: unbind,
+            handler,
+        });
+        compile(self);
+        self.exits.pop();
+        if let Some(to_cleanup) = to_cleanup {
+            self.emit(Op::PopHandler, Span::default());
+            self.emit(unbind, Span::default());
+            let to_end = self.emit(Op::Jump(0), Span::default());
+            self.patch(to_cleanup);
+            self.emit(unbind, Span::default());
+            self.emit(Op::Throw, Span::default());
+            self.patch(to_end);
+        } else {
+            self.emit(unbind, Span::default());
+        }
+    }
+    fn compile_match<T>(
+        &mut self,
+        match_block: &'a MatchBlock<T>,
+        compile_body: impl Fn(&mut Self, &'a T),
+    ) {
+        self.compile_expr(&match_block.scrutinee);
+        let value = self.hidden_slot();
+        self.emit(Op::SetLocal(value), Span::default());
+        let mut to_end = Vec::new();
+        for arm in match_block.arms.iter() {
+            let mut binds = Vec::new();
+            let pattern = self.pattern(&arm.pattern, &mut binds);
+            self.function.patterns.push(ArmPattern { pattern, binds });
+            let pattern = self.function.patterns.len() - 1;
+            let to_next = self.emit(
+                Op::TestPattern {
+                    pattern,
+                    slot: value,
+                    fail: 0,
+                },
+                Span::default(),
+            );
+            let mut to_guard_fail = None;
+            self.compile_bound(Op::Unbind(pattern), |compiler| {
+                if let Some(guard) = &arm.guard {
+                    compiler.compile_expr(guard);
+                    to_guard_fail = Some(compiler.emit(Op::JumpIfFalse(0), Span::default()));
+                }
+                compile_body(compiler, &arm.body);
+            });
+            to_end.push(self.emit(Op::Jump(0), Span::default()));
+            if let Some(to_guard_fail) = to_guard_fail {
+                self.patch(to_guard_fail);
+                if self.try_active() {
+                    self.emit(Op::PopHandler, Span::default());
+                }
+                self.emit(Op::Unbind(pattern), Span::default());
+                let to_next_arm = self.emit(Op::Jump(0), Span::default());
+                self.patch(to_next);
+                self.patch(to_next_arm);
+            } else {
+                self.patch(to_next);
+            }
+        }
+        self.emit(Op::NoMatch(value), match_block.span);
+        for at in to_end {
+            self.patch(at);
+        }
+    }
+    fn pattern(&mut self, pattern: &Pattern, binds: &mut Vec<(usize, usize)>) -> CompiledPattern {
+        match pattern {
+            Pattern::Wildcard => CompiledPattern::Wildcard,
+            Pattern::Literal(x) => CompiledPattern::Literal(x.clone()),
+            // only names that aren't variants get a slot
+            Pattern::Ident(_, Some(slot)) => {
+                binds.push((*slot, self.hidden_slot()));
+                CompiledPattern::Bind
+            }
+            Pattern::Ident(name, None) => CompiledPattern::Unit(name.clone()),
+            Pattern::Variant(name, subpatterns) => CompiledPattern::Variant(
+                name.clone(),
+                subpatterns.iter().map(|p| self.pattern(p, binds)).collect(),
+            ),
+        }
+    }
+
+    fn compile_args(&mut self, args: &'a [Arg]) -> ArgShape {
+        let mut shape = ArgShape {
+            positional: 0,
+            named: Vec::new(),
+        };
+        for arg in args {
+            self.compile_expr(&arg.value);
+            match &arg.name {
+                Some(name) => shape.named.push(name.clone()),
+                None => shape.positional += 1,
+            }
+        }
+        shape
+    }
+    fn compile_exprs(&mut self, exprs: &'a [ExprAST]) {
+        for expr in exprs {
+            self.compile_expr(expr);
+        }
+    }
+    fn compile_expr(&mut self, expr: &'a ExprAST) {
+        match expr {
+            ExprAST::Variable(name, span, resolution) => {
+                let op = if let Some(slot) = resolution.slot {
+                    Op::Load(slot)
+                } else if let Some(global) = resolution.global {
+                    Op::LoadGlobal(global)
+                } else {
+                    Op::LoadName(self.name(name))
+                };
+                self.emit(op, *span);
+            }
+            ExprAST::Val(x) => {
+                let constant = self.constant(x.clone());
+                self.emit(Op::Const(constant), Span::default());
+            }
+            ExprAST::BinOp(op, lhs, rhs, span) => {
+                self.compile_expr(lhs);
+                self.compile_expr(rhs);
+                self.emit(Op::Binary(*op), *span);
+            }
+            ExprAST::Call(name, args, span, resolution) => {
+                let args = self.compile_args(args);
+                let site = CallSite {
+                    name: name.clone(),
+                    slot: resolution.slot,
+                    global: resolution.global,
+                    function: resolution.function,
+                    args,
+                };
+                self.function.calls.push(site);
+                self.emit(Op::Call(self.function.calls.len() - 1), *span);
+            }
+            ExprAST::CallExpr(callee, args, span) => {
+                self.compile_expr(callee);
+                let args = self.compile_args(args);
+                self.function.shapes.push(args);
+                self.emit(Op::CallValue(self.function.shapes.len() - 1), *span);
+            }
+            ExprAST::Closure(function) => {
+                let index = self.compile_closure(function);
+                self.program
+                    .closure_index
+                    .insert(Rc::as_ptr(function), index);
+                self.function.closures.push(function.clone());
+                self.emit(
+                    Op::Closure(self.function.closures.len() - 1),
+                    Span::default(),
+                );
+            }
+            ExprAST::Match(x) => {
+                self.compile_match(x, |compiler, body| compiler.compile_expr(body));
+            }
+            ExprAST::List(items) => {
+                self.compile_exprs(items);
+                self.emit(Op::List(items.len()), Span::default());
+            }
+            ExprAST::Tuple(items) => {
+                self.compile_exprs(items);
+                self.emit(Op::Tuple(items.len()), Span::default());
+            }
+            ExprAST::Map(entries, span) => {
+                for (key, value) in entries {
+                    self.compile_expr(key);
+                    self.emit(Op::MapKey, *span);
+                    self.compile_expr(value);
+                }
+                self.emit(Op::Map(entries.len()), Span::default());
+            }
+            ExprAST::Index(container, index, span) => {
+                self.compile_expr(container);
+                self.compile_expr(index);
+                self.emit(Op::Index, *span);
+            }
+            ExprAST::StructInit(name, inits, span) => self.compile_struct(name, inits, *span),
+            ExprAST::Field(instance, field, span) => {
+                self.compile_expr(instance);
+                let field = self.name(field);
+                self.emit(Op::Field(field), *span);
+            }
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                self.compile_expr(conditional);
+                let to_else = self.emit(Op::JumpIfFalse(0), Span::default());
+                self.compile_expr(then_expr);
+                let to_end = self.emit(Op::Jump(0), Span::default());
+                self.patch(to_else);
+                self.compile_expr(else_expr);
+                self.patch(to_end);
+            }
+            ExprAST::Propagate(inner, span) => {
+                self.compile_expr(inner);
+                let to_rest = self.emit(Op::Propagate(0), *span);
+                self.compile_return();
+                self.patch(to_rest);
+            }
+        }
+    }
+    // The tree-walker finds a bad field when it gets to it, after evaluating
+    // the fields before, so the errors go in the same place here.
+    fn compile_struct(&mut self, name: &str, inits: &'a [(String, ExprAST)], span: Span) {
+        let Some((name, fields)) = self
+            .program
+            .structs
+            .get_key_value(name)
+            .map(|(n, f)| (n.clone(), f.clone()))
+        else {
+            self.error("NameError", format!("Unknown struct {}", name), span);
+            return;
+        };
+        let mut given: Vec<Name> = Vec::with_capacity(inits.len());
+        for (field, init) in inits {
+            let Some(field) = fields.iter().find(|f| ***f == **field) else {
+                let message = format!("Struct {} has no field {}", name, field);
+                self.error("FieldError", message, span);
+                return;
+            };
+            if given.contains(field) {
+                let message = format!("Field {} given twice in {} literal", field, name);
+                self.error("FieldError", message, span);
+                return;
+            }
+            self.compile_expr(init);
+            given.push(field.clone());
+        }
+        self.function.structs.push(StructLiteral {
+            name,
+            fields,
+            given,
+        });
+        self.emit(Op::Struct(self.function.structs.len() - 1), span);
+    }
+    fn compile_closure(&mut self, function: &'a Rc<FunctionAST>) -> usize {
+        let compiled = FunctionCompiler::new(self.program, &function.proto, &function.locals)
+            .finish_function(function);
+        self.program.functions.push(Some(compiled));
+        self.program.functions.len() - 1
+    }
+}
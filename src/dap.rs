@@ -0,0 +1,428 @@
+//! `willscript debug --dap`, the debugger speaking the Debug Adapter Protocol
+//! over stdin and stdout for one launch of one program. Requests are only
+//! read while the program is stopped, so a running program can't be paused,
+//! only stopped at a breakpoint.
+
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, Write},
+    mem,
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    ast::{ErrorValue, ProgramAST},
+    debugger::{Breakpoints, Command, Debugger, Frontend, Reason, Stop, call},
+    interpreter::InterpretingMastermind,
+    json::{Json, read_message, write_message},
+    load,
+};
+
+// the program runs on the one thread there is
+const THREAD: u32 = 1;
+// the only variables there are, the paused call's locals
+const LOCALS: u32 = 1;
+
+/// Serves one debugging session, from `initialize` to `disconnect`.
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> io::Result<()> {
+    let adapter = Rc::new(RefCell::new(Adapter {
+        input,
+        output,
+        seq: 0,
+        program: String::new(),
+        breakpoints: Breakpoints::default(),
+        evaluation: None,
+        disconnected: false,
+        failed: None,
+    }));
+    let Some((program, stop_on_entry)) = adapter.borrow_mut().configure()? else {
+        return Ok(());
+    };
+    let breakpoints = mem::take(&mut adapter.borrow_mut().breakpoints);
+    let frontend = Box::new(Dap(Rc::clone(&adapter)));
+    let debugger = Debugger::new(&program, breakpoints, frontend, stop_on_entry);
+    let result = InterpretingMastermind::new(program)
+        .with_debugger(debugger)
+        .run_main();
+    adapter.borrow_mut().finish(result)
+}
+
+struct Adapter {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: u32,
+    // the launched program's path, the only file with line breakpoints
+    program: String,
+    // the breakpoints set before the program starts
+    breakpoints: Breakpoints,
+    // the evaluate request the interpreter is answering
+    evaluation: Option<Json>,
+    disconnected: bool,
+    // what went wrong talking to the client while the program ran
+    failed: Option<io::Error>,
+}
+impl Adapter {
+    // Takes requests until the program is launched and configured.
+    fn configure(&mut self) -> io::Result<Option<(ProgramAST, bool)>> {
+        let mut launched = None;
+        let mut configured = false;
+        while let Some(request) = self.read()? {
+            let arguments = request.get("arguments");
+            match request.get("command").as_str().unwrap_or_default() {
+                "initialize" => {
+                    self.respond(&request, capabilities())?;
+                    self.event("initialized", Json::object(vec![]))?;
+                }
+                "launch" => {
+                    let program = arguments.get("program").as_str().unwrap_or_default();
+                    let stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+                    match load(Path::new(program)) {
+                        Ok(loaded) => {
+                            self.program = program.to_owned();
+                            launched = Some((loaded, stop_on_entry));
+                            self.respond(&request, Json::Null)?;
+                        }
+                        Err(e) => self.respond_error(&request, e)?,
+                    }
+                }
+                "configurationDone" => {
+                    configured = true;
+                    self.respond(&request, Json::Null)?;
+                }
+                "disconnect" | "terminate" => {
+                    self.respond(&request, Json::Null)?;
+                    return Ok(None);
+                }
+                _ => {
+                    let mut breakpoints = mem::take(&mut self.breakpoints);
+                    self.common(&request, &mut breakpoints)?;
+                    self.breakpoints = breakpoints;
+                }
+            }
+            if configured && launched.is_some() {
+                return Ok(launched);
+            }
+        }
+        Ok(None)
+    }
+    // Answers requests about the stop until one carries on.
+    fn command(&mut self, stop: &Stop, breakpoints: &mut Breakpoints) -> io::Result<Command> {
+        while let Some(request) = self.read()? {
+            let arguments = request.get("arguments");
+            let command = match request.get("command").as_str().unwrap_or_default() {
+                "continue" => Command::Continue,
+                "next" => Command::StepOver,
+                "stepIn" => Command::StepIn,
+                "stepOut" => Command::StepOut,
+                "disconnect" | "terminate" => {
+                    self.disconnected = true;
+                    Command::Quit
+                }
+                "evaluate" => {
+                    let expression = arguments.get("expression").as_str().unwrap_or_default();
+                    let expression = expression.to_owned();
+                    self.evaluation = Some(request);
+                    return Ok(Command::Evaluate(expression));
+                }
+                "stackTrace" => {
+                    self.respond(&request, stack_trace(stop, &self.program))?;
+                    continue;
+                }
+                "scopes" => {
+                    let scopes = match arguments.get("frameId").as_u32() {
+                        Some(0) => vec![Json::object(vec![
+                            ("name", Json::from("Locals")),
+                            ("variablesReference", Json::from(LOCALS)),
+                            ("expensive", Json::from(false)),
+                        ])],
+                        _ => vec![],
+                    };
+                    let body = Json::object(vec![("scopes", Json::Array(scopes))]);
+                    self.respond(&request, body)?;
+                    continue;
+                }
+                "variables" => {
+                    let variables = match arguments.get("variablesReference").as_u32() {
+                        Some(LOCALS) => stop
+                            .locals
+                            .iter()
+                            .map(|(name, value)| {
+                                Json::object(vec![
+                                    ("name", Json::from(name.as_str())),
+                                    ("value", Json::from(value.repr())),
+                                    ("variablesReference", Json::from(0)),
+                                ])
+                            })
+                            .collect(),
+                        _ => vec![],
+                    };
+                    let body = Json::object(vec![("variables", Json::Array(variables))]);
+                    self.respond(&request, body)?;
+                    continue;
+                }
+                _ => {
+                    self.common(&request, breakpoints)?;
+                    continue;
+                }
+            };
+            let body = match command {
+                Command::Continue => Json::object(vec![("allThreadsContinued", Json::from(true))]),
+                _ => Json::Null,
+            };
+            self.respond(&request, body)?;
+            return Ok(command);
+        }
+        // the client went away
+        self.disconnected = true;
+        Ok(Command::Quit)
+    }
+    // Requests answered the same whether or not the program has started.
+    fn common(&mut self, request: &Json, breakpoints: &mut Breakpoints) -> io::Result<()> {
+        let arguments = request.get("arguments");
+        match request.get("command").as_str().unwrap_or_default() {
+            "threads" => {
+                let thread = Json::object(vec![
+                    ("id", Json::from(THREAD)),
+                    ("name", Json::from("main")),
+                ]);
+                let body = Json::object(vec![("threads", Json::Array(vec![thread]))]);
+                self.respond(request, body)
+            }
+            "setBreakpoints" => {
+                let path = arguments.get("source").get("path").as_str();
+                // before the launch, the client can only mean the program
+                let ours =
+                    self.program.is_empty() || path.is_some_and(|p| same_file(p, &self.program));
+                let lines: Vec<u32> = arguments
+                    .get("breakpoints")
+                    .as_array()
+                    .iter()
+                    .filter_map(|b| b.get("line").as_u32())
+                    .collect();
+                if ours {
+                    breakpoints.lines = lines.iter().copied().collect();
+                }
+                let set = lines
+                    .iter()
+                    .map(|&line| {
+                        let mut breakpoint =
+                            vec![("verified", Json::from(ours)), ("line", Json::from(line))];
+                        if !ours {
+                            let message = "Only the launched program can have line breakpoints.";
+                            breakpoint.push(("message", Json::from(message)));
+                        }
+                        Json::object(breakpoint)
+                    })
+                    .collect();
+                let body = Json::object(vec![("breakpoints", Json::Array(set))]);
+                self.respond(request, body)
+            }
+            "setFunctionBreakpoints" => {
+                let names: Vec<&str> = arguments
+                    .get("breakpoints")
+                    .as_array()
+                    .iter()
+                    .filter_map(|b| b.get("name").as_str())
+                    .collect();
+                breakpoints.functions = names.iter().map(|n| n.to_string()).collect();
+                let set = names
+                    .iter()
+                    .map(|_| Json::object(vec![("verified", Json::from(true))]))
+                    .collect();
+                let body = Json::object(vec![("breakpoints", Json::Array(set))]);
+                self.respond(request, body)
+            }
+            command => {
+                let message = format!("The {} request isn't supported.", command);
+                self.respond_error(request, message)
+            }
+        }
+    }
+    // Says how the program ended, then waits for the client to disconnect.
+    fn finish(&mut self, result: Result<(), Rc<ErrorValue>>) -> io::Result<()> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        if self.disconnected {
+            return Ok(());
+        }
+        let code = match result {
+            Ok(()) => 0,
+            Err(e) => {
+                let text = format!("Uncaught error at {}\n{}\n", e, e.format_trace());
+                self.output_event("stderr", &text)?;
+                1
+            }
+        };
+        self.event("exited", Json::object(vec![("exitCode", Json::from(code))]))?;
+        self.event("terminated", Json::object(vec![]))?;
+        while let Some(request) = self.read()? {
+            match request.get("command").as_str().unwrap_or_default() {
+                "disconnect" | "terminate" => return self.respond(&request, Json::Null),
+                _ => self.common(&request, &mut Breakpoints::default())?,
+            }
+        }
+        Ok(())
+    }
+
+    // The next request, skipping anything that isn't JSON.
+    fn read(&mut self) -> io::Result<Option<Json>> {
+        loop {
+            match read_message(&mut self.input)? {
+                Some(Ok(message)) => return Ok(Some(message)),
+                Some(Err(_)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+    fn send(&mut self, kind: &str, mut entries: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        entries.insert(0, ("seq", Json::from(self.seq)));
+        entries.insert(1, ("type", Json::from(kind)));
+        write_message(&mut self.output, &Json::object(entries))
+    }
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        let mut entries = vec![
+            ("request_seq", request.get("seq").clone()),
+            ("success", Json::from(true)),
+            ("command", request.get("command").clone()),
+        ];
+        if body != Json::Null {
+            entries.push(("body", body));
+        }
+        self.send("response", entries)
+    }
+    fn respond_error(&mut self, request: &Json, message: String) -> io::Result<()> {
+        self.send(
+            "response",
+            vec![
+                ("request_seq", request.get("seq").clone()),
+                ("success", Json::from(false)),
+                ("command", request.get("command").clone()),
+                ("message", Json::from(message)),
+            ],
+        )
+    }
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send("event", vec![("event", Json::from(event)), ("body", body)])
+    }
+    fn output_event(&mut self, category: &str, text: &str) -> io::Result<()> {
+        let body = Json::object(vec![
+            ("category", Json::from(category)),
+            ("output", Json::from(text)),
+        ]);
+        self.event("output", body)
+    }
+    // Keeps the first error talking to the client, which ends the session.
+    fn keep(&mut self, result: io::Result<()>) {
+        if let Err(e) = result
+            && self.failed.is_none()
+        {
+            self.failed = Some(e);
+        }
+    }
+}
+
+// The debugger's frontend, which shares the adapter with `serve`.
+struct Dap(Rc<RefCell<Adapter>>);
+impl Frontend for Dap {
+    fn stopped(&mut self, stop: &Stop) {
+        let reason = match stop.reason {
+            Reason::Entry => "entry",
+            Reason::Breakpoint => "breakpoint",
+            Reason::FunctionBreakpoint => "function breakpoint",
+            Reason::Step => "step",
+        };
+        let body = Json::object(vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD)),
+            ("allThreadsStopped", Json::from(true)),
+        ]);
+        let mut adapter = self.0.borrow_mut();
+        let result = adapter.event("stopped", body);
+        adapter.keep(result);
+    }
+    fn command(&mut self, stop: &Stop, breakpoints: &mut Breakpoints) -> Command {
+        let mut adapter = self.0.borrow_mut();
+        if adapter.failed.is_some() {
+            return Command::Quit;
+        }
+        match adapter.command(stop, breakpoints) {
+            Ok(command) => command,
+            Err(e) => {
+                adapter.keep(Err(e));
+                Command::Quit
+            }
+        }
+    }
+    fn evaluated(&mut self, result: Result<String, String>) {
+        let mut adapter = self.0.borrow_mut();
+        let Some(request) = adapter.evaluation.take() else {
+            return;
+        };
+        let result = match result {
+            Ok(value) => {
+                let body = Json::object(vec![
+                    ("result", Json::from(value)),
+                    ("variablesReference", Json::from(0)),
+                ]);
+                adapter.respond(&request, body)
+            }
+            Err(e) => adapter.respond_error(&request, e),
+        };
+        adapter.keep(result);
+    }
+    fn print(&mut self, text: &str) {
+        let mut adapter = self.0.borrow_mut();
+        let result = adapter.output_event("stdout", text);
+        adapter.keep(result);
+    }
+    // stdin is the client's, and the protocol has no way to ask it for input
+    fn input(&mut self) -> Result<String, String> {
+        Err("The debug adapter can't give the program input".to_owned())
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        ("supportsConfigurationDoneRequest", Json::from(true)),
+        ("supportsFunctionBreakpoints", Json::from(true)),
+        ("supportsTerminateRequest", Json::from(true)),
+    ])
+}
+
+// The calls at a stop, the paused one first with id 0. Lines and columns
+// count from 1, the protocol's default.
+fn stack_trace(stop: &Stop, program: &str) -> Json {
+    let frames: Vec<Json> = stop
+        .places
+        .iter()
+        .enumerate()
+        .map(|(i, place)| {
+            let mut frame = vec![
+                ("id", Json::from(i as u32)),
+                ("name", Json::from(call(&place.frame))),
+                ("line", Json::from(place.span.line)),
+                ("column", Json::from(place.span.col)),
+            ];
+            if place.in_script {
+                let source = Json::object(vec![("path", Json::from(program))]);
+                frame.push(("source", source));
+            }
+            Json::object(frame)
+        })
+        .collect();
+    Json::object(vec![
+        ("totalFrames", Json::from(frames.len() as u32)),
+        ("stackFrames", Json::Array(frames)),
+    ])
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -0,0 +1,380 @@
+//! `willscript debug`, which runs a program in the tree-walker and stops it
+//! before statements: at breakpoints, on entering a function with one, and
+//! after steps. Wherever it stops, a frontend shows the paused call and says
+//! what to do next. The command line one is here, the Debug Adapter Protocol
+//! one is in dap.rs.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    ast::{ExprAST, Frame, Local, ProgramAST, Value},
+    lexer::{LexingMachine, Span},
+    parser::ParsingMachine,
+    resolver::Resolver,
+};
+
+/// Why the program stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Entry,
+    Breakpoint,
+    FunctionBreakpoint,
+    Step,
+}
+
+/// What a frontend wants done at a stop.
+pub enum Command {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+    /// Evaluates an expression in the paused call, then asks again.
+    Evaluate(String),
+    Quit,
+}
+
+/// Breakpoints on lines of the debugged file, and on entering functions.
+#[derive(Default)]
+pub struct Breakpoints {
+    pub lines: HashSet<u32>,
+    pub functions: HashSet<String>,
+}
+
+/// A call on the stack at a stop, and the statement it's at.
+pub struct Place {
+    pub frame: Frame,
+    pub span: Span,
+    /// False in a file the debugged one imports, whose lines aren't the
+    /// debugged file's.
+    pub in_script: bool,
+}
+
+/// Where the program stopped.
+pub struct Stop {
+    pub reason: Reason,
+    /// The paused call first, main last.
+    pub places: Vec<Place>,
+    /// The locals of the paused call that are bound, in slot order.
+    pub locals: Vec<(String, Value)>,
+}
+
+pub trait Frontend {
+    /// Shows where the program stopped.
+    fn stopped(&mut self, stop: &Stop);
+    /// Asks what to do, until it's something other than setting breakpoints.
+    fn command(&mut self, stop: &Stop, breakpoints: &mut Breakpoints) -> Command;
+    /// Shows the value of the last `Command::Evaluate`, or why it failed.
+    fn evaluated(&mut self, result: Result<String, String>);
+    /// Shows what the program printed.
+    fn print(&mut self, text: &str);
+    /// Reads a line of the program's `input`, or says why it can't.
+    fn input(&mut self) -> Result<String, String>;
+}
+
+enum Mode {
+    Run,
+    Entry,
+    StepIn,
+    // stop once no more than this many calls deep
+    StepOver(usize),
+    // stop once fewer than this many calls deep
+    StepOut(usize),
+    Quit,
+}
+
+pub struct Debugger {
+    breakpoints: Breakpoints,
+    frontend: Box<dyn Frontend>,
+    resolver: Resolver,
+    mode: Mode,
+    // a call with a function breakpoint started, so its first statement stops
+    entered: bool,
+    // no stops while evaluating for the frontend
+    evaluating: bool,
+    // the names of each call's locals by slot, the innermost last
+    names: Vec<Vec<String>>,
+}
+impl Debugger {
+    pub fn new(
+        program: &ProgramAST,
+        breakpoints: Breakpoints,
+        frontend: Box<dyn Frontend>,
+        stop_on_entry: bool,
+    ) -> Self {
+        Debugger {
+            breakpoints,
+            frontend,
+            resolver: Resolver::new(program),
+            mode: if stop_on_entry {
+                Mode::Entry
+            } else {
+                Mode::Run
+            },
+            entered: false,
+            evaluating: false,
+            names: vec![],
+        }
+    }
+    pub fn enter(&mut self, function: &str, locals: &[String]) {
+        self.names.push(locals.to_vec());
+        if !self.evaluating && self.breakpoints.functions.contains(function) {
+            self.entered = true;
+        }
+    }
+    pub fn leave(&mut self) {
+        self.names.pop();
+    }
+    pub fn quitting(&self) -> bool {
+        matches!(self.mode, Mode::Quit)
+    }
+    pub fn set_evaluating(&mut self, evaluating: bool) {
+        self.evaluating = evaluating;
+    }
+    /// Whether to stop before the statement at `span`, with `frames` being
+    /// run.
+    pub fn should_stop(&mut self, span: Span, frames: &[Frame]) -> Option<Reason> {
+        if self.evaluating {
+            return None;
+        }
+        // the return 0 the interpreter adds isn't anywhere to stop
+        if span == Span::default() {
+            self.entered = false;
+            return None;
+        }
+        if std::mem::take(&mut self.entered) {
+            return Some(Reason::FunctionBreakpoint);
+        }
+        let depth = frames.len();
+        match self.mode {
+            Mode::Entry => return Some(Reason::Entry),
+            Mode::StepIn => return Some(Reason::Step),
+            Mode::StepOver(d) if depth <= d => return Some(Reason::Step),
+            Mode::StepOut(d) if depth < d => return Some(Reason::Step),
+            Mode::Quit => return None,
+            _ => (),
+        }
+        let in_script = places(frames, span).first().is_none_or(|p| p.in_script);
+        (in_script && self.breakpoints.lines.contains(&span.line)).then_some(Reason::Breakpoint)
+    }
+    pub fn stop(&self, reason: Reason, span: Span, frames: &[Frame], locals: &[Local]) -> Stop {
+        let names = self.names.last().map_or(&[][..], |n| n.as_slice());
+        let locals = names
+            .iter()
+            .zip(locals)
+            .filter_map(|(name, value)| Some((name.clone(), value.get()?)))
+            .collect();
+        Stop {
+            reason,
+            places: places(frames, span),
+            locals,
+        }
+    }
+    pub fn stopped(&mut self, stop: &Stop) {
+        self.frontend.stopped(stop);
+    }
+    pub fn command(&mut self, stop: &Stop) -> Command {
+        self.frontend.command(stop, &mut self.breakpoints)
+    }
+    pub fn evaluated(&mut self, result: Result<String, String>) {
+        self.frontend.evaluated(result);
+    }
+    pub fn print(&mut self, text: &str) {
+        self.frontend.print(text);
+    }
+    pub fn input(&mut self) -> Result<String, String> {
+        self.frontend.input()
+    }
+    /// Carries on after a stop `depth` calls deep.
+    pub fn resume(&mut self, command: Command, depth: usize) {
+        self.mode = match command {
+            Command::Continue => Mode::Run,
+            Command::StepIn => Mode::StepIn,
+            Command::StepOver => Mode::StepOver(depth),
+            Command::StepOut => Mode::StepOut(depth),
+            Command::Quit => Mode::Quit,
+            Command::Evaluate(_) => unreachable!(),
+        };
+    }
+    /// Parses and resolves an expression to evaluate in the paused call,
+    /// giving back the call's locals with any the expression binds.
+    pub fn compile(&self, text: &str) -> Result<(ExprAST, Vec<String>), String> {
+        let mut chars = text.chars();
+        let Some(first) = chars.next() else {
+            return Err("Nothing to evaluate.".to_owned());
+        };
+        let tokens = LexingMachine::new(first, chars)
+            .activate_lexing()
+            .map_err(|(span, e)| format!("{}: {}", span, e))?;
+        let mut tokens = tokens.into_iter().peekable();
+        let first = tokens.next().expect("Lexing always ends with EndOfFile");
+        let mut expr = ParsingMachine::new(first, tokens).parse_expression()?;
+        let names = self.names.last().map_or(&[][..], |n| n.as_slice());
+        let names = self.resolver.resolve_expression(&mut expr, names)?;
+        Ok((expr, names))
+    }
+}
+
+// Where each call is, the innermost first. A closure is in the file of the
+// function it was written in, and imported functions are named `module.name`.
+fn places(frames: &[Frame], span: Span) -> Vec<Place> {
+    let mut in_script = true;
+    let mut places: Vec<Place> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            if *frame.name != *"<closure>" {
+                in_script = !frame.name.contains('.');
+            }
+            let span = frames.get(i + 1).map_or(span, |next| next.call_site);
+            Place {
+                frame: frame.clone(),
+                span,
+                in_script,
+            }
+        })
+        .collect();
+    places.reverse();
+    places
+}
+
+/// A call as the debugger shows it, like `add(1, 2)`.
+pub fn call(frame: &Frame) -> String {
+    let args: Vec<String> = frame.args.iter().map(|a| a.summary()).collect();
+    format!("{}({})", frame.name, args.join(", "))
+}
+
+const HELP: &str = "break LINE|FUNCTION  stop at a line or on entering a function (b)
+clear LINE|FUNCTION  remove a breakpoint
+continue             run to the next breakpoint (c)
+step                 run one statement, going into calls (s)
+next                 run one statement, going over calls (n)
+finish               run until the call returns (out)
+backtrace            show the calls being run (bt)
+locals               show the paused call's locals
+print EXPR           evaluate an expression in the paused call (p)
+quit                 stop the program (q)";
+
+/// The command line frontend, reading commands a line at a time.
+pub struct Cli {
+    source: Vec<String>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+impl Cli {
+    pub fn new(source: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Cli {
+            source: source.lines().map(|l| l.to_owned()).collect(),
+            input,
+            output,
+        }
+    }
+    fn say(&mut self, text: &str) {
+        // the debugger can't do anything about a closed output
+        writeln!(self.output, "{}", text).ok();
+    }
+    fn set_breakpoint(&mut self, arg: &str, breakpoints: &mut Breakpoints, set: bool) {
+        let (changed, what) = match arg.parse::<u32>() {
+            Ok(line) if set => (breakpoints.lines.insert(line), format!("line {}", line)),
+            Ok(line) => (breakpoints.lines.remove(&line), format!("line {}", line)),
+            Err(_) if set => (
+                breakpoints.functions.insert(arg.to_owned()),
+                format!("function {}", arg),
+            ),
+            Err(_) => (
+                breakpoints.functions.remove(arg),
+                format!("function {}", arg),
+            ),
+        };
+        match (set, changed) {
+            (true, _) => self.say(&format!("Breakpoint at {}", what)),
+            (false, true) => self.say(&format!("Removed the breakpoint at {}", what)),
+            (false, false) => self.say(&format!("No breakpoint at {}", what)),
+        }
+    }
+}
+impl Frontend for Cli {
+    fn stopped(&mut self, stop: &Stop) {
+        let place = &stop.places[0];
+        let reason = match stop.reason {
+            Reason::Entry => "Paused",
+            Reason::Breakpoint | Reason::FunctionBreakpoint => "Breakpoint",
+            Reason::Step => "Stepped",
+        };
+        self.say(&format!(
+            "{} at {} in {}",
+            reason,
+            place.span,
+            call(&place.frame)
+        ));
+        let line = place.span.line as usize;
+        if place.in_script
+            && let Some(text) = self.source.get(line - 1)
+        {
+            let text = format!("{}\t{}", line, text);
+            self.say(&text);
+        }
+    }
+    fn command(&mut self, stop: &Stop, breakpoints: &mut Breakpoints) -> Command {
+        loop {
+            write!(self.output, "(debug) ").ok();
+            self.output.flush().ok();
+            let mut line = String::new();
+            // the end of the input quits, like a closed terminal would
+            if self.input.read_line(&mut line).unwrap_or(0) == 0 {
+                return Command::Quit;
+            }
+            let line = line.trim();
+            let (word, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let arg = arg.trim();
+            match word {
+                "" => (),
+                "c" | "continue" => return Command::Continue,
+                "s" | "step" => return Command::StepIn,
+                "n" | "next" => return Command::StepOver,
+                "out" | "finish" => return Command::StepOut,
+                "q" | "quit" => return Command::Quit,
+                "p" | "print" if !arg.is_empty() => return Command::Evaluate(arg.to_owned()),
+                "b" | "break" if !arg.is_empty() => self.set_breakpoint(arg, breakpoints, true),
+                "clear" if !arg.is_empty() => self.set_breakpoint(arg, breakpoints, false),
+                "bt" | "backtrace" => {
+                    for (i, place) in stop.places.iter().enumerate() {
+                        let text = format!("#{} {} {}", i, place.span, place.frame);
+                        self.say(&text);
+                    }
+                }
+                "locals" => {
+                    if stop.locals.is_empty() {
+                        self.say("No locals");
+                    }
+                    for (name, value) in &stop.locals {
+                        let text = format!("{} = {}", name, value.repr());
+                        self.say(&text);
+                    }
+                }
+                "h" | "help" => self.say(HELP),
+                _ => self.say(&format!("Unknown command {}, try help", line)),
+            }
+        }
+    }
+    fn evaluated(&mut self, result: Result<String, String>) {
+        match result {
+            Ok(value) => self.say(&value),
+            Err(e) => self.say(&format!("Error: {}", e)),
+        }
+    }
+    fn print(&mut self, text: &str) {
+        write!(self.output, "{}", text).ok();
+    }
+    // the program's input comes from the same lines as the commands
+    fn input(&mut self) -> Result<String, String> {
+        self.output.flush().ok();
+        let mut line = String::new();
+        self.input
+            .read_line(&mut line)
+            .map_err(|e| format!("Could not read input: {}", e))?;
+        Ok(line)
+    }
+}
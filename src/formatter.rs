@@ -0,0 +1,615 @@
+//! Prints a file back as canonical WillScript: tabs for indentation, a space
+//! around binary operators and after commas, and a blank line between
+//! top-level items. `#` comments are kept, and so is a single blank line
+//! between statements where the source had one or more.
+
+use std::mem;
+
+use crate::{
+    ast::{
+        Arg, Assignment, BuiltIn, EnumAST, ExprAST, FunctionAST, GlobalAST, IfBlock, ImportAST,
+        MatchArm, MatchBlock, Pattern, ProgramAST, PrototypeAST, Statement, StructAST, TryBlock,
+        Value,
+    },
+    lexer::{LexingMachine, Span},
+    parser::{Item, Mark, ParsingMachine, get_priority},
+};
+
+/// Formats the source of one file, or gives back why it doesn't parse.
+/// Formatting the result again gives the same text.
+pub fn format_source(source: &str) -> Result<String, String> {
+    let mut chars = source.chars();
+    let Some(first) = chars.next() else {
+        return Ok(String::new());
+    };
+    let mut lexer = LexingMachine::new(first, chars);
+    let tokens = lexer
+        .activate_lexing()
+        .map_err(|(span, e)| format!("{}: {}", span, e))?;
+    let mut tokens = tokens.into_iter().peekable();
+    let first_token = tokens.next().expect("Lexing always ends with EndOfFile");
+    let mut parser = ParsingMachine::new(first_token, tokens);
+    let program = parser.activate_parsing_machine()?;
+    let mut formatter = Formatter {
+        out: String::new(),
+        lines: source.lines().collect(),
+        marks: parser.take_marks(),
+        next_mark: 0,
+        comments: lexer.take_comments(),
+        next_comment: 0,
+        indent: 0,
+        owe_blank: false,
+        condition: false,
+    };
+    formatter.program(&program);
+    Ok(formatter.out)
+}
+
+struct Formatter<'a> {
+    out: String,
+    lines: Vec<&'a str>,
+    marks: Vec<Mark>,
+    next_mark: usize,
+    comments: Vec<(Span, String)>,
+    next_comment: usize,
+    indent: usize,
+    // a blank line goes before the next line, whatever the source had
+    owe_blank: bool,
+    // like the parser's struct_literal_allowed, but the other way around
+    condition: bool,
+}
+impl Formatter<'_> {
+    fn program(&mut self, program: &ProgramAST) {
+        let mut functions = program.functions.iter();
+        let mut structs = program.structs.iter();
+        let mut enums = program.enums.iter().skip(EnumAST::prelude().len());
+        let mut globals = program.globals.iter();
+        let mut imports = program.imports.iter();
+        let mut one_liner_before = false;
+        while let Some(Mark::Item(item, span)) = self.marks.get(self.next_mark).copied() {
+            self.next_mark += 1;
+            // imports, consts and globals can sit in a group of their own
+            let one_liner = matches!(item, Item::Global { .. } | Item::Import);
+            self.owe_blank = !(one_liner && one_liner_before);
+            one_liner_before = one_liner;
+            self.line_start(span);
+            match item {
+                Item::Function { exported } => {
+                    self.export(exported);
+                    self.function(functions.next().unwrap());
+                }
+                Item::Struct => self.struct_item(structs.next().unwrap(), span),
+                Item::Enum => self.enum_item(enums.next().unwrap(), span),
+                Item::Global { exported } => {
+                    self.export(exported);
+                    self.global(globals.next().unwrap());
+                }
+                Item::Import => self.import(imports.next().unwrap()),
+            }
+            self.out.push('\n');
+        }
+        self.comments_before(None);
+    }
+    fn export(&mut self, exported: bool) {
+        if exported {
+            self.out.push_str("export ");
+        }
+    }
+    fn function(&mut self, function: &FunctionAST) {
+        self.out.push_str("fun ");
+        self.out.push_str(&function.proto.name);
+        self.params(&function.proto);
+        self.out.push(' ');
+        self.block(&function.body);
+    }
+    fn params(&mut self, proto: &PrototypeAST) {
+        self.out.push('(');
+        for (i, param) in proto.args.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(", ");
+            }
+            self.out.push_str(&param.name);
+            if let Some(default) = &param.default {
+                self.out.push_str(" = ");
+                self.nested(default);
+            }
+        }
+        if let Some(rest) = &proto.rest {
+            if !proto.args.is_empty() {
+                self.out.push_str(", ");
+            }
+            self.out.push_str("...");
+            self.out.push_str(rest);
+        }
+        self.out.push(')');
+    }
+    fn global(&mut self, global: &GlobalAST) {
+        self.out
+            .push_str(if global.is_const { "const " } else { "global " });
+        self.out.push_str(&global.name);
+        self.out.push_str(" = ");
+        self.expr(&global.init);
+        self.out.push(';');
+    }
+    fn import(&mut self, import: &ImportAST) {
+        self.out
+            .push_str(&format!("import \"{}\" as {};", import.path, import.alias));
+    }
+    fn struct_item(&mut self, struct_ast: &StructAST, span: Span) {
+        self.out.push_str("struct ");
+        self.out.push_str(&struct_ast.name);
+        let fields: Vec<String> = struct_ast.fields.iter().map(|f| f.to_string()).collect();
+        self.members(&fields, span);
+    }
+    fn enum_item(&mut self, enum_ast: &EnumAST, span: Span) {
+        self.out.push_str("enum ");
+        self.out.push_str(&enum_ast.name);
+        let variants: Vec<String> = enum_ast
+            .variants
+            .iter()
+            .map(|v| match v.fields.is_empty() {
+                true => v.name.to_string(),
+                false => format!("{}({})", v.name, v.fields.join(", ")),
+            })
+            .collect();
+        self.members(&variants, span);
+    }
+    // The fields of a struct or variants of an enum, on the line of its name if
+    // they were written that way, otherwise one to a line.
+    fn members(&mut self, members: &[String], span: Span) {
+        let marks = &self.marks[self.next_mark..=self.next_mark + members.len()];
+        let Mark::End(end) = marks[members.len()] else {
+            lost(marks[members.len()]);
+        };
+        let one_line = marks.iter().all(|m| match m {
+            Mark::Start(x) | Mark::End(x) => x.line == span.line,
+            _ => false,
+        }) && !self.comment_before(end);
+        if one_line {
+            self.next_mark += members.len() + 1;
+            match members.is_empty() {
+                true => self.out.push_str(" {}"),
+                false => self.out.push_str(&format!(" {{ {} }}", members.join(", "))),
+            }
+            return;
+        }
+        self.out.push_str(" {\n");
+        self.indent += 1;
+        for member in members {
+            let span = self.start();
+            self.line_start(span);
+            self.out.push_str(member);
+            self.out.push_str(",\n");
+        }
+        self.close();
+    }
+
+    // Where the next statement, arm or member starts.
+    fn start(&mut self) -> Span {
+        match self.marks[self.next_mark] {
+            Mark::Start(span) => {
+                self.next_mark += 1;
+                span
+            }
+            x => lost(x),
+        }
+    }
+    fn end(&mut self) -> Span {
+        match self.marks[self.next_mark] {
+            Mark::End(span) => {
+                self.next_mark += 1;
+                span
+            }
+            x => lost(x),
+        }
+    }
+    fn comment_before(&self, span: Span) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .is_some_and(|(at, _)| *at < span)
+    }
+    // Opens the line for something at `span`, after the comments before it.
+    fn line_start(&mut self, span: Span) {
+        self.comments_before(Some(span));
+        self.blank_line(span.line);
+        self.push_indent();
+    }
+    fn blank_line(&mut self, line: u32) {
+        let owed = mem::take(&mut self.owe_blank);
+        let blank_before = line >= 2
+            && self
+                .lines
+                .get(line as usize - 2)
+                .is_some_and(|l| l.trim().is_empty());
+        if (owed || blank_before)
+            && !self.out.is_empty()
+            && !self.out.ends_with("{\n")
+            && !self.out.ends_with("\n\n")
+        {
+            self.out.push('\n');
+        }
+    }
+    fn push_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push('\t');
+        }
+    }
+    // Puts out the comments before `span`, or all that are left. One after
+    // code on its line stays at the end of the line printed last.
+    fn comments_before(&mut self, span: Option<Span>) {
+        while let Some((at, text)) = self.comments.get(self.next_comment) {
+            if span.is_some_and(|span| *at >= span) {
+                break;
+            }
+            self.next_comment += 1;
+            let (at, text) = (*at, format!("#{}", text));
+            let line = self.lines[at.line as usize - 1];
+            let trailing = line
+                .chars()
+                .take(at.col as usize - 1)
+                .any(|c| !c.is_whitespace());
+            if trailing && self.out.ends_with('\n') {
+                self.out.pop();
+                self.out.push(' ');
+            } else {
+                self.blank_line(at.line);
+                self.push_indent();
+            }
+            self.out.push_str(&text);
+            self.out.push('\n');
+        }
+    }
+
+    fn block(&mut self, body: &[Statement]) {
+        if body.is_empty() && !self.comment_before(self.peek_end()) {
+            self.end();
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.indent += 1;
+        for statement in body {
+            self.statement(statement);
+        }
+        self.close();
+    }
+    fn peek_end(&self) -> Span {
+        match self.marks[self.next_mark] {
+            Mark::End(span) => span,
+            x => lost(x),
+        }
+    }
+    // The `}` one level out, after the comments inside it.
+    fn close(&mut self) {
+        let span = self.end();
+        self.comments_before(Some(span));
+        self.indent -= 1;
+        self.push_indent();
+        self.out.push('}');
+    }
+    fn statement(&mut self, statement: &Statement) {
+        let span = self.start();
+        self.line_start(span);
+        match statement {
+            Statement::Assign(assignment) => self.assignment(assignment),
+            Statement::If(if_block) => self.if_statement(if_block),
+            Statement::While(while_block) => {
+                self.out.push_str("while ");
+                self.condition(&while_block.conditional);
+                self.out.push(' ');
+                self.block(&while_block.body);
+            }
+            Statement::Call(expr) => {
+                self.expr(expr);
+                self.out.push(';');
+            }
+            Statement::Built(built) => self.builtin(built),
+            Statement::Match(match_block) => self.match_statement(match_block),
+            Statement::Try(try_block) => self.try_statement(try_block),
+            Statement::Throw(expr, _) => {
+                self.out.push_str("throw ");
+                self.expr(expr);
+                self.out.push(';');
+            }
+        }
+        self.out.push('\n');
+    }
+    fn assignment(&mut self, assignment: &Assignment) {
+        if assignment.is_declaration {
+            self.out.push_str(if assignment.is_mutable {
+                "var "
+            } else {
+                "let "
+            });
+        }
+        self.expr(&assignment.variable);
+        match assignment.compound {
+            Some(op) => self.out.push_str(&format!(" {}= ", op)),
+            None => self.out.push_str(" = "),
+        }
+        self.expr(&assignment.right_hand);
+        self.out.push(';');
+    }
+    fn if_statement(&mut self, if_block: &IfBlock) {
+        self.out.push_str("if ");
+        self.condition(&if_block.conditional);
+        self.out.push(' ');
+        self.block(&if_block.body);
+        let Some(Mark::Else { chained }) = self.marks.get(self.next_mark).copied() else {
+            return;
+        };
+        self.next_mark += 1;
+        self.out.push_str(" else ");
+        match (chained, if_block.else_body.as_slice()) {
+            (true, [Statement::If(chained)]) => self.if_statement(chained),
+            (true, _) => unreachable!("else if is one if statement"),
+            (false, body) => self.block(body),
+        }
+    }
+    fn builtin(&mut self, built: &BuiltIn) {
+        let (keyword, expr) = match built {
+            BuiltIn::Print(x, _) => ("print ", x),
+            BuiltIn::Return(x, _) => ("return ", x),
+            BuiltIn::Input(x) => ("input ", x),
+            BuiltIn::Drop(x) => ("drop ", x),
+        };
+        self.out.push_str(keyword);
+        match expr {
+            // `return a, b;` rather than `return (a, b);`
+            ExprAST::Tuple(items) if matches!(built, BuiltIn::Return(..)) && items.len() > 1 => {
+                self.list(items, |f, x| f.expr(x));
+            }
+            x => self.expr(x),
+        }
+        self.out.push(';');
+    }
+    fn try_statement(&mut self, try_block: &TryBlock) {
+        self.out.push_str("try ");
+        self.block(&try_block.body);
+        if let Some((name, _, body)) = &try_block.catch {
+            self.out.push_str(" catch ");
+            self.out.push_str(name);
+            self.out.push(' ');
+            self.block(body);
+        }
+        if let Some(body) = &try_block.finally {
+            self.out.push_str(" finally ");
+            self.block(body);
+        }
+    }
+    fn match_statement(&mut self, match_block: &MatchBlock<Vec<Statement>>) {
+        self.match_arms(match_block, |f, body| {
+            f.block(body);
+            f.out.push('\n');
+        });
+    }
+    fn match_expr(&mut self, match_block: &MatchBlock<ExprAST>) {
+        self.match_arms(match_block, |f, body| {
+            f.nested(body);
+            f.out.push_str(",\n");
+        });
+    }
+    fn match_arms<T>(&mut self, match_block: &MatchBlock<T>, body: fn(&mut Self, &T)) {
+        self.out.push_str("match ");
+        self.condition(&match_block.scrutinee);
+        if match_block.arms.is_empty() && !self.comment_before(self.peek_end()) {
+            self.end();
+            self.out.push_str(" {}");
+            return;
+        }
+        self.out.push_str(" {\n");
+        self.indent += 1;
+        for arm in &match_block.arms {
+            let span = self.start();
+            self.line_start(span);
+            self.arm_head(arm);
+            body(self, &arm.body);
+        }
+        self.close();
+    }
+    fn arm_head<T>(&mut self, arm: &MatchArm<T>) {
+        self.pattern(&arm.pattern);
+        if let Some(guard) = &arm.guard {
+            self.out.push_str(" if ");
+            self.nested(guard);
+        }
+        self.out.push_str(" => ");
+    }
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => self.out.push('_'),
+            Pattern::Literal(x) => self.value(x),
+            Pattern::Ident(name, _) => self.out.push_str(name),
+            Pattern::Variant(name, subpatterns) => {
+                self.out.push_str(name);
+                self.out.push('(');
+                self.list(subpatterns, Self::pattern);
+                self.out.push(')');
+            }
+        }
+    }
+
+    // An expression before a block's `{`, where a struct literal needs parens.
+    fn condition(&mut self, expr: &ExprAST) {
+        let outer = mem::replace(&mut self.condition, true);
+        self.expr(expr);
+        self.condition = outer;
+    }
+    // An expression inside brackets, where it doesn't.
+    fn nested(&mut self, expr: &ExprAST) {
+        let outer = mem::replace(&mut self.condition, false);
+        self.expr(expr);
+        self.condition = outer;
+    }
+    fn list<T>(&mut self, items: &[T], item: fn(&mut Self, &T)) {
+        for (i, x) in items.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(", ");
+            }
+            item(self, x);
+        }
+    }
+    fn args(&mut self, args: &[Arg]) {
+        self.out.push('(');
+        self.list(args, |f, arg| {
+            if let Some(name) = &arg.name {
+                f.out.push_str(name);
+                f.out.push_str(": ");
+            }
+            f.nested(&arg.value);
+        });
+        self.out.push(')');
+    }
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Int(x) => {
+                let Mark::Int { word } = self.marks[self.next_mark] else {
+                    lost(self.marks[self.next_mark]);
+                };
+                self.next_mark += 1;
+                match (word, x) {
+                    (true, 0) => self.out.push_str("false"),
+                    (true, _) => self.out.push_str("true"),
+                    (false, x) => self.out.push_str(&x.to_string()),
+                }
+            }
+            // strings are kept as written, backslashes and all
+            Value::Str(x) => self.out.push_str(&format!("\"{}\"", x)),
+            x => unreachable!("{} can't be written as a literal", x),
+        }
+    }
+    // The thing a postfix `(args)`, `[i]`, `.field` or `?` applies to.
+    fn postfix(&mut self, expr: &ExprAST) {
+        match expr {
+            ExprAST::BinOp(..) => {
+                self.out.push('(');
+                self.nested(expr);
+                self.out.push(')');
+            }
+            x => self.expr(x),
+        }
+    }
+    // An operand of a binary operator, in parens if it binds less tightly. The
+    // operators are left associative, so on the right even the same priority
+    // needs them.
+    fn operand(&mut self, expr: &ExprAST, priority: u32, right: bool) {
+        let parens = match expr {
+            ExprAST::BinOp(op, ..) => {
+                get_priority(op) < priority || (right && get_priority(op) == priority)
+            }
+            _ => false,
+        };
+        if parens {
+            self.out.push('(');
+            self.nested(expr);
+            self.out.push(')');
+        } else {
+            self.expr(expr);
+        }
+    }
+    fn expr(&mut self, expr: &ExprAST) {
+        match expr {
+            ExprAST::Variable(name, ..) => self.out.push_str(name),
+            ExprAST::Val(x) => self.value(x),
+            ExprAST::BinOp(op, lhs, rhs, _) => {
+                let priority = get_priority(op);
+                self.operand(lhs, priority, false);
+                self.out.push_str(&format!(" {} ", op));
+                self.operand(rhs, priority, true);
+            }
+            ExprAST::Call(name, args, ..) => {
+                self.out.push_str(name);
+                self.args(args);
+            }
+            ExprAST::CallExpr(callee, args, _) => {
+                self.postfix(callee);
+                self.args(args);
+            }
+            ExprAST::Closure(function) => {
+                self.out.push_str("fun");
+                self.params(&function.proto);
+                self.out.push(' ');
+                self.block(&function.body);
+            }
+            ExprAST::List(items) => {
+                self.out.push('[');
+                self.list(items, Self::nested);
+                self.out.push(']');
+            }
+            ExprAST::Tuple(items) => {
+                self.out.push('(');
+                self.list(items, Self::nested);
+                if items.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            ExprAST::Map(entries, _) => {
+                self.out.push('{');
+                self.list(entries, |f, (key, value)| {
+                    f.nested(key);
+                    f.out.push_str(": ");
+                    f.nested(value);
+                });
+                self.out.push('}');
+            }
+            ExprAST::Index(list, index, _) => {
+                self.postfix(list);
+                self.out.push('[');
+                self.nested(index);
+                self.out.push(']');
+            }
+            ExprAST::StructInit(..) if self.condition => {
+                self.out.push('(');
+                self.nested(expr);
+                self.out.push(')');
+            }
+            ExprAST::StructInit(name, fields, _) => {
+                self.out.push_str(name);
+                if fields.is_empty() {
+                    self.out.push_str(" {}");
+                    return;
+                }
+                self.out.push_str(" { ");
+                self.list(fields, |f, (field, value)| {
+                    f.out.push_str(field);
+                    // `Point { x }` is short for `Point { x: x }`
+                    if !matches!(value, ExprAST::Variable(name, ..) if name == field) {
+                        f.out.push_str(": ");
+                        f.nested(value);
+                    }
+                });
+                self.out.push_str(" }");
+            }
+            ExprAST::Field(object, field, _) => {
+                self.postfix(object);
+                self.out.push('.');
+                self.out.push_str(field);
+            }
+            ExprAST::Match(match_block) => self.match_expr(match_block),
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                self.out.push_str("if ");
+                self.condition(conditional);
+                self.out.push_str(" { ");
+                self.nested(then_expr);
+                self.out.push_str(" } else ");
+                match &**else_expr {
+                    x @ ExprAST::If(..) => self.expr(x),
+                    x => {
+                        self.out.push_str("{ ");
+                        self.nested(x);
+                        self.out.push_str(" }");
+                    }
+                }
+            }
+            ExprAST::Propagate(x, _) => {
+                self.postfix(x);
+                self.out.push('?');
+            }
+        }
+    }
+}
+
+fn lost(mark: Mark) -> ! {
+    panic!("The formatter lost its place in the source at {:?}", mark)
+}
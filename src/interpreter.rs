@@ -1,290 +1,1160 @@
-use std::{collections::HashMap, io};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io, mem,
+    rc::Rc,
+};
 
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
 use crate::{
-    ast::{Assignment, BuiltIn, ExprAST, FunctionAST, IfBlock, Statement, Value, WhileBlock},
-    lexer::Operator,
+    ast::{
+        Arg, Assignment, BuiltIn, Closure, ErrorValue, ExprAST, Frame, FunctionAST, FunctionValue,
+        GlobalAST, IfBlock, Local, MatchArm, MatchBlock, Name, OrderedMap, Pattern, ProgramAST,
+        Resolution, Statement, StructAST, StructValue, TryBlock, Value, VariantValue, WhileBlock,
+    },
+    builtins::{call_builtin, check_arity, held_value, map_key, type_error},
+    checker::constant_order,
+    debugger::{Command, Debugger},
+    lexer::{Operator, Span},
 };
 
+pub const HIGHER_ORDER: [&str; 3] = ["map", "filter", "reduce"];
+// deeper script recursion than this is a RecursionError, which main.rs gives
+// enough stack to reach
+pub const MAX_DEPTH: usize = 10_000;
+
+// What cuts evaluation short. A thrown error unwinds until a try catches it,
+// or run_main hands it to whoever is running the program. A `?` on an Err or
+// None unwinds to the function it's in, which returns that value. Quitting
+// the debugger unwinds everything, ending the program where it is.
+enum Unwind {
+    Throw(Rc<ErrorValue>),
+    Return(Value),
+    Stop,
+}
+impl From<Rc<ErrorValue>> for Unwind {
+    fn from(error: Rc<ErrorValue>) -> Self {
+        Unwind::Throw(error)
+    }
+}
+
+type Outcome<T> = Result<T, Unwind>;
+
+// Names bound for a match arm or catch block, with what they hid.
+type Shadowed = Vec<(usize, Local)>;
+
+// A frame's locals, by the slots the resolver gave them.
+type Locals = Vec<Local>;
+
+fn fail<T>(kind: &str, span: Span, message: String) -> Outcome<T> {
+    Err(ErrorValue::new(kind, message).at(span).into())
+}
+
+// The evaluated arguments of one call, and where the call was made.
+struct CallArgs {
+    positional: Vec<Value>,
+    named: Vec<(String, Value)>,
+    span: Span,
+}
+impl CallArgs {
+    fn positional(positional: Vec<Value>, span: Span) -> Self {
+        CallArgs {
+            positional,
+            named: vec![],
+            span,
+        }
+    }
+    // for builtins and variants, which don't have parameter names
+    fn into_positional(self, name: &str) -> Outcome<Vec<Value>> {
+        if let Some((arg, _)) = self.named.first() {
+            return fail(
+                "ArgumentError",
+                self.span,
+                format!("{} doesn't take named arguments, got {}", name, arg),
+            );
+        }
+        Ok(self.positional)
+    }
+}
+
 pub struct InterpretingMastermind {
-    funcmap: HashMap<String, FunctionAST>,
+    // shared, so a call doesn't copy the body it runs
+    functions: Vec<Rc<FunctionAST>>,
+    // for function values, which name the function
+    function_index: HashMap<Name, usize>,
+    structmap: HashMap<Name, StructAST>,
+    // variant name -> (enum name, payload length)
+    variantmap: HashMap<Name, (Name, usize)>,
+    // constants and globals by index, filled in by run_main from global_inits
+    globals: Vec<Option<Value>>,
+    constants: HashSet<usize>,
+    global_inits: Vec<(usize, GlobalAST)>,
+    // the calls being run, for stack traces
+    frames: Vec<Frame>,
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
+    debugger: Option<Debugger>,
 }
 impl InterpretingMastermind {
-    pub fn new(mut funcvec: Vec<FunctionAST>) -> Self {
-        funcvec = funcvec
+    /// Takes a resolved program.
+    pub fn new(program: ProgramAST) -> Self {
+        let global_index: HashMap<&str, usize> = program
+            .globals
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (g.name.as_str(), i))
+            .collect();
+        let mut global_inits: Vec<(usize, GlobalAST)> = constant_order(&program)
+            .expect("Constant cycles are caught by the checker.")
+            .into_iter()
+            .map(|g| (global_index[g.name.as_str()], g.clone()))
+            .collect();
+        global_inits.extend(
+            program
+                .globals
+                .iter()
+                .enumerate()
+                .filter(|(_, g)| !g.is_const)
+                .map(|(i, g)| (i, g.clone())),
+        );
+        let constants = program
+            .globals
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| g.is_const)
+            .map(|(i, _)| i)
+            .collect();
+        let globals = vec![None; program.globals.len()];
+        let functions: Vec<Rc<FunctionAST>> = program
+            .functions
             .into_iter()
             .map(|mut x| {
-                x.body
-                    .push(Statement::Built(BuiltIn::Return(ExprAST::Val(Value::Int(
-                        0,
-                    )))));
-                x
+                x.body.push(Statement::Built(BuiltIn::Return(
+                    ExprAST::Val(Value::Int(0)),
+                    Span::default(),
+                )));
+                Rc::new(x)
             })
             .collect();
         // that is temporary until we analyise the code and add the void type.
-        let mut funcmap = HashMap::with_capacity(funcvec.len());
-        for func in funcvec {
-            funcmap.insert(func.proto.name.clone(), func);
+        let function_index = functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.proto.name.clone(), i))
+            .collect();
+        let mut structmap = HashMap::with_capacity(program.structs.len());
+        for struct_ast in program.structs {
+            structmap.insert(struct_ast.name.clone(), struct_ast);
+        }
+        let mut variantmap = HashMap::new();
+        for enum_ast in program.enums {
+            for variant in enum_ast.variants {
+                variantmap.insert(variant.name, (enum_ast.name.clone(), variant.fields.len()));
+            }
+        }
+        InterpretingMastermind {
+            functions,
+            function_index,
+            structmap,
+            variantmap,
+            globals,
+            constants,
+            global_inits,
+            frames: Vec::new(),
+            #[cfg(feature = "jit")]
+            jit: None,
+            debugger: None,
+        }
+    }
+    /// Compiles the functions that only do integer work, which then run as
+    /// native code. Without a Cranelift backend for the machine, everything
+    /// is still interpreted.
+    #[cfg(feature = "jit")]
+    pub fn with_jit(mut self) -> Self {
+        self.jit = Jit::new(&self.functions);
+        self
+    }
+    /// Stops the program wherever the debugger says to.
+    pub fn with_debugger(mut self, debugger: Debugger) -> Self {
+        self.debugger = Some(debugger);
+        self
+    }
+    /// Runs the program, giving back the error if one was thrown and never
+    /// caught.
+    pub fn run_main(&mut self) -> Result<(), Rc<ErrorValue>> {
+        for (index, global) in std::mem::take(&mut self.global_inits) {
+            let mut locals = vec![Local::Unset; global.locals.len()];
+            // a `?` in an initializer gives the Err or None as the value
+            let value = match self.eval_expr(&global.init, &mut locals) {
+                Ok(x) | Err(Unwind::Return(x)) => x,
+                Err(Unwind::Throw(e)) => return Err(e),
+                Err(Unwind::Stop) => return Ok(()),
+            };
+            self.globals[index] = Some(value);
+        }
+        let args = CallArgs::positional(vec![], Span::default());
+        match self.run_function(self.function_index["main"], args) {
+            Err(Unwind::Throw(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+    fn run_function(&mut self, index: usize, args: CallArgs) -> Outcome<Value> {
+        #[cfg(feature = "jit")]
+        if let Some(x) = self.run_compiled(index, &args) {
+            return Ok(Value::Int(x));
+        }
+        let func = Rc::clone(&self.functions[index]);
+        self.run_body(&func, vec![], args)
+    }
+    // Runs the compiled function on ints. Compiled code gives up rather than
+    // fail, and can't print or change a global, so if it does the call is
+    // simply run again by the interpreter to raise the error properly.
+    #[cfg(feature = "jit")]
+    fn run_compiled(&self, index: usize, args: &CallArgs) -> Option<i32> {
+        let jit = self.jit.as_ref()?;
+        if !jit.is_compiled(index) || !args.named.is_empty() {
+            return None;
+        }
+        let ints: Option<Vec<i32>> = args
+            .positional
+            .iter()
+            .map(|x| match x {
+                Value::Int(x) => Some(*x),
+                _ => None,
+            })
+            .collect();
+        jit.call(index, self.frames.len(), &ints?)
+    }
+    fn run_body(
+        &mut self,
+        func: &FunctionAST,
+        mut locals: Locals,
+        args: CallArgs,
+    ) -> Outcome<Value> {
+        let named: Vec<&str> = args.named.iter().map(|(n, _)| n.as_str()).collect();
+        if let Err(e) = func.proto.check_args(args.positional.len(), &named) {
+            return fail("ArgumentError", args.span, e);
+        }
+        if self.frames.len() >= MAX_DEPTH {
+            return fail(
+                "RecursionError",
+                args.span,
+                format!(
+                    "Calling {} went over {} nested calls",
+                    func.proto.name, MAX_DEPTH
+                ),
+            );
+        }
+        // a closure's frame starts as what it captured
+        locals.resize(func.locals.len(), Local::Unset);
+        let mut positional = args.positional.into_iter();
+        let mut named = args.named;
+        for (param, &slot) in func.proto.args.iter().zip(func.param_slots.iter()) {
+            let value = match positional.next() {
+                Some(x) => x,
+                None => match named.iter().position(|(n, _)| *n == param.name) {
+                    Some(i) => named.swap_remove(i).1,
+                    None => {
+                        let default = param.default.as_ref().expect("Checked by check_args.");
+                        match self.eval_expr(default, &mut locals) {
+                            Err(Unwind::Return(x)) => return Ok(x),
+                            x => x?,
+                        }
+                    }
+                },
+            };
+            locals[slot] = Local::Set(value);
+        }
+        if func.proto.rest.is_some() {
+            let slot = *func.param_slots.last().unwrap();
+            locals[slot] = Local::Set(Value::new_list(positional.collect()));
+        }
+        let frame_args = func
+            .param_slots
+            .iter()
+            .map(|&slot| locals[slot].get().expect("Parameters are bound."))
+            .collect();
+        self.frames.push(Frame {
+            name: func.proto.name.clone(),
+            call_site: args.span,
+            args: frame_args,
+        });
+        if let Some(debugger) = &mut self.debugger {
+            debugger.enter(&func.proto.name, &func.locals);
+        }
+        let outcome = match self.run_statements(&func.body, &mut locals) {
+            Ok(Some(x)) | Err(Unwind::Return(x)) => Ok(x),
+            // only closures can fall off the end, declared functions get a return 0
+            Ok(None) => Ok(Value::Int(0)),
+            // the first function an error leaves still has the whole stack
+            // above it, so that's where the trace comes from
+            Err(Unwind::Throw(mut e)) => {
+                if e.trace.is_empty() {
+                    Rc::make_mut(&mut e).trace = self.frames.clone();
+                }
+                Err(Unwind::Throw(e))
+            }
+            Err(Unwind::Stop) => Err(Unwind::Stop),
+        };
+        self.frames.pop();
+        if let Some(debugger) = &mut self.debugger {
+            debugger.leave();
+        }
+        outcome
+    }
+    // The value of a name, if it's a bound local or an initialized global.
+    fn lookup(&self, resolution: &Resolution, locals: &Locals) -> Option<Value> {
+        resolution
+            .slot
+            .and_then(|s| locals[s].get())
+            .or_else(|| resolution.global.and_then(|g| self.globals[g].clone()))
+    }
+    fn call_value(&mut self, callee: &Value, args: CallArgs) -> Outcome<Value> {
+        let Value::Function(function) = callee else {
+            return fail(
+                "TypeError",
+                args.span,
+                format!("{} is not a function", callee),
+            );
+        };
+        match &**function {
+            FunctionValue::Named(name) => self.run_function(self.function_index[name], args),
+            FunctionValue::Closure(closure) => {
+                let func = closure.function.clone();
+                self.run_body(&func, closure.captured.clone(), args)
+            }
         }
-        InterpretingMastermind { funcmap }
     }
-    pub fn run_main(&mut self) {
-        self.run_function(&"main".to_string(), vec![]);
+    // Builtins that call back into script functions, so they live here
+    // instead of with the rest of the builtins.
+    fn call_higher_order(&mut self, name: &str, args: Vec<Value>, span: Span) -> Outcome<Value> {
+        let expected = match name {
+            "map" | "filter" => 2,
+            "reduce" => 3,
+            _ => unreachable!(),
+        };
+        check_arity(name, &args, expected).map_err(|e| e.at(span))?;
+        let mut args = args.into_iter();
+        let Some(Value::List(xs)) = args.next() else {
+            return fail("TypeError", span, format!("{} needs a list", name));
+        };
+        let f = args.next().unwrap();
+        // copy the items out so the callback is free to change the list
+        let items = xs.borrow().clone();
+        match name {
+            "map" => {
+                let mut mapped = Vec::with_capacity(items.len());
+                for x in items {
+                    mapped.push(self.call_value(&f, CallArgs::positional(vec![x], span))?);
+                }
+                Ok(Value::new_list(mapped))
+            }
+            "filter" => {
+                let mut kept = Vec::new();
+                for x in items {
+                    let args = CallArgs::positional(vec![x.clone()], span);
+                    if self.call_value(&f, args)? != Value::Int(0) {
+                        kept.push(x);
+                    }
+                }
+                Ok(Value::new_list(kept))
+            }
+            "reduce" => {
+                let mut acc = args.next().unwrap();
+                for x in items {
+                    acc = self.call_value(&f, CallArgs::positional(vec![acc, x], span))?;
+                }
+                Ok(acc)
+            }
+            _ => unreachable!(),
+        }
     }
-    fn run_function(&mut self, func_name: &String, args: Vec<Value>) -> Value {
-        let mut varmap: HashMap<String, Value> = HashMap::new();
-        let func = self
-            .funcmap
-            .get(func_name)
-            .expect("Called unknown function.")
-            .clone();
-        for (i, arg) in func.proto.args.iter().enumerate() {
-            varmap.insert(arg.clone(), args[i].clone());
+    fn eval_args(&mut self, exprvec: &[Arg], span: Span, locals: &mut Locals) -> Outcome<CallArgs> {
+        let mut args = CallArgs::positional(Vec::with_capacity(exprvec.len()), span);
+        for arg in exprvec {
+            let value = self.eval_expr(&arg.value, locals)?;
+            match &arg.name {
+                Some(name) => args.named.push((name.clone(), value)),
+                None => args.positional.push(value),
+            }
         }
-        for statement in &func.body {
-            if let Some(x) = self.run_statement(statement, &mut varmap) {
-                return x;
+        Ok(args)
+    }
+    // Runs statements until one returns, and gives what it returned.
+    fn run_statements(
+        &mut self,
+        body: &[Statement],
+        locals: &mut Locals,
+    ) -> Outcome<Option<Value>> {
+        for statement in body {
+            if let Some(x) = self.run_statement(statement, locals)? {
+                return Ok(Some(x));
             }
         }
-        todo!()
+        Ok(None)
     }
     fn run_statement(
         &mut self,
         statement: &Statement,
-        varmap: &mut HashMap<String, Value>,
-    ) -> Option<Value> {
+        locals: &mut Locals,
+    ) -> Outcome<Option<Value>> {
+        if self.debugger.is_some() {
+            self.pause_if_asked(statement.span(), locals)?;
+        }
         match statement {
-            Statement::Assign(x) => self.run_assignment(x, varmap),
+            Statement::Assign(x) => self.run_assignment(x, locals)?,
             Statement::Call(x) => {
-                self.eval_expr(x, varmap);
+                self.eval_expr(x, locals)?;
             }
-            Statement::If(x) => {
-                if let Some(x) = self.run_if_block(x, varmap) {
-                    return Some(x);
+            Statement::If(x) => return self.run_if_block(x, locals),
+            Statement::While(x) => return self.run_while_block(x, locals),
+            Statement::Match(x) => return self.run_match_block(x, locals),
+            Statement::Try(x) => return self.run_try_block(x, locals),
+            Statement::Throw(x, span) => {
+                let error = match self.eval_expr(x, locals)? {
+                    Value::Error(error) => error,
+                    // anything else thrown becomes the message of an Error
+                    value => ErrorValue::new("Error", value.to_string()).at(*span),
+                };
+                return Err(Unwind::Throw(error));
+            }
+            Statement::Built(BuiltIn::Return(x, _)) => return Ok(Some(self.eval_expr(x, locals)?)),
+            Statement::Built(x) => self.run_built(x, locals)?,
+        }
+        Ok(None)
+    }
+    // Stops before the statement at `span` if the debugger says to, taking
+    // commands until one carries on.
+    fn pause_if_asked(&mut self, span: Span, locals: &mut Locals) -> Outcome<()> {
+        let debugger = self
+            .debugger
+            .as_mut()
+            .expect("Only called while debugging.");
+        if debugger.quitting() {
+            return Err(Unwind::Stop);
+        }
+        let Some(reason) = debugger.should_stop(span, &self.frames) else {
+            return Ok(());
+        };
+        let stop = debugger.stop(reason, span, &self.frames, locals);
+        debugger.stopped(&stop);
+        loop {
+            // an evaluation can change what the locals hold
+            let debugger = self.debugger.as_mut().unwrap();
+            let stop = debugger.stop(reason, span, &self.frames, locals);
+            match debugger.command(&stop) {
+                Command::Evaluate(text) => {
+                    let result = self.evaluate(&text, locals);
+                    self.debugger.as_mut().unwrap().evaluated(result);
+                }
+                command => {
+                    debugger.resume(command, self.frames.len());
+                    if debugger.quitting() {
+                        return Err(Unwind::Stop);
+                    }
+                    return Ok(());
                 }
             }
-            Statement::While(x) => {
-                if let Some(x) = self.run_while_block(x, varmap) {
-                    return Some(x);
+        }
+    }
+    // Evaluates an expression typed into the debugger in the paused call.
+    fn evaluate(&mut self, text: &str, locals: &mut Locals) -> Result<String, String> {
+        let debugger = self.debugger.as_mut().unwrap();
+        let (expr, names) = debugger.compile(text)?;
+        debugger.set_evaluating(true);
+        let len = locals.len();
+        locals.resize(names.len().max(len), Local::Unset);
+        let value = self.eval_expr(&expr, locals);
+        locals.truncate(len);
+        self.debugger.as_mut().unwrap().set_evaluating(false);
+        match value {
+            Ok(x) | Err(Unwind::Return(x)) => Ok(x.repr()),
+            Err(Unwind::Throw(e)) => Err(e.to_string()),
+            Err(Unwind::Stop) => unreachable!("Evaluating never stops."),
+        }
+    }
+    fn run_match_block(
+        &mut self,
+        match_block: &MatchBlock<Vec<Statement>>,
+        locals: &mut Locals,
+    ) -> Outcome<Option<Value>> {
+        let value = self.eval_expr(&match_block.scrutinee, locals)?;
+        let (arm, shadowed) = self.select_arm(match_block, &value, locals)?;
+        let ans = self.run_statements(&arm.body, locals);
+        unbind(shadowed, locals);
+        ans
+    }
+    // Finds the first arm whose pattern and guard accept the value. Its bindings
+    // are left in the locals, and what they shadowed is returned for `unbind`.
+    fn select_arm<'a, T>(
+        &mut self,
+        match_block: &'a MatchBlock<T>,
+        value: &Value,
+        locals: &mut Locals,
+    ) -> Outcome<(&'a MatchArm<T>, Shadowed)> {
+        for arm in match_block.arms.iter() {
+            let mut bindings = Vec::new();
+            if !self.match_pattern(&arm.pattern, value, &mut bindings) {
+                continue;
+            }
+            let shadowed = bindings
+                .into_iter()
+                .map(|(slot, value)| (slot, mem::replace(&mut locals[slot], Local::Set(value))))
+                .collect();
+            let accepted = match &arm.guard {
+                Some(guard) => self.eval_expr(guard, locals),
+                None => Ok(Value::Int(1)),
+            };
+            match accepted {
+                Ok(Value::Int(0)) => unbind(shadowed, locals),
+                Ok(_) => return Ok((arm, shadowed)),
+                Err(e) => {
+                    unbind(shadowed, locals);
+                    return Err(e);
                 }
             }
-            Statement::Built(BuiltIn::Return(x)) => return Some(self.eval_expr(x, varmap)),
-            Statement::Built(x) => self.run_built(x, varmap),
         }
-        None
+        fail(
+            "MatchError",
+            match_block.span,
+            format!("No match arm matched {}", value),
+        )
     }
-    fn run_assignment(&mut self, assignment: &Assignment, varmap: &mut HashMap<String, Value>) {
-        let rhs = self.eval_expr(&assignment.right_hand, varmap);
-        let ExprAST::Variable(ref varname) = assignment.variable else {
-            eprintln!("The parser messed up, and this exprast is wrong");
-            panic!();
-        };
-        if assignment.is_declaration {
-            if varmap.contains_key(varname) {
-                panic!("Declared item, but we already have the key!");
+    fn match_pattern(
+        &self,
+        pattern: &Pattern,
+        value: &Value,
+        bindings: &mut Vec<(usize, Value)>,
+    ) -> bool {
+        match pattern {
+            Pattern::Wildcard => true,
+            Pattern::Literal(x) => x == value,
+            // only names that aren't variants get a slot
+            Pattern::Ident(_, Some(slot)) => {
+                bindings.push((*slot, value.clone()));
+                true
             }
-            varmap.insert(varname.clone(), rhs);
-        } else {
-            if !varmap.contains_key(varname) {
-                panic!("Tried to assign item, but it wasn't declared!");
+            Pattern::Ident(name, None) => {
+                matches!(value, Value::Variant(v) if *v.variant == **name)
+            }
+            Pattern::Variant(name, subpatterns) => {
+                let Value::Variant(v) = value else {
+                    return false;
+                };
+                *v.variant == **name
+                    && v.payload.len() == subpatterns.len()
+                    && subpatterns
+                        .iter()
+                        .zip(v.payload.iter())
+                        .all(|(p, x)| self.match_pattern(p, x, bindings))
             }
-            varmap.insert(varname.clone(), rhs);
         }
     }
-    fn run_if_block(
+    fn run_try_block(
         &mut self,
-        if_block: &IfBlock,
-        varmap: &mut HashMap<String, Value>,
-    ) -> Option<Value> {
-        if self.eval_expr(&if_block.conditional, varmap) != Value::Int(0) {
-            for statement in if_block.body.iter() {
-                if let Some(x) = self.run_statement(&statement, varmap) {
-                    return Some(x);
+        try_block: &TryBlock,
+        locals: &mut Locals,
+    ) -> Outcome<Option<Value>> {
+        let outcome = match (
+            self.run_statements(&try_block.body, locals),
+            &try_block.catch,
+        ) {
+            (Err(Unwind::Throw(mut error)), Some((_, slot, body))) => {
+                // caught in the function that threw it, so no trace yet
+                if error.trace.is_empty() {
+                    Rc::make_mut(&mut error).trace = self.frames.clone();
                 }
+                let shadowed = mem::replace(&mut locals[*slot], Local::Set(Value::Error(error)));
+                let outcome = self.run_statements(body, locals);
+                unbind(vec![(*slot, shadowed)], locals);
+                outcome
+            }
+            (outcome, _) => outcome,
+        };
+        if let Some(body) = &try_block.finally
+            && let Some(x) = self.run_statements(body, locals)?
+        {
+            return Ok(Some(x));
+        }
+        outcome
+    }
+    fn run_assignment(&mut self, assignment: &Assignment, locals: &mut Locals) -> Outcome<()> {
+        let rhs = self.eval_expr(&assignment.right_hand, locals)?;
+        let compound = &assignment.compound;
+        let (varname, span, resolution) = match &assignment.variable {
+            ExprAST::Variable(x, span, resolution) => (x, *span, resolution),
+            target @ (ExprAST::Tuple(_) | ExprAST::List(_) | ExprAST::StructInit(..)) => {
+                return destructure(target, rhs, locals, assignment.span);
+            }
+            ExprAST::Index(container, index, span) => {
+                let container = self.eval_expr(container, locals)?;
+                let index = self.eval_expr(index, locals)?;
+                set_index(container, index, compound, rhs).map_err(|e| e.at(*span))?;
+                return Ok(());
             }
+            ExprAST::Field(instance, field, span) => {
+                let instance = self.eval_expr(instance, locals)?;
+                set_field(instance, field, compound, rhs).map_err(|e| e.at(*span))?;
+                return Ok(());
+            }
+            _ => {
+                eprintln!("The parser messed up, and this exprast is wrong");
+                panic!();
+            }
+        };
+        let slot = resolution.slot;
+        if assignment.is_declaration {
+            declare(
+                varname,
+                slot.expect("Declarations get a slot."),
+                rhs,
+                locals,
+                span,
+            )?;
+        } else if let Some(slot) = slot.filter(|&s| locals[s].is_set()) {
+            let local = &mut locals[slot];
+            let value = assigned_value(compound, || Ok(local.get().unwrap()), rhs)
+                .map_err(|e| e.at(span))?;
+            local.set(Some(value));
+        } else if resolution
+            .global
+            .is_some_and(|g| self.constants.contains(&g))
+        {
+            return fail(
+                "NameError",
+                span,
+                format!("Tried to assign to constant {}", varname),
+            );
+        } else if let Some(global) = resolution.global.and_then(|g| self.globals[g].as_mut()) {
+            *global =
+                assigned_value(compound, || Ok(global.clone()), rhs).map_err(|e| e.at(span))?;
+        } else {
+            return fail(
+                "NameError",
+                span,
+                format!("Tried to assign {}, but it wasn't declared", varname),
+            );
         }
-        None
+        Ok(())
+    }
+    fn run_if_block(&mut self, if_block: &IfBlock, locals: &mut Locals) -> Outcome<Option<Value>> {
+        let body = if self.eval_expr(&if_block.conditional, locals)? != Value::Int(0) {
+            &if_block.body
+        } else {
+            &if_block.else_body
+        };
+        self.run_statements(body, locals)
     }
     fn run_while_block(
         &mut self,
         while_block: &WhileBlock,
-        varmap: &mut HashMap<String, Value>,
-    ) -> Option<Value> {
-        while self.eval_expr(&while_block.conditional, varmap) != Value::Int(0) {
-            for statement in while_block.body.iter() {
-                if let Some(x) = self.run_statement(&statement, varmap) {
-                    return Some(x);
-                }
+        locals: &mut Locals,
+    ) -> Outcome<Option<Value>> {
+        while self.eval_expr(&while_block.conditional, locals)? != Value::Int(0) {
+            if let Some(x) = self.run_statements(&while_block.body, locals)? {
+                return Ok(Some(x));
             }
         }
-        None
+        Ok(None)
     }
-    fn run_built(&mut self, built: &BuiltIn, varmap: &mut HashMap<String, Value>) {
+    fn run_built(&mut self, built: &BuiltIn, locals: &mut Locals) -> Outcome<()> {
         match built {
-            BuiltIn::Print(x) => match self.eval_expr(x, varmap) {
-                Value::Str(x) => println!("{}\n", x),
-                Value::Int(x) => println!("{}\n", x),
-            },
+            BuiltIn::Print(x, _) => {
+                let value = self.eval_expr(x, locals)?;
+                match &mut self.debugger {
+                    Some(debugger) => debugger.print(&format!("{}\n\n", value)),
+                    None => println!("{}\n", value),
+                }
+            }
             BuiltIn::Input(x) => {
-                let ExprAST::Variable(name) = x else {
+                let ExprAST::Variable(_, span, resolution) = x else {
                     unreachable!();
                 };
+                let slot = resolution.slot.expect("Input binds a local.");
                 let mut buf = String::new();
-                io::stdin()
-                    .read_line(&mut buf)
-                    .expect("could not get stdin");
-                let num = buf.parse::<i32>();
-                match num {
-                    Ok(number) => varmap.insert(name.clone(), Value::Int(number)),
-                    Err(_) => varmap.insert(name.clone(), Value::Str(buf)),
-                };
+                match &mut self.debugger {
+                    // stdin may be the debugger's, so the frontend reads it
+                    Some(debugger) => match debugger.input() {
+                        Ok(line) => buf = line,
+                        Err(e) => return fail("InputError", *span, e),
+                    },
+                    None => {
+                        io::stdin()
+                            .read_line(&mut buf)
+                            .expect("could not get stdin");
+                    }
+                }
+                let num = buf.trim_end().parse::<i32>();
+                locals[slot].set(Some(match num {
+                    Ok(number) => Value::Int(number),
+                    Err(_) => Value::Str(buf),
+                }));
             }
             BuiltIn::Drop(x) => {
-                let ExprAST::Variable(name) = x else {
+                let ExprAST::Variable(_, _, resolution) = x else {
                     unreachable!();
                 };
-                varmap.remove(name);
+                // a name the function never binds is never a local to drop
+                if let Some(slot) = resolution.slot {
+                    locals[slot].set(None);
+                }
             }
-            BuiltIn::Return(_) => unreachable!(),
+            BuiltIn::Return(..) => unreachable!(),
         }
+        Ok(())
     }
-    fn eval_expr(&mut self, binop: &ExprAST, varmap: &mut HashMap<String, Value>) -> Value {
-        match binop {
-            ExprAST::Variable(x) => varmap.get(x).expect("Could not find variable").to_owned(),
-            ExprAST::Val(x) => x.to_owned(),
-            ExprAST::Call(name, exprvec) => {
-                let mut argvec = Vec::new();
-                for arg in exprvec {
-                    argvec.push(self.eval_expr(arg, varmap));
+    fn eval_expr(&mut self, binop: &ExprAST, locals: &mut Locals) -> Outcome<Value> {
+        let ans = match binop {
+            ExprAST::Variable(x, span, resolution) => match self.lookup(resolution, locals) {
+                Some(x) => x,
+                None if resolution.function.is_some() => {
+                    let function = &self.functions[resolution.function.unwrap()];
+                    Value::Function(Rc::new(FunctionValue::Named(function.proto.name.clone())))
                 }
-                self.run_function(name, argvec)
-            }
-            ExprAST::BinOp(op, lhs, rhs) => {
-                let lhs = self.eval_expr(lhs, varmap);
-                let rhs = self.eval_expr(rhs, varmap);
-                match op {
-                    Operator::And => {
-                        if lhs != Value::Int(0) && rhs != Value::Int(0) {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::Or => {
-                        if lhs != Value::Int(0) || rhs != Value::Int(0) {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::Xor => {
-                        if (lhs != Value::Int(0) && rhs == Value::Int(0))
-                            || (lhs == Value::Int(0) && rhs != Value::Int(0))
-                        {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::LEq => {
-                        if lhs <= rhs {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::GEq => {
-                        if lhs >= rhs {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::Eq => {
-                        if lhs == rhs {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::Ls => {
-                        if lhs < rhs {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::Gr => {
-                        if lhs > rhs {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    Operator::BAnd => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l & r)
-                    }
-                    Operator::BOr => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l | r)
-                    }
-                    Operator::BXor => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l ^ r)
-                    }
-                    Operator::Add => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l + r)
+                None => match self.variantmap.get_key_value(x.as_str()) {
+                    Some((variant, (enum_name, 0))) => new_variant(enum_name, variant, vec![]),
+                    Some(_) => {
+                        return fail(
+                            "ArgumentError",
+                            *span,
+                            format!("Variant {} needs a payload", x),
+                        );
                     }
-                    Operator::Sub => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l - r)
+                    None => {
+                        return fail("NameError", *span, format!("Could not find variable {}", x));
                     }
-                    Operator::Mult => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l * r)
+                },
+            },
+            ExprAST::Val(x) => x.to_owned(),
+            ExprAST::Call(name, exprvec, span, resolution) => {
+                let args = self.eval_args(exprvec, *span, locals)?;
+                if let Some(callee) = self.lookup(resolution, locals) {
+                    return self.call_value(&callee, args);
+                } else if let Some(index) = resolution.function {
+                    return self.run_function(index, args);
+                }
+                let argvec = args.into_positional(name)?;
+                if let Some((variant, (enum_name, arity))) =
+                    self.variantmap.get_key_value(name.as_str())
+                {
+                    if *arity != argvec.len() {
+                        return fail(
+                            "ArgumentError",
+                            *span,
+                            format!(
+                                "Variant {} takes {} values, got {}",
+                                name,
+                                arity,
+                                argvec.len()
+                            ),
+                        );
                     }
-                    Operator::Div => {
-                        let Value::Int(l) = lhs else {
-                            panic!("Type err");
-                        };
-                        let Value::Int(r) = rhs else {
-                            panic!("Type err");
-                        };
-                        Value::Int(l / r)
+                    new_variant(enum_name, variant, argvec)
+                } else if HIGHER_ORDER.contains(&name.as_str()) {
+                    self.call_higher_order(name, argvec, *span)?
+                } else if name == "error" {
+                    // lives here since the error is placed at the call
+                    new_error(argvec, *span)?
+                } else {
+                    call_builtin(name, argvec).map_err(|e| e.at(*span))?
+                }
+            }
+            ExprAST::CallExpr(callee, exprvec, span) => {
+                let callee = self.eval_expr(callee, locals)?;
+                let args = self.eval_args(exprvec, *span, locals)?;
+                self.call_value(&callee, args)?
+            }
+            ExprAST::Closure(function) => {
+                Value::Function(Rc::new(FunctionValue::Closure(Closure {
+                    function: function.clone(),
+                    captured: locals.iter_mut().map(Local::share).collect(),
+                })))
+            }
+            ExprAST::Match(match_block) => {
+                let value = self.eval_expr(&match_block.scrutinee, locals)?;
+                let (arm, shadowed) = self.select_arm(match_block, &value, locals)?;
+                let ans = self.eval_expr(&arm.body, locals);
+                unbind(shadowed, locals);
+                ans?
+            }
+            ExprAST::List(exprvec) => {
+                let mut items = Vec::with_capacity(exprvec.len());
+                for item in exprvec {
+                    items.push(self.eval_expr(item, locals)?);
+                }
+                Value::new_list(items)
+            }
+            ExprAST::Tuple(exprvec) => {
+                let mut items = Vec::with_capacity(exprvec.len());
+                for item in exprvec {
+                    items.push(self.eval_expr(item, locals)?);
+                }
+                Value::Tuple(Rc::new(items))
+            }
+            ExprAST::Map(entries, span) => {
+                let mut map = OrderedMap::new();
+                for (key, value) in entries {
+                    let key = map_key(self.eval_expr(key, locals)?).map_err(|e| e.at(*span))?;
+                    let value = self.eval_expr(value, locals)?;
+                    map.insert(key, value);
+                }
+                Value::new_map(map)
+            }
+            ExprAST::Index(container, index, span) => {
+                let container = self.eval_expr(container, locals)?;
+                let index = self.eval_expr(index, locals)?;
+                index_value(container, index).map_err(|e| e.at(*span))?
+            }
+            ExprAST::StructInit(name, inits, span) => {
+                let Some(struct_ast) = self.structmap.get(name.as_str()) else {
+                    return fail("NameError", *span, format!("Unknown struct {}", name));
+                };
+                let struct_name = struct_ast.name.clone();
+                let field_names = struct_ast.fields.clone();
+                // evaluate in source order, then lay out in declaration order
+                let mut values: Vec<Option<Value>> = vec![None; field_names.len()];
+                for (field, init) in inits {
+                    let Some(pos) = field_names.iter().position(|f| **f == **field) else {
+                        return fail(
+                            "FieldError",
+                            *span,
+                            format!("Struct {} has no field {}", name, field),
+                        );
+                    };
+                    if values[pos].is_some() {
+                        return fail(
+                            "FieldError",
+                            *span,
+                            format!("Field {} given twice in {} literal", field, name),
+                        );
                     }
+                    values[pos] = Some(self.eval_expr(init, locals)?);
+                }
+                let mut fields = Vec::with_capacity(field_names.len());
+                for (field, value) in field_names.into_iter().zip(values) {
+                    let Some(value) = value else {
+                        return fail(
+                            "FieldError",
+                            *span,
+                            format!("Missing field {} in {} literal", field, name),
+                        );
+                    };
+                    fields.push((field, value));
+                }
+                Value::Struct(Rc::new(RefCell::new(StructValue {
+                    name: struct_name,
+                    fields,
+                })))
+            }
+            ExprAST::Field(instance, field, span) => {
+                let instance = self.eval_expr(instance, locals)?;
+                field_value(instance, field).map_err(|e| e.at(*span))?
+            }
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                if self.eval_expr(conditional, locals)? != Value::Int(0) {
+                    self.eval_expr(then_expr, locals)?
+                } else {
+                    self.eval_expr(else_expr, locals)?
+                }
+            }
+            ExprAST::Propagate(inner, span) => {
+                let value = self.eval_expr(inner, locals)?;
+                let held = held_value("?", &value).map_err(|e| e.at(*span))?.cloned();
+                match held {
+                    Some(x) => x,
+                    None => return Err(Unwind::Return(value)),
+                }
+            }
+            ExprAST::BinOp(op, lhs, rhs, span) => {
+                let lhs = self.eval_expr(lhs, locals)?;
+                let rhs = self.eval_expr(rhs, locals)?;
+                eval_binop(op, lhs, rhs).map_err(|e| e.at(*span))?
+            }
+        };
+        Ok(ans)
+    }
+}
+
+pub fn eval_binop(op: &Operator, lhs: Value, rhs: Value) -> Result<Value, ErrorValue> {
+    let truth = |x: bool| Ok(Value::Int(x as i32));
+    match op {
+        Operator::And => truth(lhs != Value::Int(0) && rhs != Value::Int(0)),
+        Operator::Or => truth(lhs != Value::Int(0) || rhs != Value::Int(0)),
+        Operator::Xor => truth((lhs != Value::Int(0)) != (rhs != Value::Int(0))),
+        Operator::LEq => truth(lhs <= rhs),
+        Operator::GEq => truth(lhs >= rhs),
+        Operator::Eq => truth(lhs == rhs),
+        Operator::Ls => truth(lhs < rhs),
+        Operator::Gr => truth(lhs > rhs),
+        _ => {
+            let (Value::Int(l), Value::Int(r)) = (&lhs, &rhs) else {
+                return Err(type_error(format!(
+                    "Can't use {} on {} and {}",
+                    op, lhs, rhs
+                )));
+            };
+            let (l, r) = (*l, *r);
+            let ans = match op {
+                Operator::BAnd => Some(l & r),
+                Operator::BOr => Some(l | r),
+                Operator::BXor => Some(l ^ r),
+                Operator::Add => l.checked_add(r),
+                Operator::Sub => l.checked_sub(r),
+                Operator::Mult => l.checked_mul(r),
+                Operator::Div | Operator::Mod if r == 0 => {
+                    return Err(ErrorValue::new("DivisionByZero", format!("{} {} 0", l, op)));
                 }
+                Operator::Div => l.checked_div(r),
+                Operator::Mod => l.checked_rem(r),
+                _ => unreachable!(),
+            };
+            match ans {
+                Some(x) => Ok(Value::Int(x)),
+                None => Err(ErrorValue::new(
+                    "Overflow",
+                    format!("{} {} {} doesn't fit in an int", l, op, r),
+                )),
             }
         }
     }
 }
+
+// The value an assignment stores: the right hand itself, or for `x op= rhs`
+// the target's old value combined with it. `old` is only read when needed,
+// since a plain assignment may be creating the map key.
+pub fn assigned_value(
+    compound: &Option<Operator>,
+    old: impl FnOnce() -> Result<Value, ErrorValue>,
+    rhs: Value,
+) -> Result<Value, ErrorValue> {
+    match compound {
+        Some(op) => eval_binop(op, old()?, rhs),
+        None => Ok(rhs),
+    }
+}
+
+fn declare(name: &str, slot: usize, value: Value, locals: &mut Locals, span: Span) -> Outcome<()> {
+    if locals[slot].is_set() {
+        return fail("NameError", span, format!("{} is already declared", name));
+    }
+    locals[slot].set(Some(value));
+    Ok(())
+}
+
+// Declares each name in a destructuring target with its part of the value.
+fn destructure(target: &ExprAST, value: Value, locals: &mut Locals, span: Span) -> Outcome<()> {
+    match (target, value) {
+        (ExprAST::Variable(name, ..), _) if name == "_" => Ok(()),
+        (ExprAST::Variable(name, span, resolution), value) => {
+            let slot = resolution.slot.expect("Declarations get a slot.");
+            declare(name, slot, value, locals, *span)
+        }
+        (ExprAST::Tuple(targets), Value::Tuple(items)) => {
+            destructure_items(targets, &items, "tuple", locals, span)
+        }
+        (ExprAST::List(targets), Value::List(xs)) => {
+            destructure_items(targets, &xs.borrow(), "list", locals, span)
+        }
+        (ExprAST::StructInit(name, fields, _), Value::Struct(instance)) => {
+            let instance = instance.borrow();
+            if *instance.name != **name {
+                return fail(
+                    "TypeError",
+                    span,
+                    format!("Can't destructure a {} as a {}", instance.name, name),
+                );
+            }
+            for (field, target) in fields {
+                let Some(value) = instance.get(field) else {
+                    return fail(
+                        "FieldError",
+                        span,
+                        format!("Struct {} has no field {}", name, field),
+                    );
+                };
+                destructure(target, value.clone(), locals, span)?;
+            }
+            Ok(())
+        }
+        (_, value) => fail(
+            "TypeError",
+            span,
+            format!("Can't destructure {} that way", value),
+        ),
+    }
+}
+
+fn destructure_items(
+    targets: &[ExprAST],
+    items: &[Value],
+    kind: &str,
+    locals: &mut Locals,
+    span: Span,
+) -> Outcome<()> {
+    if targets.len() != items.len() {
+        return fail(
+            "ValueError",
+            span,
+            format!(
+                "Can't destructure a {} of length {} into {} names",
+                kind,
+                items.len(),
+                targets.len()
+            ),
+        );
+    }
+    for (target, item) in targets.iter().zip(items) {
+        destructure(target, item.clone(), locals, span)?;
+    }
+    Ok(())
+}
+
+// `error(kind, message)`, an error value to throw.
+pub fn new_error(args: Vec<Value>, span: Span) -> Result<Value, Rc<ErrorValue>> {
+    check_arity("error", &args, 2).map_err(|e| e.at(span))?;
+    let mut args = args.into_iter();
+    let Some(Value::Str(kind)) = args.next() else {
+        return Err(type_error("error needs a string kind".to_owned()).at(span));
+    };
+    let message = args.next().unwrap().to_string();
+    Ok(Value::Error(ErrorValue::new(&kind, message).at(span)))
+}
+
+pub fn new_variant(enum_name: &Name, variant: &Name, payload: Vec<Value>) -> Value {
+    Value::Variant(Rc::new(VariantValue {
+        enum_name: enum_name.clone(),
+        variant: variant.clone(),
+        payload,
+    }))
+}
+
+fn unbind(shadowed: Shadowed, locals: &mut Locals) {
+    for (slot, old) in shadowed.into_iter().rev() {
+        locals[slot] = old;
+    }
+}
+
+fn missing_key(key: &Value) -> ErrorValue {
+    ErrorValue::new("KeyError", format!("Key {} not found in map", key))
+}
+
+fn list_index(index: &Value, len: usize) -> Result<usize, ErrorValue> {
+    let Value::Int(i) = *index else {
+        return Err(type_error(format!("Index must be an int, not {}", index)));
+    };
+    if i < 0 || i as usize >= len {
+        return Err(ErrorValue::new(
+            "IndexError",
+            format!("Index {} out of bounds for length {}", i, len),
+        ));
+    }
+    Ok(i as usize)
+}
+
+pub fn index_value(container: Value, index: Value) -> Result<Value, ErrorValue> {
+    match container {
+        Value::List(xs) => {
+            let xs = xs.borrow();
+            Ok(xs[list_index(&index, xs.len())?].clone())
+        }
+        Value::Tuple(items) => Ok(items[list_index(&index, items.len())?].clone()),
+        Value::Map(map) => match map.borrow().get(&index) {
+            Some(x) => Ok(x.clone()),
+            None => Err(missing_key(&index)),
+        },
+        x => Err(type_error(format!(
+            "Can only index into a list, tuple or map, not {}",
+            x
+        ))),
+    }
+}
+
+// `container[index] op= rhs`
+pub fn set_index(
+    container: Value,
+    index: Value,
+    compound: &Option<Operator>,
+    rhs: Value,
+) -> Result<(), ErrorValue> {
+    match container {
+        Value::List(xs) => {
+            let i = list_index(&index, xs.borrow().len())?;
+            let old = || Ok(xs.borrow()[i].clone());
+            let value = assigned_value(compound, old, rhs)?;
+            xs.borrow_mut()[i] = value;
+        }
+        Value::Map(map) => {
+            let key = map_key(index)?;
+            let old = || match map.borrow().get(&key) {
+                Some(x) => Ok(x.clone()),
+                None => Err(missing_key(&key)),
+            };
+            let value = assigned_value(compound, old, rhs)?;
+            map.borrow_mut().insert(key, value);
+        }
+        Value::Tuple(_) => return Err(type_error("Tuples can't be changed".to_owned())),
+        x => {
+            return Err(type_error(format!(
+                "Can only index into a list or map, not {}",
+                x
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn field_value(instance: Value, field: &str) -> Result<Value, ErrorValue> {
+    match instance {
+        Value::Struct(instance) => {
+            let instance = instance.borrow();
+            match instance.get(field) {
+                Some(x) => Ok(x.clone()),
+                None => Err(ErrorValue::new(
+                    "FieldError",
+                    format!("Struct {} has no field {}", instance.name, field),
+                )),
+            }
+        }
+        Value::Error(error) => match field {
+            "kind" => Ok(Value::Str(error.kind.clone())),
+            "message" => Ok(Value::Str(error.message.clone())),
+            "line" => Ok(Value::Int(error.span.line as i32)),
+            "col" => Ok(Value::Int(error.span.col as i32)),
+            "trace" => Ok(Value::Str(error.format_trace())),
+            _ => Err(ErrorValue::new(
+                "FieldError",
+                format!("Errors have no field {}", field),
+            )),
+        },
+        x => Err(type_error(format!(
+            "Can only access fields on a struct, not {}",
+            x
+        ))),
+    }
+}
+
+// `instance.field op= rhs`
+pub fn set_field(
+    instance: Value,
+    field: &str,
+    compound: &Option<Operator>,
+    rhs: Value,
+) -> Result<(), ErrorValue> {
+    let Value::Struct(instance) = instance else {
+        return Err(type_error(format!(
+            "Can only assign fields on a struct, not {}",
+            instance
+        )));
+    };
+    let mut instance = instance.borrow_mut();
+    let name = instance.name.clone();
+    let Some(slot) = instance.get_mut(field) else {
+        return Err(ErrorValue::new(
+            "FieldError",
+            format!("Struct {} has no field {}", name, field),
+        ));
+    };
+    *slot = assigned_value(compound, || Ok(slot.clone()), rhs)?;
+    Ok(())
+}
@@ -0,0 +1,601 @@
+use std::rc::Rc;
+
+use cranelift_codegen::{
+    ir::{
+        AbiParam, Block, FuncRef, InstBuilder, MemFlags, Signature, UserFuncName, Value as Reg,
+        condcodes::IntCC, types,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Linkage, Module, default_libcall_names};
+
+use crate::{
+    ast::{BuiltIn, ExprAST, FunctionAST, Statement, Value},
+    interpreter::MAX_DEPTH,
+    lexer::Operator,
+};
+
+// What compiled code returns instead of a value when it gives up. Every
+// result that isn't this fits in an i32.
+const BAIL: i64 = i64::MIN;
+
+// How the interpreter calls a compiled function: the depth the call is made
+// at and a pointer to its arguments.
+type Entry = unsafe extern "C" fn(i64, *const i32) -> i64;
+
+// Native code for the functions that only ever see ints: arithmetic,
+// comparisons, `if`, `while` and calls to each other, on locals. Those can't
+// print, read input or touch a global, so anything that would be an error
+// (overflow, dividing by zero, an unbound local, going too deep) makes the
+// call give up, and the interpreter runs it again from the start to raise
+// the error with its trace.
+pub struct Jit {
+    // kept alive for the code in it
+    _module: JITModule,
+    // by function index, with the number of parameters
+    entries: Vec<Option<(Entry, usize)>>,
+}
+impl Jit {
+    /// Compiles what it can of the program's functions, or gives back None
+    /// when Cranelift has no backend for this machine.
+    pub fn new(functions: &[Rc<FunctionAST>]) -> Option<Self> {
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", "speed")
+            .expect("opt_level is a setting.");
+        let isa = cranelift_native::builder()
+            .ok()?
+            .finish(settings::Flags::new(flags))
+            .ok()?;
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        let compiled = compilable(functions);
+
+        let mut ids = vec![None; functions.len()];
+        for (i, func) in functions.iter().enumerate().filter(|(i, _)| compiled[*i]) {
+            let mut sig = module.make_signature();
+            sig.params.push(AbiParam::new(types::I64));
+            for _ in &func.param_slots {
+                sig.params.push(AbiParam::new(types::I32));
+            }
+            sig.returns.push(AbiParam::new(types::I64));
+            let id = module
+                .declare_function(&format!("f{}_{}", i, func.proto.name), Linkage::Local, &sig)
+                .expect("Function names are unique.");
+            ids[i] = Some((id, sig));
+        }
+
+        let mut ctx = module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
+        let mut entries = vec![None; functions.len()];
+        let mut wrappers = vec![];
+        for (i, func) in functions.iter().enumerate() {
+            let Some((id, sig)) = ids[i].clone() else {
+                continue;
+            };
+            ctx.func.signature = sig.clone();
+            ctx.func.name = UserFuncName::user(0, id.as_u32());
+            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+            let mut refs = vec![None; functions.len()];
+            for (callee, declared) in ids.iter().enumerate() {
+                if let Some((callee_id, _)) = declared {
+                    refs[callee] = Some(module.declare_func_in_func(*callee_id, builder.func));
+                }
+            }
+            FunctionCompiler::new(&mut builder, refs).function(func);
+            builder.finalize();
+            module
+                .define_function(id, &mut ctx)
+                .expect("Compiled functions are valid.");
+            module.clear_context(&mut ctx);
+
+            let wrapper = entry_wrapper(&mut module, &mut ctx, &mut builder_ctx, i, id, &sig);
+            wrappers.push((i, wrapper, func.param_slots.len()));
+        }
+        module
+            .finalize_definitions()
+            .expect("Compiled functions link.");
+        for (i, wrapper, arity) in wrappers {
+            let code = module.get_finalized_function(wrapper);
+            // SAFETY: the wrapper was built with exactly this signature, in
+            // the platform's C calling convention
+            let entry = unsafe { std::mem::transmute::<*const u8, Entry>(code) };
+            entries[i] = Some((entry, arity));
+        }
+        Some(Jit {
+            _module: module,
+            entries,
+        })
+    }
+    /// Whether the function at `index` was compiled.
+    pub fn is_compiled(&self, index: usize) -> bool {
+        self.entries[index].is_some()
+    }
+    /// Runs a compiled function on ints, `depth` calls deep. None means it
+    /// wasn't compiled or gave up, and has to be interpreted.
+    pub fn call(&self, index: usize, depth: usize, args: &[i32]) -> Option<i32> {
+        let (entry, arity) = self.entries[index]?;
+        if args.len() != arity {
+            return None;
+        }
+        // SAFETY: the pointer is to `arity` ints, all the wrapper reads
+        let result = unsafe { entry(depth as i64, args.as_ptr()) };
+        (result != BAIL).then_some(result as i32)
+    }
+}
+
+// A function the interpreter can call through one signature, which loads the
+// arguments and calls the compiled one.
+fn entry_wrapper(
+    module: &mut JITModule,
+    ctx: &mut cranelift_codegen::Context,
+    builder_ctx: &mut FunctionBuilderContext,
+    index: usize,
+    inner: FuncId,
+    inner_sig: &Signature,
+) -> FuncId {
+    let mut sig = module.make_signature();
+    let pointer = module.target_config().pointer_type();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.params.push(AbiParam::new(pointer));
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module
+        .declare_function(&format!("entry{}", index), Linkage::Local, &sig)
+        .expect("Wrapper names are unique.");
+    ctx.func.signature = sig;
+    ctx.func.name = UserFuncName::user(1, id.as_u32());
+    let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+    let block = builder.create_block();
+    builder.append_block_params_for_function_params(block);
+    builder.switch_to_block(block);
+    builder.seal_block(block);
+    let (depth, args) = (
+        builder.block_params(block)[0],
+        builder.block_params(block)[1],
+    );
+    let mut values = vec![depth];
+    for i in 0..inner_sig.params.len() - 1 {
+        values.push(
+            builder
+                .ins()
+                .load(types::I32, MemFlags::trusted(), args, 4 * i as i32),
+        );
+    }
+    let callee = module.declare_func_in_func(inner, builder.func);
+    let call = builder.ins().call(callee, &values);
+    let result = builder.inst_results(call)[0];
+    builder.ins().return_(&[result]);
+    builder.finalize();
+    module
+        .define_function(id, ctx)
+        .expect("Wrappers are valid.");
+    module.clear_context(ctx);
+    id
+}
+
+// Which functions get compiled: the ones that only do int work themselves,
+// less any that call one that doesn't, until none do.
+fn compilable(functions: &[Rc<FunctionAST>]) -> Vec<bool> {
+    let mut compiled: Vec<bool> = functions
+        .iter()
+        .map(|f| {
+            f.proto.rest.is_none()
+                && f.proto.args.iter().all(|p| p.default.is_none())
+                && f.body.iter().all(int_statement)
+        })
+        .collect();
+    loop {
+        let before = compiled.clone();
+        for (i, func) in functions.iter().enumerate() {
+            if compiled[i] {
+                let mut callees = vec![];
+                func.body
+                    .iter()
+                    .for_each(|s| statement_calls(s, &mut callees));
+                compiled[i] = callees.iter().all(|&(callee, arity)| {
+                    before[callee] && functions[callee].param_slots.len() == arity
+                });
+            }
+        }
+        if compiled == before {
+            return compiled;
+        }
+    }
+}
+
+// A name that's only ever a local here.
+fn is_local(expr: &ExprAST) -> bool {
+    matches!(expr, ExprAST::Variable(_, _, r) if r.slot.is_some() && r.global.is_none())
+}
+
+fn int_statement(statement: &Statement) -> bool {
+    match statement {
+        Statement::Assign(x) => is_local(&x.variable) && int_expr(&x.right_hand),
+        Statement::If(x) => {
+            int_expr(&x.conditional)
+                && x.body.iter().all(int_statement)
+                && x.else_body.iter().all(int_statement)
+        }
+        Statement::While(x) => int_expr(&x.conditional) && x.body.iter().all(int_statement),
+        Statement::Call(x) | Statement::Built(BuiltIn::Return(x, _)) => int_expr(x),
+        Statement::Built(BuiltIn::Drop(x)) => is_local(x),
+        _ => false,
+    }
+}
+
+fn int_expr(expr: &ExprAST) -> bool {
+    match expr {
+        ExprAST::Val(Value::Int(_)) => true,
+        ExprAST::Variable(..) => is_local(expr),
+        ExprAST::BinOp(_, lhs, rhs, _) => int_expr(lhs) && int_expr(rhs),
+        ExprAST::Call(_, args, _, resolution) => {
+            resolution.function.is_some()
+                && resolution.slot.is_none()
+                && resolution.global.is_none()
+                && args.iter().all(|a| a.name.is_none() && int_expr(&a.value))
+        }
+        ExprAST::If(condition, then_expr, else_expr) => {
+            int_expr(condition) && int_expr(then_expr) && int_expr(else_expr)
+        }
+        _ => false,
+    }
+}
+
+// The functions called in something int_statement accepted, with how many
+// arguments each call gives.
+fn statement_calls(statement: &Statement, calls: &mut Vec<(usize, usize)>) {
+    match statement {
+        Statement::Assign(x) => expr_calls(&x.right_hand, calls),
+        Statement::If(x) => {
+            expr_calls(&x.conditional, calls);
+            x.body.iter().for_each(|s| statement_calls(s, calls));
+            x.else_body.iter().for_each(|s| statement_calls(s, calls));
+        }
+        Statement::While(x) => {
+            expr_calls(&x.conditional, calls);
+            x.body.iter().for_each(|s| statement_calls(s, calls));
+        }
+        Statement::Call(x) | Statement::Built(BuiltIn::Return(x, _)) => expr_calls(x, calls),
+        _ => {}
+    }
+}
+
+fn expr_calls(expr: &ExprAST, calls: &mut Vec<(usize, usize)>) {
+    match expr {
+        ExprAST::BinOp(_, lhs, rhs, _) => {
+            expr_calls(lhs, calls);
+            expr_calls(rhs, calls);
+        }
+        ExprAST::Call(_, args, _, resolution) => {
+            calls.push((resolution.function.unwrap(), args.len()));
+            args.iter().for_each(|a| expr_calls(&a.value, calls));
+        }
+        ExprAST::If(condition, then_expr, else_expr) => {
+            expr_calls(condition, calls);
+            expr_calls(then_expr, calls);
+            expr_calls(else_expr, calls);
+        }
+        _ => {}
+    }
+}
+
+// Builds one function. Each local has a variable for its value and one
+// saying whether it's bound, which Cranelift mostly optimizes out.
+struct FunctionCompiler<'a, 'b> {
+    builder: &'a mut FunctionBuilder<'b>,
+    // by function index, for the ones this can call
+    refs: Vec<Option<FuncRef>>,
+    depth: Reg,
+    bail: Block,
+}
+impl<'a, 'b> FunctionCompiler<'a, 'b> {
+    fn new(builder: &'a mut FunctionBuilder<'b>, refs: Vec<Option<FuncRef>>) -> Self {
+        let bail = builder.create_block();
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let depth = builder.block_params(entry)[0];
+        FunctionCompiler {
+            builder,
+            refs,
+            depth,
+            bail,
+        }
+    }
+    fn value_var(slot: usize) -> Variable {
+        Variable::from_u32(2 * slot as u32)
+    }
+    fn bound_var(slot: usize) -> Variable {
+        Variable::from_u32(2 * slot as u32 + 1)
+    }
+    fn function(mut self, func: &FunctionAST) {
+        let entry = self.builder.current_block().unwrap();
+        let params = self.builder.block_params(entry)[1..].to_vec();
+        for slot in 0..func.locals.len() {
+            let (value, bound) = match func.param_slots.iter().position(|&s| s == slot) {
+                Some(i) => (params[i], self.builder.ins().iconst(types::I8, 1)),
+                None => (
+                    self.builder.ins().iconst(types::I32, 0),
+                    self.builder.ins().iconst(types::I8, 0),
+                ),
+            };
+            self.builder.declare_var(Self::value_var(slot), types::I32);
+            self.builder.declare_var(Self::bound_var(slot), types::I8);
+            self.builder.def_var(Self::value_var(slot), value);
+            self.builder.def_var(Self::bound_var(slot), bound);
+        }
+        // the interpreter makes the same check before it runs a call
+        let too_deep = self.builder.ins().icmp_imm(
+            IntCC::SignedGreaterThanOrEqual,
+            self.depth,
+            MAX_DEPTH as i64,
+        );
+        self.bail_if(too_deep);
+        self.statements(&func.body);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.builder.ins().return_(&[zero]);
+
+        self.builder.switch_to_block(self.bail);
+        self.builder.seal_block(self.bail);
+        let bail = self.builder.ins().iconst(types::I64, BAIL);
+        self.builder.ins().return_(&[bail]);
+    }
+    // Gives up if `condition` is nonzero, carrying on in a new block if not.
+    fn bail_if(&mut self, condition: Reg) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, self.bail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+        self.builder.seal_block(next);
+    }
+    fn statements(&mut self, body: &[Statement]) {
+        for statement in body {
+            self.statement(statement);
+        }
+    }
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assign(x) => {
+                let ExprAST::Variable(_, _, resolution) = &x.variable else {
+                    unreachable!();
+                };
+                let slot = resolution.slot.unwrap();
+                let rhs = self.expr(&x.right_hand);
+                let bound = self.builder.use_var(Self::bound_var(slot));
+                // declaring twice, or assigning what isn't declared
+                let wrong = if x.is_declaration {
+                    bound
+                } else {
+                    self.builder.ins().icmp_imm(IntCC::Equal, bound, 0)
+                };
+                self.bail_if(wrong);
+                let value = match &x.compound {
+                    Some(op) => {
+                        let old = self.builder.use_var(Self::value_var(slot));
+                        self.operator(op, old, rhs)
+                    }
+                    None => rhs,
+                };
+                let one = self.builder.ins().iconst(types::I8, 1);
+                self.builder.def_var(Self::value_var(slot), value);
+                self.builder.def_var(Self::bound_var(slot), one);
+            }
+            Statement::If(x) => {
+                let condition = self.expr(&x.conditional);
+                let (then_block, else_block, merge) = (
+                    self.builder.create_block(),
+                    self.builder.create_block(),
+                    self.builder.create_block(),
+                );
+                self.builder
+                    .ins()
+                    .brif(condition, then_block, &[], else_block, &[]);
+                for (block, body) in [(then_block, &x.body), (else_block, &x.else_body)] {
+                    self.builder.switch_to_block(block);
+                    self.builder.seal_block(block);
+                    self.statements(body);
+                    self.builder.ins().jump(merge, &[]);
+                }
+                self.builder.switch_to_block(merge);
+                self.builder.seal_block(merge);
+            }
+            Statement::While(x) => {
+                let (header, body, exit) = (
+                    self.builder.create_block(),
+                    self.builder.create_block(),
+                    self.builder.create_block(),
+                );
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                let condition = self.expr(&x.conditional);
+                self.builder.ins().brif(condition, body, &[], exit, &[]);
+                self.builder.switch_to_block(body);
+                self.builder.seal_block(body);
+                self.statements(&x.body);
+                self.builder.ins().jump(header, &[]);
+                self.builder.seal_block(header);
+                self.builder.switch_to_block(exit);
+                self.builder.seal_block(exit);
+            }
+            Statement::Call(x) => {
+                self.expr(x);
+            }
+            Statement::Built(BuiltIn::Return(x, _)) => {
+                let value = self.expr(x);
+                let value = self.builder.ins().sextend(types::I64, value);
+                self.builder.ins().return_(&[value]);
+                // anything after the return goes in a block nothing jumps to
+                let dead = self.builder.create_block();
+                self.builder.switch_to_block(dead);
+                self.builder.seal_block(dead);
+            }
+            Statement::Built(BuiltIn::Drop(x)) => {
+                let ExprAST::Variable(_, _, resolution) = x else {
+                    unreachable!();
+                };
+                let zero = self.builder.ins().iconst(types::I8, 0);
+                self.builder
+                    .def_var(Self::bound_var(resolution.slot.unwrap()), zero);
+            }
+            _ => unreachable!("Only int statements are compiled."),
+        }
+    }
+    fn operator(&mut self, op: &Operator, lhs: Reg, rhs: Reg) -> Reg {
+        let ins = self.builder.ins();
+        let truth = match op {
+            Operator::And | Operator::Or | Operator::Xor => {
+                let l = ins.icmp_imm(IntCC::NotEqual, lhs, 0);
+                let r = self.builder.ins().icmp_imm(IntCC::NotEqual, rhs, 0);
+                let ins = self.builder.ins();
+                match op {
+                    Operator::And => ins.band(l, r),
+                    Operator::Or => ins.bor(l, r),
+                    _ => ins.bxor(l, r),
+                }
+            }
+            Operator::LEq => ins.icmp(IntCC::SignedLessThanOrEqual, lhs, rhs),
+            Operator::GEq => ins.icmp(IntCC::SignedGreaterThanOrEqual, lhs, rhs),
+            Operator::Eq => ins.icmp(IntCC::Equal, lhs, rhs),
+            Operator::Ls => ins.icmp(IntCC::SignedLessThan, lhs, rhs),
+            Operator::Gr => ins.icmp(IntCC::SignedGreaterThan, lhs, rhs),
+            Operator::BAnd => return ins.band(lhs, rhs),
+            Operator::BOr => return ins.bor(lhs, rhs),
+            Operator::BXor => return ins.bxor(lhs, rhs),
+            Operator::Add | Operator::Sub | Operator::Mult => {
+                // done in i64, where it can't overflow, then checked to fit
+                let l = ins.sextend(types::I64, lhs);
+                let r = self.builder.ins().sextend(types::I64, rhs);
+                let ins = self.builder.ins();
+                let wide = match op {
+                    Operator::Add => ins.iadd(l, r),
+                    Operator::Sub => ins.isub(l, r),
+                    _ => ins.imul(l, r),
+                };
+                let narrow = self.builder.ins().ireduce(types::I32, wide);
+                let back = self.builder.ins().sextend(types::I64, narrow);
+                let overflowed = self.builder.ins().icmp(IntCC::NotEqual, back, wide);
+                self.bail_if(overflowed);
+                return narrow;
+            }
+            Operator::Div | Operator::Mod => {
+                let zero = ins.icmp_imm(IntCC::Equal, rhs, 0);
+                self.bail_if(zero);
+                let ins = self.builder.ins();
+                let min = ins.icmp_imm(IntCC::Equal, lhs, i32::MIN as i64);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let overflowed = self.builder.ins().band(min, minus_one);
+                self.bail_if(overflowed);
+                return match op {
+                    Operator::Div => self.builder.ins().sdiv(lhs, rhs),
+                    _ => self.builder.ins().srem(lhs, rhs),
+                };
+            }
+        };
+        self.builder.ins().uextend(types::I32, truth)
+    }
+    fn expr(&mut self, expr: &ExprAST) -> Reg {
+        match expr {
+            ExprAST::Val(Value::Int(x)) => self.builder.ins().iconst(types::I32, *x as i64),
+            ExprAST::Variable(_, _, resolution) => {
+                let slot = resolution.slot.unwrap();
+                let bound = self.builder.use_var(Self::bound_var(slot));
+                let unbound = self.builder.ins().icmp_imm(IntCC::Equal, bound, 0);
+                self.bail_if(unbound);
+                self.builder.use_var(Self::value_var(slot))
+            }
+            ExprAST::BinOp(op, lhs, rhs, _) => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                self.operator(op, lhs, rhs)
+            }
+            ExprAST::Call(_, args, _, resolution) => {
+                let callee = self.refs[resolution.function.unwrap()].unwrap();
+                let depth = self.builder.ins().iadd_imm(self.depth, 1);
+                let mut values = vec![depth];
+                for arg in args {
+                    values.push(self.expr(&arg.value));
+                }
+                let call = self.builder.ins().call(callee, &values);
+                let result = self.builder.inst_results(call)[0];
+                // a callee that gave up makes its caller give up too
+                let bailed = self.builder.ins().icmp_imm(IntCC::Equal, result, BAIL);
+                self.bail_if(bailed);
+                self.builder.ins().ireduce(types::I32, result)
+            }
+            ExprAST::If(condition, then_expr, else_expr) => {
+                let condition = self.expr(condition);
+                let (then_block, else_block, merge) = (
+                    self.builder.create_block(),
+                    self.builder.create_block(),
+                    self.builder.create_block(),
+                );
+                let result = self.builder.append_block_param(merge, types::I32);
+                self.builder
+                    .ins()
+                    .brif(condition, then_block, &[], else_block, &[]);
+                for (block, branch) in [(then_block, then_expr), (else_block, else_expr)] {
+                    self.builder.switch_to_block(block);
+                    self.builder.seal_block(block);
+                    let value = self.expr(branch);
+                    self.builder.ins().jump(merge, &[value]);
+                }
+                self.builder.switch_to_block(merge);
+                self.builder.seal_block(merge);
+                result
+            }
+            _ => unreachable!("Only int expressions are compiled."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, rc::Rc};
+
+    use super::Jit;
+
+    // The functions that get compiled. `name` keeps the temp file apart from
+    // the other tests'.
+    fn compiled_names(name: &str, source: &str) -> Vec<String> {
+        let path =
+            env::temp_dir().join(format!("willscript-jit-{}-{}.ws", std::process::id(), name));
+        fs::write(&path, source).unwrap();
+        let program = crate::load(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let functions: Vec<_> = program.functions.into_iter().map(Rc::new).collect();
+        let jit = Jit::new(&functions).expect("this machine has a Cranelift backend");
+        functions
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| jit.is_compiled(*i))
+            .map(|(_, f)| f.proto.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn compiles_fib_and_loop_from_willcode() {
+        let source = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/willcode.ws"));
+        assert_eq!(
+            compiled_names("willcode", &source.unwrap()),
+            ["fib", "loop"]
+        );
+    }
+
+    #[test]
+    fn leaves_callers_of_interpreted_functions() {
+        let names = compiled_names(
+            "callers",
+            "global g = 1;\n\
+             fun square(x) {\n\treturn x * x;\n}\n\
+             fun shout(x) {\n\tprint x;\n\treturn x;\n}\n\
+             fun both(x) {\n\treturn square(x) + shout(x);\n}\n\
+             fun uses_global(x) {\n\treturn x + g;\n}\n\
+             fun sum(n) {\n\tvar total = 0;\n\twhile n > 0 {\n\t\ttotal += square(n);\n\t\tn -= 1;\n\t}\n\treturn total;\n}\n\
+             fun main() {\n\tprint sum(3);\n\treturn 0;\n}\n",
+        );
+        assert_eq!(names, ["square", "sum"]);
+    }
+}
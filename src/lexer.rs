@@ -1,9 +1,14 @@
-use std::str::Chars;
+use std::{fmt, str::Chars};
 
 pub struct LexingMachine<'a> {
     cur_char: char,
     chars: Chars<'a>,
     lexing_finished: bool,
+    // where cur_char is, and where the token being lexed started
+    cur_span: Span,
+    tok_span: Span,
+    // every `#` comment, with where its `#` is, for the formatter
+    comments: Vec<(Span, String)>,
 }
 impl<'a> LexingMachine<'a> {
     pub fn new(cur_char: char, chars: Chars<'a>) -> Self {
@@ -11,20 +16,35 @@ impl<'a> LexingMachine<'a> {
             cur_char,
             chars,
             lexing_finished: false,
+            cur_span: Span::new(1, 1),
+            tok_span: Span::new(1, 1),
+            comments: Vec::new(),
         }
     }
-    pub fn activate_lexing(&mut self) -> Vec<Token> {
+    /// The tokens up to the end of the file, or the first thing that isn't
+    /// one and where it starts.
+    pub fn activate_lexing(&mut self) -> Result<Vec<(Token, Span)>, (Span, String)> {
         let mut tokvec = Vec::new();
         loop {
-            let tok = self.get_token();
-            tokvec.push(tok.clone());
+            let tok = self.get_token().map_err(|e| (self.tok_span, e))?;
+            tokvec.push((tok.clone(), self.tok_span));
             if let Token::EndOfFile = tok {
                 break;
             }
         }
-        tokvec
+        Ok(tokvec)
+    }
+    /// The comments lexing skipped, each with the text after its `#`.
+    pub fn take_comments(&mut self) -> Vec<(Span, String)> {
+        std::mem::take(&mut self.comments)
     }
     fn eat_char(&mut self) {
+        if self.cur_char == '\n' {
+            self.cur_span.line += 1;
+            self.cur_span.col = 1;
+        } else {
+            self.cur_span.col += 1;
+        }
         self.cur_char = match self.chars.next() {
             Some(x) => x,
             None => {
@@ -34,66 +54,74 @@ impl<'a> LexingMachine<'a> {
         }
     }
     fn cur_is_alpha(&self, no_nums: bool) -> bool {
-        if (self.cur_char >= 'a' && self.cur_char <= 'z')
-            || (self.cur_char >= 'A' && self.cur_char <= 'Z')
-        {
-            true
-        } else if !no_nums && self.cur_char >= '0' && self.cur_char <= '9' {
-            true
-        } else {
-            false
-        }
+        self.cur_char.is_ascii_alphabetic()
+            || self.cur_char == '_'
+            || (!no_nums && self.cur_is_digit())
     }
     fn cur_is_digit(&self) -> bool {
-        if self.cur_char >= '0' && self.cur_char <= '9' {
-            true
+        self.cur_char.is_ascii_digit()
+    }
+    fn cur_is_op(&self) -> bool {
+        matches!(
+            self.cur_char,
+            '<' | '>' | '&' | '=' | '|' | '^' | '+' | '-' | '*' | '/' | '%'
+        )
+    }
+    // `op=` is a compound assignment, otherwise it's just the operator
+    fn op_or_compound(&mut self, op: Operator) -> Token {
+        if self.cur_char == '=' {
+            self.eat_char();
+            Token::CompoundAssign(op)
         } else {
-            false
+            Token::Op(op)
         }
     }
-    fn cur_is_op(&self) -> bool {
-        match self.cur_char {
-            '<' => true,
-            '>' => true,
-            '&' => true,
-            '=' => true,
-            '|' => true,
-            '^' => true,
-            '+' => true,
-            '-' => true,
-            '*' => true,
-            '/' => true,
-            _ => false,
+    // a comment runs from # to the end of the line
+    fn skip_comment(&mut self) {
+        let span = self.cur_span;
+        self.eat_char(); // eats the #
+        let mut text = String::new();
+        while self.cur_char != '\n' && !self.lexing_finished {
+            text.push(self.cur_char);
+            self.eat_char();
         }
+        self.eat_char();
+        self.comments.push((span, text.trim_end().to_owned()));
     }
-    fn get_token(&mut self) -> Token {
+    fn get_token(&mut self) -> Result<Token, String> {
+        self.tok_span = self.cur_span;
         if self.lexing_finished {
-            return Token::EndOfFile;
+            return Ok(Token::EndOfFile);
         }
-        if self.cur_char == '#' {
-            while self.cur_char != '\n' {
+        loop {
+            if self.cur_char == '#' {
+                self.skip_comment();
+            } else if self.cur_char.is_ascii_whitespace() {
+                if self.lexing_finished {
+                    self.tok_span = self.cur_span;
+                    return Ok(Token::EndOfFile);
+                }
                 self.eat_char();
+            } else {
+                break;
             }
-            self.eat_char();
-        }
-        while self.cur_char.is_ascii_whitespace() {
-            if self.lexing_finished {
-                return Token::EndOfFile;
-            }
-            self.eat_char();
         }
-        //Whitespace done
+        //Whitespace and comments done
+        self.tok_span = self.cur_span;
         if self.cur_char == '"' {
             self.eat_char();
             //eat "
             let mut string_str = String::new();
             string_str.push(' ');
-            while self.cur_char != '"' || string_str[string_str.len() - 1..] == *"\\" {
+            while self.cur_char != '"' || string_str.ends_with('\\') {
+                if self.lexing_finished {
+                    return Err("The string never ends.".to_owned());
+                }
                 string_str.push(self.cur_char);
                 self.eat_char();
             }
             self.eat_char();
-            return Token::Str(string_str[1..].to_owned());
+            return Ok(Token::Str(string_str[1..].to_owned()));
         }
         if self.cur_is_alpha(true) {
             let mut ident_str = String::new();
@@ -103,19 +131,33 @@ impl<'a> LexingMachine<'a> {
                 ident_str.push(self.cur_char);
                 self.eat_char();
             }
-            return match ident_str.as_str() {
+            return Ok(match ident_str.as_str() {
                 "var" => Token::Var,
+                "let" => Token::Let,
                 "if" => Token::If,
+                "else" => Token::Else,
                 "fun" => Token::Fun,
+                "struct" => Token::Struct,
+                "enum" => Token::Enum,
+                "const" => Token::Const,
+                "global" => Token::Global,
+                "match" => Token::Match,
                 "return" => Token::Return,
                 "print" => Token::Print,
                 "input" => Token::Input,
                 "drop" => Token::Drop,
                 "while" => Token::While,
-                "true" => Token::Number(1),
-                "false" => Token::Number(0),
+                "try" => Token::Try,
+                "catch" => Token::Catch,
+                "finally" => Token::Finally,
+                "throw" => Token::Throw,
+                "import" => Token::Import,
+                "export" => Token::Export,
+                "as" => Token::As,
+                "true" => Token::Bool(true),
+                "false" => Token::Bool(false),
                 x => Token::Identifier(x.to_owned()),
-            };
+            });
         }
         //And thats Identifiers done!
         if self.cur_is_digit() {
@@ -126,87 +168,109 @@ impl<'a> LexingMachine<'a> {
                 dig_string.push(self.cur_char);
                 self.eat_char();
             }
-            let num: i32 = dig_string
-                .parse()
-                .expect("Critical error in parsing number");
-            return Token::Number(num);
+            let Ok(num) = dig_string.parse::<i32>() else {
+                return Err(format!("{} is too big for a number.", dig_string));
+            };
+            return Ok(Token::Number(num));
         }
         //Numbers done!
         if self.cur_is_op() {
+            let this_char = self.cur_char;
             // big bundle of if statements
             // I don't know how else to handle finite atomata
             if self.cur_char == '<' {
                 self.eat_char();
                 if self.cur_is_op() {
                     if self.cur_char == '=' {
-                        return Token::Op(Operator::LEq);
+                        self.eat_char();
+                        return Ok(Token::Op(Operator::LEq));
                     } else {
-                        panic!("Lexing Error, bad operator");
+                        return Err(format!("Bad operator starting with '{}'.", this_char));
                     }
                 } else {
-                    return Token::Op(Operator::Ls);
+                    return Ok(Token::Op(Operator::Ls));
                 }
             } else if self.cur_char == '>' {
                 self.eat_char();
                 if self.cur_is_op() {
                     if self.cur_char == '=' {
-                        return Token::Op(Operator::GEq);
+                        self.eat_char();
+                        return Ok(Token::Op(Operator::GEq));
                     } else {
-                        panic!("Lexing Error, bad operator");
+                        return Err(format!("Bad operator starting with '{}'.", this_char));
                     }
                 } else {
-                    return Token::Op(Operator::Gr);
+                    return Ok(Token::Op(Operator::Gr));
                 }
             } else if self.cur_char == '=' {
                 self.eat_char();
                 if self.cur_is_op() {
                     if self.cur_char == '=' {
-                        return Token::Op(Operator::Eq);
+                        self.eat_char();
+                        return Ok(Token::Op(Operator::Eq));
+                    } else if self.cur_char == '>' {
+                        self.eat_char();
+                        return Ok(Token::FatArrow);
                     } else {
-                        panic!("Lexing Error, bad operator");
+                        return Err(format!("Bad operator starting with '{}'.", this_char));
                     }
                 } else {
-                    return Token::Assignment;
+                    return Ok(Token::Assignment);
                 }
             } else if self.cur_char == '&' {
                 self.eat_char();
                 if self.cur_char == '&' {
-                    return Token::Op(Operator::And);
+                    self.eat_char();
+                    return Ok(Token::Op(Operator::And));
+                } else if self.cur_char == '=' {
+                    self.eat_char();
+                    return Ok(Token::CompoundAssign(Operator::BAnd));
                 } else if self.cur_is_op() {
-                    panic!("Lexing Error, bad operator");
+                    return Err(format!("Bad operator starting with '{}'.", this_char));
                 } else {
-                    return Token::Op(Operator::BAnd);
+                    return Ok(Token::Op(Operator::BAnd));
                 }
             } else if self.cur_char == '|' {
                 self.eat_char();
                 if self.cur_char == '|' {
-                    return Token::Op(Operator::Or);
+                    self.eat_char();
+                    return Ok(Token::Op(Operator::Or));
+                } else if self.cur_char == '=' {
+                    self.eat_char();
+                    return Ok(Token::CompoundAssign(Operator::BOr));
                 } else if self.cur_is_op() {
-                    panic!("Lexing Error, bad operator");
+                    return Err(format!("Bad operator starting with '{}'.", this_char));
                 } else {
-                    return Token::Op(Operator::BOr);
+                    return Ok(Token::Op(Operator::BOr));
                 }
             } else if self.cur_char == '^' {
                 self.eat_char();
                 if self.cur_char == '^' {
-                    return Token::Op(Operator::Xor);
+                    self.eat_char();
+                    return Ok(Token::Op(Operator::Xor));
+                } else if self.cur_char == '=' {
+                    self.eat_char();
+                    return Ok(Token::CompoundAssign(Operator::BXor));
                 } else if self.cur_is_op() {
-                    panic!("Lexing Error, bad operator");
+                    return Err(format!("Bad operator starting with '{}'.", this_char));
                 } else {
-                    return Token::Op(Operator::BXor);
+                    return Ok(Token::Op(Operator::BXor));
                 }
             } else if self.cur_char == '+' {
                 self.eat_char();
-                return Token::Op(Operator::Add);
+                return Ok(self.op_or_compound(Operator::Add));
             } else if self.cur_char == '-' {
                 self.eat_char();
-                return Token::Op(Operator::Sub);
+                return Ok(self.op_or_compound(Operator::Sub));
             } else if self.cur_char == '*' {
                 self.eat_char();
-                return Token::Op(Operator::Mult);
+                return Ok(self.op_or_compound(Operator::Mult));
             } else if self.cur_char == '/' {
                 self.eat_char();
-                return Token::Op(Operator::Div);
+                return Ok(self.op_or_compound(Operator::Div));
+            } else if self.cur_char == '%' {
+                self.eat_char();
+                return Ok(self.op_or_compound(Operator::Mod));
             } else {
                 unreachable!();
             }
@@ -215,52 +279,101 @@ impl<'a> LexingMachine<'a> {
         // I might be able to fix it with some match statements.
         let this_char = self.cur_char;
         self.eat_char();
-        return match this_char {
+        Ok(match this_char {
             '(' => Token::LeftParen,
             ')' => Token::RightParen,
             '{' => Token::LeftCurly,
             '}' => Token::RightCurly,
+            '[' => Token::LeftSquare,
+            ']' => Token::RightSquare,
             ',' => Token::Comma,
-            ';' => Token::Semicolon,
-            '#' => {
-                self.cur_char = '#';
-                self.get_token()
-            }
-            x => {
-                eprintln!("{x}");
-                panic!("unexpected char (are you only using ASCII");
+            ':' => Token::Colon,
+            '?' => Token::Question,
+            '.' => {
+                if self.cur_char != '.' {
+                    return Ok(Token::Dot);
+                }
+                self.eat_char();
+                if self.cur_char != '.' {
+                    return Err("'..' should be '.' or '...'.".to_owned());
+                }
+                self.eat_char();
+                Token::Ellipsis
             }
-        };
+            ';' => Token::Semicolon,
+            x => return Err(format!("Unexpected character '{}'.", x)),
+        })
         //Nice clean ending, with all the other chars.
     }
 }
 
+/// Where a token starts in the source, both counted from 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+}
+impl Span {
+    pub fn new(line: u32, col: u32) -> Self {
+        Span { line, col }
+    }
+}
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Token {
     // Add more when the time comes
     Identifier(String),
     Number(i32),
+    /// `true` or `false`, which are the ints 1 and 0.
+    Bool(bool),
     Str(String),
     Var,
+    Let,
     Fun,
+    Struct,
+    Enum,
+    Const,
+    Global,
+    Match,
+    FatArrow,
     LeftParen,
     RightParen,
     LeftCurly,
     RightCurly,
+    LeftSquare,
+    RightSquare,
     Op(Operator),
     Return,
     If,
+    Else,
     While,
+    Try,
+    Catch,
+    Finally,
+    Throw,
+    Import,
+    Export,
+    As,
     Assignment,
+    CompoundAssign(Operator),
     Semicolon,
     Comma,
+    Colon,
+    Dot,
+    Ellipsis,
+    Question,
     EndOfFile,
     Print,
     Input,
     Drop,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Operator {
     //Logical
     And,
@@ -281,4 +394,28 @@ pub enum Operator {
     Sub,
     Mult,
     Div,
+    Mod,
+}
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Xor => "^^",
+            Operator::LEq => "<=",
+            Operator::GEq => ">=",
+            Operator::Eq => "==",
+            Operator::Ls => "<",
+            Operator::Gr => ">",
+            Operator::BAnd => "&",
+            Operator::BOr => "|",
+            Operator::BXor => "^",
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mult => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+        };
+        write!(f, "{}", symbol)
+    }
 }
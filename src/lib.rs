@@ -0,0 +1,140 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use checker::check_program;
+use debugger::{Breakpoints, Cli, Debugger};
+use interpreter::InterpretingMastermind;
+use lexer::Span;
+use modules::load_program;
+use resolver::resolve_program;
+use vm::Vm;
+
+pub use ast::{ErrorValue, ProgramAST};
+pub use interpreter::MAX_DEPTH;
+
+mod ast;
+mod builtins;
+mod bytecode;
+mod cgen;
+mod checker;
+mod compiler;
+mod dap;
+mod debugger;
+mod formatter;
+mod interpreter;
+#[cfg(feature = "jit")]
+mod jit;
+mod json;
+mod lexer;
+mod lsp;
+mod modules;
+mod optimizer;
+mod parser;
+mod resolver;
+mod vm;
+mod watgen;
+
+/// How a program gets run. Both give the same output and errors, the VM
+/// just gets there faster.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Walks the AST directly.
+    #[default]
+    Tree,
+    /// Compiles to bytecode first, and runs that on a stack machine.
+    Vm,
+    /// Walks the AST, except for the functions that only do integer work,
+    /// which run as native code compiled with Cranelift.
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+/// What `willscript build` can translate a program to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    /// A standalone C file, for the system C compiler. Only programs of ints
+    /// and strings translate.
+    C,
+    /// A WebAssembly text module, with `print` and `input` imported from the
+    /// host. Only programs of ints translate.
+    Wat,
+}
+
+/// Reads, parses and checks the script at `path` and everything it imports,
+/// then resolves every name in it to a slot, global or function.
+pub fn load(path: &Path) -> Result<ProgramAST, String> {
+    let mut program = load_program(path)?;
+    check_program(&program)?;
+    resolve_program(&mut program)?;
+    Ok(program)
+}
+
+/// Gives back the source of one file formatted the canonical way, with its
+/// comments kept, or why it doesn't parse. Imports aren't followed.
+pub fn format(source: &str) -> Result<String, String> {
+    formatter::format_source(source)
+}
+
+/// Runs a loaded program in the command line debugger, which stops before
+/// the first statement and reads commands from `input`. What the program
+/// prints goes to `output` along with what the debugger says.
+pub fn debug(
+    program: ProgramAST,
+    source: &str,
+    input: impl BufRead + 'static,
+    output: impl Write + 'static,
+) -> Result<(), Rc<ErrorValue>> {
+    let frontend = Cli::new(source, Box::new(input), Box::new(output));
+    let debugger = Debugger::new(&program, Breakpoints::default(), Box::new(frontend), true);
+    InterpretingMastermind::new(program)
+        .with_debugger(debugger)
+        .run_main()
+}
+
+/// Serves the Debug Adapter Protocol on `input` and `output` for one launch
+/// of one program, until the client disconnects.
+pub fn serve_dap(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<()> {
+    dap::serve(Box::new(input), Box::new(output))
+}
+
+/// Runs a language server on `input` and `output` until the client says to
+/// exit, giving back whether it asked for a shutdown first.
+pub fn serve_lsp(input: impl BufRead, output: impl Write) -> io::Result<bool> {
+    lsp::serve(input, output)
+}
+
+/// Rewrites a loaded program to do the same work faster: constant operators
+/// are folded, code that can never run is removed and small functions are
+/// inlined. Calls that got inlined don't show up in stack traces.
+pub fn optimize(program: &mut ProgramAST) {
+    optimizer::optimize(&mut program.functions);
+}
+
+/// Translates a loaded program to source in another language, or says what
+/// in it can't be translated.
+pub fn emit(program: &ProgramAST, format: Emit) -> Result<String, String> {
+    match format {
+        Emit::C => cgen::generate(program),
+        Emit::Wat => watgen::generate(program),
+    }
+}
+
+/// Runs a loaded program, giving back the error if one was thrown and never
+/// caught. Script calls recurse on the Rust stack under the tree-walker, so
+/// it needs room for MAX_DEPTH of them.
+pub fn run(program: ProgramAST, backend: Backend) -> Result<(), Rc<ErrorValue>> {
+    // load makes sure of main, but a program can be put together without it
+    if !program.functions.iter().any(|f| &*f.proto.name == "main") {
+        let e = ErrorValue::new("NameError", "There is no main function.".to_owned());
+        return Err(e.at(Span::default()));
+    }
+    match backend {
+        Backend::Tree => InterpretingMastermind::new(program).run_main(),
+        Backend::Vm => Vm::new(program).run_main(),
+        #[cfg(feature = "jit")]
+        Backend::Jit => InterpretingMastermind::new(program).with_jit().run_main(),
+    }
+}
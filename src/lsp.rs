@@ -0,0 +1,820 @@
+//! `willscript lsp`, a language server speaking the Language Server Protocol
+//! over stdin and stdout. Every open document is lexed and parsed again on
+//! each change. Names are looked up from the tokens, so going to definitions
+//! and completing keeps working while a file doesn't parse.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    ast::{EnumAST, ProgramAST, PrototypeAST},
+    builtins::BUILTINS,
+    formatter::format_source,
+    interpreter::HIGHER_ORDER,
+    json::{Json, read_message, write_message},
+    lexer::{LexingMachine, Span, Token},
+    parser::{Mark, ParsingMachine},
+};
+
+const KEYWORDS: [&str; 24] = [
+    "var", "let", "if", "else", "fun", "struct", "enum", "const", "global", "match", "return",
+    "print", "input", "drop", "while", "try", "catch", "finally", "throw", "import", "export",
+    "as", "true", "false",
+];
+
+// JSON-RPC and LSP error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const REQUEST_FAILED: i32 = -32803;
+
+// LSP symbol and completion item kinds
+const SYMBOL_FUNCTION: u32 = 12;
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_KEYWORD: u32 = 14;
+const COMPLETION_STRUCT: u32 = 22;
+const COMPLETION_ENUM_MEMBER: u32 = 20;
+
+/// Answers requests until the client sends `exit`, then gives back whether
+/// it asked for a shutdown first, which the exit code is supposed to say.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<bool> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shut_down: false,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        match message {
+            Ok(message) => {
+                if server.handle(&message)? {
+                    break;
+                }
+            }
+            Err(e) => server.respond_error(&Json::Null, PARSE_ERROR, e)?,
+        }
+    }
+    Ok(server.shut_down)
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+impl<W: Write> Server<W> {
+    // Handles one message, saying whether it was the last.
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let Some(method) = message.get("method").as_str() else {
+            // a response to something we never ask
+            return Ok(false);
+        };
+        let params = message.get("params");
+        let id = message.get("id");
+        if *id == Json::Null {
+            self.notification(method, params)?;
+            return Ok(method == "exit");
+        }
+        if self.shut_down {
+            return self
+                .respond_error(id, INVALID_REQUEST, "The server was shut down.".to_owned())
+                .map(|_| false);
+        }
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition"
+            | "textDocument/hover"
+            | "textDocument/documentSymbol"
+            | "textDocument/completion"
+            | "textDocument/formatting" => self.document_request(method, params),
+            _ => Err((METHOD_NOT_FOUND, format!("No method {}.", method))),
+        };
+        match result {
+            Ok(result) => self.respond(id, result)?,
+            Err((code, message)) => self.respond_error(id, code, message)?,
+        }
+        Ok(false)
+    }
+    fn notification(&mut self, method: &str, params: &Json) -> io::Result<()> {
+        let uri = params.get("textDocument").get("uri").as_str();
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.open(uri, text.unwrap_or_default())
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // only whole documents are synced, so the last change has it all
+                let changes = params.get("contentChanges").as_array();
+                match changes.last().and_then(|c| c.get("text").as_str()) {
+                    Some(text) => self.open(uri, text),
+                    None => Ok(()),
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri, vec![])
+            }
+            _ => Ok(()),
+        }
+    }
+    fn open(&mut self, uri: &str, text: &str) -> io::Result<()> {
+        let document = Document::new(text);
+        let diagnostics = match &document.error {
+            Some((span, message)) => vec![Json::object(vec![
+                ("range", range(*span, 1)),
+                ("severity", Json::from(1)),
+                ("source", Json::from("willscript")),
+                ("message", Json::from(message.as_str())),
+            ])],
+            None => vec![],
+        };
+        self.documents.insert(uri.to_owned(), document);
+        self.publish_diagnostics(uri, diagnostics)
+    }
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        self.send(&Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object(vec![
+                    ("uri", Json::from(uri)),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ]))
+    }
+    fn document_request(&self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        let uri = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .unwrap_or_default();
+        let Some(document) = self.documents.get(uri) else {
+            return Err((INVALID_PARAMS, format!("{} isn't open.", uri)));
+        };
+        let position = params.get("position");
+        let at = (
+            position.get("line").as_u32(),
+            position.get("character").as_u32(),
+        );
+        let at = match at {
+            (Some(line), Some(character)) => Span::new(line + 1, character + 1),
+            _ => Span::default(),
+        };
+        Ok(match method {
+            "textDocument/definition" => match document.definition(at) {
+                Some(span) => Json::object(vec![
+                    ("uri", Json::from(uri)),
+                    ("range", range(span, document.name_len(span))),
+                ]),
+                None => Json::Null,
+            },
+            "textDocument/hover" => document.hover(at).unwrap_or(Json::Null),
+            "textDocument/documentSymbol" => Json::Array(document.symbols()),
+            "textDocument/completion" => Json::Array(document.completions(at)),
+            _ => match format_source(&document.text) {
+                Ok(formatted) if formatted == document.text => Json::Array(vec![]),
+                Ok(formatted) => Json::Array(vec![Json::object(vec![
+                    ("range", document.whole_range()),
+                    ("newText", Json::from(formatted)),
+                ])]),
+                Err(e) => return Err((REQUEST_FAILED, e)),
+            },
+        })
+    }
+    fn respond(&mut self, id: &Json, result: Json) -> io::Result<()> {
+        self.send(&Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id.clone()),
+            ("result", result),
+        ]))
+    }
+    fn respond_error(&mut self, id: &Json, code: i32, message: String) -> io::Result<()> {
+        let error = Json::object(vec![
+            ("code", Json::Number(code as f64)),
+            ("message", Json::from(message)),
+        ]);
+        self.send(&Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id.clone()),
+            ("error", error),
+        ]))
+    }
+    fn send(&mut self, message: &Json) -> io::Result<()> {
+        write_message(&mut self.output, message)
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                // the whole text comes with every change
+                ("textDocumentSync", Json::from(1)),
+                ("definitionProvider", Json::from(true)),
+                ("hoverProvider", Json::from(true)),
+                ("documentSymbolProvider", Json::from(true)),
+                ("completionProvider", Json::object(vec![])),
+                ("documentFormattingProvider", Json::from(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", Json::from("willscript"))]),
+        ),
+    ])
+}
+
+// The LSP counts lines and characters from 0, spans from 1.
+fn position(span: Span) -> Json {
+    Json::object(vec![
+        ("line", Json::from(span.line.saturating_sub(1))),
+        ("character", Json::from(span.col.saturating_sub(1))),
+    ])
+}
+
+fn range(start: Span, len: u32) -> Json {
+    Json::object(vec![
+        ("start", position(start)),
+        ("end", position(Span::new(start.line, start.col + len))),
+    ])
+}
+
+// A name the document binds, and the index of its token.
+#[derive(Clone, Debug)]
+struct Symbol {
+    name: String,
+    span: Span,
+    token: usize,
+}
+
+// A top-level function: its name, and the tokens from its `fun` to its `{`
+// and its `}`.
+struct Function {
+    symbol: Symbol,
+    start: usize,
+    body: usize,
+    end: Option<usize>,
+}
+
+// The locals of a function or closure, which run from its `fun` to its `}`.
+struct Scope {
+    start: usize,
+    end: usize,
+    parent: Option<usize>,
+    names: Vec<Symbol>,
+}
+
+struct Document {
+    text: String,
+    tokens: Vec<(Token, Span)>,
+    // where the lexer or parser gave up, and why
+    error: Option<(Span, String)>,
+    program: Option<ProgramAST>,
+    functions: Vec<Function>,
+    globals: Vec<Symbol>,
+    // structs, enums and variants
+    types: Vec<Symbol>,
+    variants: HashSet<String>,
+    scopes: Vec<Scope>,
+}
+impl Document {
+    fn new(text: &str) -> Self {
+        let mut document = Document {
+            text: text.to_owned(),
+            tokens: vec![],
+            error: None,
+            program: None,
+            functions: vec![],
+            globals: vec![],
+            types: vec![],
+            variants: EnumAST::prelude()
+                .iter()
+                .flat_map(|e| e.variants.iter().map(|v| v.name.to_string()))
+                .collect(),
+            scopes: vec![],
+        };
+        let mut chars = text.chars();
+        let Some(first) = chars.next() else {
+            return document;
+        };
+        let mut lexer = LexingMachine::new(first, chars);
+        match lexer.activate_lexing() {
+            Ok(tokens) => document.tokens = tokens,
+            Err(e) => {
+                document.error = Some(e);
+                return document;
+            }
+        }
+        let mut tokens = document.tokens.clone().into_iter().peekable();
+        let first_token = tokens.next().expect("Lexing always ends with EndOfFile");
+        let mut parser = ParsingMachine::new(first_token, tokens);
+        match parser.parse_located() {
+            Ok(program) => document.program = Some(program),
+            Err(e) => document.error = Some(e),
+        }
+        // what got parsed before an error still marks match arms
+        document.scan(&parser.take_marks());
+        document
+    }
+
+    fn identifier(&self, index: usize) -> Option<&str> {
+        match self.tokens.get(index) {
+            Some((Token::Identifier(name), _)) => Some(name),
+            _ => None,
+        }
+    }
+    fn is(&self, index: usize, token: fn(&Token) -> bool) -> bool {
+        self.tokens.get(index).is_some_and(|(t, _)| token(t))
+    }
+    fn symbol(&self, index: usize) -> Option<Symbol> {
+        let name = self.identifier(index)?;
+        Some(Symbol {
+            name: name.to_owned(),
+            span: self.tokens[index].1,
+            token: index,
+        })
+    }
+    // How long the name at `span` is, for its range.
+    fn name_len(&self, span: Span) -> u32 {
+        self.tokens
+            .iter()
+            .find(|(_, s)| *s == span)
+            .and_then(|(t, _)| match t {
+                Token::Identifier(name) => Some(name.chars().count() as u32),
+                _ => None,
+            })
+            .unwrap_or(1)
+    }
+
+    // Finds every name the document binds, and the scope each is in.
+    fn scan(&mut self, marks: &[Mark]) {
+        let starts: HashSet<Span> = marks
+            .iter()
+            .filter_map(|m| match m {
+                Mark::Start(span) => Some(*span),
+                _ => None,
+            })
+            .collect();
+        let mut depth = 0;
+        // the scopes whose bodies are open, with the depth inside them
+        let mut open: Vec<(usize, usize)> = vec![];
+        // the `{` each scope's body starts at, before the parser gets there
+        let mut bodies: HashMap<usize, usize> = HashMap::new();
+        // the `{` of each match, and the depths inside the open ones
+        let mut match_braces: HashSet<usize> = HashSet::new();
+        let mut match_depths: Vec<usize> = vec![];
+        // arm bindings, which turn out to be unit variants when an enum has one
+        // by that name
+        let mut arm_names: Vec<(usize, Symbol)> = vec![];
+        for i in 0..self.tokens.len() {
+            let scope = open.last().map(|(s, _)| *s);
+            let span = self.tokens[i].1;
+            if starts.contains(&span)
+                && match_depths.last() == Some(&depth)
+                && let Some(scope) = scope
+            {
+                for name in self.pattern_names(i) {
+                    arm_names.push((scope, name));
+                }
+            }
+            match &self.tokens[i].0 {
+                Token::LeftCurly => {
+                    depth += 1;
+                    if let Some(scope) = bodies.remove(&i) {
+                        open.push((scope, depth));
+                    }
+                    if match_braces.remove(&i) {
+                        match_depths.push(depth);
+                    }
+                }
+                Token::RightCurly => {
+                    if let Some(&(scope, d)) = open.last()
+                        && d == depth
+                    {
+                        open.pop();
+                        self.scopes[scope].end = i;
+                        let start = self.scopes[scope].start;
+                        if let Some(function) = self
+                            .functions
+                            .iter_mut()
+                            .find(|f| f.start == start || f.start + 1 == start)
+                        {
+                            function.end = Some(i);
+                        }
+                    }
+                    if match_depths.last() == Some(&depth) {
+                        match_depths.pop();
+                    }
+                    depth = depth.saturating_sub(1);
+                }
+                Token::Fun => {
+                    let named = open.is_empty() && self.identifier(i + 1).is_some();
+                    let paren = if named { i + 2 } else { i + 1 };
+                    let (params, close) = self.params(paren);
+                    self.scopes.push(Scope {
+                        start: i,
+                        end: usize::MAX,
+                        parent: scope,
+                        names: params,
+                    });
+                    bodies.insert(close + 1, self.scopes.len() - 1);
+                    if named {
+                        let exported = i > 0 && self.is(i - 1, |t| matches!(t, Token::Export));
+                        self.functions.push(Function {
+                            symbol: self.symbol(i + 1).unwrap(),
+                            start: if exported { i - 1 } else { i },
+                            body: close + 1,
+                            end: None,
+                        });
+                    }
+                }
+                Token::Var | Token::Let => {
+                    let names = self.declared_names(i + 1);
+                    if let Some(scope) = scope {
+                        self.scopes[scope].names.extend(names);
+                    }
+                }
+                Token::Catch => {
+                    if let (Some(scope), Some(name)) = (scope, self.symbol(i + 1)) {
+                        self.scopes[scope].names.push(name);
+                    }
+                }
+                Token::Const | Token::Global if open.is_empty() => {
+                    self.globals.extend(self.symbol(i + 1));
+                }
+                Token::Struct if open.is_empty() => self.types.extend(self.symbol(i + 1)),
+                Token::Enum if open.is_empty() => {
+                    self.types.extend(self.symbol(i + 1));
+                    let mut j = i + 3;
+                    // a variant follows the `{` or a `,`
+                    while self.identifier(j).is_some() {
+                        let variant = self.symbol(j).unwrap();
+                        self.variants.insert(variant.name.clone());
+                        self.types.push(variant);
+                        j += 1;
+                        if self.is(j, |t| matches!(t, Token::LeftParen)) {
+                            while !self.is(j, |t| matches!(t, Token::RightParen | Token::EndOfFile))
+                            {
+                                j += 1;
+                            }
+                            j += 1;
+                        }
+                        if !self.is(j, |t| matches!(t, Token::Comma)) {
+                            break;
+                        }
+                        j += 1;
+                    }
+                }
+                Token::Match => {
+                    if let Some(brace) = self.match_brace(i + 1) {
+                        match_braces.insert(brace);
+                    }
+                }
+                _ => (),
+            }
+        }
+        for (scope, name) in arm_names {
+            if !self.variants.contains(&name.name) {
+                self.scopes[scope].names.push(name);
+            }
+        }
+        for scope in self.scopes.iter_mut() {
+            scope.names.sort_by_key(|n| n.token);
+        }
+    }
+    // The parameters in the parens starting at `paren`, and where they close.
+    fn params(&self, paren: usize) -> (Vec<Symbol>, usize) {
+        let mut names = vec![];
+        let mut nesting = 0;
+        let mut i = paren;
+        while i < self.tokens.len() {
+            match &self.tokens[i].0 {
+                Token::LeftParen | Token::LeftSquare | Token::LeftCurly => nesting += 1,
+                Token::RightParen | Token::RightSquare | Token::RightCurly => {
+                    nesting -= 1;
+                    if nesting <= 0 {
+                        return (names, i);
+                    }
+                }
+                Token::Identifier(_)
+                    if nesting == 1
+                        && self.is(i - 1, |t| {
+                            matches!(t, Token::LeftParen | Token::Comma | Token::Ellipsis)
+                        }) =>
+                {
+                    names.extend(self.symbol(i));
+                }
+                Token::EndOfFile => return (names, i),
+                _ => (),
+            }
+            i += 1;
+        }
+        (names, i)
+    }
+    // The names a `var` or `let` binds, which can be destructured out of a
+    // tuple, list or struct.
+    fn declared_names(&self, from: usize) -> Vec<Symbol> {
+        let mut names = vec![];
+        let mut i = from;
+        while !self.is(i, |t| {
+            matches!(t, Token::Assignment | Token::Semicolon | Token::EndOfFile)
+        }) {
+            if let Some(name) = self.identifier(i)
+                && name != "_"
+                // a struct name or a field name, not a binding
+                && !self.is(i + 1, |t| matches!(t, Token::LeftCurly | Token::Colon))
+            {
+                names.extend(self.symbol(i));
+            }
+            i += 1;
+        }
+        names
+    }
+    // The names a match arm's pattern binds, from its first token to its `=>`
+    // or guard. Variant names come out later.
+    fn pattern_names(&self, from: usize) -> Vec<Symbol> {
+        let mut names = vec![];
+        let mut i = from;
+        while !self.is(i, |t| {
+            matches!(t, Token::FatArrow | Token::If | Token::EndOfFile)
+        }) {
+            if let Some(name) = self.identifier(i)
+                && name != "_"
+                && !self.is(i + 1, |t| matches!(t, Token::LeftParen))
+            {
+                names.extend(self.symbol(i));
+            }
+            i += 1;
+        }
+        names
+    }
+    // The `{` a match's arms start after, the first outside any brackets.
+    fn match_brace(&self, from: usize) -> Option<usize> {
+        let mut nesting = 0;
+        for i in from..self.tokens.len() {
+            match self.tokens[i].0 {
+                Token::LeftParen | Token::LeftSquare => nesting += 1,
+                Token::RightParen | Token::RightSquare => nesting -= 1,
+                Token::LeftCurly if nesting == 0 => return Some(i),
+                Token::Semicolon | Token::EndOfFile => return None,
+                _ => (),
+            }
+        }
+        None
+    }
+
+    // The token at `at`, counting the spot just after a name as on it.
+    fn token_at(&self, at: Span) -> Option<usize> {
+        self.tokens.iter().position(|(token, span)| {
+            let len = match token {
+                Token::Identifier(name) => name.chars().count() as u32,
+                _ => 1,
+            };
+            span.line == at.line && span.col <= at.col && at.col <= span.col + len
+        })
+    }
+    // The innermost function or closure `index` is in.
+    fn scope_at(&self, index: usize) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rposition(|s| s.start <= index && index <= s.end)
+    }
+    // Looks a name up the way the interpreter would: a local of the function
+    // it's in or of one around it, a global, a function, then a struct, enum or
+    // variant. Of a function's locals, the last declared before it wins.
+    fn lookup(&self, name: &str, index: usize) -> Option<&Symbol> {
+        let mut scope = self.scope_at(index);
+        while let Some(s) = scope {
+            let mut names = self.scopes[s].names.iter().filter(|n| n.name == name);
+            let first = names.clone().next();
+            if let Some(found) = names.rfind(|n| n.token <= index).or(first) {
+                return Some(found);
+            }
+            scope = self.scopes[s].parent;
+        }
+        self.globals
+            .iter()
+            .chain(self.functions.iter().map(|f| &f.symbol))
+            .chain(self.types.iter())
+            .find(|s| s.name == name)
+    }
+    // The name at `at`, unless it's a field or named argument, which don't
+    // refer to anything here.
+    fn name_at(&self, at: Span) -> Option<(usize, &str)> {
+        let index = self.token_at(at)?;
+        let name = self.identifier(index)?;
+        let is_field = index > 0 && self.is(index - 1, |t| matches!(t, Token::Dot));
+        let is_label = self.is(index + 1, |t| matches!(t, Token::Colon));
+        (!is_field && !is_label).then_some((index, name))
+    }
+    fn definition(&self, at: Span) -> Option<Span> {
+        let (index, name) = self.name_at(at)?;
+        self.lookup(name, index).map(|s| s.span)
+    }
+    fn hover(&self, at: Span) -> Option<Json> {
+        let (index, name) = self.name_at(at)?;
+        let text = match self.lookup(name, index) {
+            Some(symbol) => {
+                let function = self
+                    .functions
+                    .iter()
+                    .find(|f| f.symbol.token == symbol.token)?;
+                self.describe(function)
+            }
+            None if BUILTINS.contains(&name) || HIGHER_ORDER.contains(&name) || name == "error" => {
+                format!("```willscript\n{}\n```\nA builtin function.", name)
+            }
+            None => return None,
+        };
+        Some(Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![
+                    ("kind", Json::from("markdown")),
+                    ("value", Json::from(text)),
+                ]),
+            ),
+            (
+                "range",
+                range(self.tokens[index].1, name.chars().count() as u32),
+            ),
+        ]))
+    }
+    // A function's prototype, and how many arguments it takes.
+    fn describe(&self, function: &Function) -> String {
+        let mut text = format!("```willscript\n{}\n```", self.signature(function));
+        let proto = self.program.as_ref().and_then(|p| {
+            p.functions
+                .iter()
+                .map(|f| &f.proto)
+                .find(|proto| *proto.name == function.symbol.name)
+        });
+        if let Some(proto) = proto {
+            text.push_str(&format!("\n{}", takes(proto)));
+        }
+        text
+    }
+    // The prototype as the formatter would write it, or as written when it
+    // doesn't parse on its own.
+    fn signature(&self, function: &Function) -> String {
+        let fun = if self.is(function.start, |t| matches!(t, Token::Export)) {
+            function.start + 1
+        } else {
+            function.start
+        };
+        let (Some(from), Some(to)) = (
+            self.tokens.get(fun).map(|(_, s)| self.offset(*s)),
+            self.tokens.get(function.body).map(|(_, s)| self.offset(*s)),
+        ) else {
+            return function.symbol.name.clone();
+        };
+        let written = &self.text[from..to];
+        match format_source(&format!("{}{{}}", written)) {
+            Ok(formatted) => formatted
+                .trim_end()
+                .trim_end_matches("{}")
+                .trim_end()
+                .to_owned(),
+            Err(_) => written.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+    // The byte offset of `span` in the text.
+    fn offset(&self, span: Span) -> usize {
+        let line_start: usize = self
+            .text
+            .split_inclusive('\n')
+            .take(span.line as usize - 1)
+            .map(|l| l.len())
+            .sum();
+        let line = &self.text[line_start..];
+        line_start
+            + line
+                .char_indices()
+                .nth(span.col as usize - 1)
+                .map_or(line.len(), |(i, _)| i)
+    }
+    fn whole_range(&self) -> Json {
+        let lines = self.text.split('\n').count() as u32;
+        let last = self.text.rsplit('\n').next().unwrap_or_default();
+        Json::object(vec![
+            ("start", position(Span::new(1, 1))),
+            (
+                "end",
+                position(Span::new(lines, last.encode_utf16().count() as u32 + 1)),
+            ),
+        ])
+    }
+    fn symbols(&self) -> Vec<Json> {
+        self.functions
+            .iter()
+            .map(|function| {
+                let name = &function.symbol;
+                let name_range = range(name.span, name.name.chars().count() as u32);
+                let whole = match function.end {
+                    Some(end) => Json::object(vec![
+                        ("start", position(self.tokens[function.start].1)),
+                        (
+                            "end",
+                            position(Span::new(
+                                self.tokens[end].1.line,
+                                self.tokens[end].1.col + 1,
+                            )),
+                        ),
+                    ]),
+                    None => name_range.clone(),
+                };
+                Json::object(vec![
+                    ("name", Json::from(name.name.as_str())),
+                    ("detail", Json::from(self.signature(function))),
+                    ("kind", Json::from(SYMBOL_FUNCTION)),
+                    ("range", whole),
+                    ("selectionRange", name_range),
+                ])
+            })
+            .collect()
+    }
+    // Every name that could be written at `at`: the locals in scope, then
+    // globals, functions, types and keywords.
+    fn completions(&self, at: Span) -> Vec<Json> {
+        let index = self
+            .tokens
+            .iter()
+            .rposition(|(_, span)| *span <= at)
+            .unwrap_or(0);
+        let mut seen = HashSet::new();
+        let mut items = vec![];
+        let mut add = |label: &str, kind: u32, detail: &str| {
+            if seen.insert(label.to_owned()) {
+                items.push(Json::object(vec![
+                    ("label", Json::from(label)),
+                    ("kind", Json::from(kind)),
+                    ("detail", Json::from(detail)),
+                ]));
+            }
+        };
+        let mut scope = self.scope_at(index);
+        while let Some(s) = scope {
+            for name in self.scopes[s].names.iter().rev() {
+                add(&name.name, COMPLETION_VARIABLE, "local");
+            }
+            scope = self.scopes[s].parent;
+        }
+        for name in &self.globals {
+            add(&name.name, COMPLETION_VARIABLE, "global");
+        }
+        for function in &self.functions {
+            add(
+                &function.symbol.name,
+                COMPLETION_FUNCTION,
+                &self.signature(function),
+            );
+        }
+        for name in BUILTINS
+            .iter()
+            .chain(HIGHER_ORDER.iter())
+            .chain(["error"].iter())
+        {
+            add(name, COMPLETION_FUNCTION, "builtin");
+        }
+        for name in &self.types {
+            let kind = match self.variants.contains(&name.name) {
+                true => COMPLETION_ENUM_MEMBER,
+                false => COMPLETION_STRUCT,
+            };
+            add(&name.name, kind, "type");
+        }
+        for prelude in EnumAST::prelude() {
+            add(&prelude.name, COMPLETION_STRUCT, "type");
+            for variant in &prelude.variants {
+                add(&variant.name, COMPLETION_ENUM_MEMBER, "type");
+            }
+        }
+        for keyword in KEYWORDS {
+            add(keyword, COMPLETION_KEYWORD, "keyword");
+        }
+        items
+    }
+}
+
+fn takes(proto: &PrototypeAST) -> String {
+    let expected = proto.expected_args();
+    let noun = if expected == "1" {
+        "argument"
+    } else {
+        "arguments"
+    };
+    match proto.rest {
+        Some(_) => format!("Takes {} {}, then any number more.", expected, noun),
+        None => format!("Takes {} {}.", expected, noun),
+    }
+}
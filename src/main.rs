@@ -1,42 +1,221 @@
-use std::fs;
+use std::{
+    env, fs,
+    io::{self, BufReader},
+    path::Path,
+    process, thread,
+};
 
-use interpreter::InterpretingMastermind;
-use lexer::LexingMachine;
-use parser::ParsingMachine;
+use willscript::{
+    Backend, Emit, ProgramAST, debug, emit, format, load, optimize, run, serve_dap, serve_lsp,
+};
 
-mod ast;
-mod interpreter;
-mod lexer;
-mod parser;
+// Script calls recurse in the tree-walker, so it runs on a thread with room
+// for willscript::MAX_DEPTH of them even in a debug build.
+const STACK_SIZE: usize = 1 << 30;
 
 fn main() {
-    let raw_string = fs::read_to_string("./hello_world.ws").expect("Could not find file");
-    let mut file_iter = raw_string.chars();
-    let cur_char = file_iter
-        .next()
-        .expect("Come on, you gotta have at least one character, right?");
-    let mut awesome_lexing_machine = LexingMachine::new(cur_char, file_iter);
-    let tokvec = awesome_lexing_machine.activate_lexing();
-    // for val in tokvec.iter() {
+    let interpreter_thread = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_cli)
+        .expect("Could not start the interpreter thread");
+    if interpreter_thread.join().is_err() {
+        // the panic message is already out
+        process::exit(101);
+    }
+}
+
+const USAGE: &str = "Usage: willscript [--vm | --jit] [-O] [FILE]
+       willscript build [-O] --emit c|wat FILE [-o OUT]
+       willscript fmt [--check] FILE...
+       willscript lsp
+       willscript debug FILE | --dap";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+// willscript [--vm | --jit] [-O] [FILE]
+fn run_cli() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "build") {
+        return build(&args[1..]);
+    }
+    if args.first().is_some_and(|a| a == "fmt") {
+        return fmt(&args[1..]);
+    }
+    if args.first().is_some_and(|a| a == "debug") {
+        return debugger(&args[1..]);
+    }
+    if args.first().is_some_and(|a| a == "lsp") {
+        return lsp(&args[1..]);
+    }
+    let mut backend = Backend::Tree;
+    let mut optimized = false;
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--vm" => backend = Backend::Vm,
+            "--jit" => backend = jit_backend(),
+            "-O" => optimized = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| "./hello_world.ws".to_owned());
+    let program = load_script(&path, optimized);
+    // println!("\n[[START OF AST]]\n");
+    // for val in program.functions.iter() {
     //     println!("{:#?}", val);
     // }
-    let mut tok_iter = tokvec.into_iter().peekable();
-    let cur_tok = tok_iter
-        .next()
-        .expect("Come on, you gotta have at least one token, right?");
-    let mut amazing_parsing_machine = ParsingMachine::new(cur_tok, tok_iter);
-    let ast_vec = match amazing_parsing_machine.activate_parsing_machine() {
+    // println!("\n[[END OF AST]]\n");
+    if let Err(e) = run(program, backend) {
+        eprintln!("Uncaught error at {}", e);
+        eprintln!("{}", e.format_trace());
+        process::exit(1);
+    }
+}
+
+#[cfg(feature = "jit")]
+fn jit_backend() -> Backend {
+    Backend::Jit
+}
+
+#[cfg(not(feature = "jit"))]
+fn jit_backend() -> Backend {
+    eprintln!("--jit needs willscript built with the jit feature");
+    process::exit(2);
+}
+
+// willscript build [-O] --emit c|wat FILE [-o OUT], writing next to FILE unless
+// told where
+fn build(args: &[String]) {
+    let mut optimized = false;
+    let mut format = None;
+    let mut path = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-O" => optimized = true,
+            "--emit" => match args.next().map(|x| x.as_str()) {
+                Some("c") => format = Some(Emit::C),
+                Some("wat") => format = Some(Emit::Wat),
+                _ => usage(),
+            },
+            "-o" => out = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => usage(),
+        }
+    }
+    let (Some(format), Some(path)) = (format, path) else {
+        usage();
+    };
+    let program = load_script(&path, optimized);
+    let source = emit(&program, format).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let extension = match format {
+        Emit::C => "c",
+        Emit::Wat => "wat",
+    };
+    let out = out.unwrap_or_else(|| {
+        let out = Path::new(&path).with_extension(extension);
+        out.display().to_string()
+    });
+    if let Err(e) = fs::write(&out, source) {
+        eprintln!("Could not write {}: {}", out, e);
+        process::exit(1);
+    }
+}
+
+// willscript fmt [--check] FILE..., rewriting each file in place, or with
+// --check only naming the ones that aren't formatted and exiting with 1
+fn fmt(args: &[String]) {
+    let check = args.iter().any(|a| a == "--check");
+    let paths: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+    if paths.is_empty() {
+        usage();
+    }
+    let mut failed = false;
+    for path in paths {
+        let source = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", path, e);
+            process::exit(1);
+        });
+        let formatted = match format(&source) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            eprintln!("{} is not formatted", path);
+            failed = true;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("Could not write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// willscript lsp, serving the Language Server Protocol on stdin and stdout and
+// exiting with 1 if the client never asked for a shutdown
+fn lsp(args: &[String]) {
+    if !args.is_empty() {
+        usage();
+    }
+    match serve_lsp(BufReader::new(io::stdin()), io::stdout()) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("The language server stopped: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// willscript debug FILE, taking commands on stdin, or willscript debug --dap,
+// serving the Debug Adapter Protocol on stdin and stdout
+fn debugger(args: &[String]) {
+    let [arg] = args else {
+        usage();
+    };
+    if arg == "--dap" {
+        if let Err(e) = serve_dap(io::stdin().lock(), io::stdout()) {
+            eprintln!("The debug adapter stopped: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let program = load_script(arg, false);
+    let source = fs::read_to_string(arg).expect("load_script read it already");
+    if let Err(e) = debug(program, &source, io::stdin().lock(), io::stdout()) {
+        eprintln!("Uncaught error at {}", e);
+        eprintln!("{}", e.format_trace());
+        process::exit(1);
+    }
+}
+
+fn load_script(path: &str, optimized: bool) -> ProgramAST {
+    let mut program = match load(Path::new(path)) {
         Ok(x) => x,
         Err(e) => {
             eprintln!("{}", e);
-            panic!()
+            process::exit(1);
         }
     };
-    // println!("\n[[START OF AST]]\n");
-    // for val in ast_vec.iter() {
-    //     println!("{:#?}", val);
-    // }
-    // println!("\n[[END OF AST]]\n");
-    let mut fantastic_interpreting_machine = InterpretingMastermind::new(ast_vec);
-    fantastic_interpreting_machine.run_main();
+    if optimized {
+        optimize(&mut program);
+    }
+    program
 }
@@ -0,0 +1,460 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{
+        Arg, BuiltIn, EnumAST, ExprAST, FunctionAST, MatchBlock, Name, Pattern, ProgramAST,
+        Resolution, Statement, intern,
+    },
+    lexer::{LexingMachine, Span},
+    parser::ParsingMachine,
+};
+
+// Loads the program at `path` along with every file it imports, linked into
+// one program. Functions and globals of an imported file are renamed to
+// `module.name`, which no file can declare itself, so files never see each
+// other's names except through an import. Structs and enums are shared by
+// every file.
+pub fn load_program(path: &Path) -> Result<ProgramAST, String> {
+    let mut loader = Loader {
+        program: ProgramAST::new(),
+        ..Loader::default()
+    };
+    loader.load(path, path.display().to_string(), true)?;
+    Ok(loader.program)
+}
+
+// What an importer can see of a loaded file.
+struct Module {
+    // exported name -> its name in the linked program
+    exports: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct Loader {
+    program: ProgramAST,
+    modules: Vec<Module>,
+    // canonical path -> index into modules, so each file loads once
+    cache: HashMap<PathBuf, usize>,
+    // the files being loaded, each importing the next, for finding cycles
+    loading: Vec<(PathBuf, String)>,
+    prefixes: HashSet<String>,
+}
+impl Loader {
+    // `shown` is the path as the user wrote it, for error messages.
+    fn load(&mut self, path: &Path, shown: String, is_main: bool) -> Result<usize, String> {
+        let Ok(canonical) = path.canonicalize() else {
+            return Err(format!("Could not find file {}", shown));
+        };
+        if let Some(start) = self.loading.iter().position(|(p, _)| *p == canonical) {
+            let mut cycle: Vec<&str> = self.loading[start..]
+                .iter()
+                .map(|(_, shown)| shown.as_str())
+                .collect();
+            cycle.push(&shown);
+            return Err(format!("Imports form a cycle: {}.", cycle.join(" -> ")));
+        }
+        if let Some(&index) = self.cache.get(&canonical) {
+            return Ok(index);
+        }
+        let raw_string = fs::read_to_string(&canonical)
+            .map_err(|e| format!("Could not read {}: {}", shown, e))?;
+        // errors in the main file read as they always have, the rest say
+        // which file they're in
+        let in_file = |e: String| {
+            if is_main {
+                e
+            } else {
+                format!("{}: {}", shown, e)
+            }
+        };
+        let mut program = parse_file(&raw_string).map_err(in_file)?;
+        self.loading.push((canonical.clone(), shown.clone()));
+        let dir = canonical.parent().unwrap_or(Path::new("."));
+        let mut aliases = HashMap::new();
+        for import in std::mem::take(&mut program.imports) {
+            let index = self.load(&dir.join(&import.path), import.path, false)?;
+            if aliases.insert(import.alias.clone(), index).is_some() {
+                return Err(in_file(format!(
+                    "{}: {} is imported twice.",
+                    import.span, import.alias
+                )));
+            }
+        }
+        self.loading.pop();
+        let prefix = if is_main {
+            None
+        } else {
+            Some(self.new_prefix(&canonical))
+        };
+        self.link(program, prefix, &aliases).map_err(in_file)?;
+        self.cache.insert(canonical, self.modules.len() - 1);
+        Ok(self.modules.len() - 1)
+    }
+    // The file name, unless another file with that name was loaded first.
+    fn new_prefix(&mut self, path: &Path) -> String {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "module".to_owned());
+        let mut prefix = stem.clone();
+        let mut n = 1;
+        while self.prefixes.contains(&prefix) {
+            n += 1;
+            prefix = format!("{}{}", stem, n);
+        }
+        self.prefixes.insert(prefix.clone());
+        prefix
+    }
+    fn link(
+        &mut self,
+        mut program: ProgramAST,
+        prefix: Option<String>,
+        aliases: &HashMap<String, usize>,
+    ) -> Result<(), String> {
+        let top_level: Vec<&str> = program
+            .functions
+            .iter()
+            .map(|f| &*f.proto.name)
+            .chain(program.globals.iter().map(|g| g.name.as_str()))
+            .collect();
+        for &name in top_level.iter() {
+            if aliases.contains_key(name) {
+                return Err(format!(
+                    "{} is both an imported module and declared here.",
+                    name
+                ));
+            }
+        }
+        let renamed: HashMap<String, String> = match &prefix {
+            Some(prefix) => top_level
+                .iter()
+                .map(|name| (name.to_string(), format!("{}.{}", prefix, name)))
+                .collect(),
+            None => HashMap::new(),
+        };
+        let modules: HashMap<&str, &Module> = aliases
+            .iter()
+            .map(|(alias, &index)| (alias.as_str(), &self.modules[index]))
+            .collect();
+        let renamer = Renamer {
+            renamed: &renamed,
+            modules,
+        };
+        for func in program.functions.iter_mut() {
+            renamer
+                .rename_function(func, HashSet::new())
+                .map_err(|e| format!("In function {}: {}", func.proto.name, e))?;
+        }
+        for global in program.globals.iter_mut() {
+            renamer
+                .rename_expr(&mut global.init, &HashSet::new())
+                .map_err(|e| format!("In the initializer of {}: {}", global.name, e))?;
+        }
+        let exports = program
+            .exports
+            .iter()
+            .map(|name| {
+                let linked = renamed.get(name).unwrap_or(name);
+                (name.clone(), linked.clone())
+            })
+            .collect();
+        self.modules.push(Module { exports });
+        for func in program.functions.iter_mut() {
+            if let Some(name) = renamed.get(&*func.proto.name) {
+                func.proto.name = intern(name);
+            }
+        }
+        for global in program.globals.iter_mut() {
+            if let Some(name) = renamed.get(&global.name) {
+                global.name = name.clone();
+            }
+        }
+        for struct_ast in program.structs {
+            if self
+                .program
+                .structs
+                .iter()
+                .any(|s| s.name == struct_ast.name)
+            {
+                return Err(format!(
+                    "Struct {} is declared in more than one file.",
+                    struct_ast.name
+                ));
+            }
+            self.program.structs.push(struct_ast);
+        }
+        // every file starts with the prelude enums, the program only needs
+        // them once
+        let prelude: Vec<Name> = EnumAST::prelude().into_iter().map(|e| e.name).collect();
+        self.program.enums.extend(
+            program
+                .enums
+                .into_iter()
+                .filter(|e| !prelude.contains(&e.name)),
+        );
+        self.program.functions.extend(program.functions);
+        self.program.globals.extend(program.globals);
+        if prefix.is_none() {
+            self.program.exports = program.exports;
+        }
+        Ok(())
+    }
+}
+
+fn parse_file(raw_string: &str) -> Result<ProgramAST, String> {
+    let mut file_iter = raw_string.chars();
+    let cur_char = file_iter
+        .next()
+        .expect("Come on, you gotta have at least one character, right?");
+    let mut awesome_lexing_machine = LexingMachine::new(cur_char, file_iter);
+    let tokvec = awesome_lexing_machine
+        .activate_lexing()
+        .map_err(|(span, e)| format!("{}: {}", span, e))?;
+    let mut tok_iter = tokvec.into_iter().peekable();
+    let cur_tok = tok_iter
+        .next()
+        .expect("Come on, you gotta have at least one token, right?");
+    let mut amazing_parsing_machine = ParsingMachine::new(cur_tok, tok_iter);
+    amazing_parsing_machine.activate_parsing_machine()
+}
+
+// The locals declared so far in a function. Like the checker, scoping is per
+// function, so a local declared anywhere earlier hides a top-level name.
+type Locals = HashSet<String>;
+
+// Rewrites one file's code for the linked program: its own top-level names get
+// their new names, and `alias.name` becomes the imported name.
+struct Renamer<'a> {
+    renamed: &'a HashMap<String, String>,
+    modules: HashMap<&'a str, &'a Module>,
+}
+impl Renamer<'_> {
+    fn rename_function(&self, func: &mut FunctionAST, mut locals: Locals) -> Result<(), String> {
+        for param in func.proto.args.iter_mut() {
+            if let Some(default) = &mut param.default {
+                self.rename_expr(default, &locals)?;
+            }
+            locals.insert(param.name.clone());
+        }
+        if let Some(rest) = &func.proto.rest {
+            locals.insert(rest.clone());
+        }
+        self.rename_statements(&mut func.body, &mut locals)
+    }
+    fn rename_statements(&self, body: &mut [Statement], locals: &mut Locals) -> Result<(), String> {
+        for statement in body {
+            match statement {
+                Statement::Assign(x) => {
+                    self.rename_expr(&mut x.right_hand, locals)?;
+                    if x.is_declaration {
+                        declare(&x.variable, locals);
+                    } else {
+                        self.rename_expr(&mut x.variable, locals)?;
+                    }
+                }
+                Statement::If(x) => {
+                    self.rename_expr(&mut x.conditional, locals)?;
+                    self.rename_statements(&mut x.body, locals)?;
+                    self.rename_statements(&mut x.else_body, locals)?;
+                }
+                Statement::While(x) => {
+                    self.rename_expr(&mut x.conditional, locals)?;
+                    self.rename_statements(&mut x.body, locals)?;
+                }
+                Statement::Call(x) | Statement::Throw(x, _) => self.rename_expr(x, locals)?,
+                Statement::Built(BuiltIn::Print(x, _) | BuiltIn::Return(x, _)) => {
+                    self.rename_expr(x, locals)?;
+                }
+                // input always sets a local
+                Statement::Built(BuiltIn::Input(x)) => declare(x, locals),
+                Statement::Built(BuiltIn::Drop(x)) => {
+                    if let ExprAST::Variable(name, ..) = x {
+                        locals.remove(name);
+                    }
+                }
+                Statement::Match(x) => {
+                    self.rename_match(x, locals, |renamer, body, locals| {
+                        renamer.rename_statements(body, locals)
+                    })?;
+                }
+                Statement::Try(x) => {
+                    self.rename_statements(&mut x.body, locals)?;
+                    if let Some((name, _, body)) = &mut x.catch {
+                        let added = locals.insert(name.clone());
+                        self.rename_statements(body, locals)?;
+                        if added {
+                            locals.remove(name);
+                        }
+                    }
+                    if let Some(body) = &mut x.finally {
+                        self.rename_statements(body, locals)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    // The arm bodies are statements or expressions, `rename_body` does either.
+    fn rename_match<T>(
+        &self,
+        match_block: &mut MatchBlock<T>,
+        locals: &mut Locals,
+        rename_body: impl Fn(&Self, &mut T, &mut Locals) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.rename_expr(&mut match_block.scrutinee, locals)?;
+        for arm in match_block.arms.iter_mut() {
+            let mut bound = Vec::new();
+            pattern_names(&arm.pattern, &mut bound);
+            bound.retain(|name| locals.insert(name.clone()));
+            if let Some(guard) = &mut arm.guard {
+                self.rename_expr(guard, locals)?;
+            }
+            rename_body(self, &mut arm.body, locals)?;
+            for name in bound {
+                locals.remove(&name);
+            }
+        }
+        Ok(())
+    }
+    fn rename_args(&self, args: &mut [Arg], locals: &Locals) -> Result<(), String> {
+        for arg in args {
+            self.rename_expr(&mut arg.value, locals)?;
+        }
+        Ok(())
+    }
+    // The linked name of `alias.name`, if `expr` is one.
+    fn imported_name(&self, expr: &ExprAST, locals: &Locals) -> Result<Option<String>, String> {
+        let ExprAST::Field(instance, name, span) = expr else {
+            return Ok(None);
+        };
+        let ExprAST::Variable(alias, ..) = &**instance else {
+            return Ok(None);
+        };
+        let Some(module) = self.modules.get(alias.as_str()) else {
+            return Ok(None);
+        };
+        if locals.contains(alias) {
+            return Ok(None);
+        }
+        match module.exports.get(name) {
+            Some(linked) => Ok(Some(linked.clone())),
+            None => Err(format!(
+                "{}: Module {} has no exported {}.",
+                span, alias, name
+            )),
+        }
+    }
+    fn rename_name(&self, name: &mut String, span: Span, locals: &Locals) -> Result<(), String> {
+        if locals.contains(name) {
+            return Ok(());
+        }
+        if let Some(linked) = self.renamed.get(name) {
+            *name = linked.clone();
+        } else if self.modules.contains_key(name.as_str()) {
+            return Err(format!(
+                "{}: Module {} can only be used as {}.name.",
+                span, name, name
+            ));
+        }
+        Ok(())
+    }
+    fn rename_expr(&self, expr: &mut ExprAST, locals: &Locals) -> Result<(), String> {
+        if let Some(linked) = self.imported_name(expr, locals)? {
+            let ExprAST::Field(_, _, span) = expr else {
+                unreachable!();
+            };
+            *expr = ExprAST::Variable(linked, *span, Resolution::default());
+            return Ok(());
+        }
+        match expr {
+            ExprAST::Variable(name, span, _) => self.rename_name(name, *span, locals)?,
+            ExprAST::Val(_) => (),
+            ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => {
+                self.rename_expr(lhs, locals)?;
+                self.rename_expr(rhs, locals)?;
+            }
+            ExprAST::Call(name, args, span, _) => {
+                self.rename_name(name, *span, locals)?;
+                self.rename_args(args, locals)?;
+            }
+            ExprAST::CallExpr(callee, args, span) => {
+                self.rename_args(args, locals)?;
+                // `alias.name(...)` calls the function by name, like any other
+                // call of a declared function
+                if let Some(linked) = self.imported_name(callee, locals)? {
+                    let args = std::mem::take(args);
+                    *expr = ExprAST::Call(linked, args, *span, Resolution::default());
+                    return Ok(());
+                }
+                self.rename_expr(callee, locals)?;
+            }
+            ExprAST::Closure(function) => {
+                self.rename_function(Rc::make_mut(function), locals.clone())?;
+            }
+            ExprAST::List(items) | ExprAST::Tuple(items) => {
+                for item in items {
+                    self.rename_expr(item, locals)?;
+                }
+            }
+            ExprAST::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.rename_expr(key, locals)?;
+                    self.rename_expr(value, locals)?;
+                }
+            }
+            ExprAST::StructInit(_, fields, _) => {
+                for (_, value) in fields {
+                    self.rename_expr(value, locals)?;
+                }
+            }
+            ExprAST::Field(instance, ..) | ExprAST::Propagate(instance, _) => {
+                self.rename_expr(instance, locals)?;
+            }
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                self.rename_expr(conditional, locals)?;
+                self.rename_expr(then_expr, locals)?;
+                self.rename_expr(else_expr, locals)?;
+            }
+            ExprAST::Match(x) => {
+                self.rename_match(x, &mut locals.clone(), |renamer, body, locals| {
+                    renamer.rename_expr(body, locals)
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The names a declaration binds, nested in a tuple, list or struct when it
+// destructures.
+fn declare(target: &ExprAST, locals: &mut Locals) {
+    match target {
+        ExprAST::Variable(name, ..) => {
+            locals.insert(name.clone());
+        }
+        ExprAST::Tuple(items) | ExprAST::List(items) => {
+            items.iter().for_each(|x| declare(x, locals));
+        }
+        ExprAST::StructInit(_, fields, _) => {
+            fields.iter().for_each(|(_, x)| declare(x, locals));
+        }
+        _ => (),
+    }
+}
+
+// Every name a pattern could bind. Unit variants are in here too, which only
+// matters to a global with the same name as a variant.
+fn pattern_names(pattern: &Pattern, names: &mut Vec<String>) {
+    match pattern {
+        Pattern::Ident(name, _) => names.push(name.clone()),
+        Pattern::Variant(_, subpatterns) => {
+            subpatterns.iter().for_each(|p| pattern_names(p, names));
+        }
+        Pattern::Wildcard | Pattern::Literal(_) => (),
+    }
+}
@@ -0,0 +1,369 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{BuiltIn, ExprAST, FunctionAST, Resolution, Statement, Value},
+    interpreter::eval_binop,
+};
+
+// Inlined bodies are at most this many expression nodes.
+const MAX_INLINE_SIZE: usize = 16;
+
+// Rewrites resolved functions into ones that do the same with less work:
+// - operators on two constants are computed once here
+// - statements after a return or throw are dropped, they can never run
+// - `if` on a constant keeps only the branch it takes, `while` on a false one
+//   goes away
+// - a call of a small function is replaced by its body, with the arguments
+//   put in for the parameters
+// A function gets inlined when all it does is return an expression that calls
+// no declared function, so inlining can't recurse, binds nothing and can't
+// return early. The arguments have to be constants or locals, which reading
+// twice can't change. An inlined call doesn't show up in stack traces.
+pub fn optimize(functions: &mut [FunctionAST]) {
+    let folder = Optimizer { inline: vec![] };
+    for func in functions.iter_mut() {
+        folder.function(func);
+    }
+    let inliner = Optimizer {
+        inline: functions.iter().map(inline_body).collect(),
+    };
+    for func in functions.iter_mut() {
+        inliner.function(func);
+    }
+}
+
+// A function that can be inlined: the slots of its parameters, and what it
+// returns.
+struct Inline {
+    params: Vec<usize>,
+    body: ExprAST,
+}
+
+fn inline_body(func: &FunctionAST) -> Option<Inline> {
+    let [Statement::Built(BuiltIn::Return(body, _))] = func.body.as_slice() else {
+        return None;
+    };
+    let proto = &func.proto;
+    if proto.rest.is_some() || proto.args.iter().any(|p| p.default.is_some()) {
+        return None;
+    }
+    let params = func.param_slots.clone();
+    if size(body) > MAX_INLINE_SIZE || !inlinable(body, &params) {
+        return None;
+    }
+    Some(Inline {
+        params,
+        body: body.clone(),
+    })
+}
+
+// Whether the expression can be moved into another function: its locals are
+// all parameters, and it neither calls a declared function nor does anything
+// that needs a frame of its own.
+fn inlinable(expr: &ExprAST, params: &[usize]) -> bool {
+    let own = match expr {
+        ExprAST::Variable(_, _, resolution) => {
+            resolution.function.is_none() && resolution.slot.is_none_or(|s| params.contains(&s))
+        }
+        ExprAST::Call(_, args, _, resolution) => {
+            *resolution == Resolution::default() && args.iter().all(|a| a.name.is_none())
+        }
+        ExprAST::CallExpr(..)
+        | ExprAST::Closure(_)
+        | ExprAST::Match(_)
+        | ExprAST::Propagate(..) => false,
+        _ => true,
+    };
+    own && children(expr).into_iter().all(|x| inlinable(x, params))
+}
+
+fn size(expr: &ExprAST) -> usize {
+    1 + children(expr).into_iter().map(size).sum::<usize>()
+}
+
+struct Optimizer {
+    // by function index, empty while only folding
+    inline: Vec<Option<Inline>>,
+}
+impl Optimizer {
+    fn function(&self, func: &mut FunctionAST) {
+        for param in func.proto.args.iter_mut() {
+            if let Some(default) = &mut param.default {
+                self.expr(default);
+            }
+        }
+        self.block(&mut func.body);
+    }
+    fn block(&self, body: &mut Vec<Statement>) {
+        let mut optimized = Vec::with_capacity(body.len());
+        for statement in std::mem::take(body) {
+            self.statement(statement, &mut optimized);
+            if matches!(
+                optimized.last(),
+                Some(Statement::Built(BuiltIn::Return(..)) | Statement::Throw(..))
+            ) {
+                break;
+            }
+        }
+        *body = optimized;
+    }
+    // Puts what's left of the statement in `out`, which may be nothing or the
+    // statements of the branch an `if` always takes.
+    fn statement(&self, mut statement: Statement, out: &mut Vec<Statement>) {
+        match &mut statement {
+            Statement::Assign(x) => {
+                self.expr(&mut x.right_hand);
+                // declarations only name what they bind
+                if !x.is_declaration {
+                    children_mut(&mut x.variable)
+                        .into_iter()
+                        .for_each(|x| self.expr(x));
+                }
+            }
+            Statement::If(x) => {
+                self.expr(&mut x.conditional);
+                if let ExprAST::Val(condition) = &x.conditional {
+                    let mut taken = if *condition != Value::Int(0) {
+                        std::mem::take(&mut x.body)
+                    } else {
+                        std::mem::take(&mut x.else_body)
+                    };
+                    self.block(&mut taken);
+                    out.extend(taken);
+                    return;
+                }
+                self.block(&mut x.body);
+                self.block(&mut x.else_body);
+            }
+            Statement::While(x) => {
+                self.expr(&mut x.conditional);
+                if matches!(x.conditional, ExprAST::Val(Value::Int(0))) {
+                    return;
+                }
+                self.block(&mut x.body);
+            }
+            Statement::Call(x)
+            | Statement::Throw(x, _)
+            | Statement::Built(BuiltIn::Print(x, _) | BuiltIn::Return(x, _)) => self.expr(x),
+            Statement::Built(BuiltIn::Input(_) | BuiltIn::Drop(_)) => (),
+            Statement::Match(x) => {
+                self.expr(&mut x.scrutinee);
+                for arm in x.arms.iter_mut() {
+                    if let Some(guard) = &mut arm.guard {
+                        self.expr(guard);
+                    }
+                    self.block(&mut arm.body);
+                }
+            }
+            Statement::Try(x) => {
+                self.block(&mut x.body);
+                if let Some((_, _, body)) = &mut x.catch {
+                    self.block(body);
+                }
+                if let Some(body) = &mut x.finally {
+                    self.block(body);
+                }
+            }
+        }
+        out.push(statement);
+    }
+    fn expr(&self, expr: &mut ExprAST) {
+        if let ExprAST::Closure(function) = expr {
+            self.function(Rc::make_mut(function));
+            return;
+        }
+        children_mut(expr).into_iter().for_each(|x| self.expr(x));
+        match expr {
+            ExprAST::BinOp(op, lhs, rhs, _) => {
+                if let (ExprAST::Val(lhs), ExprAST::Val(rhs)) = (&**lhs, &**rhs)
+                    // an error is left to happen when the program runs
+                    && let Ok(value @ (Value::Int(_) | Value::Str(_))) =
+                        eval_binop(op, lhs.clone(), rhs.clone())
+                {
+                    *expr = ExprAST::Val(value);
+                }
+            }
+            ExprAST::If(condition, then_expr, else_expr) => {
+                if let ExprAST::Val(condition) = &**condition {
+                    let taken = if *condition != Value::Int(0) {
+                        then_expr
+                    } else {
+                        else_expr
+                    };
+                    *expr = std::mem::replace(&mut **taken, ExprAST::Val(Value::Int(0)));
+                }
+            }
+            ExprAST::Call(_, args, _, resolution) => {
+                let Some(inline) = resolution
+                    .function
+                    .filter(|_| resolution.slot.is_none() && resolution.global.is_none())
+                    .and_then(|f| self.inline.get(f))
+                    .and_then(|x| x.as_ref())
+                else {
+                    return;
+                };
+                let simple = |x: &ExprAST| match x {
+                    ExprAST::Val(_) => true,
+                    ExprAST::Variable(_, _, resolution) => resolution.global.is_none(),
+                    _ => false,
+                };
+                if args.len() != inline.params.len()
+                    || args.iter().any(|a| a.name.is_some() || !simple(&a.value))
+                {
+                    return;
+                }
+                let args: Vec<ExprAST> = args.iter().map(|a| a.value.clone()).collect();
+                let mut body = inline.body.clone();
+                substitute(&mut body, &inline.params, &args);
+                // the arguments may have made more of it constant
+                Optimizer { inline: vec![] }.expr(&mut body);
+                *expr = body;
+            }
+            _ => (),
+        }
+    }
+}
+
+// Puts the arguments in for the parameters of an inlined body.
+fn substitute(expr: &mut ExprAST, params: &[usize], args: &[ExprAST]) {
+    if let ExprAST::Variable(_, _, resolution) = expr
+        && let Some(slot) = resolution.slot
+    {
+        let i = params
+            .iter()
+            .position(|&p| p == slot)
+            .expect("Checked by inlinable.");
+        *expr = args[i].clone();
+        return;
+    }
+    children_mut(expr)
+        .into_iter()
+        .for_each(|x| substitute(x, params, args));
+}
+
+// The expressions directly inside one, apart from a closure's body, which is
+// a function of its own.
+fn children(expr: &ExprAST) -> Vec<&ExprAST> {
+    match expr {
+        ExprAST::Variable(..) | ExprAST::Val(_) | ExprAST::Closure(_) => vec![],
+        ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => vec![lhs, rhs],
+        ExprAST::Call(_, args, _, _) => args.iter().map(|a| &a.value).collect(),
+        ExprAST::CallExpr(callee, args, _) => std::iter::once(&**callee)
+            .chain(args.iter().map(|a| &a.value))
+            .collect(),
+        ExprAST::List(items) | ExprAST::Tuple(items) => items.iter().collect(),
+        ExprAST::Map(entries, _) => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+        ExprAST::StructInit(_, fields, _) => fields.iter().map(|(_, x)| x).collect(),
+        ExprAST::Field(instance, _, _) | ExprAST::Propagate(instance, _) => vec![instance],
+        ExprAST::Match(x) => std::iter::once(&x.scrutinee)
+            .chain(
+                x.arms
+                    .iter()
+                    .flat_map(|arm| arm.guard.iter().chain(std::iter::once(&arm.body))),
+            )
+            .collect(),
+        ExprAST::If(condition, then_expr, else_expr) => vec![condition, then_expr, else_expr],
+    }
+}
+
+fn children_mut(expr: &mut ExprAST) -> Vec<&mut ExprAST> {
+    match expr {
+        ExprAST::Variable(..) | ExprAST::Val(_) | ExprAST::Closure(_) => vec![],
+        ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => vec![lhs, rhs],
+        ExprAST::Call(_, args, _, _) => args.iter_mut().map(|a| &mut a.value).collect(),
+        ExprAST::CallExpr(callee, args, _) => std::iter::once(&mut **callee)
+            .chain(args.iter_mut().map(|a| &mut a.value))
+            .collect(),
+        ExprAST::List(items) | ExprAST::Tuple(items) => items.iter_mut().collect(),
+        ExprAST::Map(entries, _) => entries.iter_mut().flat_map(|(k, v)| [k, v]).collect(),
+        ExprAST::StructInit(_, fields, _) => fields.iter_mut().map(|(_, x)| x).collect(),
+        ExprAST::Field(instance, _, _) | ExprAST::Propagate(instance, _) => vec![instance],
+        ExprAST::Match(x) => std::iter::once(&mut x.scrutinee)
+            .chain(
+                x.arms
+                    .iter_mut()
+                    .flat_map(|arm| arm.guard.iter_mut().chain(std::iter::once(&mut arm.body))),
+            )
+            .collect(),
+        ExprAST::If(condition, then_expr, else_expr) => vec![condition, then_expr, else_expr],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::{ast::ProgramAST, load};
+
+    // Loads and optimizes a script, giving back its main function.
+    fn optimized_main(name: &str, source: &str) -> FunctionAST {
+        let file = format!("willscript-unit-{}-{}.ws", std::process::id(), name);
+        let path = env::temp_dir().join(file);
+        fs::write(&path, source).unwrap();
+        let mut program: ProgramAST = load(&path).unwrap();
+        fs::remove_file(&path).ok();
+        optimize(&mut program.functions);
+        program
+            .functions
+            .into_iter()
+            .find(|f| &*f.proto.name == "main")
+            .unwrap()
+    }
+
+    fn returned(func: &FunctionAST) -> &ExprAST {
+        match func.body.last() {
+            Some(Statement::Built(BuiltIn::Return(x, _))) => x,
+            x => panic!("expected a return, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn folds_to_a_constant() {
+        let main = optimized_main("fold", "fun main() {\n\treturn 2 * 3 + 4;\n}\n");
+        assert!(matches!(returned(&main), ExprAST::Val(Value::Int(10))));
+    }
+
+    #[test]
+    fn leaves_failing_operators() {
+        let main = optimized_main("fold_error", "fun main() {\n\treturn 1 / 0;\n}\n");
+        assert!(matches!(returned(&main), ExprAST::BinOp(..)));
+    }
+
+    #[test]
+    fn cuts_the_block_at_return() {
+        let main = optimized_main(
+            "dead",
+            "fun main() {\n\tprint 1;\n\treturn 0;\n\tprint 2;\n\tprint 3;\n}\n",
+        );
+        assert_eq!(main.body.len(), 2);
+    }
+
+    #[test]
+    fn drops_constant_branches() {
+        let main = optimized_main(
+            "branches",
+            "fun main() {\n\tif 0 { print 1; }\n\twhile false { print 2; }\n\tif 1 { return 3; }\n\tprint 4;\n}\n",
+        );
+        assert_eq!(main.body.len(), 1);
+        assert!(matches!(returned(&main), ExprAST::Val(Value::Int(3))));
+    }
+
+    #[test]
+    fn inlines_and_folds_the_result() {
+        let main = optimized_main(
+            "inline",
+            "fun square(x) {\n\treturn x * x;\n}\n\nfun main() {\n\treturn square(4);\n}\n",
+        );
+        assert!(matches!(returned(&main), ExprAST::Val(Value::Int(16))));
+    }
+
+    #[test]
+    fn does_not_inline_recursion() {
+        let main = optimized_main(
+            "recursive",
+            "fun down(x) {\n\treturn if x < 1 { 0 } else { down(x - 1) };\n}\n\nfun main() {\n\treturn down(3);\n}\n",
+        );
+        assert!(matches!(returned(&main), ExprAST::Call(..)));
+    }
+}
@@ -1,39 +1,288 @@
 use crate::{
     ast::{
-        Assignment, BuiltIn, ExprAST, FunctionAST, IfBlock, PrototypeAST, Statement, Value,
-        WhileBlock,
+        Arg, Assignment, BuiltIn, EnumAST, ExprAST, FunctionAST, GlobalAST, IfBlock, ImportAST,
+        MatchArm, MatchBlock, Param, Pattern, ProgramAST, PrototypeAST, Resolution, Statement,
+        StructAST, TryBlock, Value, VariantAST, WhileBlock, intern,
     },
-    lexer::{Operator, Token},
+    lexer::{Operator, Span, Token},
 };
-use std::{iter::Peekable, vec::IntoIter};
+use std::{iter::Peekable, rc::Rc, vec::IntoIter};
+
+/// What the formatter needs to know about the source that the AST leaves out.
+/// The parser records them as it meets them, which is the order the formatter
+/// prints things in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    /// A top-level item starts, at its `export` if it has one.
+    Item(Item, Span),
+    /// A statement, match arm, struct field or enum variant starts.
+    Start(Span),
+    /// The `}` of a block, match, struct or enum.
+    End(Span),
+    /// The else of an if statement, and whether an `if` came straight after it.
+    Else { chained: bool },
+    /// An int literal, and whether it was written `true` or `false`.
+    Int { word: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Item {
+    Function { exported: bool },
+    Struct,
+    Enum,
+    Global { exported: bool },
+    Import,
+}
 
 pub struct ParsingMachine {
     cur_tok: Token,
-    tok_iter: Peekable<IntoIter<Token>>,
+    cur_span: Span,
+    tok_iter: Peekable<IntoIter<(Token, Span)>>,
+    struct_literal_allowed: bool,
+    marks: Vec<Mark>,
 }
 impl ParsingMachine {
-    pub fn new(cur_tok: Token, tok_iter: Peekable<IntoIter<Token>>) -> Self {
-        ParsingMachine { cur_tok, tok_iter }
+    pub fn new(
+        (cur_tok, cur_span): (Token, Span),
+        tok_iter: Peekable<IntoIter<(Token, Span)>>,
+    ) -> Self {
+        ParsingMachine {
+            cur_tok,
+            cur_span,
+            tok_iter,
+            struct_literal_allowed: true,
+            marks: Vec::new(),
+        }
     }
     fn eat_tok(&mut self) {
-        self.cur_tok = match self.tok_iter.next() {
-            Some(x) => x,
-            None => Token::EndOfFile,
+        match self.tok_iter.next() {
+            Some((tok, span)) => {
+                self.cur_tok = tok;
+                self.cur_span = span;
+            }
+            None => self.cur_tok = Token::EndOfFile,
         }
     }
-    pub fn activate_parsing_machine(&mut self) -> Result<Vec<FunctionAST>, String> {
-        let mut ansvec = Vec::new();
+    // Parse errors get the position the parser had reached put in front.
+    pub fn activate_parsing_machine(&mut self) -> Result<ProgramAST, String> {
+        self.parse_located()
+            .map_err(|(span, e)| format!("{}: {}", span, e))
+    }
+    /// The same, with where the parser stopped kept apart from the message.
+    pub fn parse_located(&mut self) -> Result<ProgramAST, (Span, String)> {
+        self.parse_program().map_err(|e| (self.cur_span, e))
+    }
+    /// Parses input that is one expression, like one typed into the debugger.
+    pub fn parse_expression(&mut self) -> Result<ExprAST, String> {
+        let expr = self
+            .parse_expr()
+            .map_err(|e| format!("{}: {}", self.cur_span, e))?;
+        match self.cur_tok {
+            Token::EndOfFile => Ok(expr),
+            _ => Err(format!(
+                "{}: Expected the end after the expression.",
+                self.cur_span
+            )),
+        }
+    }
+    /// The marks for the formatter, from the last parse.
+    pub fn take_marks(&mut self) -> Vec<Mark> {
+        std::mem::take(&mut self.marks)
+    }
+    fn mark_end(&mut self) {
+        self.marks.push(Mark::End(self.cur_span));
+    }
+    fn parse_program(&mut self) -> Result<ProgramAST, String> {
+        let mut program = ProgramAST::new();
         loop {
+            let item = match &self.cur_tok {
+                Token::Fun => Some(Item::Function { exported: false }),
+                Token::Struct => Some(Item::Struct),
+                Token::Enum => Some(Item::Enum),
+                Token::Const | Token::Global => Some(Item::Global { exported: false }),
+                Token::Import => Some(Item::Import),
+                Token::Export => match self.tok_iter.peek() {
+                    Some((Token::Fun, _)) => Some(Item::Function { exported: true }),
+                    _ => Some(Item::Global { exported: true }),
+                },
+                _ => None,
+            };
+            if let Some(item) = item {
+                self.marks.push(Mark::Item(item, self.cur_span));
+            }
             match &self.cur_tok {
                 Token::Fun => {
                     let fun = self.parse_function()?;
-                    ansvec.push(fun);
+                    program.functions.push(fun);
+                }
+                Token::Struct => {
+                    let struct_ast = self.parse_struct()?;
+                    program.structs.push(struct_ast);
+                }
+                Token::Enum => {
+                    let enum_ast = self.parse_enum()?;
+                    program.enums.push(enum_ast);
+                }
+                Token::Const | Token::Global => {
+                    let global = self.parse_global()?;
+                    program.globals.push(global);
+                }
+                Token::Import => {
+                    let import = self.parse_import()?;
+                    program.imports.push(import);
+                }
+                Token::Export => {
+                    self.eat_tok(); // eats the 'export'
+                    match self.cur_tok {
+                        Token::Fun => {
+                            let fun = self.parse_function()?;
+                            program.exports.push(fun.proto.name.to_string());
+                            program.functions.push(fun);
+                        }
+                        Token::Const => {
+                            let global = self.parse_global()?;
+                            program.exports.push(global.name.clone());
+                            program.globals.push(global);
+                        }
+                        _ => return Err("Only functions and constants can be exported.".to_owned()),
+                    }
                 }
                 Token::EndOfFile => break,
-                x => return Err(format!("Expected 'fun' or EOF. Got: {:#?}", x)),
+                x => {
+                    return Err(format!(
+                        "Expected 'fun', 'struct', 'enum', 'const', 'global', 'import', 'export' or EOF. Got: {:#?}",
+                        x
+                    ));
+                }
             }
         }
-        Ok(ansvec)
+        Ok(program)
+    }
+    fn parse_import(&mut self) -> Result<ImportAST, String> {
+        let span = self.cur_span;
+        self.eat_tok(); // eats the 'import'
+        let Token::Str(path) = self.cur_tok.clone() else {
+            return Err("Expected a file path in quotes after import.".to_owned());
+        };
+        self.eat_tok(); // eats the path
+        let Token::As = self.cur_tok else {
+            return Err(format!("Expected 'as' after import \"{}\".", path));
+        };
+        self.eat_tok(); // eats the 'as'
+        let Token::Identifier(alias) = self.cur_tok.clone() else {
+            return Err(format!("Expected a name for \"{}\" after 'as'.", path));
+        };
+        self.eat_tok(); // eats the alias
+        let Token::Semicolon = self.cur_tok else {
+            return Err(format!("No semicolon after import of {}.", alias));
+        };
+        self.eat_tok(); // eats the semicolon
+        Ok(ImportAST::new(path, alias, span))
+    }
+    fn parse_global(&mut self) -> Result<GlobalAST, String> {
+        let is_const = matches!(self.cur_tok, Token::Const);
+        self.eat_tok(); // eats the 'const' or 'global'
+        let Token::Identifier(name) = self.cur_tok.clone() else {
+            return Err("Expected a name after const or global.".to_owned());
+        };
+        self.eat_tok(); // eats the name
+        let Token::Assignment = self.cur_tok else {
+            return Err(format!("Expected '=' after {}.", name));
+        };
+        self.eat_tok(); // eats the =
+        let init = self.parse_expr()?;
+        let Token::Semicolon = self.cur_tok else {
+            return Err(format!("No semicolon after {}.", name));
+        };
+        self.eat_tok(); // eats the semicolon
+        Ok(GlobalAST::new(is_const, name, init))
+    }
+    fn parse_struct(&mut self) -> Result<StructAST, String> {
+        self.eat_tok(); // eats the 'struct'
+        let Token::Identifier(name) = self.cur_tok.clone() else {
+            return Err("The struct needs a name.".to_owned());
+        };
+        self.eat_tok(); // eats the name
+        let Token::LeftCurly = self.cur_tok else {
+            return Err(format!("Expected '{{' after struct {}.", name));
+        };
+        self.eat_tok(); // eats the left curly
+        let mut fields: Vec<String> = Vec::new();
+        while !matches!(self.cur_tok, Token::RightCurly) {
+            let Token::Identifier(field) = self.cur_tok.clone() else {
+                return Err(format!("Not an ident inside struct {}.", name));
+            };
+            self.marks.push(Mark::Start(self.cur_span));
+            if fields.contains(&field) {
+                return Err(format!(
+                    "Field {} declared twice in struct {}.",
+                    field, name
+                ));
+            }
+            self.eat_tok();
+            fields.push(field);
+            match &self.cur_tok {
+                Token::Comma => self.eat_tok(),
+                Token::RightCurly => break,
+                x => return Err(format!("Unexpected token in struct: {:#?}", x)),
+            }
+        }
+        self.mark_end();
+        self.eat_tok(); // eat the right curly
+        let fields = fields.iter().map(|f| intern(f)).collect();
+        Ok(StructAST::new(intern(&name), fields))
+    }
+    fn parse_enum(&mut self) -> Result<EnumAST, String> {
+        self.eat_tok(); // eats the 'enum'
+        let Token::Identifier(name) = self.cur_tok.clone() else {
+            return Err("The enum needs a name.".to_owned());
+        };
+        self.eat_tok(); // eats the name
+        let Token::LeftCurly = self.cur_tok else {
+            return Err(format!("Expected '{{' after enum {}.", name));
+        };
+        self.eat_tok(); // eats the left curly
+        let mut variants: Vec<VariantAST> = Vec::new();
+        while !matches!(self.cur_tok, Token::RightCurly) {
+            let Token::Identifier(variant) = self.cur_tok.clone() else {
+                return Err(format!("Not a variant name inside enum {}.", name));
+            };
+            self.marks.push(Mark::Start(self.cur_span));
+            if variants.iter().any(|v| *v.name == *variant) {
+                return Err(format!(
+                    "Variant {} declared twice in enum {}.",
+                    variant, name
+                ));
+            }
+            self.eat_tok(); // eats the variant name
+            let mut fields = Vec::new();
+            if let Token::LeftParen = self.cur_tok {
+                self.eat_tok(); // eats the left paren
+                while !matches!(self.cur_tok, Token::RightParen) {
+                    let Token::Identifier(field) = self.cur_tok.clone() else {
+                        return Err(format!("Not an ident inside variant {}.", variant));
+                    };
+                    self.eat_tok();
+                    fields.push(field);
+                    match &self.cur_tok {
+                        Token::Comma => self.eat_tok(),
+                        Token::RightParen => break,
+                        x => return Err(format!("Unexpected token in variant: {:#?}", x)),
+                    }
+                }
+                self.eat_tok(); // eats the right paren
+            }
+            let fields = fields.iter().map(|f| intern(f)).collect();
+            variants.push(VariantAST::new(intern(&variant), fields));
+            match &self.cur_tok {
+                Token::Comma => self.eat_tok(),
+                Token::RightCurly => break,
+                x => return Err(format!("Unexpected token in enum: {:#?}", x)),
+            }
+        }
+        self.mark_end();
+        self.eat_tok(); // eat the right curly
+        Ok(EnumAST::new(intern(&name), variants))
     }
     fn parse_function(&mut self) -> Result<FunctionAST, String> {
         let Token::Fun = self.cur_tok else {
@@ -50,17 +299,44 @@ impl ParsingMachine {
             return Err("The prototype needs a name bro.".to_owned());
         };
         self.eat_tok(); // eats the name
+        self.parse_params(name)
+    }
+    fn parse_params(&mut self, name: String) -> Result<PrototypeAST, String> {
         let Token::LeftParen = self.cur_tok else {
             return Err("Every prototype needs a left parenthesis.".to_owned());
         };
         self.eat_tok(); // eats the left parenthesis
-        let mut args = Vec::new();
+        let mut args: Vec<Param> = Vec::new();
+        let mut rest = None;
         while !matches!(self.cur_tok, Token::RightParen) {
+            if rest.is_some() {
+                return Err("The ...rest parameter has to be the last one.".to_owned());
+            }
+            let is_rest = matches!(self.cur_tok, Token::Ellipsis);
+            if is_rest {
+                self.eat_tok(); // eats the ...
+            }
             let Token::Identifier(arg_name) = self.cur_tok.clone() else {
                 return Err("Not an ident inside prototype.".to_owned());
             };
+            if args.iter().any(|a| a.name == arg_name) || rest.as_ref() == Some(&arg_name) {
+                return Err(format!("Parameter {} is declared twice.", arg_name));
+            }
             self.eat_tok();
-            args.push(arg_name);
+            if is_rest {
+                rest = Some(arg_name);
+            } else if let Token::Assignment = self.cur_tok {
+                self.eat_tok(); // eats the =
+                let default = self.parse_nested_expr()?;
+                args.push(Param::new(arg_name, Some(default)));
+            } else if args.last().is_some_and(|a| a.default.is_some()) {
+                return Err(format!(
+                    "Parameter {} needs a default, since the one before it has one.",
+                    arg_name
+                ));
+            } else {
+                args.push(Param::new(arg_name, None));
+            }
             match &self.cur_tok {
                 Token::Comma => {
                     self.eat_tok();
@@ -71,16 +347,52 @@ impl ParsingMachine {
             }
         }
         self.eat_tok(); // eat the right parenthesis
-        Ok(PrototypeAST::new(name, args))
+        Ok(PrototypeAST::new(intern(&name), args, rest))
+    }
+    // `fun(x) { ... }` in an expression, an anonymous function.
+    fn parse_closure(&mut self) -> Result<ExprAST, String> {
+        self.eat_tok(); // eats the 'fun'
+        let proto = self.parse_params("<closure>".to_owned())?;
+        let Token::LeftCurly = self.cur_tok else {
+            return Err("Anonymous function needs a '{' body.".to_owned());
+        };
+        let body = self.collect_statements()?;
+        Ok(ExprAST::Closure(Rc::new(FunctionAST::new(proto, body))))
     }
     fn parse_statement(&mut self) -> Result<Statement, String> {
+        self.marks.push(Mark::Start(self.cur_span));
         match &self.cur_tok {
-            Token::Var => Ok(Statement::Assign(self.parse_assignment()?)),
-            Token::Identifier(_) => match self.tok_iter.peek() {
-                Some(&Token::LeftParen) => self.parse_call(),
-                _ => Ok(Statement::Assign(self.parse_assignment()?)),
-            },
+            Token::Var | Token::Let => Ok(Statement::Assign(self.parse_assignment()?)),
+            Token::Identifier(_) => {
+                // a call or an assignment, which only the token after the
+                // expression tells apart
+                let span = self.cur_span;
+                let expr = self.parse_expr()?;
+                match self.cur_tok {
+                    Token::Assignment | Token::CompoundAssign(_) => Ok(Statement::Assign(
+                        self.parse_assigned(false, true, expr, span)?,
+                    )),
+                    _ => self.parse_call(expr),
+                }
+            }
             Token::If | Token::While => Ok(self.parse_block()?),
+            Token::Match => Ok(Statement::Match(self.parse_match(|parser| {
+                let Token::LeftCurly = parser.cur_tok else {
+                    return Err("A match statement arm needs a '{' block.".to_owned());
+                };
+                parser.collect_statements()
+            })?)),
+            Token::Try => Ok(Statement::Try(self.parse_try()?)),
+            Token::Throw => {
+                let span = self.cur_span;
+                self.eat_tok(); // eat the throw
+                let expr = self.parse_expr()?;
+                let Token::Semicolon = self.cur_tok else {
+                    return Err("No semicolon after throw statement.".to_owned());
+                };
+                self.eat_tok(); // eat the semicolon
+                Ok(Statement::Throw(expr, span))
+            }
             Token::Print | Token::Input | Token::Drop | Token::Return => {
                 Ok(Statement::Built(self.parse_builtin()?))
             }
@@ -95,33 +407,45 @@ impl ParsingMachine {
             codevec.push(statement);
         }
         // it matches a right curly, so eat that.
+        self.mark_end();
         self.eat_tok();
         Ok(codevec)
     }
     fn parse_builtin(&mut self) -> Result<BuiltIn, String> {
         match &self.cur_tok {
             Token::Print => {
+                let span = self.cur_span;
                 self.eat_tok(); // eat the print
                 let expr = self.parse_expr()?;
                 let Token::Semicolon = self.cur_tok else {
                     return Err("No semicolon after print statement.".to_owned());
                 };
                 self.eat_tok(); // eat the semicolon
-                Ok(BuiltIn::Print(expr))
+                Ok(BuiltIn::Print(expr, span))
             }
             Token::Return => {
-                self.eat_tok(); // eat the print
-                let expr = self.parse_expr()?;
+                let span = self.cur_span;
+                self.eat_tok(); // eat the return
+                let mut expr = self.parse_expr()?;
+                // `return a, b;` returns the tuple (a, b)
+                if let Token::Comma = self.cur_tok {
+                    let mut items = vec![expr];
+                    while let Token::Comma = self.cur_tok {
+                        self.eat_tok(); // eat the comma
+                        items.push(self.parse_expr()?);
+                    }
+                    expr = ExprAST::Tuple(items);
+                }
                 let Token::Semicolon = self.cur_tok else {
                     return Err("No semicolon after return statement.".to_owned());
                 };
                 self.eat_tok(); // eat the semicolon
-                Ok(BuiltIn::Return(expr))
+                Ok(BuiltIn::Return(expr, span))
             }
             Token::Input => {
                 self.eat_tok(); // eat the input
                 let expr = self.parse_expr()?;
-                let ExprAST::Variable(_) = expr else {
+                let ExprAST::Variable(..) = expr else {
                     return Err("Input did not recieve a variable.".to_owned());
                 };
                 let Token::Semicolon = self.cur_tok else {
@@ -133,7 +457,7 @@ impl ParsingMachine {
             Token::Drop => {
                 self.eat_tok(); // eat the drop
                 let expr = self.parse_expr()?;
-                let ExprAST::Variable(_) = expr else {
+                let ExprAST::Variable(..) = expr else {
                     return Err("Drop did not recieve a variable.".to_owned());
                 };
                 let Token::Semicolon = self.cur_tok else {
@@ -146,62 +470,290 @@ impl ParsingMachine {
         }
     }
     fn parse_block(&mut self) -> Result<Statement, String> {
-        let is_if;
-        match self.cur_tok {
-            Token::If => is_if = true,
-            Token::While => is_if = false,
+        let is_if = match self.cur_tok {
+            Token::If => true,
+            Token::While => false,
             _ => return Err("Could not find 'if' or 'while'".to_owned()),
-        }
+        };
+        let span = self.cur_span;
         self.eat_tok(); //eat the 'if' or 'while'
-        let conditional = self.parse_expr()?;
+        let conditional = self.parse_condition()?;
         let Token::LeftCurly = self.cur_tok else {
             return Err("Could not find '{' required for block.".to_owned());
         };
         let statements = self.collect_statements()?;
         // we dont need to check for right curly, collect statements already does that.
-        if is_if {
-            Ok(Statement::If(IfBlock::new(conditional, statements)))
-        } else {
-            Ok(Statement::While(WhileBlock::new(conditional, statements)))
+        if !is_if {
+            return Ok(Statement::While(WhileBlock::new(
+                conditional,
+                statements,
+                span,
+            )));
         }
+        let Token::Else = self.cur_tok else {
+            return Ok(Statement::If(IfBlock::new(
+                conditional,
+                statements,
+                vec![],
+                span,
+            )));
+        };
+        self.eat_tok(); // eat the 'else'
+        let chained = matches!(self.cur_tok, Token::If);
+        self.marks.push(Mark::Else { chained });
+        let else_body = match self.cur_tok {
+            // else if is an if statement as the only thing in the else block
+            Token::If => vec![self.parse_block()?],
+            Token::LeftCurly => self.collect_statements()?,
+            _ => return Err("Expected '{' or 'if' after else.".to_owned()),
+        };
+        Ok(Statement::If(IfBlock::new(
+            conditional,
+            statements,
+            else_body,
+            span,
+        )))
     }
-    fn parse_call(&mut self) -> Result<Statement, String> {
-        let expr = self.parse_expr()?;
-        let ExprAST::Call(_, _) = expr else {
-            return Err("Not a call. Think long and hard about that one.".to_owned());
+    fn parse_try(&mut self) -> Result<TryBlock, String> {
+        let span = self.cur_span;
+        self.eat_tok(); // eat the 'try'
+        let Token::LeftCurly = self.cur_tok else {
+            return Err("Could not find '{' required for try.".to_owned());
+        };
+        let body = self.collect_statements()?;
+        let catch = match self.cur_tok {
+            Token::Catch => {
+                self.eat_tok(); // eat the 'catch'
+                let Token::Identifier(name) = self.cur_tok.clone() else {
+                    return Err("Expected a name for the error after catch.".to_owned());
+                };
+                self.eat_tok(); // eat the name
+                let Token::LeftCurly = self.cur_tok else {
+                    return Err("Could not find '{' required for catch.".to_owned());
+                };
+                // the slot is filled in by the resolver
+                Some((name, 0, self.collect_statements()?))
+            }
+            _ => None,
+        };
+        let finally = match self.cur_tok {
+            Token::Finally => {
+                self.eat_tok(); // eat the 'finally'
+                let Token::LeftCurly = self.cur_tok else {
+                    return Err("Could not find '{' required for finally.".to_owned());
+                };
+                Some(self.collect_statements()?)
+            }
+            _ => None,
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err("A try needs a catch, a finally or both.".to_owned());
+        }
+        Ok(TryBlock::new(body, catch, finally, span))
+    }
+    // The expression before a block's '{'. `if p {` would otherwise read as the
+    // start of a struct literal.
+    fn parse_condition(&mut self) -> Result<ExprAST, String> {
+        self.struct_literal_allowed = false;
+        let conditional = self.parse_expr();
+        self.struct_literal_allowed = true;
+        conditional
+    }
+    // `if c { a } else { b }` as an expression. Unlike the statement, the else
+    // is required, otherwise there'd be no value when c is false.
+    fn parse_if_expr(&mut self) -> Result<ExprAST, String> {
+        self.eat_tok(); // eat the 'if'
+        let conditional = self.parse_condition()?;
+        let then_expr = self.parse_braced_expr()?;
+        let Token::Else = self.cur_tok else {
+            return Err(
+                "An if expression needs an else branch, so it has a value either way.".to_owned(),
+            );
         };
+        self.eat_tok(); // eat the 'else'
+        let else_expr = match self.cur_tok {
+            Token::If => self.parse_if_expr()?,
+            _ => self.parse_braced_expr()?,
+        };
+        Ok(ExprAST::If(
+            Box::new(conditional),
+            Box::new(then_expr),
+            Box::new(else_expr),
+        ))
+    }
+    fn parse_braced_expr(&mut self) -> Result<ExprAST, String> {
+        let Token::LeftCurly = self.cur_tok else {
+            return Err("Expected '{' around the branch of an if expression.".to_owned());
+        };
+        self.eat_tok(); // eat the {
+        let expr = self.parse_nested_expr()?;
+        let Token::RightCurly = self.cur_tok else {
+            return Err("Expected '}' after the branch of an if expression.".to_owned());
+        };
+        self.eat_tok(); // eat the }
+        Ok(expr)
+    }
+    // Parses `match scrutinee { pattern if guard => body, ... }`, with the arm
+    // bodies parsed by `parse_body` so statements and expressions can share it.
+    fn parse_match<T>(
+        &mut self,
+        parse_body: fn(&mut Self) -> Result<T, String>,
+    ) -> Result<MatchBlock<T>, String> {
+        let span = self.cur_span;
+        self.eat_tok(); // eat the 'match'
+        let scrutinee = self.parse_condition()?;
+        let Token::LeftCurly = self.cur_tok else {
+            return Err("Could not find '{' required for match.".to_owned());
+        };
+        self.eat_tok(); // eat the {
+        let mut arms = Vec::new();
+        while !matches!(self.cur_tok, Token::RightCurly) {
+            self.marks.push(Mark::Start(self.cur_span));
+            let pattern = self.parse_pattern()?;
+            let guard = match self.cur_tok {
+                Token::If => {
+                    self.eat_tok(); // eat the 'if'
+                    Some(self.parse_nested_expr()?)
+                }
+                _ => None,
+            };
+            let Token::FatArrow = self.cur_tok else {
+                return Err("Expected '=>' after match pattern.".to_owned());
+            };
+            self.eat_tok(); // eat the =>
+            arms.push(MatchArm::new(pattern, guard, parse_body(self)?));
+            match &self.cur_tok {
+                Token::Comma => self.eat_tok(),
+                _ => continue,
+            }
+        }
+        self.mark_end();
+        self.eat_tok(); // eat the }
+        Ok(MatchBlock::new(scrutinee, arms, span))
+    }
+    fn parse_pattern(&mut self) -> Result<Pattern, String> {
+        match self.cur_tok.clone() {
+            Token::Identifier(name) => {
+                self.eat_tok(); // eat the name
+                if name == "_" {
+                    return Ok(Pattern::Wildcard);
+                }
+                let Token::LeftParen = self.cur_tok else {
+                    return Ok(Pattern::Ident(name, None));
+                };
+                self.eat_tok(); // eat the left paren
+                let mut subpatterns = Vec::new();
+                while !matches!(self.cur_tok, Token::RightParen) {
+                    subpatterns.push(self.parse_pattern()?);
+                    match &self.cur_tok {
+                        Token::Comma => self.eat_tok(),
+                        Token::RightParen => break,
+                        x => return Err(format!("Unexpected token in pattern: {:#?}", x)),
+                    }
+                }
+                self.eat_tok(); // eat the right paren
+                Ok(Pattern::Variant(name, subpatterns))
+            }
+            Token::Number(_) | Token::Bool(_) => {
+                let ExprAST::Val(num) = self.parse_num()? else {
+                    unreachable!()
+                };
+                Ok(Pattern::Literal(num))
+            }
+            Token::Str(string) => {
+                self.eat_tok();
+                Ok(Pattern::Literal(Value::Str(string)))
+            }
+            x => Err(format!("Expected a pattern, got: {:#?}", x)),
+        }
+    }
+    fn parse_call(&mut self, expr: ExprAST) -> Result<Statement, String> {
+        let (ExprAST::Call(..) | ExprAST::CallExpr(..)) = expr else {
+            return Err("No assignment after variable.".to_owned());
+        };
+        let Token::Semicolon = self.cur_tok else {
+            return Err("No semicolon after call statement.".to_owned());
+        };
+        self.eat_tok(); // eat the semicolon
         Ok(Statement::Call(expr))
     }
     fn parse_assignment(&mut self) -> Result<Assignment, String> {
-        let is_declaration = match self.cur_tok {
+        let span = self.cur_span;
+        let (is_declaration, is_mutable) = match self.cur_tok {
             Token::Var => {
                 self.eat_tok();
-                true
+                (true, true)
             }
-            _ => false,
+            Token::Let => {
+                self.eat_tok();
+                (true, false)
+            }
+            _ => (false, true),
         };
-        let ExprAST::Variable(var_string) = self.parse_expr()? else {
-            return Err(
-                "No ident after var. (or you put parse_assignment the wrong place)".to_owned(),
-            );
+        let variable = self.parse_expr()?;
+        self.parse_assigned(is_declaration, is_mutable, variable, span)
+    }
+    // The rest of an assignment, once its target has been parsed.
+    fn parse_assigned(
+        &mut self,
+        is_declaration: bool,
+        is_mutable: bool,
+        variable: ExprAST,
+        span: Span,
+    ) -> Result<Assignment, String> {
+        let variable = match variable {
+            x @ ExprAST::Variable(..) => x,
+            x @ (ExprAST::Index(..) | ExprAST::Field(..)) if !is_declaration => x,
+            x @ (ExprAST::Tuple(_) | ExprAST::List(_) | ExprAST::StructInit(..))
+                if is_declaration =>
+            {
+                check_destructure(&x, &mut Vec::new())?;
+                x
+            }
+            ExprAST::Index(..) => {
+                return Err("Can't declare an index, declare the list or map first.".to_owned());
+            }
+            ExprAST::Field(..) => {
+                return Err("Can't declare a field, it comes from the struct.".to_owned());
+            }
+            _ => {
+                return Err(
+                    "No ident after var or let. (or you put parse_assignment the wrong place)"
+                        .to_owned(),
+                );
+            }
         };
-        let Token::Assignment = self.cur_tok else {
-            return Err("No assignment after variable.".to_owned());
+        let compound = match self.cur_tok.clone() {
+            Token::Assignment => None,
+            Token::CompoundAssign(_) if is_declaration => {
+                return Err(
+                    "A declaration needs a plain '=', not a compound assignment.".to_owned(),
+                );
+            }
+            Token::CompoundAssign(op) => Some(op),
+            _ => return Err("No assignment after variable.".to_owned()),
         };
         self.eat_tok();
         let expr = self.parse_expr()?;
         match &self.cur_tok {
             Token::Semicolon => {
                 self.eat_tok(); // eats the semicolon
-                Ok(Assignment::new(
-                    is_declaration,
-                    ExprAST::Variable(var_string),
-                    expr,
-                ))
+                let mut assignment =
+                    Assignment::new(is_declaration, is_mutable, variable, expr, span);
+                assignment.compound = compound;
+                Ok(assignment)
             }
             x => Err(format!("Expected semicolon, got {:#?}.", x)),
         }
     }
+    // For expressions inside brackets, where a '{' can't be a block.
+    fn parse_nested_expr(&mut self) -> Result<ExprAST, String> {
+        let outer = self.struct_literal_allowed;
+        self.struct_literal_allowed = true;
+        let expr = self.parse_expr();
+        self.struct_literal_allowed = outer;
+        expr
+    }
     fn parse_expr(&mut self) -> Result<ExprAST, String> {
         let lhs = self.parse_primary()?;
 
@@ -217,38 +769,100 @@ impl ParsingMachine {
             if tok_prior < expr_prior {
                 return Ok(lhs);
             }
+            let span = self.cur_span;
             self.eat_tok(); // eating the operator
             let mut rhs = self.parse_primary()?;
-            if let Token::Op(new_binop) = self.cur_tok.clone() {
-                if get_priority(&new_binop) > tok_prior {
-                    rhs = self.parse_rhs(tok_prior + 1, rhs)?;
-                }
+            if let Token::Op(new_binop) = self.cur_tok.clone()
+                && get_priority(&new_binop) > tok_prior
+            {
+                rhs = self.parse_rhs(tok_prior + 1, rhs)?;
             }
-            lhs = ExprAST::BinOp(binop, Box::new(lhs), Box::new(rhs));
+            lhs = ExprAST::BinOp(binop, Box::new(lhs), Box::new(rhs), span);
         }
     }
     fn parse_ident(&mut self) -> Result<ExprAST, String> {
         let Token::Identifier(ident_string) = self.cur_tok.clone() else {
             return Err("Parse Ident did not get an identifier.".to_owned());
         };
+        let span = self.cur_span;
         self.eat_tok();
         if let Token::LeftParen = self.cur_tok {
-            self.eat_tok();
-            let mut arg_vec = Vec::new();
-            loop {
-                let expr = self.parse_expr()?;
-                arg_vec.push(expr);
-                match self.cur_tok {
-                    Token::RightParen => break,
-                    Token::Comma => self.eat_tok(), //eat the comma
-                    _ => return Err("Unexpected token in function call".to_owned()),
+            let arg_vec = self.parse_call_args()?;
+            return Ok(ExprAST::Call(
+                ident_string,
+                arg_vec,
+                span,
+                Resolution::default(),
+            ));
+        }
+        if let Token::LeftCurly = self.cur_tok
+            && self.struct_literal_allowed
+        {
+            return self.parse_struct_literal(ident_string, span);
+        }
+        Ok(ExprAST::Variable(ident_string, span, Resolution::default()))
+    }
+    fn parse_call_args(&mut self) -> Result<Vec<Arg>, String> {
+        self.eat_tok(); // eat the left paren
+        let mut arg_vec: Vec<Arg> = Vec::new();
+        while !matches!(self.cur_tok, Token::RightParen) {
+            // `name: value` is a named argument
+            let name = match (&self.cur_tok, self.tok_iter.peek()) {
+                (Token::Identifier(name), Some((Token::Colon, _))) => {
+                    let name = name.clone();
+                    if arg_vec.iter().any(|a| a.name.as_ref() == Some(&name)) {
+                        return Err(format!("Argument {} is given twice.", name));
+                    }
+                    self.eat_tok(); // eat the name
+                    self.eat_tok(); // eat the :
+                    Some(name)
                 }
+                _ => None,
+            };
+            if name.is_none() && arg_vec.last().is_some_and(|a| a.name.is_some()) {
+                return Err("Positional arguments can't come after named ones.".to_owned());
+            }
+            let expr = self.parse_nested_expr()?;
+            arg_vec.push(Arg::new(name, expr));
+            match self.cur_tok {
+                Token::RightParen => break,
+                Token::Comma => self.eat_tok(), //eat the comma
+                _ => return Err("Unexpected token in function call".to_owned()),
             }
-            //eat right paren
-            self.eat_tok();
-            return Ok(ExprAST::Call(ident_string, arg_vec));
         }
-        Ok(ExprAST::Variable(ident_string))
+        //eat right paren
+        self.eat_tok();
+        Ok(arg_vec)
+    }
+    fn parse_struct_literal(&mut self, name: String, span: Span) -> Result<ExprAST, String> {
+        self.eat_tok(); // eat the {
+        let mut fields = Vec::new();
+        while !matches!(self.cur_tok, Token::RightCurly) {
+            let Token::Identifier(field) = self.cur_tok.clone() else {
+                return Err(format!("Expected a field name in {} literal.", name));
+            };
+            let field_span = self.cur_span;
+            self.eat_tok(); // eat the field name
+            let value = match self.cur_tok {
+                Token::Colon => {
+                    self.eat_tok(); // eat the :
+                    self.parse_nested_expr()?
+                }
+                // `Point { x, y }` is short for `Point { x: x, y: y }`
+                Token::Comma | Token::RightCurly => {
+                    ExprAST::Variable(field.clone(), field_span, Resolution::default())
+                }
+                _ => return Err(format!("Expected ':' after field {}.", field)),
+            };
+            fields.push((field, value));
+            match &self.cur_tok {
+                Token::Comma => self.eat_tok(),
+                Token::RightCurly => break,
+                x => return Err(format!("Unexpected token in struct literal: {:#?}", x)),
+            }
+        }
+        self.eat_tok(); // eat the }
+        Ok(ExprAST::StructInit(name, fields, span))
     }
     fn parse_str(&mut self) -> Result<ExprAST, String> {
         let Token::Str(string) = self.cur_tok.clone() else {
@@ -258,9 +872,12 @@ impl ParsingMachine {
         Ok(ExprAST::Val(Value::Str(string)))
     }
     fn parse_num(&mut self) -> Result<ExprAST, String> {
-        let Token::Number(num) = self.cur_tok else {
-            return Err("Parse Num did not get a number.".to_owned());
+        let (num, word) = match self.cur_tok {
+            Token::Number(num) => (num, false),
+            Token::Bool(x) => (x as i32, true),
+            _ => return Err("Parse Num did not get a number.".to_owned()),
         };
+        self.marks.push(Mark::Int { word });
         self.eat_tok();
         Ok(ExprAST::Val(Value::Int(num)))
     }
@@ -270,28 +887,155 @@ impl ParsingMachine {
         };
         // eat that left paren
         self.eat_tok();
-        let expr = self.parse_expr()?;
-        match self.cur_tok {
-            Token::RightParen => Ok(expr),
-            _ => Err("Expected right paren".to_owned()),
+        let expr = self.parse_nested_expr()?;
+        // a comma makes it a tuple, `(x,)` being the tuple of one
+        let Token::Comma = self.cur_tok else {
+            let Token::RightParen = self.cur_tok else {
+                return Err("Expected right paren".to_owned());
+            };
+            self.eat_tok(); // eat the right paren
+            return Ok(expr);
+        };
+        let mut items = vec![expr];
+        while let Token::Comma = self.cur_tok {
+            self.eat_tok(); // eat the comma
+            if let Token::RightParen = self.cur_tok {
+                break;
+            }
+            items.push(self.parse_nested_expr()?);
+        }
+        let Token::RightParen = self.cur_tok else {
+            return Err("Expected right paren after tuple".to_owned());
+        };
+        self.eat_tok(); // eat the right paren
+        Ok(ExprAST::Tuple(items))
+    }
+    fn parse_list(&mut self) -> Result<ExprAST, String> {
+        let Token::LeftSquare = self.cur_tok else {
+            return Err("No left square bracket given to parse list".to_owned());
+        };
+        self.eat_tok(); // eat the [
+        let mut items = Vec::new();
+        while !matches!(self.cur_tok, Token::RightSquare) {
+            items.push(self.parse_nested_expr()?);
+            match &self.cur_tok {
+                Token::Comma => self.eat_tok(),
+                Token::RightSquare => break,
+                x => return Err(format!("Unexpected token in list literal: {:#?}", x)),
+            }
+        }
+        self.eat_tok(); // eat the ]
+        Ok(ExprAST::List(items))
+    }
+    // A '{' can only reach parse_primary when an operand is expected, which a
+    // block brace never is (blocks follow a complete conditional expression or
+    // prototype), so here it is always a map literal.
+    fn parse_map(&mut self) -> Result<ExprAST, String> {
+        let Token::LeftCurly = self.cur_tok else {
+            return Err("No left curly given to parse map".to_owned());
+        };
+        let span = self.cur_span;
+        self.eat_tok(); // eat the {
+        let mut entries = Vec::new();
+        while !matches!(self.cur_tok, Token::RightCurly) {
+            let key = self.parse_nested_expr()?;
+            let Token::Colon = self.cur_tok else {
+                return Err("Expected ':' after map key.".to_owned());
+            };
+            self.eat_tok(); // eat the :
+            let value = self.parse_nested_expr()?;
+            entries.push((key, value));
+            match &self.cur_tok {
+                Token::Comma => self.eat_tok(),
+                Token::RightCurly => break,
+                x => return Err(format!("Unexpected token in map literal: {:#?}", x)),
+            }
+        }
+        self.eat_tok(); // eat the }
+        Ok(ExprAST::Map(entries, span))
+    }
+    fn parse_postfix(&mut self, expr: ExprAST) -> Result<ExprAST, String> {
+        let mut expr = expr;
+        loop {
+            match self.cur_tok {
+                Token::LeftSquare => {
+                    let span = self.cur_span;
+                    self.eat_tok(); // eat the [
+                    let index = self.parse_nested_expr()?;
+                    let Token::RightSquare = self.cur_tok else {
+                        return Err("Expected ']' after index.".to_owned());
+                    };
+                    self.eat_tok(); // eat the ]
+                    expr = ExprAST::Index(Box::new(expr), Box::new(index), span);
+                }
+                Token::Dot => {
+                    let span = self.cur_span;
+                    self.eat_tok(); // eat the .
+                    let Token::Identifier(field) = self.cur_tok.clone() else {
+                        return Err("Expected a field name after '.'.".to_owned());
+                    };
+                    self.eat_tok(); // eat the field name
+                    expr = ExprAST::Field(Box::new(expr), field, span);
+                }
+                Token::LeftParen => {
+                    let span = self.cur_span;
+                    let arg_vec = self.parse_call_args()?;
+                    expr = ExprAST::CallExpr(Box::new(expr), arg_vec, span);
+                }
+                Token::Question => {
+                    let span = self.cur_span;
+                    self.eat_tok(); // eat the ?
+                    expr = ExprAST::Propagate(Box::new(expr), span);
+                }
+                _ => return Ok(expr),
+            }
         }
     }
     fn parse_primary(&mut self) -> Result<ExprAST, String> {
-        match &self.cur_tok {
-            Token::Identifier(_) => self.parse_ident(),
-            Token::Number(_) => self.parse_num(),
-            Token::Str(_) => self.parse_str(),
-            Token::LeftParen => self.parse_paren(),
-            x => Err(format!("Bad Token given to parse primary: {:#?}", x)),
+        let expr = match &self.cur_tok {
+            Token::Identifier(_) => self.parse_ident()?,
+            Token::Number(_) | Token::Bool(_) => self.parse_num()?,
+            Token::Str(_) => self.parse_str()?,
+            Token::LeftParen => self.parse_paren()?,
+            Token::LeftSquare => self.parse_list()?,
+            Token::LeftCurly => self.parse_map()?,
+            Token::Fun => self.parse_closure()?,
+            Token::If => self.parse_if_expr()?,
+            Token::Match => ExprAST::Match(Box::new(
+                self.parse_match(|parser| parser.parse_nested_expr())?,
+            )),
+            x => return Err(format!("Bad Token given to parse primary: {:#?}", x)),
+        };
+        self.parse_postfix(expr)
+    }
+}
+// A destructuring target may only hold names, each bound once, nested in
+// tuples, lists and structs.
+fn check_destructure<'a>(target: &'a ExprAST, names: &mut Vec<&'a str>) -> Result<(), String> {
+    match target {
+        ExprAST::Variable(name, ..) if name == "_" => Ok(()),
+        ExprAST::Variable(name, ..) if names.contains(&name.as_str()) => {
+            Err(format!("{} is bound twice in one declaration.", name))
+        }
+        ExprAST::Variable(name, ..) => {
+            names.push(name);
+            Ok(())
+        }
+        ExprAST::Tuple(items) | ExprAST::List(items) => {
+            items.iter().try_for_each(|x| check_destructure(x, names))
         }
+        ExprAST::StructInit(_, fields, _) => fields
+            .iter()
+            .try_for_each(|(_, x)| check_destructure(x, names)),
+        _ => Err("Can only destructure into names.".to_owned()),
     }
 }
-fn get_priority(operator: &Operator) -> u32 {
+pub fn get_priority(operator: &Operator) -> u32 {
     match operator {
         Operator::And | Operator::Or | Operator::Xor => 10,
         Operator::LEq | Operator::Ls | Operator::GEq | Operator::Gr | Operator::Eq => 20,
         Operator::BAnd | Operator::BOr | Operator::BXor => 30,
         Operator::Add | Operator::Sub => 40,
-        Operator::Mult | Operator::Div => 50,
+        Operator::Mult | Operator::Div | Operator::Mod => 50,
     }
 }
@@ -0,0 +1,452 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{
+        Arg, BuiltIn, ExprAST, FunctionAST, MatchBlock, Pattern, ProgramAST, Resolution, Statement,
+    },
+    builtins::BUILTINS,
+    interpreter::HIGHER_ORDER,
+};
+
+// Gives every local a slot in its function's frame and every name a
+// Resolution, so running the program never looks a name up. Scoping is per
+// function, so a name gets a slot if the function binds it anywhere, and a
+// name that could never mean anything is reported here instead of when it's
+// reached. Runs after the checker, on a linked program.
+pub fn resolve_program(program: &mut ProgramAST) -> Result<(), String> {
+    let resolver = Resolver::new(program);
+    if !resolver.functions.contains_key("main") {
+        return Err("There is no main function.".to_owned());
+    }
+    for global in program.globals.iter_mut() {
+        let mut scope = Scope::new(&[]);
+        resolver.collect_expr(&global.init, &mut scope);
+        resolver
+            .resolve_expr(&mut global.init, &scope)
+            .map_err(|e| format!("In the initializer of {}: {}", global.name, e))?;
+        global.locals = scope.locals;
+    }
+    for func in program.functions.iter_mut() {
+        resolver
+            .resolve_function(func, &[])
+            .map_err(|e| format!("In function {}: {}", func.proto.name, e))?;
+    }
+    Ok(())
+}
+
+fn index_names<'a>(names: impl Iterator<Item = &'a str>) -> HashMap<String, usize> {
+    names.enumerate().map(|(i, n)| (n.to_owned(), i)).collect()
+}
+
+// The slots of the function being resolved.
+struct Scope {
+    locals: Vec<String>,
+    slots: HashMap<String, usize>,
+}
+impl Scope {
+    // A closure's scope starts with the one it's in, so its frame can start
+    // as a copy of that one.
+    fn new(enclosing: &[String]) -> Self {
+        Scope {
+            locals: enclosing.to_vec(),
+            slots: index_names(enclosing.iter().map(|n| n.as_str())),
+        }
+    }
+    fn bind(&mut self, name: &str) {
+        if !self.slots.contains_key(name) {
+            self.slots.insert(name.to_owned(), self.locals.len());
+            self.locals.push(name.to_owned());
+        }
+    }
+}
+
+pub struct Resolver {
+    globals: HashMap<String, usize>,
+    functions: HashMap<String, usize>,
+    variants: HashSet<String>,
+}
+impl Resolver {
+    /// Knows the globals, functions and variants of a linked program.
+    pub fn new(program: &ProgramAST) -> Self {
+        Resolver {
+            globals: index_names(program.globals.iter().map(|g| g.name.as_str())),
+            functions: index_names(program.functions.iter().map(|f| &*f.proto.name)),
+            variants: program
+                .enums
+                .iter()
+                .flat_map(|e| e.variants.iter().map(|v| v.name.to_string()))
+                .collect(),
+        }
+    }
+    /// Resolves an expression as if it were in a function with the given
+    /// locals, like one typed into the debugger, giving back those locals with
+    /// any the expression binds after them.
+    pub fn resolve_expression(
+        &self,
+        expr: &mut ExprAST,
+        enclosing: &[String],
+    ) -> Result<Vec<String>, String> {
+        let mut scope = Scope::new(enclosing);
+        self.collect_expr(expr, &mut scope);
+        self.resolve_expr(expr, &scope)?;
+        Ok(scope.locals)
+    }
+    fn resolve_function(&self, func: &mut FunctionAST, enclosing: &[String]) -> Result<(), String> {
+        let mut scope = Scope::new(enclosing);
+        for param in func.proto.args.iter() {
+            scope.bind(&param.name);
+            if let Some(default) = &param.default {
+                self.collect_expr(default, &mut scope);
+            }
+        }
+        if let Some(rest) = &func.proto.rest {
+            scope.bind(rest);
+        }
+        self.collect_statements(&func.body, &mut scope);
+        func.param_slots = func
+            .proto
+            .args
+            .iter()
+            .map(|p| &p.name)
+            .chain(&func.proto.rest)
+            .map(|name| scope.slots[name])
+            .collect();
+        for param in func.proto.args.iter_mut() {
+            if let Some(default) = &mut param.default {
+                self.resolve_expr(default, &scope)?;
+            }
+        }
+        self.resolve_statements(&mut func.body, &scope)?;
+        func.locals = scope.locals;
+        Ok(())
+    }
+    fn resolution(&self, name: &str, scope: &Scope) -> Resolution {
+        Resolution {
+            slot: scope.slots.get(name).copied(),
+            global: self.globals.get(name).copied(),
+            function: self.functions.get(name).copied(),
+        }
+    }
+
+    // Binds every name the statements bind. Closures are left out, since they
+    // have their own frames.
+    fn collect_statements(&self, body: &[Statement], scope: &mut Scope) {
+        for statement in body {
+            match statement {
+                Statement::Assign(x) => {
+                    self.collect_expr(&x.right_hand, scope);
+                    if x.is_declaration {
+                        collect_target(&x.variable, scope);
+                    } else {
+                        self.collect_expr(&x.variable, scope);
+                    }
+                }
+                Statement::If(x) => {
+                    self.collect_expr(&x.conditional, scope);
+                    self.collect_statements(&x.body, scope);
+                    self.collect_statements(&x.else_body, scope);
+                }
+                Statement::While(x) => {
+                    self.collect_expr(&x.conditional, scope);
+                    self.collect_statements(&x.body, scope);
+                }
+                Statement::Call(x)
+                | Statement::Throw(x, _)
+                | Statement::Built(BuiltIn::Print(x, _) | BuiltIn::Return(x, _)) => {
+                    self.collect_expr(x, scope);
+                }
+                Statement::Built(BuiltIn::Input(x)) => {
+                    if let ExprAST::Variable(name, ..) = x {
+                        scope.bind(name);
+                    }
+                }
+                Statement::Built(BuiltIn::Drop(_)) => (),
+                Statement::Match(x) => {
+                    self.collect_match(x, scope, |body, scope| {
+                        self.collect_statements(body, scope);
+                    });
+                }
+                Statement::Try(x) => {
+                    self.collect_statements(&x.body, scope);
+                    if let Some((name, _, body)) = &x.catch {
+                        scope.bind(name);
+                        self.collect_statements(body, scope);
+                    }
+                    if let Some(body) = &x.finally {
+                        self.collect_statements(body, scope);
+                    }
+                }
+            }
+        }
+    }
+    // Expressions only bind names in match arms.
+    fn collect_expr(&self, expr: &ExprAST, scope: &mut Scope) {
+        match expr {
+            ExprAST::Variable(..) | ExprAST::Val(_) | ExprAST::Closure(_) => (),
+            ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => {
+                self.collect_expr(lhs, scope);
+                self.collect_expr(rhs, scope);
+            }
+            ExprAST::Call(_, args, ..) => self.collect_args(args, scope),
+            ExprAST::CallExpr(callee, args, _) => {
+                self.collect_expr(callee, scope);
+                self.collect_args(args, scope);
+            }
+            ExprAST::List(items) | ExprAST::Tuple(items) => {
+                items.iter().for_each(|x| self.collect_expr(x, scope));
+            }
+            ExprAST::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.collect_expr(key, scope);
+                    self.collect_expr(value, scope);
+                }
+            }
+            ExprAST::StructInit(_, fields, _) => {
+                fields.iter().for_each(|(_, x)| self.collect_expr(x, scope));
+            }
+            ExprAST::Field(instance, ..) | ExprAST::Propagate(instance, _) => {
+                self.collect_expr(instance, scope);
+            }
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                self.collect_expr(conditional, scope);
+                self.collect_expr(then_expr, scope);
+                self.collect_expr(else_expr, scope);
+            }
+            ExprAST::Match(x) => {
+                self.collect_match(x, scope, |body, scope| self.collect_expr(body, scope));
+            }
+        }
+    }
+    fn collect_args(&self, args: &[Arg], scope: &mut Scope) {
+        args.iter().for_each(|a| self.collect_expr(&a.value, scope));
+    }
+    fn collect_match<T>(
+        &self,
+        match_block: &MatchBlock<T>,
+        scope: &mut Scope,
+        collect_body: impl Fn(&T, &mut Scope),
+    ) {
+        self.collect_expr(&match_block.scrutinee, scope);
+        for arm in match_block.arms.iter() {
+            self.collect_pattern(&arm.pattern, scope);
+            if let Some(guard) = &arm.guard {
+                self.collect_expr(guard, scope);
+            }
+            collect_body(&arm.body, scope);
+        }
+    }
+    fn collect_pattern(&self, pattern: &Pattern, scope: &mut Scope) {
+        match pattern {
+            Pattern::Ident(name, _) if !self.variants.contains(name) => scope.bind(name),
+            Pattern::Variant(_, subpatterns) => {
+                subpatterns
+                    .iter()
+                    .for_each(|p| self.collect_pattern(p, scope));
+            }
+            _ => (),
+        }
+    }
+
+    fn resolve_statements(&self, body: &mut [Statement], scope: &Scope) -> Result<(), String> {
+        for statement in body {
+            match statement {
+                Statement::Assign(x) => {
+                    self.resolve_expr(&mut x.right_hand, scope)?;
+                    match &mut x.variable {
+                        ExprAST::Variable(name, span, resolution) => {
+                            *resolution = self.resolution(name, scope);
+                            if !x.is_declaration
+                                && resolution.slot.is_none()
+                                && resolution.global.is_none()
+                            {
+                                return Err(format!(
+                                    "{}: Tried to assign {}, but it wasn't declared.",
+                                    span, name
+                                ));
+                            }
+                        }
+                        target if x.is_declaration => self.resolve_target(target, scope),
+                        target => self.resolve_expr(target, scope)?,
+                    }
+                }
+                Statement::If(x) => {
+                    self.resolve_expr(&mut x.conditional, scope)?;
+                    self.resolve_statements(&mut x.body, scope)?;
+                    self.resolve_statements(&mut x.else_body, scope)?;
+                }
+                Statement::While(x) => {
+                    self.resolve_expr(&mut x.conditional, scope)?;
+                    self.resolve_statements(&mut x.body, scope)?;
+                }
+                Statement::Call(x)
+                | Statement::Throw(x, _)
+                | Statement::Built(BuiltIn::Print(x, _) | BuiltIn::Return(x, _)) => {
+                    self.resolve_expr(x, scope)?;
+                }
+                // input binds the name, and dropping a name that isn't bound
+                // does nothing, so neither can be undefined
+                Statement::Built(BuiltIn::Input(x) | BuiltIn::Drop(x)) => {
+                    if let ExprAST::Variable(name, _, resolution) = x {
+                        *resolution = self.resolution(name, scope);
+                    }
+                }
+                Statement::Match(x) => {
+                    self.resolve_match(x, scope, |body, scope| {
+                        self.resolve_statements(body, scope)
+                    })?;
+                }
+                Statement::Try(x) => {
+                    self.resolve_statements(&mut x.body, scope)?;
+                    if let Some((name, slot, body)) = &mut x.catch {
+                        *slot = scope.slots[name.as_str()];
+                        self.resolve_statements(body, scope)?;
+                    }
+                    if let Some(body) = &mut x.finally {
+                        self.resolve_statements(body, scope)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    // The names in a destructuring declaration, which are all bound.
+    fn resolve_target(&self, target: &mut ExprAST, scope: &Scope) {
+        match target {
+            ExprAST::Variable(name, _, resolution) => {
+                *resolution = self.resolution(name, scope);
+            }
+            ExprAST::Tuple(items) | ExprAST::List(items) => {
+                items.iter_mut().for_each(|x| self.resolve_target(x, scope));
+            }
+            ExprAST::StructInit(_, fields, _) => {
+                fields
+                    .iter_mut()
+                    .for_each(|(_, x)| self.resolve_target(x, scope));
+            }
+            _ => (),
+        }
+    }
+    fn resolve_expr(&self, expr: &mut ExprAST, scope: &Scope) -> Result<(), String> {
+        match expr {
+            ExprAST::Variable(name, span, resolution) => {
+                *resolution = self.resolution(name, scope);
+                if *resolution == Resolution::default() && !self.variants.contains(name.as_str()) {
+                    return Err(format!("{}: Undefined variable {}.", span, name));
+                }
+            }
+            ExprAST::Val(_) => (),
+            ExprAST::BinOp(_, lhs, rhs, _) | ExprAST::Index(lhs, rhs, _) => {
+                self.resolve_expr(lhs, scope)?;
+                self.resolve_expr(rhs, scope)?;
+            }
+            ExprAST::Call(name, args, span, resolution) => {
+                self.resolve_args(args, scope)?;
+                *resolution = self.resolution(name, scope);
+                let known = *resolution != Resolution::default()
+                    || self.variants.contains(name.as_str())
+                    || BUILTINS.contains(&name.as_str())
+                    || HIGHER_ORDER.contains(&name.as_str())
+                    || name == "error";
+                if !known {
+                    return Err(format!("{}: Unknown function {}.", span, name));
+                }
+            }
+            ExprAST::CallExpr(callee, args, _) => {
+                self.resolve_expr(callee, scope)?;
+                self.resolve_args(args, scope)?;
+            }
+            ExprAST::Closure(function) => {
+                self.resolve_function(Rc::make_mut(function), &scope.locals)?;
+            }
+            ExprAST::List(items) | ExprAST::Tuple(items) => {
+                for item in items {
+                    self.resolve_expr(item, scope)?;
+                }
+            }
+            ExprAST::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key, scope)?;
+                    self.resolve_expr(value, scope)?;
+                }
+            }
+            ExprAST::StructInit(_, fields, _) => {
+                for (_, value) in fields {
+                    self.resolve_expr(value, scope)?;
+                }
+            }
+            ExprAST::Field(instance, ..) | ExprAST::Propagate(instance, _) => {
+                self.resolve_expr(instance, scope)?;
+            }
+            ExprAST::If(conditional, then_expr, else_expr) => {
+                self.resolve_expr(conditional, scope)?;
+                self.resolve_expr(then_expr, scope)?;
+                self.resolve_expr(else_expr, scope)?;
+            }
+            ExprAST::Match(x) => {
+                self.resolve_match(x, scope, |body, scope| self.resolve_expr(body, scope))?;
+            }
+        }
+        Ok(())
+    }
+    fn resolve_args(&self, args: &mut [Arg], scope: &Scope) -> Result<(), String> {
+        for arg in args {
+            self.resolve_expr(&mut arg.value, scope)?;
+        }
+        Ok(())
+    }
+    fn resolve_match<T>(
+        &self,
+        match_block: &mut MatchBlock<T>,
+        scope: &Scope,
+        resolve_body: impl Fn(&mut T, &Scope) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.resolve_expr(&mut match_block.scrutinee, scope)?;
+        for arm in match_block.arms.iter_mut() {
+            self.resolve_pattern(&mut arm.pattern, scope);
+            if let Some(guard) = &mut arm.guard {
+                self.resolve_expr(guard, scope)?;
+            }
+            resolve_body(&mut arm.body, scope)?;
+        }
+        Ok(())
+    }
+    fn resolve_pattern(&self, pattern: &mut Pattern, scope: &Scope) {
+        match pattern {
+            Pattern::Ident(name, slot) if !self.variants.contains(name.as_str()) => {
+                *slot = Some(scope.slots[name.as_str()]);
+            }
+            Pattern::Variant(_, subpatterns) => {
+                subpatterns
+                    .iter_mut()
+                    .for_each(|p| self.resolve_pattern(p, scope));
+            }
+            _ => (),
+        }
+    }
+}
+
+// Binds the names of a declaration, nested in a tuple, list or struct when it
+// destructures. A plain `let _` still declares `_`, only destructuring skips it.
+fn collect_target(target: &ExprAST, scope: &mut Scope) {
+    match target {
+        ExprAST::Variable(name, ..) => scope.bind(name),
+        _ => collect_parts(target, scope),
+    }
+}
+
+fn collect_parts(target: &ExprAST, scope: &mut Scope) {
+    match target {
+        ExprAST::Variable(name, ..) if name == "_" => (),
+        ExprAST::Variable(name, ..) => scope.bind(name),
+        ExprAST::Tuple(items) | ExprAST::List(items) => {
+            items.iter().for_each(|x| collect_parts(x, scope));
+        }
+        ExprAST::StructInit(_, fields, _) => {
+            fields.iter().for_each(|(_, x)| collect_parts(x, scope));
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,218 @@
+/* The runtime every C file from `willscript build --emit c` starts with.
+   Values are ints or strings. Strings are never freed: the only ones made
+   while running come from input. */
+
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+enum ws_kind { WS_UNSET, WS_INT, WS_STR };
+
+typedef struct {
+    enum ws_kind kind;
+    int32_t i;
+    const char *s;
+} ws_value;
+
+enum ws_op {
+    WS_NONE, WS_AND, WS_OR, WS_XOR, WS_LEQ, WS_GEQ, WS_EQ, WS_LS, WS_GR,
+    WS_BAND, WS_BOR, WS_BXOR, WS_ADD, WS_SUB, WS_MULT, WS_DIV, WS_MOD
+};
+
+static const char *const ws_symbols[] = {
+    "", "&&", "||", "^^", "<=", ">=", "==", "<", ">",
+    "&", "|", "^", "+", "-", "*", "/", "%"
+};
+
+static int ws_depth = 0;
+
+static inline ws_value ws_int(int32_t i) {
+    ws_value v = {WS_INT, i, NULL};
+    return v;
+}
+
+static inline ws_value ws_str(const char *s) {
+    ws_value v = {WS_STR, 0, s};
+    return v;
+}
+
+/* uncaught, since C output has no try */
+static inline void ws_fail(const char *at, const char *kind, const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fprintf(stderr, "Uncaught error at %s: %s: ", at, kind);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+/* how print shows a value, `buf` holding an int's digits */
+static inline const char *ws_show(ws_value v, char buf[12]) {
+    if (v.kind == WS_STR) {
+        return v.s;
+    }
+    snprintf(buf, 12, "%d", (int)v.i);
+    return buf;
+}
+
+static inline int ws_truthy(ws_value v) {
+    return !(v.kind == WS_INT && v.i == 0);
+}
+
+/* strings sort before ints, like the interpreter's values */
+static inline int ws_cmp(ws_value a, ws_value b) {
+    if (a.kind != b.kind) {
+        return a.kind == WS_STR ? -1 : 1;
+    }
+    if (a.kind == WS_INT) {
+        return (a.i > b.i) - (a.i < b.i);
+    }
+    return strcmp(a.s, b.s);
+}
+
+static inline ws_value ws_binop(enum ws_op op, ws_value a, ws_value b, const char *at) {
+    int64_t l, r, ans;
+    switch (op) {
+    case WS_AND: return ws_int(ws_truthy(a) && ws_truthy(b));
+    case WS_OR: return ws_int(ws_truthy(a) || ws_truthy(b));
+    case WS_XOR: return ws_int(ws_truthy(a) != ws_truthy(b));
+    case WS_LEQ: return ws_int(ws_cmp(a, b) <= 0);
+    case WS_GEQ: return ws_int(ws_cmp(a, b) >= 0);
+    case WS_EQ: return ws_int(ws_cmp(a, b) == 0);
+    case WS_LS: return ws_int(ws_cmp(a, b) < 0);
+    case WS_GR: return ws_int(ws_cmp(a, b) > 0);
+    default: break;
+    }
+    if (a.kind != WS_INT || b.kind != WS_INT) {
+        char lbuf[12], rbuf[12];
+        ws_fail(at, "TypeError", "Can't use %s on %s and %s", ws_symbols[op],
+                ws_show(a, lbuf), ws_show(b, rbuf));
+    }
+    l = a.i;
+    r = b.i;
+    switch (op) {
+    case WS_BAND: return ws_int(a.i & b.i);
+    case WS_BOR: return ws_int(a.i | b.i);
+    case WS_BXOR: return ws_int(a.i ^ b.i);
+    case WS_ADD: ans = l + r; break;
+    case WS_SUB: ans = l - r; break;
+    case WS_MULT: ans = l * r; break;
+    default:
+        if (r == 0) {
+            ws_fail(at, "DivisionByZero", "%d %s 0", (int)l, ws_symbols[op]);
+        }
+        /* INT32_MIN by -1 doesn't fit either way */
+        if (l == INT32_MIN && r == -1) {
+            ans = -l;
+        } else {
+            ans = op == WS_DIV ? l / r : l % r;
+        }
+        break;
+    }
+    if (ans < INT32_MIN || ans > INT32_MAX) {
+        ws_fail(at, "Overflow", "%d %s %d doesn't fit in an int", (int)l,
+                ws_symbols[op], (int)r);
+    }
+    return ws_int((int32_t)ans);
+}
+
+/* a name is a local if it's bound, or else a global if that's initialized */
+static inline ws_value ws_load(const ws_value *local, const ws_value *global, const char *at,
+                        const char *name) {
+    if (local && local->kind != WS_UNSET) {
+        return *local;
+    }
+    if (global && global->kind != WS_UNSET) {
+        return *global;
+    }
+    ws_fail(at, "NameError", "Could not find variable %s", name);
+    return ws_int(0);
+}
+
+static inline void ws_declare(ws_value *local, ws_value v, const char *at, const char *name) {
+    if (local->kind != WS_UNSET) {
+        ws_fail(at, "NameError", "%s is already declared", name);
+    }
+    *local = v;
+}
+
+static inline void ws_assign(ws_value *local, ws_value *global, int is_const, enum ws_op op,
+                      ws_value rhs, const char *at, const char *name) {
+    ws_value *target = NULL;
+    if (local && local->kind != WS_UNSET) {
+        target = local;
+    } else if (global && is_const) {
+        ws_fail(at, "NameError", "Tried to assign to constant %s", name);
+    } else if (global && global->kind != WS_UNSET) {
+        target = global;
+    } else {
+        ws_fail(at, "NameError", "Tried to assign %s, but it wasn't declared", name);
+    }
+    *target = op == WS_NONE ? rhs : ws_binop(op, *target, rhs, at);
+}
+
+static inline void ws_enter(const char *at, const char *name) {
+    if (ws_depth >= 10000) {
+        ws_fail(at, "RecursionError", "Calling %s went over 10000 nested calls", name);
+    }
+    ws_depth++;
+}
+
+static inline void ws_leave(void) {
+    ws_depth--;
+}
+
+static inline void ws_print(ws_value v) {
+    char buf[12];
+    printf("%s\n\n", ws_show(v, buf));
+}
+
+/* a line, newline and all, which is an int if it reads as one once the
+   trailing whitespace is gone */
+static inline void ws_input(ws_value *local) {
+    size_t len = 0, cap = 64;
+    char *line = malloc(cap);
+    int c;
+    while ((c = getchar()) != EOF) {
+        if (len + 2 > cap) {
+            cap *= 2;
+            line = realloc(line, cap);
+        }
+        line[len++] = (char)c;
+        if (c == '\n') {
+            break;
+        }
+    }
+    line[len] = '\0';
+    {
+        size_t end = len, i = 0;
+        int64_t n = 0;
+        int negative = 0, digits = 0;
+        while (end > 0 && strchr(" \t\n\r\v\f", line[end - 1])) {
+            end--;
+        }
+        if (i < end && (line[i] == '+' || line[i] == '-')) {
+            negative = line[i] == '-';
+            i++;
+        }
+        for (; i < end && line[i] >= '0' && line[i] <= '9'; i++, digits++) {
+            n = n * 10 + (line[i] - '0');
+            if (n > (int64_t)INT32_MAX + 1) {
+                break;
+            }
+        }
+        if (negative) {
+            n = -n;
+        }
+        if (digits > 0 && i == end && n >= INT32_MIN && n <= INT32_MAX) {
+            *local = ws_int((int32_t)n);
+            free(line);
+            return;
+        }
+    }
+    *local = ws_str(line);
+}
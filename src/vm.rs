@@ -0,0 +1,885 @@
+use std::{cell::RefCell, io, mem, rc::Rc};
+
+use crate::{
+    ast::{
+        Closure, ErrorValue, Frame, FunctionValue, Local, OrderedMap, ProgramAST, StructValue,
+        Value,
+    },
+    builtins::{call_builtin, check_arity, held_value, map_key},
+    bytecode::{ArgShape, Function, Op, Pattern, Program, Target},
+    compiler::compile,
+    interpreter::{
+        HIGHER_ORDER, MAX_DEPTH, assigned_value, eval_binop, field_value, index_value, new_error,
+        new_variant, set_field, set_index,
+    },
+    lexer::Span,
+};
+
+fn fail<T>(kind: &str, span: Span, message: String) -> Result<T, Rc<ErrorValue>> {
+    Err(ErrorValue::new(kind, message).at(span))
+}
+
+// One call being run.
+struct CallFrame {
+    function: usize,
+    pc: usize,
+    // where its locals start in `slots`, and its temporaries in `stack`
+    slots: usize,
+    stack: usize,
+    call_site: Span,
+    // where its arguments start in `trace_args`, once they're all bound
+    entered: Option<usize>,
+    // the arguments the prologue has yet to bind, for calls that leave some
+    // out or name them
+    pending: Option<Box<Pending>>,
+}
+
+struct Pending {
+    // by parameter
+    args: Vec<Option<Value>>,
+    rest: Vec<Value>,
+}
+
+// Where a throw goes while a try, or a match arm inside one, is running.
+struct Handler {
+    frame: usize,
+    target: usize,
+    stack: usize,
+}
+
+/// Runs programs compiled to bytecode, which behave the same as under the
+/// tree-walking InterpretingMastermind but look their variables up by slot.
+pub struct Vm {
+    program: Rc<Program>,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    // the locals of every frame, one after another
+    slots: Vec<Local>,
+    frames: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    // the arguments of every entered frame, for stack traces
+    trace_args: Vec<Value>,
+    // how many frames are entered, which is what MAX_DEPTH counts
+    depth: usize,
+}
+impl Vm {
+    pub fn new(program: ProgramAST) -> Self {
+        let program = compile(&program);
+        Vm {
+            globals: vec![None; program.globals.len()],
+            program: Rc::new(program),
+            stack: Vec::new(),
+            slots: Vec::new(),
+            frames: Vec::new(),
+            handlers: Vec::new(),
+            trace_args: Vec::new(),
+            depth: 0,
+        }
+    }
+    /// Runs the program, giving back the error if one was thrown and never
+    /// caught.
+    pub fn run_main(&mut self) -> Result<(), Rc<ErrorValue>> {
+        let program = self.program.clone();
+        for &(global, init) in program.inits.iter() {
+            let slots = program.functions[init].slot_count;
+            self.push_frame(init, 0, slots, Span::default(), None);
+            self.globals[global] = Some(self.execute(self.frames.len() - 1)?);
+        }
+        let main = program.function_index["main"];
+        let shape = ArgShape {
+            positional: 0,
+            named: vec![],
+        };
+        self.call_function(&program, main, None, &shape, Span::default())?;
+        self.execute(0)?;
+        Ok(())
+    }
+    fn push_frame(
+        &mut self,
+        function: usize,
+        pc: usize,
+        slot_count: usize,
+        call_site: Span,
+        pending: Option<Box<Pending>>,
+    ) {
+        let slots = self.slots.len();
+        self.slots.resize(slots + slot_count, Local::Unset);
+        self.frames.push(CallFrame {
+            function,
+            pc,
+            slots,
+            stack: self.stack.len(),
+            call_site,
+            entered: None,
+            pending,
+        });
+    }
+    fn pop_frame(&mut self) {
+        let frame = self.frames.pop().expect("Popped a frame that isn't there.");
+        self.slots.truncate(frame.slots);
+        self.stack.truncate(frame.stack);
+        if let Some(start) = frame.entered {
+            self.trace_args.truncate(start);
+            self.depth -= 1;
+        }
+    }
+    // Runs until the frame at `base` returns, and gives what it returned.
+    fn execute(&mut self, base: usize) -> Result<Value, Rc<ErrorValue>> {
+        let program = self.program.clone();
+        loop {
+            let frame = self.frames.last_mut().expect("Ran with no frame.");
+            let function = &program.functions[frame.function];
+            let pc = frame.pc;
+            frame.pc += 1;
+            match self.step(&program, function, pc) {
+                Ok(None) => (),
+                Ok(Some(value)) => {
+                    self.pop_frame();
+                    if self.frames.len() == base {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+                Err(e) => self.unwind(e, base)?,
+            }
+        }
+    }
+    // Sends a thrown error to the innermost handler, leaving frames that don't
+    // have one, but not the one at `base`'s caller.
+    fn unwind(&mut self, mut error: Rc<ErrorValue>, base: usize) -> Result<(), Rc<ErrorValue>> {
+        loop {
+            let current = self.frames.len() - 1;
+            if let Some(handler) = self.handlers.last()
+                && handler.frame == current
+            {
+                let handler = self.handlers.pop().unwrap();
+                self.stack.truncate(handler.stack);
+                self.stack.push(Value::Error(error));
+                self.frames[current].pc = handler.target;
+                return Ok(());
+            }
+            // the first function an error leaves still has the whole stack
+            // above it, so that's where the trace comes from
+            if self.frames[current].entered.is_some() && error.trace.is_empty() {
+                Rc::make_mut(&mut error).trace = self.trace();
+            }
+            self.pop_frame();
+            if self.frames.len() == base {
+                return Err(error);
+            }
+        }
+    }
+    fn trace(&self) -> Vec<Frame> {
+        let entered: Vec<&CallFrame> = self.frames.iter().filter(|f| f.entered.is_some()).collect();
+        let mut trace = Vec::with_capacity(entered.len());
+        for (i, frame) in entered.iter().enumerate() {
+            let start = frame.entered.unwrap();
+            let end = match entered.get(i + 1) {
+                Some(next) => next.entered.unwrap(),
+                None => self.trace_args.len(),
+            };
+            trace.push(Frame {
+                name: self.program.functions[frame.function].proto.name.clone(),
+                call_site: frame.call_site,
+                args: self.trace_args[start..end].to_vec(),
+            });
+        }
+        trace
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Popped an empty stack.")
+    }
+    fn jump(&mut self, target: usize) {
+        self.frames.last_mut().unwrap().pc = target;
+    }
+    // Runs the instruction at `pc`, giving the value if it returned.
+    fn step(
+        &mut self,
+        program: &Program,
+        function: &Function,
+        pc: usize,
+    ) -> Result<Option<Value>, Rc<ErrorValue>> {
+        let span = function.spans[pc];
+        let base = self.frames.last().unwrap().slots;
+        match function.code[pc] {
+            Op::Const(i) => self.stack.push(function.constants[i].clone()),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Load(slot) => {
+                let value = match self.slots[base + slot].get() {
+                    Some(x) => x,
+                    None => self.load_name(program, &function.slot_names[slot], span)?,
+                };
+                self.stack.push(value);
+            }
+            Op::LoadGlobal(global) => {
+                let value = match &self.globals[global] {
+                    Some(x) => x.clone(),
+                    None => self.load_name(program, &program.globals[global], span)?,
+                };
+                self.stack.push(value);
+            }
+            Op::LoadName(name) => {
+                let value = self.load_name(program, &function.names[name], span)?;
+                self.stack.push(value);
+            }
+            Op::Declare(slot) => {
+                let value = self.pop();
+                self.declare(function, base + slot, slot, value, span)?;
+            }
+            Op::Destructure(target) => {
+                let value = self.pop();
+                self.destructure(function, &function.targets[target], value, span)?;
+            }
+            Op::Assign {
+                slot,
+                global,
+                name,
+                compound,
+            } => {
+                let rhs = self.pop();
+                self.assign(
+                    slot.map(|s| base + s),
+                    global,
+                    &function.names[name],
+                    &compound,
+                    rhs,
+                    span,
+                )?;
+            }
+            Op::SetIndex(compound) => {
+                let index = self.pop();
+                let container = self.pop();
+                let rhs = self.pop();
+                set_index(container, index, &compound, rhs).map_err(|e| e.at(span))?;
+            }
+            Op::SetField(field, compound) => {
+                let instance = self.pop();
+                let rhs = self.pop();
+                set_field(instance, &function.names[field], &compound, rhs)
+                    .map_err(|e| e.at(span))?;
+            }
+            Op::SetLocal(slot) => self.slots[base + slot] = Local::Set(self.pop()),
+            Op::Unset(slot) => self.slots[base + slot].set(None),
+            Op::Input(slot) => {
+                let mut buf = String::new();
+                io::stdin()
+                    .read_line(&mut buf)
+                    .expect("could not get stdin");
+                let num = buf.trim_end().parse::<i32>();
+                self.slots[base + slot].set(Some(match num {
+                    Ok(number) => Value::Int(number),
+                    Err(_) => Value::Str(buf),
+                }));
+            }
+            Op::Print => println!("{}\n", self.pop()),
+            Op::List(len) => {
+                let items = self.stack.split_off(self.stack.len() - len);
+                self.stack.push(Value::new_list(items));
+            }
+            Op::Tuple(len) => {
+                let items = self.stack.split_off(self.stack.len() - len);
+                self.stack.push(Value::Tuple(Rc::new(items)));
+            }
+            Op::MapKey => {
+                let key = map_key(self.pop()).map_err(|e| e.at(span))?;
+                self.stack.push(key);
+            }
+            Op::Map(len) => {
+                let entries = self.stack.split_off(self.stack.len() - 2 * len);
+                let mut map = OrderedMap::new();
+                let mut entries = entries.into_iter();
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    map.insert(key, value);
+                }
+                self.stack.push(Value::new_map(map));
+            }
+            Op::Struct(literal) => {
+                let literal = &function.structs[literal];
+                let given = self.stack.split_off(self.stack.len() - literal.given.len());
+                let mut given: Vec<Option<Value>> = given.into_iter().map(Some).collect();
+                // evaluated in source order, laid out in declaration order
+                let mut fields = Vec::with_capacity(literal.fields.len());
+                for field in literal.fields.iter() {
+                    let Some(pos) = literal.given.iter().position(|f| f == field) else {
+                        return fail(
+                            "FieldError",
+                            span,
+                            format!("Missing field {} in {} literal", field, literal.name),
+                        );
+                    };
+                    fields.push((field.clone(), given[pos].take().unwrap()));
+                }
+                self.stack
+                    .push(Value::Struct(Rc::new(RefCell::new(StructValue {
+                        name: literal.name.clone(),
+                        fields,
+                    }))));
+            }
+            Op::Closure(closure) => {
+                // the closure's slots start with this function's named ones
+                let captured = self.slots[base..base + function.slot_names.len()]
+                    .iter_mut()
+                    .map(Local::share)
+                    .collect();
+                self.stack
+                    .push(Value::Function(Rc::new(FunctionValue::Closure(Closure {
+                        function: function.closures[closure].clone(),
+                        captured,
+                    }))));
+            }
+            Op::Index => {
+                let index = self.pop();
+                let container = self.pop();
+                let value = index_value(container, index).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::Field(field) => {
+                let instance = self.pop();
+                let value =
+                    field_value(instance, &function.names[field]).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::Binary(op) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let value = eval_binop(&op, lhs, rhs).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::Fail(error) => {
+                let (kind, message) = &function.errors[error];
+                return fail(kind, span, message.clone());
+            }
+            Op::Jump(target) => self.jump(target),
+            Op::JumpIfFalse(target) => {
+                if self.pop() == Value::Int(0) {
+                    self.jump(target);
+                }
+            }
+            Op::Call(site) => self.call_name(program, function, site, span)?,
+            Op::CallValue(shape) => {
+                let shape = &function.shapes[shape];
+                let callee = self.stack.remove(self.stack.len() - shape.len() - 1);
+                self.call_value(program, callee, shape, span)?;
+            }
+            Op::BindParam(i, skip) => {
+                let frame = self.frames.last_mut().unwrap();
+                let pending = frame
+                    .pending
+                    .as_mut()
+                    .expect("Prologue ran without a call.");
+                if let Some(value) = pending.args[i].take() {
+                    frame.pc = skip;
+                    self.slots[base + function.params[i]] = Local::Set(value);
+                }
+            }
+            Op::BindRest => {
+                let frame = self.frames.last_mut().unwrap();
+                let pending = frame
+                    .pending
+                    .as_mut()
+                    .expect("Prologue ran without a call.");
+                let rest = std::mem::take(&mut pending.rest);
+                self.slots[base + function.rest.unwrap()] = Local::Set(Value::new_list(rest));
+            }
+            Op::Enter => self.enter(function),
+            Op::Return => return Ok(Some(self.pop())),
+            Op::Propagate(target) => {
+                let value = self.pop();
+                let held = held_value("?", &value).map_err(|e| e.at(span))?.cloned();
+                match held {
+                    Some(x) => {
+                        self.stack.push(x);
+                        self.jump(target);
+                    }
+                    None => self.stack.push(value),
+                }
+            }
+            Op::Throw => {
+                return Err(match self.pop() {
+                    Value::Error(error) => error,
+                    // anything else thrown becomes the message of an Error
+                    value => ErrorValue::new("Error", value.to_string()).at(span),
+                });
+            }
+            Op::PushHandler(target) => self.handlers.push(Handler {
+                frame: self.frames.len() - 1,
+                target,
+                stack: self.stack.len(),
+            }),
+            Op::PopHandler => {
+                self.handlers.pop();
+            }
+            Op::TestPattern {
+                pattern,
+                slot,
+                fail,
+            } => {
+                let arm = &function.patterns[pattern];
+                let value = self.slots[base + slot].get().unwrap();
+                let mut values = Vec::with_capacity(arm.binds.len());
+                if match_pattern(&arm.pattern, &value, &mut values) {
+                    for (&(slot, save), value) in arm.binds.iter().zip(values) {
+                        self.slots[base + save] = mem::take(&mut self.slots[base + slot]);
+                        self.slots[base + slot] = Local::Set(value);
+                    }
+                } else {
+                    self.jump(fail);
+                }
+            }
+            Op::Unbind(pattern) => {
+                for &(slot, save) in function.patterns[pattern].binds.iter().rev() {
+                    self.slots[base + slot] = mem::take(&mut self.slots[base + save]);
+                }
+            }
+            Op::Catch { slot, save } => {
+                let Value::Error(mut error) = self.pop() else {
+                    unreachable!("Handlers get the error.");
+                };
+                // caught in the function that threw it, so no trace yet
+                if error.trace.is_empty() {
+                    Rc::make_mut(&mut error).trace = self.trace();
+                }
+                self.slots[base + save] = mem::take(&mut self.slots[base + slot]);
+                self.slots[base + slot] = Local::Set(Value::Error(error));
+            }
+            Op::Restore { slot, save } => {
+                self.slots[base + slot] = mem::take(&mut self.slots[base + save]);
+            }
+            Op::NoMatch(slot) => {
+                let value = self.slots[base + slot].get().unwrap();
+                return fail(
+                    "MatchError",
+                    span,
+                    format!("No match arm matched {}", value),
+                );
+            }
+        }
+        Ok(None)
+    }
+
+    // What a name that isn't a bound local means: an initialized global, a
+    // declared function or a unit variant.
+    fn load_name(
+        &self,
+        program: &Program,
+        name: &str,
+        span: Span,
+    ) -> Result<Value, Rc<ErrorValue>> {
+        if let Some(&global) = program.global_index.get(name)
+            && let Some(x) = &self.globals[global]
+        {
+            return Ok(x.clone());
+        }
+        if let Some((name, _)) = program.function_index.get_key_value(name) {
+            return Ok(Value::Function(Rc::new(FunctionValue::Named(name.clone()))));
+        }
+        match program.variants.get_key_value(name) {
+            Some((variant, (enum_name, 0))) => Ok(new_variant(enum_name, variant, vec![])),
+            Some(_) => fail(
+                "ArgumentError",
+                span,
+                format!("Variant {} needs a payload", name),
+            ),
+            None => fail(
+                "NameError",
+                span,
+                format!("Could not find variable {}", name),
+            ),
+        }
+    }
+    fn declare(
+        &mut self,
+        function: &Function,
+        at: usize,
+        slot: usize,
+        value: Value,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        if self.slots[at].is_set() {
+            let name = &function.slot_names[slot];
+            return fail("NameError", span, format!("{} is already declared", name));
+        }
+        self.slots[at].set(Some(value));
+        Ok(())
+    }
+    // Declares each name in a destructuring target with its part of the value.
+    fn destructure(
+        &mut self,
+        function: &Function,
+        target: &Target,
+        value: Value,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        let base = self.frames.last().unwrap().slots;
+        match (target, value) {
+            (Target::Skip, _) => Ok(()),
+            (Target::Name(slot, name_span), value) => {
+                self.declare(function, base + slot, *slot, value, *name_span)
+            }
+            (Target::Tuple(targets), Value::Tuple(items)) => {
+                self.destructure_items(function, targets, &items, "tuple", span)
+            }
+            (Target::List(targets), Value::List(xs)) => {
+                let items = xs.borrow().clone();
+                self.destructure_items(function, targets, &items, "list", span)
+            }
+            (Target::Struct(name, fields), Value::Struct(instance)) => {
+                let instance = instance.borrow().clone();
+                if *instance.name != **name {
+                    return fail(
+                        "TypeError",
+                        span,
+                        format!("Can't destructure a {} as a {}", instance.name, name),
+                    );
+                }
+                for (field, target) in fields {
+                    let Some(value) = instance.get(field) else {
+                        return fail(
+                            "FieldError",
+                            span,
+                            format!("Struct {} has no field {}", name, field),
+                        );
+                    };
+                    self.destructure(function, target, value.clone(), span)?;
+                }
+                Ok(())
+            }
+            (_, value) => fail(
+                "TypeError",
+                span,
+                format!("Can't destructure {} that way", value),
+            ),
+        }
+    }
+    fn destructure_items(
+        &mut self,
+        function: &Function,
+        targets: &[Target],
+        items: &[Value],
+        kind: &str,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        if targets.len() != items.len() {
+            return fail(
+                "ValueError",
+                span,
+                format!(
+                    "Can't destructure a {} of length {} into {} names",
+                    kind,
+                    items.len(),
+                    targets.len()
+                ),
+            );
+        }
+        for (target, item) in targets.iter().zip(items) {
+            self.destructure(function, target, item.clone(), span)?;
+        }
+        Ok(())
+    }
+    // `name op= rhs`, to the local at `at` if it's bound, or else the global.
+    fn assign(
+        &mut self,
+        at: Option<usize>,
+        global: Option<usize>,
+        name: &str,
+        compound: &Option<crate::lexer::Operator>,
+        rhs: Value,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        if let Some(at) = at
+            && self.slots[at].is_set()
+        {
+            let local = &mut self.slots[at];
+            let value = assigned_value(compound, || Ok(local.get().unwrap()), rhs)
+                .map_err(|e| e.at(span))?;
+            local.set(Some(value));
+            return Ok(());
+        }
+        if let Some(global) = global
+            && self.program.constants.contains(&global)
+        {
+            return fail(
+                "NameError",
+                span,
+                format!("Tried to assign to constant {}", name),
+            );
+        }
+        if let Some(global) = global
+            && let Some(value) = &mut self.globals[global]
+        {
+            *value = assigned_value(compound, || Ok(value.clone()), rhs).map_err(|e| e.at(span))?;
+            return Ok(());
+        }
+        fail(
+            "NameError",
+            span,
+            format!("Tried to assign {}, but it wasn't declared", name),
+        )
+    }
+
+    // A call by name, with its arguments on the stack.
+    fn call_name(
+        &mut self,
+        program: &Program,
+        function: &Function,
+        site: usize,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        let site = &function.calls[site];
+        let base = self.frames.last().unwrap().slots;
+        let value = site
+            .slot
+            .and_then(|slot| self.slots[base + slot].get())
+            .or_else(|| site.global.and_then(|g| self.globals[g].clone()));
+        if let Some(callee) = value {
+            return self.call_value(program, callee, &site.args, span);
+        } else if let Some(index) = site.function {
+            return self.call_function(program, index, None, &site.args, span);
+        }
+        let name = site.name.as_str();
+        let args = self.stack.split_off(self.stack.len() - site.args.len());
+        // builtins and variants don't have parameter names
+        if let Some(arg) = site.args.named.first() {
+            return fail(
+                "ArgumentError",
+                span,
+                format!("{} doesn't take named arguments, got {}", name, arg),
+            );
+        }
+        let value =
+            if let Some((variant, (enum_name, arity))) = program.variants.get_key_value(name) {
+                if *arity != args.len() {
+                    return fail(
+                        "ArgumentError",
+                        span,
+                        format!(
+                            "Variant {} takes {} values, got {}",
+                            name,
+                            arity,
+                            args.len()
+                        ),
+                    );
+                }
+                new_variant(enum_name, variant, args)
+            } else if HIGHER_ORDER.contains(&name) {
+                self.call_higher_order(program, name, args, span)?
+            } else if name == "error" {
+                new_error(args, span)?
+            } else {
+                call_builtin(name, args).map_err(|e| e.at(span))?
+            };
+        self.stack.push(value);
+        Ok(())
+    }
+    fn call_value(
+        &mut self,
+        program: &Program,
+        callee: Value,
+        args: &ArgShape,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        let Value::Function(function) = &callee else {
+            return fail("TypeError", span, format!("{} is not a function", callee));
+        };
+        match &**function {
+            FunctionValue::Named(name) => {
+                self.call_function(program, program.function_index[name], None, args, span)
+            }
+            FunctionValue::Closure(closure) => {
+                let index = program.closure_index[&Rc::as_ptr(&closure.function)];
+                self.call_function(program, index, Some(&closure.captured), args, span)
+            }
+        }
+    }
+    // Starts running a function on the arguments on the stack.
+    fn call_function(
+        &mut self,
+        program: &Program,
+        index: usize,
+        captured: Option<&[Local]>,
+        args: &ArgShape,
+        span: Span,
+    ) -> Result<(), Rc<ErrorValue>> {
+        let function = &program.functions[index];
+        let check = if args.named.is_empty() {
+            function.proto.check_args(args.positional, &[])
+        } else {
+            let named: Vec<&str> = args.named.iter().map(|n| n.as_str()).collect();
+            function.proto.check_args(args.positional, &named)
+        };
+        if let Err(e) = check {
+            return fail("ArgumentError", span, e);
+        }
+        if self.depth >= MAX_DEPTH {
+            return fail(
+                "RecursionError",
+                span,
+                format!(
+                    "Calling {} went over {} nested calls",
+                    function.proto.name, MAX_DEPTH
+                ),
+            );
+        }
+        let start = self.stack.len() - args.len();
+        let params = function.params.len();
+        if args.named.is_empty() && args.positional >= params {
+            // every parameter is given in order, so the prologue is skipped
+            let mut values = self.stack.split_off(start).into_iter();
+            self.push_frame(
+                index,
+                function.body_start - 1,
+                function.slot_count,
+                span,
+                None,
+            );
+            let base = self.capture(captured);
+            for &slot in function.params.iter() {
+                self.slots[base + slot] = values.next().into();
+            }
+            if let Some(rest) = function.rest {
+                self.slots[base + rest] = Local::Set(Value::new_list(values.collect()));
+            }
+        } else {
+            let mut positional = self.stack.split_off(start);
+            let named = positional.split_off(args.positional);
+            let mut pending = Pending {
+                args: vec![None; params],
+                rest: if positional.len() > params {
+                    positional.split_off(params)
+                } else {
+                    vec![]
+                },
+            };
+            for (i, value) in positional.into_iter().enumerate() {
+                pending.args[i] = Some(value);
+            }
+            for (name, value) in args.named.iter().zip(named) {
+                let i = function
+                    .proto
+                    .args
+                    .iter()
+                    .position(|p| p.name == *name)
+                    .expect("Checked by check_args.");
+                pending.args[i].get_or_insert(value);
+            }
+            self.push_frame(index, 0, function.slot_count, span, Some(Box::new(pending)));
+            self.capture(captured);
+        }
+        Ok(())
+    }
+    // Gives the new frame what its closure captured, before the parameters
+    // are bound over it. Returns where the frame's slots start.
+    fn capture(&mut self, captured: Option<&[Local]>) -> usize {
+        let base = self.frames.last().unwrap().slots;
+        if let Some(captured) = captured {
+            self.slots[base..base + captured.len()].clone_from_slice(captured);
+        }
+        base
+    }
+    // Every parameter is bound, so the call goes on the stack trace.
+    fn enter(&mut self, function: &Function) {
+        let frame = self.frames.last_mut().unwrap();
+        frame.pending = None;
+        frame.entered = Some(self.trace_args.len());
+        let base = frame.slots;
+        for &slot in function.params.iter().chain(&function.rest) {
+            let value = self.slots[base + slot]
+                .get()
+                .expect("Parameters are bound.");
+            self.trace_args.push(value);
+        }
+        self.depth += 1;
+    }
+    // Calls a function value and runs it to the end, for builtins that call
+    // back into the script.
+    fn call_sync(
+        &mut self,
+        program: &Program,
+        callee: &Value,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Rc<ErrorValue>> {
+        let shape = ArgShape {
+            positional: args.len(),
+            named: vec![],
+        };
+        self.stack.extend(args);
+        let base = self.frames.len();
+        self.call_value(program, callee.clone(), &shape, span)?;
+        self.execute(base)
+    }
+    fn call_higher_order(
+        &mut self,
+        program: &Program,
+        name: &str,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Rc<ErrorValue>> {
+        let expected = match name {
+            "map" | "filter" => 2,
+            "reduce" => 3,
+            _ => unreachable!(),
+        };
+        check_arity(name, &args, expected).map_err(|e| e.at(span))?;
+        let mut args = args.into_iter();
+        let Some(Value::List(xs)) = args.next() else {
+            return fail("TypeError", span, format!("{} needs a list", name));
+        };
+        let f = args.next().unwrap();
+        // copy the items out so the callback is free to change the list
+        let items = xs.borrow().clone();
+        match name {
+            "map" => {
+                let mut mapped = Vec::with_capacity(items.len());
+                for x in items {
+                    mapped.push(self.call_sync(program, &f, vec![x], span)?);
+                }
+                Ok(Value::new_list(mapped))
+            }
+            "filter" => {
+                let mut kept = Vec::new();
+                for x in items {
+                    if self.call_sync(program, &f, vec![x.clone()], span)? != Value::Int(0) {
+                        kept.push(x);
+                    }
+                }
+                Ok(Value::new_list(kept))
+            }
+            "reduce" => {
+                let mut acc = args.next().unwrap();
+                for x in items {
+                    acc = self.call_sync(program, &f, vec![acc, x], span)?;
+                }
+                Ok(acc)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Whether the value fits the pattern, collecting what its names bind to.
+fn match_pattern(pattern: &Pattern, value: &Value, values: &mut Vec<Value>) -> bool {
+    match pattern {
+        Pattern::Wildcard => true,
+        Pattern::Literal(x) => x == value,
+        Pattern::Bind => {
+            values.push(value.clone());
+            true
+        }
+        Pattern::Unit(name) => matches!(value, Value::Variant(v) if *v.variant == **name),
+        Pattern::Variant(name, subpatterns) => {
+            let Value::Variant(v) = value else {
+                return false;
+            };
+            *v.variant == **name
+                && v.payload.len() == subpatterns.len()
+                && subpatterns
+                    .iter()
+                    .zip(v.payload.iter())
+                    .all(|(p, x)| match_pattern(p, x, values))
+        }
+    }
+}
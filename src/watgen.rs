@@ -0,0 +1,678 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{BuiltIn, ExprAST, FunctionAST, ProgramAST, Statement, Value},
+    checker::constant_order,
+    interpreter::MAX_DEPTH,
+    lexer::{Operator, Span},
+};
+
+// What the host has to give the module. `fail` gets where the error happened
+// and its message, with each `{}` in the message standing for the next of the
+// two ints after it, and the module traps once it returns.
+const HEADER: &str = r#";; Generated by `willscript build --emit wat`. The host provides:
+;;   print_int(n) and print_str(ptr, len), which show a value like `print`
+;;   input() -> i32, which reads a line holding an int
+;;   fail(at_ptr, at_len, message_ptr, message_len, a, b), which reports an
+;;     uncaught error, the message's `{}`s standing for a and b in order
+;; then calls the exported main. Strings are in the exported memory.
+(module
+  (import "willscript" "print_int" (func $print_int (param i32)))
+  (import "willscript" "print_str" (func $print_str (param i32 i32)))
+  (import "willscript" "input" (func $input (result i32)))
+  (import "willscript" "fail" (func $fail (param i32 i32 i32 i32 i32 i32)))
+"#;
+
+// Translates a resolved program to a WebAssembly text module. Every value is
+// an i32, with operators checked for overflow in i64 the way the interpreter
+// checks them, so only programs of ints translate; a string can only be a
+// literal that's printed. `print` and `input` are imported from the host and
+// the script's main is exported as `main`, after the globals it initializes.
+pub fn generate(program: &ProgramAST) -> Result<String, String> {
+    let mut data = Data::default();
+    let mut funcs = String::new();
+    for (i, func) in program.functions.iter().enumerate() {
+        let proto = &func.proto;
+        if proto.rest.is_some() || proto.args.iter().any(|p| p.default.is_some()) {
+            return Err(format!(
+                "In function {}: default and rest parameters can't be compiled to WebAssembly.",
+                proto.name
+            ));
+        }
+        let mut writer = FunctionWriter::new(program, &func.locals, &mut data);
+        writer
+            .function(func)
+            .map_err(|e| format!("In function {}: {}", proto.name, e))?;
+        funcs += &format!("\n  (func {}", function_name(program, i));
+        for &slot in &func.param_slots {
+            funcs += &format!(" (param {} i32)", local_name(&func.locals, slot));
+        }
+        funcs += " (result i32)\n";
+        funcs += &writer.finish(&func.param_slots);
+        funcs += "  )\n";
+    }
+    let mut init_order: Vec<&str> = constant_order(program)?
+        .into_iter()
+        .map(|g| g.name.as_str())
+        .collect();
+    init_order.extend(
+        program
+            .globals
+            .iter()
+            .filter(|g| !g.is_const)
+            .map(|g| g.name.as_str()),
+    );
+    let mut entry = String::from("\n  (func (export \"main\")\n");
+    for name in init_order {
+        let index = program
+            .globals
+            .iter()
+            .position(|g| g.name == name)
+            .expect("Every global is in the order.");
+        let global = &program.globals[index];
+        let mut writer = FunctionWriter::new(program, &global.locals, &mut data);
+        writer
+            .expr(&global.init)
+            .map_err(|e| format!("In the initializer of {}: {}", global.name, e))?;
+        funcs += &format!(
+            "\n  (func $init_{} (result i32)\n",
+            &global_name(program, index)[1..]
+        );
+        funcs += &writer.finish(&[]);
+        funcs += "  )\n";
+        entry += &format!("    call $init_{}\n", &global_name(program, index)[1..]);
+        entry += &format!("    global.set {}\n", global_name(program, index));
+        entry += "    i32.const 1\n";
+        entry += &format!(
+            "    global.set {}\n",
+            set_name(&global_name(program, index))
+        );
+    }
+    let Some(main) = program
+        .functions
+        .iter()
+        .position(|f| &*f.proto.name == "main")
+    else {
+        return Err("There is no main function.".to_owned());
+    };
+    // main counts toward the recursion limit, as it does in the interpreter
+    entry += "    i32.const 1\n    global.set $depth\n";
+    entry += &format!("    call {}\n    drop\n  )\n", function_name(program, main));
+
+    let mut out = String::from(HEADER);
+    out += "\n  (global $depth (mut i32) (i32.const 0))\n";
+    for i in 0..program.globals.len() {
+        let name = global_name(program, i);
+        out += &format!("  (global {} (mut i32) (i32.const 0))\n", name);
+        out += &format!("  (global {} (mut i32) (i32.const 0))\n", set_name(&name));
+    }
+    out += &runtime(&mut data);
+    out += &funcs;
+    out += &entry;
+    out += &format!(
+        "\n  (memory (export \"memory\") {})\n",
+        data.bytes.len().div_ceil(65536).max(1)
+    );
+    out += &format!("  (data (i32.const 0) \"{}\")\n)\n", wat_bytes(&data.bytes));
+    Ok(out)
+}
+
+// The checked operators, and entering and leaving a call.
+fn runtime(data: &mut Data) -> String {
+    let mut out = String::new();
+    for (name, instr, symbol) in [
+        ("add", "i64.add", "+"),
+        ("sub", "i64.sub", "-"),
+        ("mult", "i64.mul", "*"),
+    ] {
+        let (message, len) = data.string(&format!(
+            "Overflow: {{}} {} {{}} doesn't fit in an int",
+            symbol
+        ));
+        out += &format!(
+            "
+  (func $ws_{name} (param $l i32) (param $r i32) (param $at i32) (param $at_len i32) (result i32)
+    (local $ans i64)
+    local.get $l
+    i64.extend_i32_s
+    local.get $r
+    i64.extend_i32_s
+    {instr}
+    local.set $ans
+    local.get $ans
+    local.get $ans
+    i32.wrap_i64
+    i64.extend_i32_s
+    i64.ne
+    if
+      local.get $at
+      local.get $at_len
+      i32.const {message}
+      i32.const {len}
+      local.get $l
+      local.get $r
+      call $fail
+      unreachable
+    end
+    local.get $ans
+    i32.wrap_i64
+  )
+"
+        );
+    }
+    for (name, instr, symbol) in [("div", "i32.div_s", "/"), ("mod", "i32.rem_s", "%")] {
+        let (zero, zero_len) = data.string(&format!("DivisionByZero: {{}} {} 0", symbol));
+        let (overflow, overflow_len) = data.string(&format!(
+            "Overflow: {{}} {} {{}} doesn't fit in an int",
+            symbol
+        ));
+        out += &format!(
+            "
+  (func $ws_{name} (param $l i32) (param $r i32) (param $at i32) (param $at_len i32) (result i32)
+    local.get $r
+    i32.eqz
+    if
+      local.get $at
+      local.get $at_len
+      i32.const {zero}
+      i32.const {zero_len}
+      local.get $l
+      local.get $r
+      call $fail
+      unreachable
+    end
+    ;; the one quotient that doesn't fit in an i32
+    local.get $l
+    i32.const -2147483648
+    i32.eq
+    local.get $r
+    i32.const -1
+    i32.eq
+    i32.and
+    if
+      local.get $at
+      local.get $at_len
+      i32.const {overflow}
+      i32.const {overflow_len}
+      local.get $l
+      local.get $r
+      call $fail
+      unreachable
+    end
+    local.get $l
+    local.get $r
+    {instr}
+  )
+"
+        );
+    }
+    out += &format!(
+        "
+  (func $ws_enter (param $at i32) (param $at_len i32) (param $message i32) (param $message_len i32)
+    global.get $depth
+    i32.const {MAX_DEPTH}
+    i32.ge_s
+    if
+      local.get $at
+      local.get $at_len
+      local.get $message
+      local.get $message_len
+      i32.const 0
+      i32.const 0
+      call $fail
+      unreachable
+    end
+    global.get $depth
+    i32.const 1
+    i32.add
+    global.set $depth
+  )
+
+  (func $ws_leave
+    global.get $depth
+    i32.const 1
+    i32.sub
+    global.set $depth
+  )
+"
+    );
+    out
+}
+
+// The strings the module needs, laid out one after another in its memory.
+#[derive(Default)]
+struct Data {
+    bytes: Vec<u8>,
+    offsets: HashMap<String, usize>,
+}
+impl Data {
+    // Where the string is and how long it is, adding it the first time.
+    fn string(&mut self, s: &str) -> (usize, usize) {
+        let offset = match self.offsets.get(s) {
+            Some(&offset) => offset,
+            None => {
+                let offset = self.bytes.len();
+                self.bytes.extend_from_slice(s.as_bytes());
+                self.offsets.insert(s.to_owned(), offset);
+                offset
+            }
+        };
+        (offset, s.len())
+    }
+}
+
+// Names start with what they are and their index, so the script's names
+// can't clash with each other or with the runtime's.
+fn wat_name(kind: char, index: usize, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("${}{}_{}", kind, index, name)
+}
+
+fn function_name(program: &ProgramAST, index: usize) -> String {
+    wat_name('f', index, &program.functions[index].proto.name)
+}
+
+fn global_name(program: &ProgramAST, index: usize) -> String {
+    wat_name('g', index, &program.globals[index].name)
+}
+
+fn local_name(locals: &[String], slot: usize) -> String {
+    wat_name('l', slot, &locals[slot])
+}
+
+// Every variable has a flag beside it saying whether it's bound.
+fn set_name(name: &str) -> String {
+    format!("$set_{}", &name[1..])
+}
+
+fn wat_bytes(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => out += &format!("\\{:02x}", byte),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out += &format!("\\{:02x}", byte),
+        }
+    }
+    out
+}
+
+fn unsupported<T>(what: &str, span: Option<&Span>) -> Result<T, String> {
+    match span {
+        Some(span) => Err(format!(
+            "{}: {} can't be compiled to WebAssembly.",
+            span, what
+        )),
+        None => Err(format!("{} can't be compiled to WebAssembly.", what)),
+    }
+}
+
+// Writes the body of one function. Every expression leaves exactly one i32 on
+// the stack.
+struct FunctionWriter<'a> {
+    program: &'a ProgramAST,
+    locals: &'a [String],
+    data: &'a mut Data,
+    code: String,
+    indent: usize,
+}
+impl<'a> FunctionWriter<'a> {
+    fn new(program: &'a ProgramAST, locals: &'a [String], data: &'a mut Data) -> Self {
+        FunctionWriter {
+            program,
+            locals,
+            data,
+            code: String::new(),
+            indent: 2,
+        }
+    }
+    // The locals that aren't parameters and every flag, then the parameters'
+    // flags set, then the code.
+    fn finish(self, params: &[usize]) -> String {
+        let mut out = String::new();
+        for slot in 0..self.locals.len() {
+            let name = local_name(self.locals, slot);
+            if !params.contains(&slot) {
+                out += &format!("    (local {} i32)\n", name);
+            }
+            out += &format!("    (local {} i32)\n", set_name(&name));
+        }
+        out += "    (local $tmp i32)\n";
+        for &slot in params {
+            out += "    i32.const 1\n";
+            out += &format!(
+                "    local.set {}\n",
+                set_name(&local_name(self.locals, slot))
+            );
+        }
+        out + &self.code
+    }
+    fn line(&mut self, line: impl AsRef<str>) {
+        self.code += &"  ".repeat(self.indent);
+        self.code += line.as_ref();
+        self.code.push('\n');
+    }
+    fn string(&mut self, s: &str) {
+        let (offset, len) = self.data.string(s);
+        self.line(format!("i32.const {}", offset));
+        self.line(format!("i32.const {}", len));
+    }
+    // Reports an uncaught error, which the host can't return from.
+    fn fail(&mut self, span: &Span, message: &str) {
+        self.string(&span.to_string());
+        self.string(message);
+        self.line("i32.const 0");
+        self.line("i32.const 0");
+        self.line("call $fail");
+        self.line("unreachable");
+    }
+    fn function(&mut self, func: &FunctionAST) -> Result<(), String> {
+        self.statements(&func.body)?;
+        self.line("i32.const 0");
+        Ok(())
+    }
+    fn block(&mut self, body: &[Statement]) -> Result<(), String> {
+        self.indent += 1;
+        self.statements(body)?;
+        self.indent -= 1;
+        Ok(())
+    }
+    fn statements(&mut self, body: &[Statement]) -> Result<(), String> {
+        for statement in body {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Assign(x) => {
+                let ExprAST::Variable(name, span, resolution) = &x.variable else {
+                    return unsupported("Assigning to anything but a name", Some(&x.span));
+                };
+                self.expr(&x.right_hand)?;
+                self.line("local.set $tmp");
+                if x.is_declaration {
+                    let local = local_name(
+                        self.locals,
+                        resolution.slot.expect("Declarations get a slot."),
+                    );
+                    self.line(format!("local.get {}", set_name(&local)));
+                    self.line("if");
+                    self.indent += 1;
+                    self.fail(span, &format!("NameError: {} is already declared", name));
+                    self.indent -= 1;
+                    self.line("end");
+                    self.line("local.get $tmp");
+                    self.line(format!("local.set {}", local));
+                    self.line("i32.const 1");
+                    self.line(format!("local.set {}", set_name(&local)));
+                    return Ok(());
+                }
+                let mut ends = 0;
+                if let Some(slot) = resolution.slot {
+                    let local = local_name(self.locals, slot);
+                    self.line(format!("local.get {}", set_name(&local)));
+                    self.line("if");
+                    self.indent += 1;
+                    self.update("local", &local, x.compound.as_ref(), span);
+                    self.indent -= 1;
+                    self.line("else");
+                    self.indent += 1;
+                    ends += 1;
+                }
+                match resolution.global {
+                    Some(g) if self.program.globals[g].is_const => self.fail(
+                        span,
+                        &format!("NameError: Tried to assign to constant {}", name),
+                    ),
+                    Some(g) => {
+                        let global = global_name(self.program, g);
+                        self.line(format!("global.get {}", set_name(&global)));
+                        self.line("if");
+                        self.indent += 1;
+                        self.update("global", &global, x.compound.as_ref(), span);
+                        self.indent -= 1;
+                        self.line("else");
+                        self.indent += 1;
+                        ends += 1;
+                        self.undeclared(name, span);
+                    }
+                    None => self.undeclared(name, span),
+                }
+                for _ in 0..ends {
+                    self.indent -= 1;
+                    self.line("end");
+                }
+            }
+            Statement::If(x) => {
+                self.expr(&x.conditional)?;
+                self.line("if");
+                self.block(&x.body)?;
+                if !x.else_body.is_empty() {
+                    self.line("else");
+                    self.block(&x.else_body)?;
+                }
+                self.line("end");
+            }
+            Statement::While(x) => {
+                // the condition is evaluated again on every pass
+                self.line("block");
+                self.indent += 1;
+                self.line("loop");
+                self.indent += 1;
+                self.expr(&x.conditional)?;
+                self.line("i32.eqz");
+                self.line("br_if 1");
+                self.statements(&x.body)?;
+                self.line("br 0");
+                self.indent -= 1;
+                self.line("end");
+                self.indent -= 1;
+                self.line("end");
+            }
+            Statement::Call(x) => {
+                self.expr(x)?;
+                self.line("drop");
+            }
+            Statement::Built(BuiltIn::Print(ExprAST::Val(Value::Str(x)), _)) => {
+                self.string(x);
+                self.line("call $print_str");
+            }
+            Statement::Built(BuiltIn::Print(x, _)) => {
+                self.expr(x)?;
+                self.line("call $print_int");
+            }
+            Statement::Built(BuiltIn::Return(x, _)) => {
+                self.expr(x)?;
+                self.line("return");
+            }
+            Statement::Built(BuiltIn::Input(x)) => {
+                let ExprAST::Variable(_, _, resolution) = x else {
+                    unreachable!();
+                };
+                let local = local_name(self.locals, resolution.slot.expect("Input binds a local."));
+                self.line("call $input");
+                self.line(format!("local.set {}", local));
+                self.line("i32.const 1");
+                self.line(format!("local.set {}", set_name(&local)));
+            }
+            Statement::Built(BuiltIn::Drop(x)) => {
+                let ExprAST::Variable(_, _, resolution) = x else {
+                    unreachable!();
+                };
+                if let Some(slot) = resolution.slot {
+                    self.line("i32.const 0");
+                    self.line(format!(
+                        "local.set {}",
+                        set_name(&local_name(self.locals, slot))
+                    ));
+                }
+            }
+            Statement::Match(x) => return unsupported("match", Some(&x.span)),
+            Statement::Try(_) => return unsupported("try", None),
+            Statement::Throw(_, span) => return unsupported("throw", Some(span)),
+        }
+        Ok(())
+    }
+    // Stores $tmp in a bound local or global, combining it with the old
+    // value first for a compound assignment.
+    fn update(&mut self, scope: &str, name: &str, compound: Option<&Operator>, span: &Span) {
+        if let Some(op) = compound {
+            self.line(format!("{}.get {}", scope, name));
+            self.line("local.get $tmp");
+            self.operator(op, span);
+        } else {
+            self.line("local.get $tmp");
+        }
+        self.line(format!("{}.set {}", scope, name));
+    }
+    fn undeclared(&mut self, name: &str, span: &Span) {
+        self.fail(
+            span,
+            &format!(
+                "NameError: Tried to assign {}, but it wasn't declared",
+                name
+            ),
+        );
+    }
+    // The local if it's bound, or else the global if that's initialized.
+    fn load(&mut self, name: &str, span: &Span, slot: Option<usize>, global: Option<usize>) {
+        let (scope, variable) = match (slot, global) {
+            (Some(slot), _) => ("local", local_name(self.locals, slot)),
+            (None, Some(g)) => ("global", global_name(self.program, g)),
+            (None, None) => {
+                return self.fail(
+                    span,
+                    &format!("NameError: Could not find variable {}", name),
+                );
+            }
+        };
+        self.line(format!("{}.get {}", scope, set_name(&variable)));
+        self.line("if (result i32)");
+        self.indent += 1;
+        self.line(format!("{}.get {}", scope, variable));
+        self.indent -= 1;
+        self.line("else");
+        self.indent += 1;
+        self.load(name, span, None, global.filter(|_| slot.is_some()));
+        self.indent -= 1;
+        self.line("end");
+    }
+    // Combines the two values on top of the stack.
+    fn operator(&mut self, op: &Operator, span: &Span) {
+        let checked = match op {
+            Operator::And | Operator::Or | Operator::Xor => {
+                // both sides are already on the stack, so the left one is
+                // made a bool through $tmp
+                self.line("i32.const 0");
+                self.line("i32.ne");
+                self.line("local.set $tmp");
+                self.line("i32.const 0");
+                self.line("i32.ne");
+                self.line("local.get $tmp");
+                self.line(match op {
+                    Operator::And => "i32.and",
+                    Operator::Or => "i32.or",
+                    _ => "i32.xor",
+                });
+                return;
+            }
+            Operator::LEq => return self.line("i32.le_s"),
+            Operator::GEq => return self.line("i32.ge_s"),
+            Operator::Eq => return self.line("i32.eq"),
+            Operator::Ls => return self.line("i32.lt_s"),
+            Operator::Gr => return self.line("i32.gt_s"),
+            Operator::BAnd => return self.line("i32.and"),
+            Operator::BOr => return self.line("i32.or"),
+            Operator::BXor => return self.line("i32.xor"),
+            Operator::Add => "add",
+            Operator::Sub => "sub",
+            Operator::Mult => "mult",
+            Operator::Div => "div",
+            Operator::Mod => "mod",
+        };
+        self.string(&span.to_string());
+        self.line(format!("call $ws_{}", checked));
+    }
+    fn expr(&mut self, expr: &ExprAST) -> Result<(), String> {
+        match expr {
+            ExprAST::Val(Value::Int(x)) => self.line(format!("i32.const {}", x)),
+            ExprAST::Val(Value::Str(_)) => {
+                return unsupported("A string that isn't printed straight away", None);
+            }
+            ExprAST::Val(_) => return unsupported("That value", None),
+            ExprAST::Variable(name, span, resolution) => {
+                if resolution.slot.is_none() && resolution.global.is_none() {
+                    let what = if resolution.function.is_some() {
+                        "A function used as a value"
+                    } else {
+                        "An enum variant"
+                    };
+                    return unsupported(what, Some(span));
+                }
+                self.load(name, span, resolution.slot, resolution.global);
+            }
+            ExprAST::BinOp(op, lhs, rhs, span) => {
+                self.expr(lhs)?;
+                self.expr(rhs)?;
+                self.operator(op, span);
+            }
+            ExprAST::Call(name, args, span, resolution) => {
+                let Some(index) = resolution
+                    .function
+                    .filter(|_| resolution.slot.is_none() && resolution.global.is_none())
+                else {
+                    return unsupported(&format!("Calling {}", name), Some(span));
+                };
+                if args.iter().any(|a| a.name.is_some()) {
+                    return unsupported("A named argument", Some(span));
+                }
+                for arg in args {
+                    self.expr(&arg.value)?;
+                }
+                self.string(&span.to_string());
+                self.string(&format!(
+                    "RecursionError: Calling {} went over {} nested calls",
+                    name, MAX_DEPTH
+                ));
+                self.line("call $ws_enter");
+                self.line(format!("call {}", function_name(self.program, index)));
+                self.line("call $ws_leave");
+            }
+            ExprAST::If(condition, then_expr, else_expr) => {
+                self.expr(condition)?;
+                self.line("if (result i32)");
+                self.indent += 1;
+                self.expr(then_expr)?;
+                self.indent -= 1;
+                self.line("else");
+                self.indent += 1;
+                self.expr(else_expr)?;
+                self.indent -= 1;
+                self.line("end");
+            }
+            ExprAST::CallExpr(_, _, span) => {
+                return unsupported("Calling a function value", Some(span));
+            }
+            ExprAST::Closure(_) => return unsupported("A closure", None),
+            ExprAST::List(_) => return unsupported("A list", None),
+            ExprAST::Tuple(_) => return unsupported("A tuple", None),
+            ExprAST::Map(_, span) => return unsupported("A map", Some(span)),
+            ExprAST::Index(_, _, span) => return unsupported("Indexing", Some(span)),
+            ExprAST::StructInit(_, _, span) => return unsupported("A struct", Some(span)),
+            ExprAST::Field(_, _, span) => return unsupported("A field", Some(span)),
+            ExprAST::Match(x) => return unsupported("match", Some(&x.span)),
+            ExprAST::Propagate(_, span) => return unsupported("?", Some(span)),
+        }
+        Ok(())
+    }
+}
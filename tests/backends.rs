@@ -0,0 +1,43 @@
+//! The tree-walker and the VM have to behave the same: every example script
+//! prints the same, fails the same and exits the same under both.
+
+use std::{fs, path::Path};
+
+use common::run;
+
+mod common;
+
+#[test]
+fn examples_run_the_same_under_both_backends() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut paths: Vec<_> = fs::read_dir(&examples)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|x| x == "ws"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let tree = run(&[], &path);
+        let vm = run(&["--vm"], &path);
+        let name = path.display();
+        assert_eq!(
+            String::from_utf8_lossy(&vm.stdout),
+            String::from_utf8_lossy(&tree.stdout),
+            "stdout of {}",
+            name
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&vm.stderr),
+            String::from_utf8_lossy(&tree.stderr),
+            "stderr of {}",
+            name
+        );
+        assert_eq!(
+            vm.status.code(),
+            tree.status.code(),
+            "exit code of {}",
+            name
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! Builtins called from scripts.
+
+use std::fs;
+
+use common::{run, script};
+
+mod common;
+
+fn stdout(name: &str, source: &str) -> String {
+    let path = script(&format!("{}.ws", name), source);
+    let output = run(&[], &path);
+    fs::remove_file(&path).ok();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn len_counts_characters() {
+    let source = "fun main() {\n    print len(\"héllo\");\n    print len(\"\");\n    print len(\"✓✓\");\n    return 0;\n}\n";
+    assert_eq!(stdout("len", source), "5\n\n0\n\n2\n\n");
+}
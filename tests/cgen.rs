@@ -0,0 +1,152 @@
+//! `build --emit c` has to give a program that does what the interpreter does.
+//! The scripts here are built, compiled with the system C compiler when there
+//! is one, and run against the interpreter with the same input.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use common::{feed, script, temp_path, willscript};
+
+mod common;
+
+fn build(script: &Path, out: &Path) -> Output {
+    willscript()
+        .args(["build", "--emit", "c"])
+        .arg(script)
+        .arg("-o")
+        .arg(out)
+        .output()
+        .expect("could not run willscript")
+}
+
+// Builds and runs the script both ways, checking stdout and the uncaught error
+// agree. Without a C compiler only the build itself is checked.
+fn assert_same(name: &str, source: &str, stdin: &str) {
+    let script = script(&format!("{}.ws", name), source);
+    let c_file = temp_path(&format!("{}.c", name));
+    let binary = temp_path(&format!("{}.bin", name));
+    let built = build(&script, &c_file);
+    assert!(
+        built.status.success(),
+        "{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+    let compiled = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_owned()))
+        .arg(&c_file)
+        .arg("-o")
+        .arg(&binary)
+        .status();
+    if compiled.is_ok_and(|s| s.success()) {
+        let native = feed(&mut Command::new(&binary), stdin);
+        let interpreted = feed(willscript().arg(&script), stdin);
+        assert_eq!(
+            String::from_utf8_lossy(&native.stdout),
+            String::from_utf8_lossy(&interpreted.stdout)
+        );
+        assert_eq!(native.status.success(), interpreted.status.success());
+        let first_line = |o: &Output| {
+            let stderr = String::from_utf8_lossy(&o.stderr).into_owned();
+            stderr.lines().next().unwrap_or_default().to_owned()
+        };
+        assert_eq!(first_line(&native), first_line(&interpreted));
+        fs::remove_file(&binary).ok();
+    }
+    fs::remove_file(&script).ok();
+    fs::remove_file(&c_file).ok();
+}
+
+#[test]
+fn runs_functions_loops_and_globals() {
+    assert_same(
+        "loops",
+        r#"
+global count = 0;
+const LIMIT = 2 * 5;
+
+fun fib(x) {
+	if x < 2 {
+		return 1;
+	}
+	return fib(x - 1) + fib(x - 2);
+}
+
+fun bump() {
+	count += 1;
+	return count;
+}
+
+fun main() {
+	print "Hello\tC";
+	print fib(15);
+	var i = 0;
+	while i < LIMIT {
+		i += 1;
+		bump();
+	}
+	print count;
+	print if count == 10 { "ten" } else { "not ten" };
+	print "a" < "b";
+	print "z" < 1;
+	print 7 % 3 + 10 / 3 - 1;
+	return 0;
+}
+"#,
+        "",
+    );
+}
+
+#[test]
+fn reads_input() {
+    assert_same(
+        "input",
+        r#"
+fun main() {
+	input name;
+	print name;
+	input n;
+	print n * 2;
+	return 0;
+}
+"#,
+        "will\n21\n",
+    );
+}
+
+#[test]
+fn fails_like_the_interpreter() {
+    assert_same(
+        "errors",
+        r#"
+fun down(n) {
+	return down(n + 1);
+}
+
+fun main() {
+	print 2147483647 - 1;
+	if 0 {
+		print 1 / 0;
+	}
+	print down(0);
+	return 0;
+}
+"#,
+        "",
+    );
+}
+
+#[test]
+fn reports_what_it_cannot_translate() {
+    let script = script(
+        "unsupported.ws",
+        "fun main() {\n\tvar xs = [1];\n\tprint xs;\n}\n",
+    );
+    let c_file = temp_path("unsupported.c");
+    let built = build(&script, &c_file);
+    assert!(!built.status.success());
+    assert!(!c_file.exists());
+    assert!(String::from_utf8_lossy(&built.stderr).contains("can't be compiled to C"));
+    fs::remove_file(&script).ok();
+}
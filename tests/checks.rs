@@ -0,0 +1,298 @@
+//! Programs that are rejected before they run, with the reason they're
+//! rejected.
+
+use std::fs;
+
+use common::{script, temp_path};
+use willscript::{Backend, load, run};
+
+mod common;
+
+// Why loading `source` failed.
+fn rejected(name: &str, source: &str) -> String {
+    let path = script(&format!("{}.ws", name), source);
+    let result = load(&path);
+    fs::remove_file(&path).ok();
+    match result {
+        Ok(_) => panic!("{} loaded", name),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn needs_a_main_function() {
+    assert_eq!(
+        rejected("no-main", "fun helper() {\n    return 1;\n}\n"),
+        "There is no main function."
+    );
+    // and the command line says so instead of panicking
+    let path = script("no-main-cli.ws", "fun helper() {\n    return 1;\n}\n");
+    let output = common::run(&[], &path);
+    fs::remove_file(&path).ok();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "There is no main function.\n"
+    );
+}
+
+#[test]
+fn run_without_main_is_an_error() {
+    let path = script("run.ws", "fun main() {\n    return 0;\n}\n");
+    let program = load(&path);
+    fs::remove_file(&path).ok();
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut program = program.clone().unwrap();
+        program.functions.clear();
+        let e = run(program, backend).unwrap_err();
+        assert_eq!(e.to_string(), "0:0: NameError: There is no main function.");
+    }
+}
+
+#[test]
+fn match_arms_bind_fresh_names() {
+    let arm = "fun main() {
+    let x = 1;
+    match 2 {
+        x => { x = 5; }
+    }
+    var f = match Some(3) { Some(x) if x > 0 => fun() { x = 0; return x; }, _ => fun() { return 1; } };
+    return f();
+}
+";
+    let path = script("arms.ws", arm);
+    let loaded = load(&path);
+    fs::remove_file(&path).ok();
+    assert!(loaded.is_ok(), "{:?}", loaded.err());
+    // outside the arm, x is the let again
+    let after = "fun main() {\n    let x = 1;\n    match 2 {\n        x => { x = 5; }\n    }\n    x = 3;\n    return x;\n}\n";
+    assert_eq!(
+        rejected("after-arm", after),
+        "In function main: 6:5: Can't assign to x, it was declared with let."
+    );
+}
+
+#[test]
+fn initializers_only_use_what_is_set_before_them() {
+    assert_eq!(
+        rejected(
+            "const-global",
+            "global g = 5;\nconst A = g + 1;\nfun main() {\n    return A;\n}\n"
+        ),
+        "Constant A can't use global g, constants are initialized before any global."
+    );
+    assert_eq!(
+        rejected(
+            "later-global",
+            "global h = g + 1;\nglobal g = 5;\nfun main() {\n    return h;\n}\n"
+        ),
+        "Global h uses global g before it's initialized, globals are initialized in the order they're declared."
+    );
+    // constants come first wherever they're declared
+    let path = script(
+        "order.ws",
+        "global g = A;\nglobal h = g + A;\nconst A = 1;\nfun main() {\n    return h;\n}\n",
+    );
+    let loaded = load(&path);
+    fs::remove_file(&path).ok();
+    assert!(loaded.is_ok(), "{:?}", loaded.err());
+}
+
+#[test]
+fn matches_over_enums_cover_every_variant() {
+    let shapes = "enum Shape { Circle(r), Square(s), Dot }\n";
+    assert_eq!(
+        rejected(
+            "missing-variant",
+            &format!(
+                "{}fun main() {{\n    match Dot {{\n        Circle(r) => {{ print r; }}\n        Dot => {{ print 0; }}\n    }}\n    return 0;\n}}\n",
+                shapes
+            )
+        ),
+        "In function main: Non-exhaustive match over enum Shape, missing: Square."
+    );
+    // a guarded arm doesn't cover its variant
+    assert_eq!(
+        rejected(
+            "guarded",
+            "fun main() {\n    var x = match Some(1) { Some(n) if n > 0 => n, None => 0 };\n    return x;\n}\n"
+        ),
+        "In function main: Non-exhaustive match over enum Option, missing: Some."
+    );
+    let path = script(
+        "wildcard.ws",
+        &format!(
+            "{}fun main() {{\n    return match Dot {{ Circle(r) => r, _ => 0 }};\n}}\n",
+            shapes
+        ),
+    );
+    let loaded = load(&path);
+    fs::remove_file(&path).ok();
+    assert!(loaded.is_ok(), "{:?}", loaded.err());
+}
+
+#[test]
+fn constants_are_acyclic_and_never_assigned() {
+    assert_eq!(
+        rejected(
+            "cycle",
+            "const A = B + 1;\nconst B = C * 2;\nconst C = A;\nfun main() {\n    return A;\n}\n"
+        ),
+        "Constant initializers form a cycle: A -> B -> C -> A."
+    );
+    assert_eq!(
+        rejected("self", "const A = A;\nfun main() {\n    return A;\n}\n"),
+        "Constant initializers form a cycle: A -> A."
+    );
+    assert_eq!(
+        rejected(
+            "assign",
+            "const A = 1;\nfun main() {\n    A = 2;\n    return A;\n}\n"
+        ),
+        "In function main: 3:5: Can't assign to constant A."
+    );
+}
+
+// Why loading main.ws failed, with `files` written next to it.
+fn rejected_modules(name: &str, files: &[(&str, &str)]) -> String {
+    let dir = temp_path(name);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        fs::write(dir.join(file), source).unwrap();
+    }
+    let result = load(&dir.join("main.ws"));
+    fs::remove_dir_all(&dir).ok();
+    match result {
+        Ok(_) => panic!("{} loaded", name),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn imports_cant_form_a_cycle() {
+    let error = rejected_modules(
+        "import-cycle",
+        &[
+            (
+                "main.ws",
+                "import \"b.ws\" as b;\nexport fun fa() {\n    return 1;\n}\nfun main() {\n    return b.fb();\n}\n",
+            ),
+            (
+                "b.ws",
+                "import \"main.ws\" as a;\nexport fun fb() {\n    return a.fa();\n}\n",
+            ),
+        ],
+    );
+    // the file loaded first is named the way it was given
+    assert!(error.starts_with("Imports form a cycle: "), "{}", error);
+    assert!(error.ends_with("main.ws -> b.ws -> main.ws."), "{}", error);
+}
+
+#[test]
+fn imports_only_see_exports() {
+    let hidden = "export fun shown() {\n    return 1;\n}\nfun hidden() {\n    return 2;\n}\n";
+    assert_eq!(
+        rejected_modules(
+            "missing-export",
+            &[
+                (
+                    "main.ws",
+                    "import \"h.ws\" as h;\nfun main() {\n    return h.hidden();\n}\n"
+                ),
+                ("h.ws", hidden),
+            ]
+        ),
+        "In function main: 3:13: Module h has no exported hidden."
+    );
+    assert_eq!(
+        rejected_modules(
+            "missing-file",
+            &[(
+                "main.ws",
+                "import \"nowhere.ws\" as n;\nfun main() {\n    return 0;\n}\n"
+            )]
+        ),
+        "Could not find file nowhere.ws"
+    );
+}
+
+#[test]
+fn names_have_to_mean_something() {
+    assert_eq!(
+        rejected(
+            "variable",
+            "fun main() {\n    var x = 1;\n    return y + x;\n}\n"
+        ),
+        "In function main: 3:12: Undefined variable y."
+    );
+    assert_eq!(
+        rejected("function", "fun main() {\n    return helper(1);\n}\n"),
+        "In function main: 2:12: Unknown function helper."
+    );
+    // even where it would never run
+    assert_eq!(
+        rejected(
+            "unreached",
+            "fun main() {\n    if 0 {\n        return y;\n    }\n    return 0;\n}\n"
+        ),
+        "In function main: 3:16: Undefined variable y."
+    );
+    assert_eq!(
+        rejected(
+            "closure",
+            "fun main() {\n    var f = fun() { return missing; };\n    return f();\n}\n"
+        ),
+        "In function main: 2:28: Undefined variable missing."
+    );
+    assert_eq!(
+        rejected(
+            "initializer",
+            "global g = nope + 1;\nfun main() {\n    return g;\n}\n"
+        ),
+        "In the initializer of g: 1:12: Undefined variable nope."
+    );
+}
+
+#[test]
+fn if_expressions_need_an_else() {
+    assert_eq!(
+        rejected(
+            "no-else",
+            "fun main() {\n    var x = if 1 { 2 };\n    return x;\n}\n"
+        ),
+        "2:23: An if expression needs an else branch, so it has a value either way."
+    );
+    assert_eq!(
+        rejected(
+            "no-final-else",
+            "fun main() {\n    return 1 + if 0 { 2 } else if 1 { 3 };\n}\n"
+        ),
+        "2:42: An if expression needs an else branch, so it has a value either way."
+    );
+}
+
+#[test]
+fn calls_to_declared_functions_fit_their_parameters() {
+    let pair = "fun pair(a, b = 0) {\n    return [a, b];\n}\n";
+    assert_eq!(
+        rejected(
+            "too-many",
+            &format!("{}fun main() {{\n    return pair(1, 2, 3);\n}}\n", pair)
+        ),
+        "In function main: 5:12: Function pair takes 1 to 2 arguments, got 3."
+    );
+    assert_eq!(
+        rejected(
+            "named",
+            &format!("{}fun main() {{\n    return pair(b: 1);\n}}\n", pair)
+        ),
+        "In function main: 5:12: Function pair is missing argument a."
+    );
+    assert_eq!(
+        rejected(
+            "after-rest",
+            "fun log(...parts, last) {\n    return 0;\n}\nfun main() {\n    return log();\n}\n"
+        ),
+        "1:19: The ...rest parameter has to be the last one."
+    );
+}
@@ -0,0 +1,93 @@
+//! What the integration tests share: files in the temp dir, and the willscript
+//! binary run on them. Each test file uses some of it.
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// A path in the temp dir, unique to this test binary. `name` has the
+/// extension, if any.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("willscript-{}-{}", std::process::id(), name))
+}
+
+/// Writes `source` to `temp_path(name)`.
+pub fn script(name: &str, source: &str) -> PathBuf {
+    let path = temp_path(name);
+    fs::write(&path, source).expect("could not write the script");
+    path
+}
+
+pub fn willscript() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_willscript"))
+}
+
+/// Runs willscript with `args`, then `path`.
+pub fn run(args: &[&str], path: &Path) -> Output {
+    willscript()
+        .args(args)
+        .arg(path)
+        .output()
+        .expect("could not run willscript")
+}
+
+/// Runs `source` under the tree-walker and the VM, which have to agree, and
+/// gives back what it printed and its exit code.
+pub fn run_both(name: &str, source: &str) -> (String, Option<i32>) {
+    let path = script(&format!("{}.ws", name), source);
+    let tree = run(&[], &path);
+    let vm = run(&["--vm"], &path);
+    fs::remove_file(&path).ok();
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    assert_eq!(text(&vm.stdout), text(&tree.stdout), "stdout of {}", name);
+    assert_eq!(text(&vm.stderr), text(&tree.stderr), "stderr of {}", name);
+    assert_eq!(
+        vm.status.code(),
+        tree.status.code(),
+        "exit code of {}",
+        name
+    );
+    (text(&tree.stdout), tree.status.code())
+}
+
+/// Runs `command` with `stdin` written to it up front.
+pub fn feed(command: &mut Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not start the command");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// A message with the `Content-Length` header the language server and the
+/// debug adapter read.
+pub fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+/// The bodies of the framed messages in `output`, checking nothing is left
+/// over.
+pub fn messages(output: &[u8]) -> Vec<String> {
+    let mut rest = String::from_utf8(output.to_vec()).unwrap();
+    let mut messages = vec![];
+    while let Some(header_end) = rest.find("\r\n\r\n") {
+        let length: usize = rest["Content-Length: ".len()..header_end].parse().unwrap();
+        let body = header_end + 4;
+        messages.push(rest[body..body + length].to_owned());
+        rest = rest[body + length..].to_owned();
+    }
+    assert_eq!(rest, "", "left over output");
+    messages
+}
@@ -0,0 +1,39 @@
+//! A compound assignment evaluates its target once, whatever the target is.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn targets_built_by_calls_run_once() {
+    let (stdout, code) = run_both(
+        "call-targets",
+        "struct Box { v }
+global calls = 0;
+fun obj(b) {
+    calls += 1;
+    return b;
+}
+fun first() {
+    calls += 1;
+    return 0;
+}
+fun main() {
+    var b = Box { v: 10 };
+    obj(b).v -= 3;
+    print b.v;
+    var xs = [1, 2];
+    var lists = [xs];
+    lists[first()][first()] += 4;
+    print xs;
+    var get = fun() { calls += 1; return xs; };
+    get()[1] *= 5;
+    print xs;
+    print calls;
+    return 0;
+}
+",
+    );
+    assert_eq!(stdout, "7\n\n[5, 2]\n\n[5, 10]\n\n4\n\n");
+    assert_eq!(code, Some(0));
+}
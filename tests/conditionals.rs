@@ -0,0 +1,31 @@
+//! `if c { a } else { b }` is an expression too, and only the branch it
+//! picks runs.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn if_expressions_pick_one_branch() {
+    let (stdout, code) = run_both(
+        "if-expressions",
+        "fun shout(x) {
+    print x;
+    return x;
+}
+fun sign(x) {
+    return if x < 0 { 0 - 1 } else if x == 0 { 0 } else { 1 };
+}
+fun main() {
+    print [sign(0 - 4), sign(0), sign(9)];
+    var picked = if shout(0) { shout(\"then\") } else { shout(\"else\") };
+    print picked;
+    print 10 * if 1 { 2 } else { 3 } + 1;
+    print if 1 { 2 } else { 1 / 0 };
+    return 0;
+}
+",
+    );
+    assert_eq!(stdout, "[-1, 0, 1]\n\n0\n\nelse\n\nelse\n\n21\n\n2\n\n");
+    assert_eq!(code, Some(0));
+}
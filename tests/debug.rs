@@ -0,0 +1,259 @@
+//! `willscript debug`, driven by commands written up front on stdin, and
+//! `willscript debug --dap` by a scripted Debug Adapter Protocol client.
+
+use std::fs;
+
+use common::{feed, frame, messages, script, willscript};
+
+mod common;
+
+const SCRIPT: &str = r#"const LIMIT = 3;
+
+fun add(a, b) {
+    var total = a + b;
+    return total;
+}
+
+fun main() {
+    var sum = 0;
+    var i = 0;
+    while i < LIMIT {
+        sum = add(sum, i);
+        i += 1;
+    }
+    print sum;
+    return 0;
+}
+"#;
+
+fn debug(name: &str, source: &str, commands: &str) -> (String, Option<i32>) {
+    let path = script(name, source);
+    let output = feed(willscript().arg("debug").arg(&path), commands);
+    fs::remove_file(&path).ok();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        output.status.code(),
+    )
+}
+
+#[test]
+fn stops_at_breakpoints_and_shows_the_frame() {
+    let (transcript, code) = debug(
+        "breakpoints.ws",
+        SCRIPT,
+        "break 5\nbreak add\ncontinue\nbt\nlocals\nnext\nlocals\nprint total * 10\ncontinue\nclear add\nclear 5\nclear 5\nc\n",
+    );
+    assert_eq!(
+        transcript,
+        "Paused at 9:5 in main()
+9\t    var sum = 0;
+(debug) Breakpoint at line 5
+(debug) Breakpoint at function add
+(debug) Breakpoint at 4:5 in add(0, 0)
+4\t    var total = a + b;
+(debug) #0 4:5 in add(0, 0) called at 12:15
+#1 12:15 in main()
+(debug) a = 0
+b = 0
+(debug) Stepped at 5:5 in add(0, 0)
+5\t    return total;
+(debug) a = 0
+b = 0
+total = 0
+(debug) 0
+(debug) Breakpoint at 4:5 in add(0, 1)
+4\t    var total = a + b;
+(debug) Removed the breakpoint at function add
+(debug) Removed the breakpoint at line 5
+(debug) No breakpoint at line 5
+(debug) 3
+
+"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn steps_in_over_and_out() {
+    let (transcript, _) = debug(
+        "steps.ws",
+        SCRIPT,
+        "break 12\nc\nstep\nstep\nfinish\nnext\nnext\nquit\n",
+    );
+    let stops: Vec<&str> = transcript
+        .lines()
+        .filter_map(|l| l.strip_prefix("(debug) "))
+        .filter(|l| l.starts_with("Stepped") || l.starts_with("Breakpoint at 1"))
+        .collect();
+    assert_eq!(
+        stops,
+        [
+            "Breakpoint at 12:9 in main()",
+            "Stepped at 4:5 in add(0, 0)",
+            "Stepped at 5:5 in add(0, 0)",
+            "Stepped at 13:9 in main()",
+            // a while's condition isn't a statement of its own
+            "Stepped at 12:9 in main()",
+            "Stepped at 13:9 in main()",
+        ]
+    );
+    // quitting ends the program where it is
+    assert!(!transcript.contains("\n3\n"));
+}
+
+#[test]
+fn evaluates_in_the_paused_frame() {
+    let source = "fun main() {
+    var xs = [1, 2, 3];
+    var name = \"will\";
+    var f = fun(x) { return x + len(xs); };
+    print f(1);
+    return 0;
+}
+";
+    let (transcript, code) = debug(
+        "evaluate.ws",
+        source,
+        "break 5\nc\np f(10)\np map(xs, f)\np name\np match len(xs) { 3 => \"three\", _ => \"other\" }\np push(xs, 4)\np xs\np missing\np 1 +\nlocals\nc\n",
+    );
+    let answers: Vec<&str> = transcript
+        .lines()
+        .skip_while(|l| !l.starts_with("5\t"))
+        .skip(1)
+        .collect();
+    assert_eq!(
+        answers,
+        [
+            "(debug) 13",
+            "(debug) [4, 5, 6]",
+            "(debug) \"will\"",
+            "(debug) \"three\"",
+            "(debug) 0",
+            "(debug) [1, 2, 3, 4]",
+            "(debug) Error: 1:1: Undefined variable missing.",
+            "(debug) Error: 1:4: Bad Token given to parse primary: EndOfFile",
+            "(debug) xs = [1, 2, 3, 4]",
+            "name = \"will\"",
+            "f = <closure>",
+            // the push changed the list f sees
+            "(debug) 5",
+            "",
+        ]
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn reports_uncaught_errors() {
+    let path = script("uncaught.ws", "fun main() {\n    throw \"bad\";\n}\n");
+    let output = feed(willscript().arg("debug").arg(&path), "c\n");
+    fs::remove_file(&path).ok();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Uncaught error at 2:5: Error: bad"));
+}
+
+#[test]
+fn reads_input_between_commands() {
+    let source = "fun main() {\n    var n = 0;\n    input n;\n    print n + 1;\n    return 0;\n}\n";
+    let (transcript, code) = debug("input.ws", source, "c\n42\n");
+    assert_eq!(
+        transcript,
+        "Paused at 2:5 in main()\n2\t    var n = 0;\n(debug) 43\n\n"
+    );
+    assert_eq!(code, Some(0));
+}
+
+// Sends the requests, numbered from 1, and gives back every message the
+// adapter wrote.
+fn dap(requests: &[String]) -> Vec<String> {
+    let input: String = requests
+        .iter()
+        .enumerate()
+        .map(|(i, r)| frame(&format!(r#"{{"seq":{},"type":"request",{}}}"#, i + 1, r)))
+        .collect();
+    let output = feed(willscript().args(["debug", "--dap"]), &input);
+    assert_eq!(output.status.code(), Some(0));
+    messages(&output.stdout)
+}
+
+#[test]
+fn speaks_the_debug_adapter_protocol() {
+    let path = script("dap.ws", SCRIPT);
+    let path = path.to_str().unwrap();
+    let messages = dap(&[
+        r#""command":"initialize","arguments":{"adapterID":"willscript"}"#.to_owned(),
+        format!(r#""command":"launch","arguments":{{"program":"{}"}}"#, path),
+        format!(
+            r#""command":"setBreakpoints","arguments":{{"source":{{"path":"{}"}},"breakpoints":[{{"line":5}}]}}"#,
+            path
+        ),
+        r#""command":"configurationDone""#.to_owned(),
+        r#""command":"stackTrace","arguments":{"threadId":1}"#.to_owned(),
+        r#""command":"variables","arguments":{"variablesReference":1}"#.to_owned(),
+        r#""command":"evaluate","arguments":{"expression":"total + LIMIT","frameId":0}"#.to_owned(),
+        r#""command":"evaluate","arguments":{"expression":"nope","frameId":0}"#.to_owned(),
+        r#""command":"stepOut","arguments":{"threadId":1}"#.to_owned(),
+        format!(
+            r#""command":"setBreakpoints","arguments":{{"source":{{"path":"{}"}},"breakpoints":[]}}"#,
+            path
+        ),
+        r#""command":"continue","arguments":{"threadId":1}"#.to_owned(),
+        r#""command":"disconnect""#.to_owned(),
+    ]);
+    fs::remove_file(path).ok();
+    let expected = [
+        r#""command":"initialize","body":{"supportsConfigurationDoneRequest":true"#.to_owned(),
+        r#""event":"initialized""#.to_owned(),
+        r#""request_seq":2,"success":true,"command":"launch""#.to_owned(),
+        r#""breakpoints":[{"verified":true,"line":5}]"#.to_owned(),
+        r#""command":"configurationDone""#.to_owned(),
+        r#""event":"stopped","body":{"reason":"breakpoint","threadId":1"#.to_owned(),
+        format!(
+            r#""stackFrames":[{{"id":0,"name":"add(0, 0)","line":5,"column":5,"source":{{"path":"{}"}}}},{{"id":1,"name":"main()","line":12,"column":15"#,
+            path
+        ),
+        r#""variables":[{"name":"a","value":"0","variablesReference":0},{"name":"b","value":"0","variablesReference":0},{"name":"total","value":"0","variablesReference":0}]"#.to_owned(),
+        r#""command":"evaluate","body":{"result":"3","variablesReference":0}"#.to_owned(),
+        r#""success":false,"command":"evaluate","message":"1:1: Undefined variable nope.""#.to_owned(),
+        r#""command":"stepOut""#.to_owned(),
+        r#""event":"stopped","body":{"reason":"step""#.to_owned(),
+        r#""command":"setBreakpoints","body":{"breakpoints":[]}"#.to_owned(),
+        r#""command":"continue","body":{"allThreadsContinued":true}"#.to_owned(),
+        r#""event":"output","body":{"category":"stdout","output":"3\n\n"}"#.to_owned(),
+        r#""event":"exited","body":{"exitCode":0}"#.to_owned(),
+        r#""event":"terminated""#.to_owned(),
+        r#""command":"disconnect""#.to_owned(),
+    ];
+    assert_eq!(messages.len(), expected.len(), "{:#?}", messages);
+    for (message, expected) in messages.iter().zip(expected) {
+        assert!(message.contains(&expected), "{} in {}", expected, message);
+    }
+}
+
+#[test]
+fn input_is_an_error_under_the_adapter() {
+    let path = script(
+        "dap-input.ws",
+        "fun main() {\n    var n = 0;\n    input n;\n    return n;\n}\n",
+    );
+    let path = path.to_str().unwrap();
+    let messages = dap(&[
+        r#""command":"initialize","arguments":{"adapterID":"willscript"}"#.to_owned(),
+        format!(r#""command":"launch","arguments":{{"program":"{}"}}"#, path),
+        r#""command":"configurationDone""#.to_owned(),
+        r#""command":"disconnect""#.to_owned(),
+    ]);
+    fs::remove_file(path).ok();
+    let error = messages
+        .iter()
+        .find(|m| m.contains(r#""category":"stderr""#))
+        .unwrap_or_else(|| panic!("no error in {:#?}", messages));
+    assert!(
+        error.contains(
+            "Uncaught error at 3:11: InputError: The debug adapter can't give the program input"
+        ),
+        "{}",
+        error
+    );
+    assert!(messages.iter().any(|m| m.contains(r#""exitCode":1"#)));
+}
@@ -0,0 +1,100 @@
+//! Runtime errors and thrown values unwind to the nearest catch, running every
+//! finally on the way.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn every_runtime_error_kind_is_catchable() {
+    let (stdout, code) = run_both(
+        "kinds",
+        "struct Point { x, y }
+fun kind(f) {
+    try {
+        f();
+    } catch e {
+        return e.kind;
+    }
+    return \"none\";
+}
+fun main() {
+    print kind(fun() { return 1 + \"a\"; });
+    print kind(fun() { var gone = 1; drop gone; return gone; });
+    print kind(fun() { return [1][3]; });
+    print kind(fun() { return {\"a\": 1}[\"b\"]; });
+    print kind(fun() { return Point { x: 1, y: 2 }.z; });
+    print kind(fun(x) { return x; });
+    print kind(fun() { var (a, b) = (1, 2, 3); return a; });
+    print kind(fun() { return match 5 { 1 => 0 }; });
+    print kind(fun() { return 1 / 0; });
+    print kind(fun() { return 2147483647 + 1; });
+    print kind(fun() { throw \"plain\"; });
+    print kind(fun() { throw error(\"Custom\", \"mine\"); });
+    print kind(fun() { return 0; });
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "TypeError\n\nNameError\n\nIndexError\n\nKeyError\n\nFieldError\n\nArgumentError\n\n\
+         ValueError\n\nMatchError\n\nDivisionByZero\n\nOverflow\n\nError\n\nCustom\n\nnone\n\n"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn finally_runs_however_the_try_ends() {
+    let (stdout, code) = run_both(
+        "finally",
+        "fun returns(log) {
+    try {
+        return 1;
+    } finally {
+        push(log, \"after return\");
+    }
+}
+fun rethrows(log) {
+    try {
+        throw \"first\";
+    } catch e {
+        throw error(\"Wrapped\", e.message);
+    } finally {
+        push(log, \"after rethrow\");
+    }
+}
+fun main() {
+    var log = [];
+    print returns(log);
+    try {
+        rethrows(log);
+    } catch e {
+        print [e.kind, e.message, e.line, e.col];
+    }
+    try {
+        push(log, \"body\");
+    } finally {
+        push(log, \"after body\");
+    }
+    print log;
+    # a caught error is only bound in its catch block
+    var e = 5;
+    try { throw 1; } catch e { print e.message; }
+    print e;
+    try {
+        throw \"uncaught\";
+    } finally {
+        print \"still runs\";
+    }
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "1\n\n[\"Wrapped\", \"first\", 12, 15]\n\n\
+         [\"after return\", \"after rethrow\", \"body\", \"after body\"]\n\n1\n\n5\n\nstill runs\n\n"
+    );
+    assert_eq!(code, Some(1));
+}
@@ -0,0 +1,197 @@
+//! `willscript fmt` has to give back a program that does what the original
+//! did, keep every comment, and leave its own output alone.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{run, script, temp_path};
+use willscript::format;
+
+mod common;
+
+const MESSY: &str = r#"# header comment
+
+import "lib.ws" as lib;   # trailing on import
+const A=1;
+global   b = true;
+struct P {x,y}
+struct Q {
+    a, # first
+  b
+}
+enum E { One(v), Two }
+fun   noop( ) { }
+export fun f(a, b = a + 1, ...rest) {
+    if a==1 {print "one";}
+    else if a == 2 { print "two" ; } else {
+        # inner
+        print "other";
+
+
+        print (a - (b - 1)) * 2;
+    }
+    while (P { x: 1, y: 2 }).x < a && b { b = b - 1; }
+    var s = match a { 1 => true, _ if a > 2 => false, x => x };  # trailing
+    match (P {x: a, y: b}) {
+        P(q) => { print q; }
+        "str" => {}
+    }
+    try { throw "t"; } catch e { print e; } finally { print "fin"; }
+    let (c, [d, _]) = (1, [2, 3]);
+    var g = fun() { return 1; }();
+    return s, P { x: a, y };
+    # end of body
+}
+# trailer
+"#;
+
+const FORMATTED: &str = r#"# header comment
+
+import "lib.ws" as lib; # trailing on import
+const A = 1;
+global b = true;
+
+struct P { x, y }
+
+struct Q {
+	a, # first
+	b,
+}
+
+enum E { One(v), Two }
+
+fun noop() {}
+
+export fun f(a, b = a + 1, ...rest) {
+	if a == 1 {
+		print "one";
+	} else if a == 2 {
+		print "two";
+	} else {
+		# inner
+		print "other";
+
+		print (a - (b - 1)) * 2;
+	}
+	while (P { x: 1, y: 2 }).x < a && b {
+		b = b - 1;
+	}
+	var s = match a {
+		1 => true,
+		_ if a > 2 => false,
+		x => x,
+	}; # trailing
+	match (P { x: a, y: b }) {
+		P(q) => {
+			print q;
+		}
+		"str" => {}
+	}
+	try {
+		throw "t";
+	} catch e {
+		print e;
+	} finally {
+		print "fin";
+	}
+	let (c, [d, _]) = (1, [2, 3]);
+	var g = fun() {
+		return 1;
+	}();
+	return s, P { x: a, y };
+	# end of body
+}
+# trailer
+"#;
+
+fn scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|x| x == "ws"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn formats_the_canonical_way() {
+    assert_eq!(format(MESSY).unwrap(), FORMATTED);
+    assert_eq!(format(FORMATTED).unwrap(), FORMATTED);
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let examples = root.join("examples");
+    let mut paths = scripts(&examples);
+    paths.extend(scripts(&examples.join("modules")));
+    paths.push(root.join("willcode.ws"));
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let once = format(&source).unwrap();
+        assert_eq!(format(&once).unwrap(), once, "{}", path.display());
+        let comments = |s: &str| s.lines().filter(|l| l.contains('#')).count();
+        assert_eq!(comments(&once), comments(&source), "{}", path.display());
+    }
+}
+
+#[test]
+fn keeps_true_false_and_comments() {
+    let source = "fun main() {\n  var done = false; # not yet\n  # loop\n  while done == false { done = true; }\n  print done;\n  return 0;\n}\n";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        formatted,
+        "fun main() {\n\tvar done = false; # not yet\n\t# loop\n\twhile done == false {\n\t\tdone = true;\n\t}\n\tprint done;\n\treturn 0;\n}\n"
+    );
+}
+
+#[test]
+fn formatted_examples_run_the_same() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let copy = temp_path("examples");
+    fs::create_dir_all(copy.join("modules")).unwrap();
+    let mut paths = scripts(&examples);
+    paths.extend(scripts(&examples.join("modules")));
+    for path in &paths {
+        let relative = path.strip_prefix(&examples).unwrap();
+        fs::copy(path, copy.join(relative)).unwrap();
+        let formatted = run(&["fmt"], &copy.join(relative));
+        assert!(formatted.status.success(), "{}", relative.display());
+    }
+    for path in scripts(&examples) {
+        let original = run(&[], &path);
+        let formatted = run(&[], &copy.join(path.file_name().unwrap()));
+        assert_eq!(
+            String::from_utf8_lossy(&formatted.stdout),
+            String::from_utf8_lossy(&original.stdout),
+            "{}",
+            path.display()
+        );
+        assert_eq!(formatted.status.code(), original.status.code());
+    }
+    fs::remove_dir_all(&copy).ok();
+}
+
+#[test]
+fn check_reports_unformatted_files() {
+    let path = script("check.ws", MESSY);
+    let checked = run(&["fmt", "--check"], &path);
+    assert_eq!(checked.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&checked.stderr).contains("is not formatted"));
+    // --check leaves the file alone
+    assert_eq!(fs::read_to_string(&path).unwrap(), MESSY);
+
+    assert!(run(&["fmt"], &path).status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), FORMATTED);
+    assert_eq!(run(&["fmt", "--check"], &path).status.code(), Some(0));
+
+    fs::write(&path, "fun main( {\n}\n").unwrap();
+    let broken = run(&["fmt"], &path);
+    assert_eq!(broken.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&broken.stderr).contains("check.ws: 1:"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "fun main( {\n}\n");
+    fs::remove_file(&path).ok();
+}
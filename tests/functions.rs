@@ -0,0 +1,139 @@
+//! Functions are values: they can be stored, passed, returned and called
+//! wherever an expression gives one.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn calls_on_indexes_and_fields_are_statements() {
+    let (stdout, code) = run_both(
+        "call-statements",
+        "struct S { f }
+fun twice(x) {
+    print x * 2;
+}
+fun main() {
+    var fs = [twice];
+    fs[0](7);
+    var s = S { f: fun(x) { print x + 1; } };
+    s.f(1);
+    var make = fun() { return twice; };
+    make()(4);
+    return 0;
+}
+",
+    );
+    assert_eq!(stdout, "14\n\n2\n\n8\n\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn closures_share_what_they_capture() {
+    let (stdout, code) = run_both(
+        "counter",
+        "fun counter() {
+    var count = 0;
+    return fun() {
+        count += 1;
+        return count;
+    };
+}
+fun main() {
+    var next = counter();
+    next();
+    next();
+    print next();
+    # each call to counter makes a new count
+    print counter()();
+    var k = 2;
+    var scale = fun(x) { return x * k; };
+    k = 10;
+    print scale(3);
+    var total = 0;
+    var add = fun(x) { total += x; };
+    add(3);
+    add(4);
+    print total;
+    # a parameter is the closure's own, even with the same name
+    var x = 7;
+    var bump = fun(x) { x += 1; return x; };
+    print bump(1);
+    print x;
+    return 0;
+}
+",
+    );
+    assert_eq!(stdout, "3\n\n1\n\n30\n\n7\n\n2\n\n7\n\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn closures_call_themselves_through_their_variable() {
+    let (stdout, code) = run_both(
+        "recursive",
+        "fun main() {
+    var fact = fun(n) {
+        if n < 2 {
+            return 1;
+        }
+        return n * fact(n - 1);
+    };
+    print fact(5);
+    let fib = fun(n) { return if n < 2 { n } else { fib(n - 1) + fib(n - 2) }; };
+    print fib(10);
+    return 0;
+}
+",
+    );
+    assert_eq!(stdout, "120\n\n55\n\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn higher_order_builtins_call_back_into_the_script() {
+    let (stdout, code) = run_both(
+        "higher-order",
+        "fun double(x) {
+    return x * 2;
+}
+fun main() {
+    var f = double;
+    print f(4);
+    print map([1, 2, 3], double);
+    print filter([1, 2, 3, 4, 5], fun(x) { return x % 2; });
+    var seen = [];
+    print reduce([1, 2, 3], fun(acc, x) { push(seen, x); return acc * 10 + x; }, 0);
+    print seen;
+    print map([], double);
+    try { map([1], 5); } catch e { print e; }
+    try { filter(1, double); } catch e { print e; }
+    try { reduce([1], double); } catch e { print e; }
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "8
+
+[2, 4, 6]
+
+[1, 3, 5]
+
+123
+
+[1, 2, 3]
+
+[]
+
+13:11: TypeError: 5 is not a function
+
+14:11: TypeError: filter needs a list
+
+15:11: ArgumentError: Builtin reduce takes 3 arguments, got 2
+
+"
+    );
+    assert_eq!(code, Some(0));
+}
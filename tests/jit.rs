@@ -0,0 +1,121 @@
+//! Compiled functions have to do what the interpreter does, and an error in
+//! one has to come out exactly as if it was interpreted, trace and all. Run
+//! with `cargo test --features jit`.
+#![cfg(feature = "jit")]
+
+use std::fs;
+
+use common::script;
+
+mod common;
+
+// stdout and stderr
+fn run(source: &str, name: &str, flags: &[&str]) -> (String, String) {
+    let path = script(&format!("{}.ws", name), source);
+    let output = common::run(flags, &path);
+    fs::remove_file(&path).ok();
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+fn assert_same(name: &str, source: &str) -> (String, String) {
+    let interpreted = run(source, name, &[]);
+    assert_eq!(run(source, name, &["--jit"]), interpreted, "{}", name);
+    // inlined calls are missing from traces, so -O is compared with itself
+    assert_eq!(
+        run(source, name, &["--jit", "-O"]),
+        run(source, name, &["-O"]),
+        "{} -O",
+        name
+    );
+    interpreted
+}
+
+#[test]
+fn runs_willcode() {
+    let source = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/willcode.ws"));
+    assert_same("willcode", &source.unwrap());
+}
+
+#[test]
+fn computes_like_the_interpreter() {
+    let (stdout, _) = assert_same(
+        "compute",
+        r#"
+fun fib(x) {
+	if x < 2 {
+		return 1;
+	}
+	return fib(x - 1) + fib(x - 2);
+}
+
+fun collatz(n) {
+	var steps = 0;
+	while n > 1 {
+		n = if n % 2 == 0 { n / 2 } else { 3 * n + 1 };
+		steps += 1;
+	}
+	return steps;
+}
+
+fun bits(x) {
+	return (x & 12) | (x ^ 3) + (x < 5) + (x >= 5 ^^ 1) + (0 - 7) % 3 + (0 - 7) / 2;
+}
+
+fun main() {
+	print fib(20);
+	print collatz(27);
+	print bits(6);
+	print bits(2);
+	return 0;
+}
+"#,
+    );
+    assert!(stdout.contains("10946"));
+    assert!(stdout.contains("111"));
+}
+
+#[test]
+fn errors_in_compiled_code_keep_their_trace() {
+    for (name, body) in [
+        ("overflow", "return x * 100000 * 100000;"),
+        ("divide", "return 10 / (x - 3);"),
+        ("modulo", "return 10 % (x - x);"),
+        ("unbound", "if x > 100 {\n\t\tvar y = 1;\n\t}\n\treturn y;"),
+        (
+            "redeclared",
+            "var i = 0;\n\twhile i < 2 {\n\t\tvar t = i;\n\t\ti += 1;\n\t}\n\treturn i;",
+        ),
+        ("dropped", "var y = x;\n\tdrop y;\n\treturn y + 1;"),
+    ] {
+        let source = format!(
+            "fun inner(x) {{\n\t{}\n}}\n\n\
+             fun outer(x) {{\n\treturn inner(x) + 1;\n}}\n\n\
+             fun main() {{\n\tprint \"before\";\n\tprint outer(3);\n\treturn 0;\n}}\n",
+            body
+        );
+        let (_, error) = assert_same(name, &source);
+        assert!(error.starts_with("Uncaught error"), "{}: {}", name, error);
+        assert!(error.contains("in inner(3)"), "{}: {}", name, error);
+    }
+}
+
+#[test]
+fn recursion_limit_is_the_same() {
+    let (_, error) = assert_same(
+        "deep",
+        r#"
+fun down(n) {
+	return down(n + 1);
+}
+
+fun main() {
+	print down(0);
+	return 0;
+}
+"#,
+    );
+    assert!(error.contains("RecursionError"));
+}
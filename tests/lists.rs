@@ -0,0 +1,159 @@
+//! Lists have reference semantics: assigning one or passing it to a function
+//! shares it, and a script can even build one that holds itself.
+
+use std::fs;
+
+use common::script;
+
+mod common;
+
+// stdout and the exit code of running `source`
+fn run(name: &str, source: &str) -> (String, Option<i32>) {
+    let path = script(&format!("{}.ws", name), source);
+    let output = common::run(&[], &path);
+    fs::remove_file(&path).ok();
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        output.status.code(),
+    )
+}
+
+#[test]
+fn prints_and_compares_values_that_hold_themselves() {
+    let (stdout, code) = run(
+        "cycles",
+        "struct Node { next }
+fun main() {
+    var xs = [1];
+    push(xs, xs);
+    print xs;
+    print [xs, xs];
+    print xs == xs;
+    print [xs] == [xs];
+    var m = {\"a\": 1};
+    m[\"self\"] = m;
+    print m;
+    var n = Node { next: 0 };
+    n.next = n;
+    print n;
+    print n == n;
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "[1, [...]]
+
+[[1, [...]], [1, [...]]]
+
+1
+
+1
+
+{\"a\": 1, \"self\": {...}}
+
+Node { next: Node { ... } }
+
+1
+
+"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn assignment_and_arguments_share_the_list() {
+    let (stdout, code) = run(
+        "aliasing",
+        "fun fill(xs, n) {
+    push(xs, n);
+    xs[0] = 0;
+}
+
+fun fresh() {
+    return [1];
+}
+
+fun main() {
+    var xs = [1, 2];
+    var ys = xs;
+    ys[1] = 5;
+    print xs;
+    fill(ys, 7);
+    print xs;
+    var grid = [xs, xs];
+    grid[0][0] = 9;
+    print grid;
+    print pop(xs);
+    print len(ys);
+    # a literal makes a new list every time it runs
+    var a = fresh();
+    var b = fresh();
+    push(a, 2);
+    print b;
+    var zs = [1, 2];
+    print xs == zs;
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "[1, 5]
+
+[0, 5, 7]
+
+[[9, 5, 7], [9, 5, 7]]
+
+7
+
+2
+
+[1]
+
+0
+
+"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn indexes_out_of_bounds_are_errors() {
+    let (stdout, code) = run(
+        "bounds",
+        "fun main() {
+    var xs = [1, 2];
+    try { print xs[2]; } catch e { print e; }
+    try { print xs[0 - 1]; } catch e { print e; }
+    try { xs[5] = 1; } catch e { print e; }
+    try { insert(xs, 3, 0); } catch e { print e; }
+    try { remove(xs, 2); } catch e { print e; }
+    try { pop([]); } catch e { print e; }
+    # nothing failed halfway
+    print xs;
+    return xs[2];
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "3:19: IndexError: Index 2 out of bounds for length 2
+
+4:19: IndexError: Index -1 out of bounds for length 2
+
+5:13: IndexError: Index 5 out of bounds for length 2
+
+6:11: IndexError: Insert index 3 out of bounds for list of length 2
+
+7:11: IndexError: Remove index 2 out of bounds for list of length 2
+
+8:11: IndexError: Tried to pop from an empty list
+
+[1, 2]
+
+"
+    );
+    assert_eq!(code, Some(1));
+}
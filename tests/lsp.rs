@@ -0,0 +1,290 @@
+//! `willscript lsp` driven by a scripted client: every request is written up
+//! front, then the responses are read back until the server exits.
+
+use common::{feed, frame, messages, willscript};
+
+mod common;
+
+const URI: &str = "file:///main.ws";
+
+const SOURCE: &str = r#"const LIMIT = 10;
+
+fun add(a, b = 1, ...rest) {
+    var total = a + b;
+    return total;
+}
+
+fun main() {
+    var count = add(1, 2);
+    var twice = fun(x) { return x * count; };
+    match Some(count) {
+        Some(n) => { print n; }
+        None => { print "none"; }
+    }
+    print twice(LIMIT);
+    return 0;
+}
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn open(text: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"willscript","version":1,"text":"{}"}}}}}}"#,
+        URI,
+        escape(text)
+    )
+}
+
+fn request(id: u32, method: &str, params: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    )
+}
+
+fn at(id: u32, method: &str, line: u32, character: u32) -> String {
+    let params = format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    );
+    request(id, method, &params)
+}
+
+fn document(id: u32, method: &str) -> String {
+    request(
+        id,
+        method,
+        &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+    )
+}
+
+// Sends the messages between an initialize and a shutdown and exit, giving
+// back every message the server wrote and its exit code.
+fn session(messages: &[String]) -> (Vec<String>, Option<i32>) {
+    let mut script = vec![
+        request(0, "initialize", r#"{"capabilities":{}}"#),
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#.to_owned(),
+    ];
+    script.extend_from_slice(messages);
+    script.push(request(99, "shutdown", "null"));
+    script.push(r#"{"jsonrpc":"2.0","method":"exit"}"#.to_owned());
+    run(&script)
+}
+
+fn run(script: &[String]) -> (Vec<String>, Option<i32>) {
+    let input: String = script.iter().map(|m| frame(m)).collect();
+    let output = feed(willscript().arg("lsp"), &input);
+    (messages(&output.stdout), output.status.code())
+}
+
+fn response(messages: &[String], id: u32) -> &str {
+    let prefix = format!(r#"{{"jsonrpc":"2.0","id":{},"#, id);
+    messages
+        .iter()
+        .find(|m| m.starts_with(&prefix))
+        .unwrap_or_else(|| panic!("no response to {} in {:#?}", id, messages))
+}
+
+#[test]
+fn initializes_and_shuts_down() {
+    let (messages, code) = session(&[]);
+    let capabilities = response(&messages, 0);
+    for capability in [
+        "\"textDocumentSync\":1",
+        "\"definitionProvider\":true",
+        "\"hoverProvider\":true",
+        "\"documentSymbolProvider\":true",
+        "\"completionProvider\":{}",
+        "\"documentFormattingProvider\":true",
+    ] {
+        assert!(capabilities.contains(capability), "{}", capabilities);
+    }
+    assert_eq!(
+        response(&messages, 99),
+        r#"{"jsonrpc":"2.0","id":99,"result":null}"#
+    );
+    assert_eq!(code, Some(0));
+
+    // exiting without a shutdown is an error
+    let (_, code) = run(&[r#"{"jsonrpc":"2.0","method":"exit"}"#.to_owned()]);
+    assert_eq!(code, Some(1));
+}
+
+#[test]
+fn reports_lexer_and_parser_errors() {
+    let change = |text: &str| {
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{}","version":2}},"contentChanges":[{{"text":"{}"}}]}}}}"#,
+            URI,
+            escape(text)
+        )
+    };
+    let (messages, _) = session(&[
+        open(SOURCE),
+        change("fun main() {\n    var x = 1 +;\n}\n"),
+        change("fun main() {\n    var s = \"never closed;\n}\n"),
+        change(SOURCE),
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didClose","params":{{"textDocument":{{"uri":"{}"}}}}}}"#,
+            URI
+        ),
+    ]);
+    let diagnostics: Vec<&String> = messages
+        .iter()
+        .filter(|m| m.contains("textDocument/publishDiagnostics"))
+        .collect();
+    assert_eq!(diagnostics.len(), 5);
+    let clean = format!(r#""params":{{"uri":"{}","diagnostics":[]}}"#, URI);
+    assert!(diagnostics[0].contains(&clean), "{}", diagnostics[0]);
+    assert!(
+        diagnostics[1].contains(r#""range":{"start":{"line":1,"character":15}"#),
+        "{}",
+        diagnostics[1]
+    );
+    assert!(diagnostics[1].contains(r#""severity":1"#));
+    assert!(diagnostics[2].contains(r#""start":{"line":1,"#));
+    assert!(diagnostics[2].contains("never"), "{}", diagnostics[2]);
+    assert!(diagnostics[3].contains(&clean));
+    assert!(diagnostics[4].contains(&clean));
+}
+
+#[test]
+fn goes_to_definitions() {
+    let (messages, _) = session(&[
+        open(SOURCE),
+        // add in main
+        at(1, "textDocument/definition", 8, 17),
+        // count in the closure
+        at(2, "textDocument/definition", 9, 39),
+        // n in the match arm
+        at(3, "textDocument/definition", 11, 27),
+        // LIMIT
+        at(4, "textDocument/definition", 14, 18),
+        // total in add
+        at(5, "textDocument/definition", 4, 12),
+        // print is a keyword
+        at(6, "textDocument/definition", 14, 5),
+    ]);
+    let location = |line: u32, start: u32, end: u32| {
+        format!(
+            r#""result":{{"uri":"{}","range":{{"start":{{"line":{},"character":{}}},"end":{{"line":{},"character":{}}}}}}}"#,
+            URI, line, start, line, end
+        )
+    };
+    assert!(response(&messages, 1).contains(&location(2, 4, 7)));
+    assert!(response(&messages, 2).contains(&location(8, 8, 13)));
+    assert!(response(&messages, 3).contains(&location(11, 13, 14)));
+    assert!(response(&messages, 4).contains(&location(0, 6, 11)));
+    assert!(response(&messages, 5).contains(&location(3, 8, 13)));
+    assert!(response(&messages, 6).contains(r#""result":null"#));
+}
+
+#[test]
+fn hovers_show_prototypes() {
+    let (messages, _) = session(&[
+        open(SOURCE),
+        at(1, "textDocument/hover", 8, 17),
+        at(2, "textDocument/hover", 14, 11),
+        at(3, "textDocument/hover", 8, 9),
+    ]);
+    let add = response(&messages, 1);
+    assert!(
+        add.contains(r#""value":"```willscript\nfun add(a, b = 1, ...rest)\n```\nTakes 1 to 2 arguments, then any number more.""#),
+        "{}",
+        add
+    );
+    // twice is a local holding a closure, not a function
+    assert!(response(&messages, 2).contains(r#""result":null"#));
+    assert!(response(&messages, 3).contains(r#""result":null"#));
+}
+
+#[test]
+fn lists_every_function() {
+    let (messages, _) = session(&[open(SOURCE), document(1, "textDocument/documentSymbol")]);
+    let symbols = response(&messages, 1);
+    assert!(symbols.contains(
+        r#"{"name":"add","detail":"fun add(a, b = 1, ...rest)","kind":12,"range":{"start":{"line":2,"character":0},"end":{"line":5,"character":1}},"selectionRange":{"start":{"line":2,"character":4},"end":{"line":2,"character":7}}}"#
+    ), "{}", symbols);
+    assert!(symbols.contains(r#"{"name":"main","detail":"fun main()","kind":12,"range":{"start":{"line":7,"character":0},"end":{"line":16,"character":1}}"#), "{}", symbols);
+    assert_eq!(symbols.matches("\"kind\":12").count(), 2);
+}
+
+#[test]
+fn completes_keywords_locals_and_functions() {
+    let (messages, _) = session(&[
+        open(SOURCE),
+        at(1, "textDocument/completion", 14, 4),
+        at(2, "textDocument/completion", 4, 4),
+    ]);
+    let main = response(&messages, 1);
+    for item in [
+        r#"{"label":"twice","kind":6,"detail":"local"}"#,
+        r#"{"label":"count","kind":6,"detail":"local"}"#,
+        r#"{"label":"LIMIT","kind":6,"detail":"global"}"#,
+        r#"{"label":"add","kind":3,"detail":"fun add(a, b = 1, ...rest)"}"#,
+        r#"{"label":"push","kind":3,"detail":"builtin"}"#,
+        r#"{"label":"Some","kind":20,"detail":"type"}"#,
+        r#"{"label":"while","kind":14,"detail":"keyword"}"#,
+    ] {
+        assert!(main.contains(item), "{} in {}", item, main);
+    }
+    // add's locals aren't in scope in main
+    assert!(!main.contains(r#""label":"total""#));
+    let add = response(&messages, 2);
+    assert!(add.contains(r#"{"label":"rest","kind":6,"detail":"local"}"#));
+    assert!(!add.contains(r#""label":"count""#));
+}
+
+#[test]
+fn formats_the_document() {
+    let messy = "fun main( ) {print 1;return 0;}\n";
+    let (messages, _) = session(&[
+        open(messy),
+        document(1, "textDocument/formatting"),
+        open(SOURCE),
+        document(2, "textDocument/formatting"),
+        open("fun main( {\n"),
+        document(3, "textDocument/formatting"),
+        request(4, "textDocument/rename", "{}"),
+    ]);
+    assert_eq!(
+        response(&messages, 1),
+        r#"{"jsonrpc":"2.0","id":1,"result":[{"range":{"start":{"line":0,"character":0},"end":{"line":1,"character":0}},"newText":"fun main() {\n\tprint 1;\n\treturn 0;\n}\n"}]}"#
+    );
+    let formatted = response(&messages, 2);
+    assert!(formatted.contains(r#""newText":"const LIMIT = 10;"#));
+    assert!(response(&messages, 3).contains(r#""error":{"code":-32803"#));
+    assert!(response(&messages, 4).contains(r#""error":{"code":-32601"#));
+}
+
+#[test]
+fn handles_text_that_isnt_ascii() {
+    let source = "fun main() {\n    var s = \"café\"; var t = s;\n    print \"naïve ✓\";\n    return 0;\n}\n";
+    let (messages, code) = session(&[
+        open(source),
+        // s after the é
+        at(1, "textDocument/definition", 1, 28),
+        document(2, "textDocument/formatting"),
+    ]);
+    let diagnostics = messages
+        .iter()
+        .find(|m| m.contains("textDocument/publishDiagnostics"))
+        .unwrap();
+    assert!(
+        diagnostics.contains(r#""diagnostics":[]"#),
+        "{}",
+        diagnostics
+    );
+    assert!(
+        response(&messages, 1).contains(
+            r#""range":{"start":{"line":1,"character":8},"end":{"line":1,"character":9}}"#
+        )
+    );
+    assert!(response(&messages, 2).contains("naïve ✓"));
+    assert_eq!(code, Some(0));
+}
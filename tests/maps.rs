@@ -0,0 +1,86 @@
+//! Maps keep their keys in insertion order for iteration and printing, but
+//! two maps with the same pairs are equal however they were built.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn equality_ignores_insertion_order() {
+    let (stdout, code) = run_both(
+        "map-equality",
+        "fun main() {
+    var a = {\"x\": 1, \"y\": 2};
+    var b = {\"y\": 2, \"x\": 1};
+    print a == b;
+    print a;
+    print b;
+    b[\"x\"] = 3;
+    print a == b;
+    print a < b;
+    print [b, a] == [a, b];
+    delete(b, \"x\");
+    b[\"x\"] = 1;
+    print keys(b);
+    print a == b;
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "1\n\n{\"x\": 1, \"y\": 2}\n\n{\"y\": 2, \"x\": 1}\n\n0\n\n1\n\n0\n\n[\"y\", \"x\"]\n\n1\n\n"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn iterates_in_insertion_order() {
+    let (stdout, code) = run_both(
+        "map-order",
+        "fun main() {
+    var m = {\"b\": 1, \"a\": 2};
+    m[\"c\"] = 3;
+    # overwriting keeps the key where it was
+    m[\"b\"] = 4;
+    print m;
+    print keys(m);
+    print values(m);
+    print has(m, \"a\");
+    print has(m, \"z\");
+    delete(m, \"a\");
+    m[\"a\"] = 5;
+    print keys(m);
+    print len(m);
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "{\"b\": 4, \"a\": 2, \"c\": 3}\n\n[\"b\", \"a\", \"c\"]\n\n[4, 2, 3]\n\n1\n\n0\n\n[\"b\", \"c\", \"a\"]\n\n3\n\n"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn missing_keys_are_errors() {
+    let (stdout, code) = run_both(
+        "map-missing",
+        "fun main() {
+    var m = {1: \"one\"};
+    try { print m[2]; } catch e { print e; }
+    try { m[3] += \"x\"; } catch e { print e; }
+    # deleting one is fine, and says it wasn't there
+    print delete(m, 4);
+    print m;
+    return m[\"one\"];
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "3:18: KeyError: Key 2 not found in map\n\n4:12: KeyError: Key 3 not found in map\n\n0\n\n{1: \"one\"}\n\n"
+    );
+    assert_eq!(code, Some(1));
+}
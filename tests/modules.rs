@@ -0,0 +1,59 @@
+//! Imported functions are called like any other: in expressions and as
+//! statements of their own.
+
+use std::fs;
+
+use common::{run, temp_path};
+
+mod common;
+
+// stdout and the exit code of main.ws, with `files` written next to it
+fn run_modules(name: &str, files: &[(&str, &str)]) -> (String, Option<i32>) {
+    let dir = temp_path(name);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        fs::write(dir.join(file), source).unwrap();
+    }
+    let tree = run(&[], &dir.join("main.ws"));
+    let vm = run(&["--vm"], &dir.join("main.ws"));
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(vm.stdout, tree.stdout);
+    assert_eq!(vm.status.code(), tree.status.code());
+    (
+        String::from_utf8_lossy(&tree.stdout).into_owned(),
+        tree.status.code(),
+    )
+}
+
+#[test]
+fn module_calls_are_statements() {
+    let (stdout, code) = run_modules(
+        "module-statements",
+        &[
+            (
+                "main.ws",
+                "import \"log.ws\" as log;
+fun main() {
+    log.say(1);
+    log.say(log.count());
+    return 0;
+}
+",
+            ),
+            (
+                "log.ws",
+                "global said = 0;
+export fun say(x) {
+    said += 1;
+    print x;
+}
+export fun count() {
+    return said;
+}
+",
+            ),
+        ],
+    );
+    assert_eq!(stdout, "1\n\n1\n\n");
+    assert_eq!(code, Some(0));
+}
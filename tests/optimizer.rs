@@ -0,0 +1,309 @@
+//! An optimized program has to do exactly what it did before. Every script
+//! here runs on both backends with and without `-O`, and has to print the same
+//! and fail with the same error each time.
+
+use std::{fs, path::Path};
+
+use common::script;
+
+mod common;
+
+// What a run showed: stdout, and the uncaught error if there was one.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    error: Option<String>,
+}
+
+fn run(path: &Path, flags: &[&str]) -> Outcome {
+    let output = common::run(flags, path);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        // the trace after it can lose inlined calls, the error itself can't
+        error: (!output.status.success())
+            .then(|| stderr.lines().next().unwrap_or_default().to_owned()),
+    }
+}
+
+// Runs the script every way, checks they agree and gives back the outcome.
+fn assert_preserved(name: &str, source: &str) -> Outcome {
+    let path = script(&format!("{}.ws", name), source);
+    let expected = run(&path, &[]);
+    for flags in [&["-O"][..], &["--vm"], &["--vm", "-O"]] {
+        assert_eq!(run(&path, flags), expected, "{} with {:?}", name, flags);
+    }
+    fs::remove_file(&path).ok();
+    expected
+}
+
+fn printed(outcome: &Outcome) -> Vec<&str> {
+    outcome.stdout.lines().filter(|l| !l.is_empty()).collect()
+}
+
+#[test]
+fn folds_constant_operators() {
+    let outcome = assert_preserved(
+        "fold",
+        r#"
+fun main() {
+	print 2 * 3 + 4;
+	print 7 % 4;
+	print 1 < 2 && 3 == 3;
+	print 10 - 2 - 3;
+	try {
+		print 1 / 0;
+	} catch e {
+		print e.kind;
+	}
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["10", "3", "1", "5", "DivisionByZero"]);
+}
+
+#[test]
+fn keeps_constant_errors_for_run_time() {
+    let outcome = assert_preserved(
+        "fold_error",
+        r#"
+fun main() {
+	print "before";
+	print 2147483647 + 1;
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["before"]);
+    assert!(outcome.error.unwrap().contains("Overflow"));
+}
+
+#[test]
+fn drops_code_after_return_and_throw() {
+    let outcome = assert_preserved(
+        "dead",
+        r#"
+fun early(x) {
+	if x > 1 {
+		return "big";
+		print "unreachable";
+	}
+	return "small";
+	print "unreachable";
+}
+
+fun fails() {
+	throw "gone";
+	print "unreachable";
+}
+
+fun main() {
+	print early(5);
+	print early(0);
+	try {
+		fails();
+	} catch e {
+		print e.message;
+	}
+	return 0;
+	print "unreachable";
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["big", "small", "gone"]);
+}
+
+#[test]
+fn removes_branches_that_never_run() {
+    let outcome = assert_preserved(
+        "branches",
+        r#"
+fun main() {
+	if 0 {
+		print "never";
+	}
+	while false {
+		print "never";
+	}
+	if 1 - 1 {
+		print "never";
+	} else {
+		print "else";
+	}
+	if true {
+		print "always";
+	}
+	var i = 0;
+	while i < 3 {
+		i += 1;
+	}
+	print i;
+	print if 2 > 1 { "then" } else { "else" };
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["else", "always", "3", "then"]);
+}
+
+#[test]
+fn declarations_in_removed_code_stay_unbound() {
+    let outcome = assert_preserved(
+        "unbound",
+        r#"
+fun main() {
+	if 0 {
+		var x = 1;
+	}
+	try {
+		print x;
+	} catch e {
+		print e.kind;
+	}
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["NameError"]);
+}
+
+#[test]
+fn inlines_small_functions() {
+    let outcome = assert_preserved(
+        "inline",
+        r#"
+global offset = 100;
+
+fun square(x) {
+	return x * x;
+}
+
+fun shifted(x, y) {
+	return x + y + offset;
+}
+
+fun wrapped(x) {
+	return Some(x);
+}
+
+fun main() {
+	var n = 7;
+	print square(n);
+	print square(3);
+	print shifted(n, 1);
+	offset = 0;
+	print shifted(n, 1);
+	print wrapped(n);
+	var total = 0;
+	var i = 0;
+	while i < 4 {
+		total += square(i);
+		i += 1;
+	}
+	print total;
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["49", "9", "108", "8", "Some(7)", "14"]);
+}
+
+#[test]
+fn inlined_calls_fail_the_same_way() {
+    let outcome = assert_preserved(
+        "inline_error",
+        r#"
+fun half(x) {
+	return x / 2;
+}
+
+fun ratio(a, b) {
+	return a / b;
+}
+
+fun main() {
+	print half(9);
+	print half("nine");
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["4"]);
+    assert!(outcome.error.unwrap().contains("TypeError"));
+}
+
+#[test]
+fn leaves_calls_it_cannot_inline() {
+    let outcome = assert_preserved(
+        "no_inline",
+        r#"
+fun fib(x) {
+	if x < 2 {
+		return 1;
+	}
+	return fib(x - 1) + fib(x - 2);
+}
+
+fun even(n) {
+	return if n == 0 { 1 } else { odd(n - 1) };
+}
+
+fun odd(n) {
+	return if n == 0 { 0 } else { even(n - 1) };
+}
+
+fun scaled(x, by = 10) {
+	return x * by;
+}
+
+fun apply(f, x) {
+	return f(x);
+}
+
+fun first(xs) {
+	return xs[0];
+}
+
+fun main() {
+	print fib(10);
+	print even(6);
+	print scaled(2);
+	print scaled(2, by: 3);
+	print apply(fun(x) { return x + 1; }, 1);
+	let xs = [5, 6];
+	print first(xs);
+	print first([len(xs), 8]);
+	print xs;
+	return 0;
+}
+"#,
+    );
+    assert_eq!(
+        printed(&outcome),
+        ["89", "1", "20", "6", "2", "5", "2", "[5, 6]"]
+    );
+}
+
+#[test]
+fn optimizes_closures() {
+    let outcome = assert_preserved(
+        "closures",
+        r#"
+fun double(x) {
+	return x * 2;
+}
+
+fun main() {
+	var base = 1 + 1;
+	var add = fun(x) {
+		return x + base + double(3);
+		print "unreachable";
+	};
+	print add(1);
+	return 0;
+}
+"#,
+    );
+    assert_eq!(printed(&outcome), ["9"]);
+}
@@ -0,0 +1,83 @@
+//! Parameters can have defaults, be named at the call and collect the rest of
+//! the arguments. Calling with arguments that don't fit is an error at the call.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn defaults_names_and_rest() {
+    let (stdout, code) = run_both(
+        "params",
+        "fun span(lo, hi = lo + 10) {
+    return [lo, hi];
+}
+fun log(first, ...rest) {
+    return [first, rest];
+}
+fun main() {
+    print span(1);
+    print span(1, 2);
+    print span(hi: 3, lo: 2);
+    print span(5, hi: 6);
+    print log(1);
+    print log(1, 2, 3);
+    var f = fun(x, scale = 2) { return x * scale; };
+    print f(scale: 5, x: 2);
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "[1, 11]\n\n[1, 2]\n\n[2, 3]\n\n[5, 6]\n\n[1, []]\n\n[1, [2, 3]]\n\n10\n\n"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn arguments_that_dont_fit_are_errors_at_the_call() {
+    // called through a value, so only checked when the call runs
+    let (stdout, code) = run_both(
+        "arity",
+        "fun pair(a, b = 0) {
+    return [a, b];
+}
+fun log(first, ...rest) {
+    return first;
+}
+fun main() {
+    var f = pair;
+    var g = log;
+    try { f(1, 2, 3); } catch e { print e; }
+    try { f(); } catch e { print e; }
+    try { f(1, c: 2); } catch e { print e; }
+    try { f(1, a: 2); } catch e { print e; }
+    try { g(); } catch e { print e; }
+    try { len(xs: [1]); } catch e { print e; }
+    var h = fun(x) { return x; };
+    try { print map([1], fun(x, y) { return x; }); } catch e { print e; }
+    return h(1, 2);
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "10:11: ArgumentError: Function pair takes 1 to 2 arguments, got 3.
+
+11:11: ArgumentError: Function pair is missing argument a.
+
+12:11: ArgumentError: Function pair has no parameter named c.
+
+13:11: ArgumentError: Argument a of function pair is given both by position and by name.
+
+14:11: ArgumentError: Function log is missing argument first.
+
+15:11: ArgumentError: len doesn't take named arguments, got xs
+
+17:17: ArgumentError: Function <closure> is missing argument y.
+
+"
+    );
+    assert_eq!(code, Some(1));
+}
@@ -0,0 +1,81 @@
+//! `?` hands an Err or None back to the caller, and the unwrap builtins take
+//! a value out of an Ok or Some.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn question_mark_returns_early() {
+    let (stdout, code) = run_both(
+        "propagate",
+        "fun half(x) {
+    if x % 2 {
+        return Err(\"odd\");
+    }
+    return Ok(x / 2);
+}
+fun quarter(x, log) {
+    var h = half(x)?;
+    push(log, h);
+    return Ok(half(h)?);
+}
+fun first(xs) {
+    if len(xs) == 0 {
+        return None;
+    }
+    return Some(xs[0]);
+}
+fun first_of_first(xss) {
+    return Some(first(first(xss)?)?);
+}
+fun main() {
+    var log = [];
+    print quarter(8, log);
+    print quarter(6, log);
+    # the Err comes back before anything after the first ? runs
+    print quarter(5, log);
+    print log;
+    print first_of_first([[1, 2]]);
+    print first_of_first([[]]);
+    print first_of_first([]);
+    try { print half(4)? + 1; } catch e { print e; }
+    try { var x = 5?; } catch e { print e; }
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "Ok(2)\n\nErr(\"odd\")\n\nErr(\"odd\")\n\n[4, 3]\n\nSome(1)\n\nNone\n\nNone\n\n\
+         3\n\n32:20: TypeError: ? needs a Result or Option, got 5\n\n"
+    );
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn unwrap_builtins() {
+    let (stdout, code) = run_both(
+        "unwrap",
+        "fun main() {
+    print [unwrap(Ok(1)), unwrap(Some(2))];
+    print [unwrap_or(Err(\"no\"), 3), unwrap_or(None, 4), unwrap_or(Some(5), 6)];
+    print [is_ok(Ok(0)), is_ok(Err(0)), is_none(None), is_none(Some(0))];
+    try { unwrap(None); } catch e { print e; }
+    try { unwrap(Err(\"bad\")); } catch e { print e; }
+    try { unwrap(Err(error(\"ParseError\", \"not a number\"))); } catch e { print e; }
+    try { is_ok(3); } catch e { print e; }
+    return unwrap(None);
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "[1, 2]\n\n[3, 4, 5]\n\n[1, 0, 1, 0]\n\n\
+         5:11: ValueError: Called unwrap on None\n\n\
+         6:11: ValueError: Called unwrap on Err(\"bad\")\n\n\
+         7:11: ParseError: not a number\n\n\
+         8:11: TypeError: is_ok needs a Result, got 3\n\n"
+    );
+    assert_eq!(code, Some(1));
+}
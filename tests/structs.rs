@@ -0,0 +1,42 @@
+//! Struct instances only have the fields their declaration lists.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn unknown_fields_are_errors() {
+    let (stdout, code) = run_both(
+        "fields",
+        "struct Point { x, y }
+fun main() {
+    var p = Point { x: 1, y: 2 };
+    try { print p.z; } catch e { print e; }
+    try { p.z = 3; } catch e { print e; }
+    try { p.w += 1; } catch e { print e; }
+    try { print 5.x; } catch e { print e.kind; }
+    p.y = 4;
+    print p;
+    try { var q = Point { x: 1, z: 2 }; } catch e { print e; }
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "4:18: FieldError: Struct Point has no field z
+
+5:12: FieldError: Struct Point has no field z
+
+6:12: FieldError: Struct Point has no field w
+
+TypeError
+
+Point { x: 1, y: 4 }
+
+10:19: FieldError: Struct Point has no field z
+
+"
+    );
+    assert_eq!(code, Some(0));
+}
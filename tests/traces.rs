@@ -0,0 +1,62 @@
+//! An uncaught error ends the program with the WillScript trace, and a script
+//! that's rejected before it runs with the reason. Neither is a Rust panic.
+
+use std::fs;
+
+use common::{script, willscript};
+
+mod common;
+
+#[test]
+fn uncaught_errors_exit_with_the_trace() {
+    let source = "fun fib(n) {\n    if n == 0 {\n        throw \"too far\";\n    }\n    return fib(n - 1);\n}\n\nfun main() {\n    return fib(4);\n}\n";
+    let path = script("fib.ws", source);
+    let output = willscript()
+        .arg(&path)
+        .env("RUST_BACKTRACE", "1")
+        .output()
+        .expect("could not run willscript");
+    fs::remove_file(&path).ok();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Uncaught error at 3:9: Error: too far
+  in fib(0) called at 5:12
+  ... repeated 3 more times
+  in fib(4) called at 9:12
+  in main()
+"
+    );
+}
+
+#[test]
+fn rejected_scripts_exit_with_the_reason() {
+    let rejected = [
+        (
+            "undefined.ws",
+            "fun main() {\n    return y;\n}\n",
+            "In function main: 2:12: Undefined variable y.\n",
+        ),
+        (
+            "unmatched.ws",
+            "fun main() {\n    return match Some(1) { None => 0 };\n}\n",
+            "In function main: Non-exhaustive match over enum Option, missing: Some.\n",
+        ),
+        (
+            "unparsed.ws",
+            "fun main() {\n    return 1\n}\n",
+            "3:1: No semicolon after return statement.\n",
+        ),
+    ];
+    for (name, source, reason) in rejected {
+        let path = script(name, source);
+        let output = willscript()
+            .arg(&path)
+            .env("RUST_BACKTRACE", "1")
+            .output()
+            .expect("could not run willscript");
+        fs::remove_file(&path).ok();
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), reason);
+    }
+}
@@ -0,0 +1,66 @@
+//! Destructuring declarations bind every name in the pattern, or fail when the
+//! value doesn't have that shape.
+
+use common::run_both;
+
+mod common;
+
+#[test]
+fn destructures_tuples_lists_and_structs() {
+    let (stdout, code) = run_both(
+        "destructure",
+        "struct Point { x, y }
+fun divmod(a, b) {
+    return a / b, a % b;
+}
+fun main() {
+    var (q, r) = divmod(17, 5);
+    print [q, r];
+    var [first, _, third] = [\"a\", \"b\", \"c\"];
+    print [first, third];
+    var Point { x, y: py } = Point { x: 3, y: 4 };
+    print [x, py];
+    var ((a, b), [c]) = ((1, 2), [3]);
+    print a + b + c;
+    print divmod(7, 2) == (3, 1);
+    return 0;
+}
+",
+    );
+    assert_eq!(stdout, "[3, 2]\n\n[\"a\", \"c\"]\n\n[3, 4]\n\n6\n\n1\n\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn shapes_that_dont_fit_are_errors() {
+    let (stdout, code) = run_both(
+        "mismatch",
+        "struct Point { x, y }
+struct Size { w, h }
+fun main() {
+    try { var (a, b) = (1, 2, 3); } catch e { print e; }
+    try { var [c, d] = [1]; } catch e { print e; }
+    try { var (e1, f) = [1, 2]; } catch e { print e; }
+    try { var Point { x, z } = Point { x: 1, y: 2 }; } catch e { print e; }
+    try { var Point { x } = Size { w: 1, h: 2 }; } catch e { print e; }
+    var (g, h) = 5;
+    return 0;
+}
+",
+    );
+    assert_eq!(
+        stdout,
+        "4:11: ValueError: Can't destructure a tuple of length 3 into 2 names
+
+5:11: ValueError: Can't destructure a list of length 1 into 2 names
+
+6:11: TypeError: Can't destructure [1, 2] that way
+
+7:11: FieldError: Struct Point has no field z
+
+8:11: TypeError: Can't destructure a Size as a Point
+
+"
+    );
+    assert_eq!(code, Some(1));
+}
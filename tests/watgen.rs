@@ -0,0 +1,226 @@
+//! `build --emit wat` has to give a well formed module with the host interface
+//! it documents. When `wat2wasm` and `node` are installed, the module is also
+//! run and has to do what the interpreter does.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use common::{feed, script, temp_path, willscript};
+
+mod common;
+
+// Instantiates the module with the imports it asks for, printing and failing
+// the way the interpreter does.
+const HOST: &str = r#"
+const fs = require("fs");
+const lines = fs.readFileSync(0, "utf8").split("\n");
+let memory;
+const text = (ptr, len) => Buffer.from(memory.buffer, ptr, len).toString();
+const imports = { willscript: {
+    print_int: (n) => process.stdout.write(n + "\n\n"),
+    print_str: (ptr, len) => process.stdout.write(text(ptr, len) + "\n\n"),
+    input: () => parseInt(lines.shift(), 10),
+    fail: (at, atLen, message, messageLen, a, b) => {
+        const shown = text(message, messageLen).replace("{}", a).replace("{}", b);
+        process.stderr.write("Uncaught error at " + text(at, atLen) + ": " + shown + "\n");
+        process.exit(1);
+    },
+} };
+WebAssembly.instantiate(fs.readFileSync(process.argv[2]), imports).then(({ instance }) => {
+    memory = instance.exports.memory;
+    instance.exports.main();
+});
+"#;
+
+fn build(script: &Path, out: &Path) -> Output {
+    willscript()
+        .args(["build", "--emit", "wat"])
+        .arg(script)
+        .arg("-o")
+        .arg(out)
+        .output()
+        .expect("could not run willscript")
+}
+
+fn has(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+// Every paren closes, and none closes early. Strings are skipped, since the
+// data segment can hold anything.
+fn assert_balanced(wat: &str) {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    for line in wat.lines().filter(|l| !l.trim_start().starts_with(";;")) {
+        for c in line.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                '(' if !in_string => depth += 1,
+                ')' if !in_string => depth -= 1,
+                _ => {}
+            }
+            assert!(depth >= 0, "a paren closes early in:\n{}", wat);
+        }
+    }
+    assert_eq!(depth, 0);
+    assert!(!in_string);
+}
+
+// Builds the script and checks the module's shape, then runs it both ways if
+// the tools are there. Gives back the module's text.
+fn assert_same(name: &str, source: &str, stdin: &str) -> String {
+    let script = script(&format!("{}.ws", name), source);
+    let wat_file = temp_path(&format!("{}.wat", name));
+    let wasm_file = temp_path(&format!("{}.wasm", name));
+    let built = build(&script, &wat_file);
+    assert!(
+        built.status.success(),
+        "{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+    let wat = fs::read_to_string(&wat_file).unwrap();
+    assert_balanced(&wat);
+    assert!(wat.starts_with(";;"));
+    for import in ["print_int", "print_str", "input", "fail"] {
+        assert!(wat.contains(&format!("(import \"willscript\" \"{}\"", import)));
+    }
+    assert!(wat.contains("(func (export \"main\")"));
+    assert!(wat.contains("(memory (export \"memory\")"));
+    if has("wat2wasm") && has("node") {
+        let converted = Command::new("wat2wasm")
+            .arg(&wat_file)
+            .arg("-o")
+            .arg(&wasm_file)
+            .status()
+            .unwrap();
+        assert!(converted.success());
+        let host = common::script(&format!("{}-host.js", name), HOST);
+        let native = feed(
+            Command::new("node")
+                .arg("--stack-size=65500")
+                .arg(&host)
+                .arg(&wasm_file),
+            stdin,
+        );
+        let interpreted = feed(willscript().arg(&script), stdin);
+        assert_eq!(
+            String::from_utf8_lossy(&native.stdout),
+            String::from_utf8_lossy(&interpreted.stdout)
+        );
+        let first_line = |o: &Output| {
+            let stderr = String::from_utf8_lossy(&o.stderr).into_owned();
+            stderr.lines().next().unwrap_or_default().to_owned()
+        };
+        assert_eq!(first_line(&native), first_line(&interpreted));
+        fs::remove_file(&wasm_file).ok();
+        fs::remove_file(&host).ok();
+    }
+    fs::remove_file(&script).ok();
+    fs::remove_file(&wat_file).ok();
+    wat
+}
+
+#[test]
+fn translates_functions_loops_and_globals() {
+    let wat = assert_same(
+        "loops",
+        r#"
+global count = 0;
+const LIMIT = 2 * 5;
+
+fun fib(x) {
+	if x < 2 {
+		return 1;
+	}
+	return fib(x - 1) + fib(x - 2);
+}
+
+fun bump() {
+	count += 1;
+	return count;
+}
+
+fun main() {
+	print "Hello, \"wasm\"";
+	print fib(15);
+	var i = 0;
+	while i < LIMIT {
+		i += 1;
+		bump();
+	}
+	print count;
+	print if count == 10 { 1 } else { 0 };
+	print 1 && 0 || 5 ^^ 0;
+	print 7 % 3 + 10 / 3 - 1;
+	return 0;
+}
+"#,
+        "",
+    );
+    for func in ["$f0_fib", "$f1_bump", "$f2_main"] {
+        assert!(wat.contains(&format!("(func {}", func)), "no {}", func);
+    }
+    assert!(wat.contains("(global $g0_count (mut i32)"));
+    assert!(wat.contains("call $f0_fib"));
+    // overflow is checked by doing the arithmetic in i64
+    assert!(wat.contains("i64.add"));
+    assert!(wat.contains("i32.lt_s"));
+    assert!(wat.contains("loop"));
+}
+
+#[test]
+fn imports_input() {
+    let wat = assert_same(
+        "input",
+        r#"
+fun main() {
+	input n;
+	print n * 2;
+	return 0;
+}
+"#,
+        "21\n",
+    );
+    assert!(wat.contains("call $input"));
+    assert!(wat.contains("call $print_int"));
+}
+
+#[test]
+fn fails_like_the_interpreter() {
+    let wat = assert_same(
+        "errors",
+        r#"
+fun main() {
+	var zero = 0;
+	print 2147483647 - 1;
+	print 7 / zero;
+	return 0;
+}
+"#,
+        "",
+    );
+    assert!(wat.contains("DivisionByZero: {} / 0"));
+}
+
+#[test]
+fn reports_what_it_cannot_translate() {
+    let script = script(
+        "unsupported.ws",
+        "fun main() {\n\tvar s = \"text\";\n\tprint s;\n}\n",
+    );
+    let wat_file = temp_path("unsupported.wat");
+    let built = build(&script, &wat_file);
+    assert!(!built.status.success());
+    assert!(!wat_file.exists());
+    assert!(String::from_utf8_lossy(&built.stderr).contains("can't be compiled to WebAssembly"));
+    fs::remove_file(&script).ok();
+}